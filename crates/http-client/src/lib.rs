@@ -0,0 +1,217 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Certificate, Client, Identity, NoProxy, Proxy};
+use serde::Deserialize;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// ✅ Settings for the long-lived clients the collector forwards with and the processor outputs with
+//
+// The processor reads them from JSON (`{"http_client": {"timeout_ms": 10000}}`), the collector from
+// the environment (`FORWARDER_TIMEOUT_MS=10000`); both use the field names below.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpClientConfig {
+    pub connect_timeout_ms: u64,
+    pub timeout_ms: u64, // Whole request, including reading the response
+    pub pool_idle_timeout_secs: u64, // Idle keep-alive connections are closed after this
+    pub pool_max_idle_per_host: usize,
+    pub tcp_keepalive_secs: u64, // 0 disables TCP keepalive probes
+    pub http2_prior_knowledge: bool, // Speak HTTP/2 without negotiating (h2c); TLS downstreams negotiate it anyway
+    pub http2_keep_alive_secs: u64, // HTTP/2 PING interval; 0 disables
+    pub proxy: Option<String>, // Proxy for all requests; HTTP(S)_PROXY is honoured when unset
+    pub no_proxy: Option<String>, // Comma-separated hosts that bypass `proxy`
+    pub ca_bundle: Option<PathBuf>, // Extra PEM root certificates, on top of the built-in and system roots
+    pub client_cert: Option<PathBuf>, // PEM certificate (chain) for mutual TLS
+    pub client_key: Option<PathBuf>, // PEM key (PKCS#8, PKCS#1 or SEC1) for `client_cert`
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 5_000,
+            timeout_ms: 30_000,
+            pool_idle_timeout_secs: 90,
+            pool_max_idle_per_host: 32,
+            tcp_keepalive_secs: 60,
+            http2_prior_knowledge: false,
+            http2_keep_alive_secs: 0,
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+            client_cert: None,
+            client_key: None,
+        }
+    }
+}
+
+impl HttpClientConfig {
+    // ✅ Reads `<PREFIX>_CONNECT_TIMEOUT_MS`, `<PREFIX>_TIMEOUT_MS`, `<PREFIX>_PROXY`, ... over the defaults
+    pub fn from_env(prefix: &str) -> Self {
+        Self::from_lookup(prefix, |name| env::var(name).ok())
+    }
+
+    // Same as `from_env` with variables from `lookup`; unset, empty and unparseable ones keep the default
+    pub fn from_lookup(prefix: &str, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |name: &str| lookup(&format!("{}_{}", prefix, name)).filter(|v| !v.is_empty());
+        let path = |name: &str| var(name).map(PathBuf::from);
+        let defaults = Self::default();
+
+        Self {
+            connect_timeout_ms: parse_or(var("CONNECT_TIMEOUT_MS"), defaults.connect_timeout_ms),
+            timeout_ms: parse_or(var("TIMEOUT_MS"), defaults.timeout_ms),
+            pool_idle_timeout_secs: parse_or(var("POOL_IDLE_TIMEOUT_SECS"), defaults.pool_idle_timeout_secs),
+            pool_max_idle_per_host: parse_or(var("POOL_MAX_IDLE_PER_HOST"), defaults.pool_max_idle_per_host),
+            tcp_keepalive_secs: parse_or(var("TCP_KEEPALIVE_SECS"), defaults.tcp_keepalive_secs),
+            http2_prior_knowledge: parse_or(var("HTTP2_PRIOR_KNOWLEDGE"), defaults.http2_prior_knowledge),
+            http2_keep_alive_secs: parse_or(var("HTTP2_KEEP_ALIVE_SECS"), defaults.http2_keep_alive_secs),
+            proxy: var("PROXY"),
+            no_proxy: var("NO_PROXY"),
+            ca_bundle: path("CA_BUNDLE"),
+            client_cert: path("CLIENT_CERT"),
+            client_key: path("CLIENT_KEY"),
+        }
+    }
+
+    pub fn build(&self) -> Result<Client> {
+        // rustls negotiates HTTP/2 over TLS via ALPN and accepts PEM client identities
+        let mut builder = Client::builder()
+            .use_rustls_tls()
+            .connect_timeout(Duration::from_millis(self.connect_timeout_ms))
+            .timeout(Duration::from_millis(self.timeout_ms))
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout_secs))
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .tcp_keepalive((self.tcp_keepalive_secs > 0).then(|| Duration::from_secs(self.tcp_keepalive_secs)));
+
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if self.http2_keep_alive_secs > 0 {
+            builder = builder
+                .http2_keep_alive_interval(Duration::from_secs(self.http2_keep_alive_secs))
+                .http2_keep_alive_while_idle(true);
+        }
+
+        if let Some(proxy) = &self.proxy {
+            let proxy = Proxy::all(proxy).with_context(|| format!("invalid proxy {}", proxy))?;
+            builder = builder.proxy(proxy.no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string)));
+        }
+
+        if let Some(path) = &self.ca_bundle {
+            for cert in Certificate::from_pem_bundle(&read_pem(path)?).with_context(|| format!("invalid CA bundle {:?}", path))? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                // The certificate and key go in as one PEM buffer
+                let mut pem = read_pem(cert)?;
+                pem.push(b'\n');
+                pem.extend(read_pem(key)?);
+                let identity = Identity::from_pem(&pem)
+                    .with_context(|| format!("invalid client certificate {:?} / key {:?}", cert, key))?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => bail!("`client_cert` and `client_key` must be set together"),
+        }
+
+        builder.build().context("failed to build HTTP client")
+    }
+}
+
+fn parse_or<T: FromStr>(value: Option<String>, default: T) -> T {
+    value.and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    fn config(overrides: Value) -> HttpClientConfig {
+        serde_json::from_value(overrides).unwrap()
+    }
+
+    fn error(overrides: Value) -> String {
+        format!("{:#}", config(overrides).build().unwrap_err())
+    }
+
+    // Answers every request with `name`, so a test can tell which server a request reached
+    async fn server(name: &'static str) -> String {
+        let app = Router::new().fallback(get(move || async move { name }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    #[test]
+    fn client_identity_needs_both_halves() {
+        let dir = tempfile::tempdir().unwrap();
+        let pem = dir.path().join("client.pem");
+        std::fs::write(&pem, "not a certificate").unwrap();
+
+        assert!(error(json!({"client_cert": pem})).contains("must be set together"));
+        assert!(error(json!({"client_key": pem})).contains("must be set together"));
+        assert!(error(json!({"client_cert": pem, "client_key": pem})).contains("invalid client certificate"));
+        assert!(error(json!({"client_cert": dir.path().join("missing.pem"), "client_key": pem})).contains("failed to read"));
+    }
+
+    #[test]
+    fn rejects_unreadable_ca_bundles_and_bad_proxies() {
+        assert!(error(json!({"ca_bundle": "/nonexistent/ca.pem"})).contains("failed to read \"/nonexistent/ca.pem\""));
+        assert!(error(json!({"proxy": "http://[::1"})).contains("invalid proxy"));
+        assert!(config(json!({})).build().is_ok());
+    }
+
+    #[test]
+    fn reads_settings_from_prefixed_variables() {
+        let vars = HashMap::from([
+            ("FORWARDER_TIMEOUT_MS", "2500"),
+            ("FORWARDER_TCP_KEEPALIVE_SECS", "0"),
+            ("FORWARDER_HTTP2_KEEP_ALIVE_SECS", "20"),
+            ("FORWARDER_HTTP2_PRIOR_KNOWLEDGE", "true"),
+            ("FORWARDER_POOL_MAX_IDLE_PER_HOST", "not a number"),
+            ("FORWARDER_PROXY", ""),
+            ("FORWARDER_NO_PROXY", "localhost,10.0.0.0/8"),
+            ("OTHER_CONNECT_TIMEOUT_MS", "1"),
+        ]);
+        let config = HttpClientConfig::from_lookup("FORWARDER", |name| vars.get(name).map(|v| v.to_string()));
+
+        assert_eq!(config.timeout_ms, 2500);
+        assert_eq!(config.connect_timeout_ms, 5_000);
+        // 0 turns keepalive probes off; unparseable values keep the default
+        assert_eq!(config.tcp_keepalive_secs, 0);
+        assert_eq!(config.http2_keep_alive_secs, 20);
+        assert!(config.http2_prior_knowledge);
+        assert_eq!(config.pool_max_idle_per_host, 32);
+        assert_eq!(config.proxy, None);
+        assert_eq!(config.no_proxy.as_deref(), Some("localhost,10.0.0.0/8"));
+    }
+
+    #[tokio::test]
+    async fn no_proxy_hosts_bypass_the_proxy() {
+        let proxy = server("proxy").await;
+        let direct = server("direct").await;
+        let through = |no_proxy: Option<&str>| {
+            config(json!({"proxy": proxy, "no_proxy": no_proxy})).build().unwrap()
+        };
+        let fetch = |client: Client, url: String| async move { client.get(url).send().await.unwrap().text().await.unwrap() };
+
+        // `example.invalid` never resolves, so only a proxied request can succeed
+        assert_eq!(fetch(through(None), "http://example.invalid/".to_string()).await, "proxy");
+        assert_eq!(fetch(through(None), direct.clone()).await, "proxy");
+        assert_eq!(fetch(through(Some("localhost, 127.0.0.1")), direct.clone()).await, "direct");
+        assert_eq!(fetch(through(Some("10.0.0.0/8,127.0.0.0/8")), direct.clone()).await, "direct");
+        assert_eq!(fetch(through(Some("10.0.0.0/8")), "http://example.invalid/".to_string()).await, "proxy");
+    }
+}
@@ -1,58 +1,250 @@
-use rusoto_core::Region;
-use rusoto_logs::{CloudWatchLogs, CloudWatchLogsClient, FilterLogEventsRequest};
+use aws_config::{sts::AssumeRoleProvider, BehaviorVersion, Region};
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_sdk_cloudwatchlogs::types::FilteredLogEvent;
+use aws_sdk_cloudwatchlogs::Client;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use crate::models::LogEntry;
+use crate::utils::{load_checkpoint, save_checkpoint};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use chrono::Utc;
+use std::time::Duration;
+use chrono::{TimeZone, Utc};
 
 #[derive(Clone)]
 pub struct AWSCloudWatchConfig {
     pub log_group_name: String, // CloudWatch Log Group
     pub log_stream_name: Option<String>, // Optional: Specific Log Stream
+    pub region: Option<String>, // Overrides the region resolved from the environment/profile
+    pub endpoint_url: Option<String>, // Custom endpoint, e.g. LocalStack (`http://localhost:4566`)
+    pub profile_name: Option<String>, // Named profile from `~/.aws/config` / `~/.aws/credentials`
+    pub role_arn: Option<String>, // Role to assume via STS on top of the base credentials
+    pub role_session_name: Option<String>,
+    pub external_id: Option<String>,
+    pub cursor_path: Option<PathBuf>, // Where to persist the poll cursor across restarts
+    pub page_size: i32,
+    pub poll_interval: Duration,
+}
+
+impl Default for AWSCloudWatchConfig {
+    fn default() -> Self {
+        Self {
+            log_group_name: String::new(),
+            log_stream_name: None,
+            region: None,
+            endpoint_url: None,
+            profile_name: None,
+            role_arn: None,
+            role_session_name: None,
+            external_id: None,
+            cursor_path: None,
+            page_size: 50,
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Where the next poll starts. `start_time` only moves once a whole pass of pages was read;
+/// `event_ids` holds what was already forwarded at or after it, so a pass that is repeated
+/// after a failed page (or shares the boundary millisecond) doesn't forward events twice.
+#[derive(Default, Serialize, Deserialize)]
+struct CloudWatchCursor {
+    start_time: Option<i64>,
+    event_ids: HashSet<String>,
+}
+
+impl CloudWatchCursor {
+    // Events without an id can't be told apart, so none of them counts as already forwarded
+    fn seen(&self, event_id: Option<&String>) -> bool {
+        event_id.is_some_and(|id| self.event_ids.contains(id))
+    }
+}
+
+// ✅ CloudWatch Logs client from the SDK provider chain (env, profile, SSO, web identity, IMDS),
+// optionally wrapped in an STS assume-role provider; `endpoint_url` and region come with it
+async fn build_client(config: &AWSCloudWatchConfig) -> Client {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(region) = &config.region {
+        loader = loader.region(Region::new(region.clone()));
+    }
+    if let Some(profile) = &config.profile_name {
+        loader = loader.profile_name(profile);
+    }
+    if let Some(endpoint) = &config.endpoint_url {
+        loader = loader.endpoint_url(endpoint);
+    }
+    let sdk_config = loader.load().await;
+
+    let mut builder = aws_sdk_cloudwatchlogs::config::Builder::from(&sdk_config);
+    if let Some(role_arn) = &config.role_arn {
+        let mut role = AssumeRoleProvider::builder(role_arn).configure(&sdk_config);
+        if let Some(session_name) = &config.role_session_name {
+            role = role.session_name(session_name);
+        }
+        if let Some(external_id) = &config.external_id {
+            role = role.external_id(external_id);
+        }
+        builder = builder.credentials_provider(SharedCredentialsProvider::new(role.build().await));
+    }
+    Client::from_conf(builder.build())
 }
 
 pub async fn start_aws_log_ingestion(
     config: Arc<AWSCloudWatchConfig>,
     sender: mpsc::Sender<LogEntry>,
 ) {
-    let client = CloudWatchLogsClient::new(Region::default());
+    let client = build_client(&config).await;
+    let region = client.config().region().map(|r| r.to_string()).unwrap_or_default();
+    info!("☁️ Polling CloudWatch log group {} in {}", config.log_group_name, region);
+
+    let mut cursor = match &config.cursor_path {
+        Some(path) => load_checkpoint(path).await.unwrap_or_default(),
+        None => CloudWatchCursor::default(),
+    };
 
     loop {
-        let request = FilterLogEventsRequest {
-            log_group_name: config.log_group_name.clone(),
-            log_stream_names: config.log_stream_name.clone().map(|s| vec![s]),
-            limit: Some(50), // Fetch up to 50 logs at a time
-            ..Default::default()
+        let complete = match poll(&client, &config, &mut cursor, &sender).await {
+            Ok(complete) => complete,
+            Err(e) => {
+                error!("❌ Failed to fetch AWS logs: {}", e);
+                false
+            }
         };
+        if !complete {
+            warn!("⚠️ CloudWatch pass incomplete, retrying from {:?}", cursor.start_time);
+        }
 
-        match client.filter_log_events(request).await {
-            Ok(response) => {
-                if let Some(events) = response.events {
-                    for event in events {
-                        if let Some(message) = event.message {
-                            let log_entry = LogEntry {
-                                source: config.log_group_name.clone(),
-                                level: "INFO".to_string(),
-                                message,
-                                timestamp: event.timestamp.map_or_else(
-                                    || Utc::now().to_rfc3339(),
-                                    |ts| chrono::TimeZone::timestamp_millis(&Utc, ts).to_rfc3339(),
-                                ),
-                            };
-
-                            if sender.send(log_entry).await.is_err() {
-                                error!("❌ Log queue full, dropping AWS log.");
-                            }
-                        }
-                    }
-                }
+        if let Some(path) = &config.cursor_path {
+            if let Err(e) = save_checkpoint(path, &cursor).await {
+                error!("❌ Failed to persist CloudWatch cursor to {:?}: {}", path, e);
             }
-            Err(e) => {
-                error!("❌ Failed to fetch AWS logs: {}", e);
+        }
+
+        if sender.is_closed() {
+            return;
+        }
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+// ✅ One pass over every page since the cursor; returns whether it reached the last page
+async fn poll(
+    client: &Client,
+    config: &AWSCloudWatchConfig,
+    cursor: &mut CloudWatchCursor,
+    sender: &mpsc::Sender<LogEntry>,
+) -> Result<bool> {
+    let mut next_token: Option<String> = None;
+    // Newest timestamp seen in this pass and the events that carry it
+    let mut newest: Option<(i64, HashSet<String>)> = None;
+
+    loop {
+        let response = client
+            .filter_log_events()
+            .log_group_name(&config.log_group_name)
+            .set_log_stream_names(config.log_stream_name.clone().map(|stream| vec![stream]))
+            .set_start_time(cursor.start_time)
+            .limit(config.page_size)
+            .set_next_token(next_token.take())
+            .send()
+            .await?;
+
+        for event in response.events.unwrap_or_default() {
+            let event_id = event.event_id.clone();
+            if cursor.seen(event_id.as_ref()) {
+                continue;
             }
+            let timestamp = event.timestamp;
+            let Some(log) = to_log_entry(&config.log_group_name, event) else {
+                continue;
+            };
+            if sender.send(log).await.is_err() {
+                // Shutting down; the event wasn't forwarded, so the cursor stays put
+                return Ok(false);
+            }
+            if let Some(id) = &event_id {
+                cursor.event_ids.insert(id.clone());
+            }
+
+            if let Some(ts) = timestamp {
+                match &mut newest {
+                    Some((newest_ts, ids)) if ts == *newest_ts => ids.extend(event_id),
+                    Some((newest_ts, _)) if ts < *newest_ts => {}
+                    _ => newest = Some((ts, event_id.into_iter().collect())),
+                }
+            }
+        }
+
+        match response.next_token {
+            Some(token) if !token.is_empty() => next_token = Some(token),
+            _ => break,
         }
+    }
+
+    // Every page was read: restart from the newest millisecond, remembering only its events
+    if let Some((ts, ids)) = newest {
+        if cursor.start_time.is_none_or(|start| ts >= start) {
+            cursor.start_time = Some(ts);
+            cursor.event_ids = ids;
+        }
+    }
+    Ok(true)
+}
+
+fn to_log_entry(log_group_name: &str, event: FilteredLogEvent) -> Option<LogEntry> {
+    let mut attributes = HashMap::new();
+    if let Some(stream) = event.log_stream_name {
+        attributes.insert("log_stream".to_string(), stream.into());
+    }
+    Some(LogEntry {
+        source: log_group_name.to_string(),
+        level: "INFO".to_string(),
+        message: event.message?,
+        timestamp: event
+            .timestamp
+            .and_then(|ts| Utc.timestamp_millis_opt(ts).single())
+            .unwrap_or_else(Utc::now)
+            .to_rfc3339(),
+        attributes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: Option<&str>, timestamp: Option<i64>, message: Option<&str>) -> FilteredLogEvent {
+        FilteredLogEvent::builder()
+            .set_event_id(id.map(str::to_string))
+            .set_timestamp(timestamp)
+            .set_message(message.map(str::to_string))
+            .log_stream_name("web-1")
+            .build()
+    }
+
+    #[test]
+    fn events_without_an_id_are_never_taken_for_duplicates() {
+        let cursor = CloudWatchCursor { start_time: Some(1), event_ids: HashSet::from(["ev-1".to_string()]) };
+        assert!(cursor.seen(Some(&"ev-1".to_string())));
+        assert!(!cursor.seen(Some(&"ev-2".to_string())));
+        assert!(!cursor.seen(None));
+    }
+
+    #[test]
+    fn converts_events() {
+        let log = to_log_entry("app-logs", event(Some("ev-1"), Some(1_714_564_800_000), Some("hello"))).unwrap();
+        assert_eq!(log.source, "app-logs");
+        assert_eq!(log.message, "hello");
+        assert_eq!(log.timestamp, "2024-05-01T12:00:00+00:00");
+        assert_eq!(log.attributes["log_stream"], "web-1");
 
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await; // Poll every 10s
+        // No message, nothing to forward; no timestamp, stamped with the current time
+        assert!(to_log_entry("app-logs", event(None, Some(1), None)).is_none());
+        let before = Utc::now();
+        let log = to_log_entry("app-logs", event(None, None, Some("late"))).unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(&log.timestamp).unwrap() >= before - chrono::Duration::seconds(1));
     }
 }
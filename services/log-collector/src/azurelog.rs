@@ -1,44 +1,415 @@
-use azure_sdk_loganalytics::{Client, QueryResponse};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::sync::mpsc;
 use tracing::{error, info};
 use crate::models::LogEntry;
+use crate::utils::{load_checkpoint, save_checkpoint};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
-use chrono::Utc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+const DEFAULT_ENDPOINT: &str = "https://api.loganalytics.io";
+const DEFAULT_AUTHORITY: &str = "https://login.microsoftonline.com";
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+const LOG_ANALYTICS_RESOURCE: &str = "https://api.loganalytics.io";
 
 #[derive(Clone)]
 pub struct AzureLogConfig {
     pub workspace_id: String,
-    pub query: String, // Log Query
+    /// KQL template. `{start}` / `{end}` are replaced with the current window bounds; if neither
+    /// placeholder is present a `TimeGenerated` window filter and ordering are appended.
+    pub query: String,
+    pub credentials: AzureCredentials,
+    pub columns: AzureColumnMapping,
+    pub endpoint_url: Option<String>, // Override for sovereign clouds / test servers
+    pub watermark_path: Option<PathBuf>, // Where to persist the last-seen `TimeGenerated`
+    pub initial_lookback: Duration, // How far back the first window reaches without a watermark
+    pub ingestion_delay: Duration, // Log Analytics ingests with latency; don't query the newest slice
+    pub poll_interval: Duration,
+}
+
+impl Default for AzureLogConfig {
+    fn default() -> Self {
+        Self {
+            workspace_id: String::new(),
+            query: String::new(),
+            credentials: AzureCredentials::ManagedIdentity { client_id: None },
+            columns: AzureColumnMapping::default(),
+            endpoint_url: None,
+            watermark_path: None,
+            initial_lookback: Duration::from_secs(3600),
+            ingestion_delay: Duration::from_secs(60),
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum AzureCredentials {
+    /// Service principal using the OAuth2 client-credentials flow.
+    ClientSecret { tenant_id: String, client_id: String, client_secret: String },
+    /// System- or user-assigned managed identity via the instance metadata service.
+    ManagedIdentity { client_id: Option<String> },
+}
+
+/// Which result columns populate each `LogEntry` field. Columns not mapped here are copied
+/// into `attributes` when `extra_columns_as_attributes` is set.
+#[derive(Clone)]
+pub struct AzureColumnMapping {
+    pub timestamp: String,
+    pub message: String,
+    pub level: Option<String>,
+    pub source: Option<String>,
+    pub extra_columns_as_attributes: bool,
+}
+
+impl Default for AzureColumnMapping {
+    fn default() -> Self {
+        Self {
+            timestamp: "TimeGenerated".to_string(),
+            message: "Message".to_string(),
+            level: Some("SeverityLevel".to_string()),
+            source: Some("_ResourceId".to_string()),
+            extra_columns_as_attributes: true,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct AzureWatermark {
+    time_generated: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    #[serde(default)]
+    tables: Vec<QueryTable>,
+}
+
+#[derive(Deserialize)]
+struct QueryTable {
+    columns: Vec<QueryColumn>,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Deserialize)]
+struct QueryColumn {
+    name: String,
+}
+
+struct AzureLogsClient {
+    http: Client,
+    config: Arc<AzureLogConfig>,
+    token: Option<(String, Instant)>,
+}
+
+impl AzureLogsClient {
+    // ✅ Fetch (or reuse) an AAD access token for the Log Analytics API
+    async fn access_token(&mut self) -> Result<String> {
+        if let Some((token, expires_at)) = &self.token {
+            if *expires_at > Instant::now() + Duration::from_secs(300) {
+                return Ok(token.clone());
+            }
+        }
+
+        let request = match &self.config.credentials {
+            AzureCredentials::ClientSecret { tenant_id, client_id, client_secret } => self
+                .http
+                .post(format!("{}/{}/oauth2/v2.0/token", DEFAULT_AUTHORITY, tenant_id))
+                .form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id.as_str()),
+                    ("client_secret", client_secret.as_str()),
+                    ("scope", &format!("{}/.default", LOG_ANALYTICS_RESOURCE)),
+                ]),
+            AzureCredentials::ManagedIdentity { client_id } => {
+                let mut query = vec![("api-version", "2018-02-01"), ("resource", LOG_ANALYTICS_RESOURCE)];
+                if let Some(client_id) = client_id {
+                    query.push(("client_id", client_id));
+                }
+                self.http.get(IMDS_TOKEN_URL).header("Metadata", "true").query(&query)
+            }
+        };
+
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Azure AD token request failed with {}: {}", status, error_text));
+        }
+
+        let body: Value = response.json().await?;
+        let token = body["access_token"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Azure AD token response has no access_token"))?
+            .to_string();
+        // IMDS returns `expires_in` as a string, the v2 token endpoint as a number
+        let expires_in = body["expires_in"]
+            .as_u64()
+            .or_else(|| body["expires_in"].as_str().and_then(|s| s.parse().ok()))
+            .unwrap_or(3600);
+
+        self.token = Some((token.clone(), Instant::now() + Duration::from_secs(expires_in)));
+        Ok(token)
+    }
+
+    async fn query(&mut self, kql: &str) -> Result<QueryResponse> {
+        let token = self.access_token().await?;
+        let endpoint = self.config.endpoint_url.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+
+        let response = self
+            .http
+            .post(format!(
+                "{}/v1/workspaces/{}/query",
+                endpoint.trim_end_matches('/'),
+                self.config.workspace_id
+            ))
+            .bearer_auth(token)
+            .json(&json!({ "query": kql }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(anyhow!("Log Analytics responded with {}: {}", status, error_text));
+        }
+
+        Ok(response.json().await?)
+    }
 }
 
 pub async fn start_azure_log_ingestion(
     config: Arc<AzureLogConfig>,
     sender: mpsc::Sender<LogEntry>,
 ) {
-    let client = match Client::new(&config.workspace_id, None) {
-        Ok(c) => c,
-        Err(e) => {
-            error!("❌ Failed to connect to Azure Logs: {}", e);
-            return;
-        }
+    let mut client = AzureLogsClient {
+        http: Client::new(),
+        config: Arc::clone(&config),
+        token: None,
+    };
+
+    let mut watermark: AzureWatermark = match &config.watermark_path {
+        Some(path) => load_checkpoint(path).await.unwrap_or_default(),
+        None => AzureWatermark::default(),
     };
 
+    info!("☁️ Polling Azure Log Analytics workspace {}", config.workspace_id);
+
     loop {
-        match client.query(&config.query).await {
-            Ok(QueryResponse::Json(json)) => {
-                if let Ok(entries) = serde_json::from_value::<Vec<LogEntry>>(json) {
-                    for entry in entries {
-                        if sender.send(entry).await.is_err() {
-                            error!("❌ Log queue full, dropping Azure log.");
+        let end = Utc::now() - config.ingestion_delay;
+        let start = watermark
+            .time_generated
+            .unwrap_or_else(|| Utc::now() - config.initial_lookback);
+
+        if start < end {
+            let kql = render_query(&config.query, &config.columns.timestamp, start, end);
+
+            match client.query(&kql).await {
+                Ok(response) => {
+                    let mut queued_all = true;
+                    'tables: for table in response.tables {
+                        for row in &table.rows {
+                            let Some(entry) = map_row(&config.columns, &table.columns, row) else {
+                                continue;
+                            };
+
+                            if sender.send(entry).await.is_err() {
+                                queued_all = false;
+                                break 'tables;
+                            }
+                        }
+                    }
+
+                    // ✅ Shutting down mid-window: keep the old watermark so the window is re-read
+                    if !queued_all {
+                        error!("❌ Log queue closed, Azure window {} .. {} will be re-read", start, end);
+                        return;
+                    }
+
+                    // ✅ Windows are (start, end], so the next poll picks up right after this one
+                    watermark.time_generated = Some(end);
+
+                    if let Some(path) = &config.watermark_path {
+                        if let Err(e) = save_checkpoint(path, &watermark).await {
+                            error!("❌ Failed to persist Azure watermark to {:?}: {}", path, e);
                         }
                     }
                 }
-            }
-            Err(e) => {
-                error!("❌ Failed to fetch Azure logs: {}", e);
+                Err(e) => {
+                    error!("❌ Failed to fetch Azure logs: {}", e);
+                }
             }
         }
 
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await; // Poll every 10s
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+// ✅ Substitutes the window bounds into the KQL template (or appends a window filter)
+fn render_query(template: &str, time_column: &str, start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    let start = start.to_rfc3339_opts(SecondsFormat::Nanos, true);
+    let end = end.to_rfc3339_opts(SecondsFormat::Nanos, true);
+
+    if template.contains("{start}") || template.contains("{end}") {
+        return template.replace("{start}", &start).replace("{end}", &end);
+    }
+
+    format!(
+        "{} | where {col} > datetime({}) and {col} <= datetime({}) | order by {col} asc",
+        template.trim_end().trim_end_matches(';'),
+        start,
+        end,
+        col = time_column,
+    )
+}
+
+// ✅ Maps one result row onto a LogEntry using the configured column names
+fn map_row(
+    mapping: &AzureColumnMapping,
+    columns: &[QueryColumn],
+    row: &[Value],
+) -> Option<LogEntry> {
+    let mut fields: HashMap<&str, &Value> = columns
+        .iter()
+        .map(|c| c.name.as_str())
+        .zip(row.iter())
+        .collect();
+
+    let time_generated = fields
+        .remove(mapping.timestamp.as_str())
+        .and_then(Value::as_str)
+        .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+        .map(|ts| ts.with_timezone(&Utc));
+    let Some(time_generated) = time_generated else {
+        error!("❌ Azure row is missing a valid {} column, skipping", mapping.timestamp);
+        return None;
+    };
+
+    let message = fields
+        .remove(mapping.message.as_str())
+        .map(value_to_string)
+        .unwrap_or_default();
+    let level = mapping
+        .level
+        .as_deref()
+        .and_then(|col| fields.remove(col))
+        .filter(|v| !v.is_null())
+        .map(value_to_string)
+        .unwrap_or_else(|| "INFO".to_string());
+    let source = mapping
+        .source
+        .as_deref()
+        .and_then(|col| fields.remove(col))
+        .filter(|v| !v.is_null())
+        .map(value_to_string)
+        .unwrap_or_else(|| "azure".to_string());
+
+    let attributes = if mapping.extra_columns_as_attributes {
+        fields
+            .into_iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    Some(LogEntry {
+        source,
+        level,
+        message,
+        timestamp: time_generated.to_rfc3339(),
+        attributes,
+    })
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn window() -> (DateTime<Utc>, DateTime<Utc>) {
+        (
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 5, 0).unwrap(),
+        )
+    }
+
+    fn columns(names: &[&str]) -> Vec<QueryColumn> {
+        names.iter().map(|name| QueryColumn { name: name.to_string() }).collect()
+    }
+
+    #[test]
+    fn render_query_substitutes_placeholders() {
+        let (start, end) = window();
+        let kql = render_query("AppTraces | where TimeGenerated between (datetime({start}) .. datetime({end}))", "TimeGenerated", start, end);
+        assert_eq!(
+            kql,
+            "AppTraces | where TimeGenerated between (datetime(2024-05-01T12:00:00.000000000Z) .. datetime(2024-05-01T12:05:00.000000000Z))"
+        );
+    }
+
+    #[test]
+    fn render_query_appends_window_filter() {
+        let (start, end) = window();
+        let kql = render_query("AppTraces | project Ts, Message; ", "Ts", start, end);
+        assert_eq!(
+            kql,
+            "AppTraces | project Ts, Message | where Ts > datetime(2024-05-01T12:00:00.000000000Z) \
+             and Ts <= datetime(2024-05-01T12:05:00.000000000Z) | order by Ts asc"
+        );
+    }
+
+    #[test]
+    fn map_row_uses_configured_columns() {
+        let columns = columns(&["TimeGenerated", "Message", "SeverityLevel", "_ResourceId", "AppRoleName", "OperationId"]);
+        let row = vec![
+            json!("2024-05-01T12:01:00.5+02:00"),
+            json!("disk almost full"),
+            json!(3),
+            json!("/subscriptions/demo/vm-1"),
+            json!("api"),
+            Value::Null,
+        ];
+
+        let entry = map_row(&AzureColumnMapping::default(), &columns, &row).unwrap();
+        assert_eq!(entry.timestamp, "2024-05-01T10:01:00.500+00:00");
+        assert_eq!(entry.message, "disk almost full");
+        assert_eq!(entry.level, "3");
+        assert_eq!(entry.source, "/subscriptions/demo/vm-1");
+        // Unmapped columns become attributes, nulls are left out
+        assert_eq!(entry.attributes, HashMap::from([("AppRoleName".to_string(), json!("api"))]));
+    }
+
+    #[test]
+    fn map_row_defaults_and_attribute_opt_out() {
+        let mapping = AzureColumnMapping { extra_columns_as_attributes: false, ..Default::default() };
+        let columns = columns(&["TimeGenerated", "Message", "SeverityLevel", "AppRoleName"]);
+        let row = vec![json!("2024-05-01T12:01:00Z"), json!({ "code": 7 }), Value::Null, json!("api")];
+
+        let entry = map_row(&mapping, &columns, &row).unwrap();
+        assert_eq!(entry.message, r#"{"code":7}"#);
+        assert_eq!(entry.level, "INFO");
+        assert_eq!(entry.source, "azure");
+        assert!(entry.attributes.is_empty());
+    }
+
+    #[test]
+    fn map_row_skips_rows_without_a_timestamp() {
+        let columns = columns(&["TimeGenerated", "Message"]);
+        assert!(map_row(&AzureColumnMapping::default(), &columns, &[json!("yesterday"), json!("x")]).is_none());
+        assert!(map_row(&AzureColumnMapping::default(), &columns, &[Value::Null, json!("x")]).is_none());
     }
 }
@@ -0,0 +1,332 @@
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info};
+use std::{io, sync::Arc, time::Duration};
+use crate::{
+    dead_letter::{DeadLetterReason, DeadLetterStore, Protocol},
+    forwarder::{send_logs, Forwarder},
+    models::LogEntry,
+    wire::{Negotiated, WireFormat},
+};
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub max_events: usize, // Flush once a batch holds this many events
+    pub max_bytes: usize, // ...or once its JSON encoding reaches this size (before compression)
+    pub max_linger: Duration, // ...or once its oldest event has waited this long
+    pub max_in_flight: usize, // Batches being sent concurrently; the queue backs up beyond this
+    pub format: WireFormat, // Encoding sent to the processor; protobuf falls back to JSON on a 415
+}
+
+// These replace the old fixed flush at 100 events or 5 s of quiet. The byte limit now bounds
+// request size, so the event limit could go up, and the linger deadline is a real one (it no
+// longer restarts with every event), so 1 s keeps the same worst-case latency a trickle used to get.
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_events: 1000,
+            max_bytes: 1024 * 1024,
+            max_linger: Duration::from_secs(1),
+            max_in_flight: 4,
+            format: WireFormat::Protobuf,
+        }
+    }
+}
+
+// Counts the bytes serde would write, without buffering them
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn encoded_len(log: &LogEntry) -> usize {
+    let mut counter = ByteCounter(0);
+    let _ = serde_json::to_writer(&mut counter, log);
+    counter.0
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FlushReason {
+    Events,
+    Bytes,
+    Linger,
+    Shutdown,
+}
+
+#[derive(Default)]
+struct Batch {
+    logs: Vec<LogEntry>,
+    bytes: usize, // Encoded size of `logs` as a JSON array
+    deadline: Option<Instant>, // Set by the first event, so later arrivals never push it back
+}
+
+impl Batch {
+    fn push(&mut self, log: LogEntry, size: usize, max_linger: Duration) {
+        self.bytes += size;
+        self.logs.push(log);
+        self.deadline.get_or_insert_with(|| Instant::now() + max_linger);
+    }
+
+    // Whether `size` more bytes would take the batch past `max_bytes`; a single oversized event still ships on its own
+    fn would_overflow(&self, size: usize, max_bytes: usize) -> bool {
+        !self.logs.is_empty() && self.bytes + size > max_bytes
+    }
+}
+
+enum Next {
+    Log(LogEntry),
+    Expired,
+    Closed,
+}
+
+// ✅ Batch Processing for Log Forwarding
+// Batches close on event count, encoded size or linger deadline, whichever comes first, and up to
+// `max_in_flight` of them are sent at once (so batches may reach the processor out of order).
+pub async fn start_log_processor(
+    mut receiver: mpsc::Receiver<LogEntry>,
+    forwarder: Forwarder,
+    config: BatchConfig,
+    dead_letter: Arc<DeadLetterStore>,
+) {
+    let max_in_flight = config.max_in_flight.max(1);
+    let dispatcher = Arc::new(Dispatcher {
+        in_flight: Arc::new(Semaphore::new(max_in_flight)),
+        forwarder,
+        wire: Negotiated::new(config.format),
+        dead_letter,
+    });
+    let mut batch = Batch::default();
+
+    info!(
+        "📦 Batching up to {} events / {} bytes / {:?}, {} in flight, sent as {:?}",
+        config.max_events, config.max_bytes, config.max_linger, max_in_flight, config.format
+    );
+
+    loop {
+        let next = tokio::select! {
+            log = receiver.recv() => match log {
+                Some(log) => Next::Log(log),
+                None => Next::Closed,
+            },
+            _ = sleep_until(batch.deadline.unwrap_or_else(Instant::now)), if batch.deadline.is_some() => Next::Expired,
+        };
+
+        match next {
+            Next::Log(log) => {
+                let size = encoded_len(&log) + 1; // `[` or `,`
+                if batch.would_overflow(size, config.max_bytes) {
+                    dispatcher.dispatch(std::mem::take(&mut batch), FlushReason::Bytes).await;
+                }
+                batch.push(log, size, config.max_linger);
+                if batch.logs.len() >= config.max_events {
+                    dispatcher.dispatch(std::mem::take(&mut batch), FlushReason::Events).await;
+                } else if batch.bytes >= config.max_bytes {
+                    dispatcher.dispatch(std::mem::take(&mut batch), FlushReason::Bytes).await;
+                }
+            }
+            Next::Expired => {
+                dispatcher.dispatch(std::mem::take(&mut batch), FlushReason::Linger).await;
+            }
+            Next::Closed => {
+                if !batch.logs.is_empty() {
+                    dispatcher.dispatch(batch, FlushReason::Shutdown).await;
+                }
+                // Wait for every in-flight batch before returning
+                let _ = dispatcher.in_flight.acquire_many(max_in_flight as u32).await;
+                info!("📦 Log queue closed, batcher stopped");
+                return;
+            }
+        }
+    }
+}
+
+// ✅ Where flushed batches go, and how many may be on their way at once
+struct Dispatcher {
+    in_flight: Arc<Semaphore>,
+    forwarder: Forwarder,
+    wire: Negotiated, // Only used over HTTP; the gRPC stream always carries protobuf
+    dead_letter: Arc<DeadLetterStore>,
+}
+
+impl Dispatcher {
+    // Hands a batch to a sender task once an in-flight slot is free
+    async fn dispatch(self: &Arc<Self>, batch: Batch, reason: FlushReason) {
+        debug!("📦 Flushing {} logs ({} bytes) on {:?}", batch.logs.len(), batch.bytes, reason);
+        let Ok(permit) = Arc::clone(&self.in_flight).acquire_owned().await else {
+            return;
+        };
+        let dispatcher = Arc::clone(self);
+        tokio::spawn(async move {
+            dispatcher.flush(batch.logs).await;
+            drop(permit);
+        });
+    }
+
+    // Sends one batch; a batch the processor didn't take is dead-lettered as a JSON array
+    async fn flush(&self, logs: Vec<LogEntry>) {
+        let result = match &self.forwarder {
+            Forwarder::Http { client, url } => send_logs(client, &logs, url, &self.wire).await,
+            Forwarder::Grpc(stream) => stream.send_logs(&logs).await,
+        };
+        if let Err(e) = result {
+            let raw = serde_json::to_vec(&logs).unwrap_or_default();
+            self.dead_letter.record("forwarder", Protocol::Native, DeadLetterReason::DeliveryFailed, e.to_string(), &raw).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dead_letter::{DeadLetterConfig, DeadLetterFilter};
+    use axum::{body::Bytes, extract::State, http::StatusCode, routing::post, Router};
+    use std::collections::HashMap;
+    use tokio::task::JoinHandle;
+
+    type Received = mpsc::UnboundedReceiver<(Duration, Vec<String>)>;
+
+    fn log(message: &str) -> LogEntry {
+        LogEntry {
+            source: "api".to_string(),
+            level: "INFO".to_string(),
+            message: message.to_string(),
+            timestamp: "2024-05-01T12:00:00Z".to_string(),
+            attributes: HashMap::new(),
+        }
+    }
+
+    fn config(max_events: usize, max_bytes: usize, max_in_flight: usize) -> BatchConfig {
+        BatchConfig { max_events, max_bytes, max_linger: Duration::from_secs(1), max_in_flight, format: WireFormat::Json }
+    }
+
+    // A processor that answers `status` after `delay`, reporting when (since `start`) each batch arrived
+    async fn processor(start: Instant, delay: Duration, status: StatusCode) -> (String, Received) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/logs",
+                post(move |State(tx): State<mpsc::UnboundedSender<(Duration, Vec<String>)>>, body: Bytes| async move {
+                    let logs: Vec<LogEntry> = serde_json::from_slice(&zstd::decode_all(&body[..]).unwrap()).unwrap();
+                    let _ = tx.send((start.elapsed(), logs.into_iter().map(|log| log.message).collect()));
+                    tokio::time::sleep(delay).await;
+                    status
+                }),
+            )
+            .with_state(tx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/logs", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, rx)
+    }
+
+    async fn batcher(url: String, config: BatchConfig) -> (mpsc::Sender<LogEntry>, JoinHandle<()>, Arc<DeadLetterStore>) {
+        let (tx, rx) = mpsc::channel(100);
+        let dead_letter = Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await);
+        let forwarder = Forwarder::Http { client: reqwest::Client::new(), url };
+        let handle = tokio::spawn(start_log_processor(rx, forwarder, config, Arc::clone(&dead_letter)));
+        (tx, handle, dead_letter)
+    }
+
+    fn sizes(received: &mut Received) -> Vec<(Duration, usize)> {
+        std::iter::from_fn(|| received.try_recv().ok()).map(|(at, messages)| (at, messages.len())).collect()
+    }
+
+    const ZERO: Duration = Duration::ZERO;
+
+    #[tokio::test(start_paused = true)]
+    async fn flushes_on_event_count() {
+        let start = Instant::now();
+        let (url, mut received) = processor(start, ZERO, StatusCode::OK).await;
+        let (tx, handle, _) = batcher(url, config(3, usize::MAX, 4)).await;
+        for i in 0..7 {
+            tx.send(log(&format!("event {}", i))).await.unwrap();
+        }
+        drop(tx);
+        handle.await.unwrap();
+
+        // The leftover event goes out on shutdown instead of waiting for the deadline
+        assert_eq!(sizes(&mut received), [(ZERO, 3), (ZERO, 3), (ZERO, 1)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flushes_on_encoded_bytes() {
+        let start = Instant::now();
+        let (url, mut received) = processor(start, ZERO, StatusCode::OK).await;
+        let size = encoded_len(&log("event 0")) + 1;
+        let (tx, handle, _) = batcher(url, config(100, size * 2 + 1, 4)).await;
+        for i in 0..5 {
+            tx.send(log(&format!("event {}", i))).await.unwrap();
+        }
+        // Bigger than the limit by itself: sent alone rather than dropped
+        tx.send(log(&"x".repeat(size * 3))).await.unwrap();
+        drop(tx);
+        handle.await.unwrap();
+
+        assert_eq!(sizes(&mut received), [(ZERO, 2), (ZERO, 2), (ZERO, 1), (ZERO, 1)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn linger_deadline_is_set_by_the_first_event() {
+        let start = Instant::now();
+        let (url, mut received) = processor(start, ZERO, StatusCode::OK).await;
+        let (tx, handle, _) = batcher(url, config(100, usize::MAX, 4)).await;
+
+        tx.send(log("a")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        // A later arrival doesn't push the deadline back
+        tx.send(log("b")).await.unwrap();
+        assert_eq!(received.recv().await.unwrap(), (Duration::from_secs(1), vec!["a".to_string(), "b".to_string()]));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        tx.send(log("c")).await.unwrap();
+        assert_eq!(received.recv().await.unwrap(), (Duration::from_millis(2500), vec!["c".to_string()]));
+
+        drop(tx);
+        handle.await.unwrap();
+        assert!(sizes(&mut received).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn in_flight_limit_holds_back_batches() {
+        let second = Duration::from_secs(1);
+        for (max_in_flight, arrivals, stopped) in [(1, [ZERO, second, second * 2], second * 3), (3, [ZERO; 3], second)] {
+            let start = Instant::now();
+            let (url, mut received) = processor(start, second, StatusCode::OK).await;
+            let (tx, handle, _) = batcher(url, config(1, usize::MAX, max_in_flight)).await;
+            for i in 0..3 {
+                tx.send(log(&format!("event {}", i))).await.unwrap();
+            }
+            drop(tx);
+            // The batcher only returns once every in-flight batch is answered
+            handle.await.unwrap();
+            assert_eq!(start.elapsed(), stopped, "max_in_flight {}", max_in_flight);
+            let at: Vec<Duration> = sizes(&mut received).into_iter().map(|(at, _)| at).collect();
+            assert_eq!(at, arrivals, "max_in_flight {}", max_in_flight);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejected_batches_are_dead_lettered() {
+        let start = Instant::now();
+        let (url, _received) = processor(start, ZERO, StatusCode::INTERNAL_SERVER_ERROR).await;
+        let (tx, handle, dead_letter) = batcher(url, config(2, usize::MAX, 4)).await;
+        tx.send(log("a")).await.unwrap();
+        tx.send(log("b")).await.unwrap();
+        drop(tx);
+        handle.await.unwrap();
+
+        let entries = dead_letter.list(&DeadLetterFilter::default()).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reason, DeadLetterReason::DeliveryFailed);
+        let logs: Vec<LogEntry> = serde_json::from_slice(&entries[0].raw.0).unwrap();
+        assert_eq!(logs.len(), 2);
+    }
+}
@@ -0,0 +1,363 @@
+use async_compression::tokio::bufread::{GzipDecoder, Lz4Decoder, ZlibDecoder, ZstdDecoder};
+use axum::body::Body;
+use axum::http::{header, HeaderMap, StatusCode};
+use futures_util::TryStreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio_util::io::StreamReader;
+use std::fmt;
+use std::pin::Pin;
+
+pub type BodyReader = Pin<Box<dyn AsyncRead + Send>>;
+
+#[derive(Debug)]
+pub struct UnsupportedEncoding(pub String);
+
+// ✅ Wraps a request body in the decoder matching its `Content-Encoding`
+pub fn decode_body(body: Body, content_encoding: Option<&str>) -> Result<BodyReader, UnsupportedEncoding> {
+    let stream = body.into_data_stream().map_err(std::io::Error::other);
+    let reader = BufReader::new(StreamReader::new(stream));
+
+    let encoding = content_encoding.map(|e| e.trim().to_ascii_lowercase());
+    let decoded: BodyReader = match encoding.as_deref() {
+        None | Some("") | Some("identity") => Box::pin(reader),
+        Some("gzip") | Some("x-gzip") => Box::pin(GzipDecoder::new(reader)),
+        Some("zstd") => Box::pin(ZstdDecoder::new(reader)),
+        Some("lz4") => Box::pin(Lz4Decoder::new(reader)),
+        // HTTP `deflate` is the zlib format (RFC 9110 §8.4.1.2)
+        Some("deflate") => Box::pin(ZlibDecoder::new(reader)),
+        Some(other) => return Err(UnsupportedEncoding(other.to_string())),
+    };
+    Ok(decoded)
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    UnsupportedEncoding(String),
+    TooLarge { limit: usize },
+    Read(std::io::Error), // Includes corrupt compressed data
+}
+
+impl BodyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BodyError::UnsupportedEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            BodyError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            BodyError::Read(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::UnsupportedEncoding(encoding) => write!(f, "unsupported Content-Encoding: {}", encoding),
+            BodyError::TooLarge { limit } => write!(f, "request body exceeds {} bytes", limit),
+            BodyError::Read(e) => write!(f, "failed to read request body: {}", e),
+        }
+    }
+}
+
+// ✅ Buffers a whole request body for the push endpoints that parse it in one piece (Loki, `_bulk`,
+// HEC): decoded per `Content-Encoding`, and cut off past `limit` decoded bytes like `/logs` is
+pub async fn read_body(body: Body, headers: &HeaderMap, limit: usize) -> Result<Vec<u8>, BodyError> {
+    let encoding = headers.get(header::CONTENT_ENCODING).and_then(|v| v.to_str().ok());
+    let reader = decode_body(body, encoding).map_err(|UnsupportedEncoding(e)| BodyError::UnsupportedEncoding(e))?;
+
+    let mut decoded = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut decoded).await.map_err(BodyError::Read)?;
+    if decoded.len() > limit {
+        return Err(BodyError::TooLarge { limit });
+    }
+    Ok(decoded)
+}
+
+// Encodes `data` the way a client sending `Content-Encoding: <encoding>` would
+#[cfg(test)]
+pub(crate) async fn compress(encoding: &str, data: &[u8]) -> Vec<u8> {
+    use async_compression::tokio::bufread::{GzipEncoder, Lz4Encoder, ZlibEncoder, ZstdEncoder};
+
+    let mut encoder: Pin<Box<dyn AsyncRead + Send + '_>> = match encoding {
+        "gzip" => Box::pin(GzipEncoder::new(data)),
+        "zstd" => Box::pin(ZstdEncoder::new(data)),
+        "lz4" => Box::pin(Lz4Encoder::new(data)),
+        "deflate" => Box::pin(ZlibEncoder::new(data)),
+        other => panic!("no encoder for {}", other),
+    };
+    let mut encoded = Vec::new();
+    encoder.read_to_end(&mut encoded).await.unwrap();
+    encoded
+}
+
+#[derive(Debug)]
+pub enum SplitError {
+    /// A single element grew beyond the per-event limit; it is skipped.
+    Oversized { size: usize },
+}
+
+/// Incrementally splits a byte stream into top-level JSON values without buffering the whole
+/// body. Accepts NDJSON, concatenated values, a single object, or one top-level array whose
+/// elements are emitted individually.
+pub struct JsonSplitter {
+    max_element_bytes: usize,
+    buf: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    in_scalar: bool,
+    in_top_array: bool,
+    seen_first_value: bool,
+    oversized: Option<usize>,
+}
+
+impl JsonSplitter {
+    pub fn new(max_element_bytes: usize) -> Self {
+        Self {
+            max_element_bytes,
+            buf: Vec::new(),
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            in_scalar: false,
+            in_top_array: false,
+            seen_first_value: false,
+            oversized: None,
+        }
+    }
+
+    /// Feeds a chunk, returning every element completed by it (in order).
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Result<Vec<u8>, SplitError>> {
+        let mut out = Vec::new();
+        for &b in chunk {
+            self.push_byte(b, &mut out);
+        }
+        out
+    }
+
+    /// Flushes a trailing scalar or an unterminated element at end of input.
+    pub fn finish(&mut self) -> Option<Result<Vec<u8>, SplitError>> {
+        if let Some(size) = self.oversized.take() {
+            return Some(Err(SplitError::Oversized { size }));
+        }
+        let rest = std::mem::take(&mut self.buf);
+        (!rest.iter().all(u8::is_ascii_whitespace)).then_some(Ok(rest))
+    }
+
+    fn push_byte(&mut self, b: u8, out: &mut Vec<Result<Vec<u8>, SplitError>>) {
+        let in_element = self.depth > 0 || self.in_scalar || self.in_string;
+
+        if !in_element {
+            match b {
+                b if b.is_ascii_whitespace() => return,
+                b',' if self.in_top_array => return,
+                b']' if self.in_top_array => {
+                    self.in_top_array = false;
+                    return;
+                }
+                b'[' if !self.seen_first_value => {
+                    // A leading array is a batch container, not an element
+                    self.seen_first_value = true;
+                    self.in_top_array = true;
+                    return;
+                }
+                _ => {}
+            }
+            self.seen_first_value = true;
+        }
+
+        if self.in_scalar && (b.is_ascii_whitespace() || b == b',' || (b == b']' && self.in_top_array)) {
+            self.in_scalar = false;
+            self.emit(out);
+            if b == b']' {
+                self.in_top_array = false;
+            }
+            return;
+        }
+
+        self.record(b);
+
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if b == b'\\' {
+                self.escaped = true;
+            } else if b == b'"' {
+                self.in_string = false;
+                if self.depth == 0 {
+                    self.emit(out);
+                }
+            }
+            return;
+        }
+
+        match b {
+            b'"' => self.in_string = true,
+            b'{' | b'[' => self.depth += 1,
+            b'}' | b']' if self.depth > 0 => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    self.emit(out);
+                }
+            }
+            _ if self.depth == 0 => self.in_scalar = true,
+            _ => {}
+        }
+    }
+
+    fn record(&mut self, b: u8) {
+        match &mut self.oversized {
+            Some(size) => *size += 1,
+            None if self.buf.len() >= self.max_element_bytes => {
+                self.oversized = Some(self.buf.len() + 1);
+                self.buf.clear();
+            }
+            None => self.buf.push(b),
+        }
+    }
+
+    fn emit(&mut self, out: &mut Vec<Result<Vec<u8>, SplitError>>) {
+        match self.oversized.take() {
+            Some(size) => {
+                self.buf.clear();
+                out.push(Err(SplitError::Oversized { size }));
+            }
+            None => out.push(Ok(std::mem::take(&mut self.buf))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `chunks` one at a time, rendering each element as text (or `oversized:<size>`)
+    fn split(max_element_bytes: usize, chunks: &[&[u8]]) -> Vec<String> {
+        let mut splitter = JsonSplitter::new(max_element_bytes);
+        let mut elements: Vec<_> = chunks.iter().flat_map(|chunk| splitter.push(chunk)).collect();
+        elements.extend(splitter.finish());
+        elements
+            .into_iter()
+            .map(|element| match element {
+                Ok(bytes) => String::from_utf8(bytes).unwrap(),
+                Err(SplitError::Oversized { size }) => format!("oversized:{}", size),
+            })
+            .collect()
+    }
+
+    // Every way of cutting `input` in two must give the same elements
+    fn split_anywhere(max_element_bytes: usize, input: &[u8]) -> Vec<String> {
+        let whole = split(max_element_bytes, &[input]);
+        for at in 0..=input.len() {
+            let (head, tail) = input.split_at(at);
+            assert_eq!(split(max_element_bytes, &[head, tail]), whole, "split at {}", at);
+        }
+        whole
+    }
+
+    #[test]
+    fn string_escapes_survive_chunk_boundaries() {
+        let input = br#"{"msg":"say \"}\" and \\","path":"C:\\{x}"} {"n":"\u005c\""}"#;
+        assert_eq!(
+            split_anywhere(1024, input),
+            [r#"{"msg":"say \"}\" and \\","path":"C:\\{x}"}"#, r#"{"n":"\u005c\""}"#]
+        );
+        // One byte at a time
+        let bytes: Vec<&[u8]> = input.chunks(1).collect();
+        assert_eq!(split(1024, &bytes).len(), 2);
+    }
+
+    #[test]
+    fn top_level_array_elements_are_emitted_individually() {
+        assert_eq!(
+            split_anywhere(1024, br#" [ {"a":[1,2]}, {"b":{"c":[]}} ,{"d":"]"} ] "#),
+            [r#"{"a":[1,2]}"#, r#"{"b":{"c":[]}}"#, r#"{"d":"]"}"#]
+        );
+        assert!(split(1024, &[b"[]"]).is_empty());
+        // Only a leading array is a container; a later one is an element like any other
+        assert_eq!(split(1024, &[br#"{"a":1}[1,2]"#]), [r#"{"a":1}"#, "[1,2]"]);
+    }
+
+    #[test]
+    fn concatenated_and_newline_delimited_objects() {
+        let expected = [r#"{"a":1}"#, r#"{"b":2}"#, r#"{"c":3}"#];
+        assert_eq!(split_anywhere(1024, br#"{"a":1}{"b":2}{"c":3}"#), expected);
+        assert_eq!(split_anywhere(1024, b"{\"a\":1}\n{\"b\":2}\r\n\n{\"c\":3}\n"), expected);
+        assert!(split(1024, &[b" \n\t "]).is_empty());
+    }
+
+    #[test]
+    fn bare_scalars_are_elements() {
+        assert_eq!(split_anywhere(1024, br#"1 "two" true null -2.5e3"#), ["1", r#""two""#, "true", "null", "-2.5e3"]);
+        assert_eq!(split_anywhere(1024, br#"[1,"two",false]"#), ["1", r#""two""#, "false"]);
+        // A trailing scalar has no terminator; `finish` flushes it
+        assert_eq!(split(1024, &[b"{}", b"42"]), ["{}", "42"]);
+    }
+
+    #[test]
+    fn oversized_elements_are_skipped_with_their_size() {
+        let big = format!(r#"{{"msg":"{}"}}"#, "x".repeat(100));
+        let input = format!(r#"[{{"a":1}},{},{{"b":2}}]"#, big);
+        assert_eq!(
+            split_anywhere(32, input.as_bytes()),
+            [r#"{"a":1}"#.to_string(), format!("oversized:{}", big.len()), r#"{"b":2}"#.to_string()]
+        );
+        // Exactly at the limit is fine
+        assert_eq!(split(7, &[br#"{"a":1}"#]), [r#"{"a":1}"#]);
+        assert_eq!(split(6, &[br#"{"a":1}"#]), ["oversized:7"]);
+        // Cut off at end of input: still reported, not dropped silently
+        assert_eq!(split(4, &[br#"{"a":"unterminated"#]), ["oversized:18"]);
+    }
+
+    #[test]
+    fn unterminated_input_is_returned_for_the_parser_to_reject() {
+        assert_eq!(split(1024, &[br#"{"a":1}{"b":"#]), [r#"{"a":1}"#, r#"{"b":"#]);
+    }
+
+    async fn read_all(mut reader: BodyReader) -> std::io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).await?;
+        Ok(decoded)
+    }
+
+    #[tokio::test]
+    async fn decodes_every_supported_encoding() {
+        let data = br#"{"message":"hello"}"#.repeat(100);
+        for encoding in ["gzip", "zstd", "lz4", "deflate"] {
+            let encoded = compress(encoding, &data).await;
+            assert_ne!(encoded, data);
+            for header in [encoding.to_string(), format!(" {} ", encoding.to_uppercase())] {
+                let reader = decode_body(Body::from(encoded.clone()), Some(&header)).unwrap();
+                assert_eq!(read_all(reader).await.unwrap(), data, "{}", header);
+            }
+        }
+        let gzip = compress("gzip", &data).await;
+        assert_eq!(read_all(decode_body(Body::from(gzip), Some("x-gzip")).unwrap()).await.unwrap(), data);
+        for identity in [None, Some(""), Some("identity")] {
+            assert_eq!(read_all(decode_body(Body::from(data.clone()), identity).unwrap()).await.unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_and_corrupt_encodings() {
+        assert!(matches!(decode_body(Body::empty(), Some("br")), Err(UnsupportedEncoding(e)) if e == "br"));
+        let reader = decode_body(Body::from("not gzip at all"), Some("gzip")).unwrap();
+        assert!(read_all(reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_body_enforces_the_limit_on_decoded_bytes() {
+        let gzip = HeaderMap::from_iter([(header::CONTENT_ENCODING, "gzip".parse().unwrap())]);
+        let data = vec![b' '; 1000];
+        let encoded = compress("gzip", &data).await;
+        assert!(encoded.len() < 100);
+
+        assert_eq!(read_body(Body::from(encoded.clone()), &gzip, 1000).await.unwrap(), data);
+        let error = read_body(Body::from(encoded), &gzip, 999).await.unwrap_err();
+        assert!(matches!(error, BodyError::TooLarge { limit: 999 }));
+        assert_eq!(error.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let brotli = HeaderMap::from_iter([(header::CONTENT_ENCODING, "br".parse().unwrap())]);
+        assert_eq!(read_body(Body::empty(), &brotli, 10).await.unwrap_err().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let error = read_body(Body::from("garbage"), &gzip, 10).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,13 +1,74 @@
 use std::env;
+use std::path::PathBuf;
+use http_client::HttpClientConfig;
+use crate::wire::WireFormat;
 
 pub struct Config {
     pub processor_url: String,
+    pub processor_grpc_url: Option<String>, // LOG_PROCESSOR_GRPC_URL, e.g. http://localhost:4001; streams batches over gRPC instead of POSTing them
+    pub max_body_bytes: usize,
+    pub max_event_bytes: usize,
+    pub queue_timeout_ms: u64,
+    pub strict_batches: bool,
+    pub dead_letter_path: Option<PathBuf>,
+    pub dead_letter_max_entries: usize,
+    pub batch_max_events: usize, // BATCH_MAX_EVENTS, 1000 by default (was a fixed 100)
+    pub batch_max_bytes: usize, // BATCH_MAX_BYTES, 1 MiB of JSON by default
+    pub batch_max_linger_ms: u64, // BATCH_MAX_LINGER_MS, 1000 by default (was 5 s, restarted by every event)
+    pub batch_max_in_flight: usize, // BATCH_MAX_IN_FLIGHT, 4 by default
+    pub forwarder_wire_format: WireFormat, // `protobuf` (default) or `json`
+    pub forwarder_stream_state_path: Option<PathBuf>, // FORWARDER_STREAM_STATE_PATH: gRPC stream id and sequences; empty starts a new stream every run
+    pub forwarder_client: HttpClientConfig, // From `FORWARDER_*`, e.g. FORWARDER_TIMEOUT_MS, FORWARDER_CA_BUNDLE
 }
 
 impl Config {
     pub fn new() -> Self {
         dotenv::dotenv().ok();
         let processor_url = env::var("LOG_PROCESSOR_URL").unwrap_or_else(|_| "http://localhost:4000/logs".to_string());
-        Self { processor_url }
+        let processor_grpc_url = env::var("LOG_PROCESSOR_GRPC_URL").ok().filter(|url| !url.is_empty());
+        let max_body_bytes = env::var("MAX_BODY_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(64 * 1024 * 1024);
+        let max_event_bytes = env::var("MAX_EVENT_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(1024 * 1024);
+        let queue_timeout_ms = env::var("QUEUE_TIMEOUT_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(1000);
+        let strict_batches = env::var("STRICT_BATCHES").is_ok_and(|v| v == "true");
+        // An empty DEAD_LETTER_PATH keeps dead letters in memory only
+        let dead_letter_path = match env::var("DEAD_LETTER_PATH") {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from("./state/dead_letter.ndjson")),
+        };
+        let dead_letter_max_entries = env::var("DEAD_LETTER_MAX_ENTRIES").ok().and_then(|v| v.parse().ok()).unwrap_or(10_000);
+        let batch_max_events = env::var("BATCH_MAX_EVENTS").ok().and_then(|v| v.parse().ok()).unwrap_or(1000);
+        let batch_max_bytes = env::var("BATCH_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(1024 * 1024);
+        let batch_max_linger_ms = env::var("BATCH_MAX_LINGER_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(1000);
+        let batch_max_in_flight = env::var("BATCH_MAX_IN_FLIGHT").ok().and_then(|v| v.parse().ok()).unwrap_or(4);
+        let forwarder_wire_format = env::var("FORWARDER_WIRE_FORMAT").ok().and_then(|v| WireFormat::parse(&v)).unwrap_or_default();
+        let forwarder_stream_state_path = match env::var("FORWARDER_STREAM_STATE_PATH") {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from("./state/grpc_stream.json")),
+        };
+        Self {
+            processor_url,
+            processor_grpc_url,
+            max_body_bytes,
+            max_event_bytes,
+            queue_timeout_ms,
+            strict_batches,
+            dead_letter_path,
+            dead_letter_max_entries,
+            batch_max_events,
+            batch_max_bytes,
+            batch_max_linger_ms,
+            batch_max_in_flight,
+            forwarder_wire_format,
+            forwarder_stream_state_path,
+            forwarder_client: HttpClientConfig::from_env("FORWARDER"),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
     }
 }
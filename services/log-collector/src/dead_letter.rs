@@ -0,0 +1,694 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use tokio::{fs::{File, OpenOptions}, io::AsyncWriteExt, sync::{mpsc, oneshot, Mutex}};
+use tracing::{info, warn, error};
+use std::{collections::{HashSet, VecDeque}, net::SocketAddr, path::{Path as FsPath, PathBuf}, sync::Arc};
+use crate::{
+    elasticsearch_ingestion::document_to_log_entry,
+    http_handler::{send_all, AppState},
+    loki_ingestion::{parse_json_push, parse_protobuf_push},
+    models::LogEntry,
+    otlp_ingestion::{decode_export, export_request_to_entries},
+    splunk_hec::{parse_hec_events, HecParams},
+    syslog_ingestion::parse_syslog,
+};
+
+#[derive(Debug, Clone)]
+pub struct DeadLetterConfig {
+    pub path: Option<PathBuf>, // NDJSON file the entries survive restarts in; `None` keeps them in memory only
+    pub max_entries: usize, // Oldest entries are evicted beyond this
+    pub max_raw_bytes: usize, // Raw payloads are truncated to this size
+}
+
+impl Default for DeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_entries: 10_000,
+            max_raw_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterReason {
+    ParseError, // The payload couldn't be decoded into log entries
+    Oversized, // A single event was over the size limit
+    DeliveryFailed, // The log processor didn't accept a batch
+}
+
+// ✅ The wire format a payload arrived in; replay parses it with that protocol's parser
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    #[default]
+    Native, // `LogEntry` JSON: `/logs`, TCP, UDP, files, Kafka and forwarder batches
+    Syslog,
+    OtlpJson,
+    OtlpProtobuf,
+    LokiJson,
+    LokiProtobuf, // Snappy-compressed
+    SplunkHec, // `/services/collector/event` envelopes
+    ElasticsearchDocument, // One `_bulk` source document
+    ElasticsearchBulk, // A whole `_bulk` request; not replayable, see `parse_replay`
+}
+
+/// Raw payload bytes: a JSON string when they are valid UTF-8, `{"base64": ...}` otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct RawBytes(pub Vec<u8>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawRepr {
+    Text(String),
+    Binary { base64: String },
+}
+
+impl Serialize for RawBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(&self.0) {
+            Ok(text) => RawRepr::Text(text.to_string()),
+            Err(_) => RawRepr::Binary { base64: BASE64.encode(&self.0) },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RawBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match RawRepr::deserialize(deserializer)? {
+            RawRepr::Text(text) => Ok(Self(text.into_bytes())),
+            RawRepr::Binary { base64 } => BASE64.decode(base64).map(Self).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterEntry {
+    pub id: u64,
+    pub received_at: String, // RFC 3339
+    pub source: String, // Where the payload came from, e.g. `tcp:10.0.0.5:41234` or `http:/logs`
+    #[serde(default)] // Entries written before protocols were recorded are native
+    pub protocol: Protocol,
+    pub reason: DeadLetterReason,
+    pub error: String,
+    pub raw: RawBytes,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool, // `raw` was cut at `max_raw_bytes`
+}
+
+impl DeadLetterEntry {
+    // ✅ List view: everything but the payload, plus its size and a short preview
+    fn summary(&self) -> Value {
+        let preview: String = String::from_utf8_lossy(&self.raw.0).chars().take(200).collect();
+        json!({
+            "id": self.id,
+            "received_at": self.received_at,
+            "source": self.source,
+            "protocol": self.protocol,
+            "reason": self.reason,
+            "error": self.error,
+            "size": self.raw.0.len(),
+            "truncated": self.truncated,
+            "preview": preview,
+        })
+    }
+}
+
+// A line of the backing file: an entry, or the ids of entries removed after it was written
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileLine {
+    Removed { removed: Vec<u64> },
+    Entry(DeadLetterEntry),
+}
+
+// Work for the writer task, in the order the store issued it
+enum FileOp {
+    Append(Vec<u8>), // One NDJSON line
+    Rewrite(Vec<u8>), // The whole file, replacing everything appended before
+    Flush(oneshot::Sender<()>),
+}
+
+// Writes queued ahead of the file before `record` waits for the writer
+const WRITE_QUEUE: usize = 1024;
+
+struct Inner {
+    entries: VecDeque<DeadLetterEntry>,
+    next_id: u64,
+    evicted: u64,
+    file_lines: usize, // Lines in the backing file, including evicted entries and removal markers
+}
+
+// ✅ Keeps payloads that couldn't be ingested or delivered, so they can be inspected and replayed.
+// File I/O happens on a writer task, so recording from an ingestion loop never waits on the disk.
+pub struct DeadLetterStore {
+    config: DeadLetterConfig,
+    inner: Mutex<Inner>,
+    writer: Option<mpsc::Sender<FileOp>>, // Set when the store has a backing file
+}
+
+impl DeadLetterStore {
+    // ✅ Opens the store, reloading entries persisted by a previous run
+    pub async fn open(config: DeadLetterConfig) -> Self {
+        let mut entries = VecDeque::new();
+        let mut file_lines = 0;
+        if let Some(path) = &config.path {
+            match tokio::fs::read(path).await {
+                Ok(data) => {
+                    for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                        file_lines += 1;
+                        match serde_json::from_slice::<FileLine>(line) {
+                            Ok(FileLine::Entry(entry)) => entries.push_back(entry),
+                            Ok(FileLine::Removed { removed }) => {
+                                let removed: HashSet<u64> = removed.into_iter().collect();
+                                entries.retain(|entry: &DeadLetterEntry| !removed.contains(&entry.id));
+                            }
+                            Err(e) => warn!("⚠️ Skipping corrupt dead-letter entry in {:?}: {}", path, e),
+                        }
+                    }
+                    info!("📥 Loaded {} dead-letter entries from {:?}", entries.len(), path);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("⚠️ Failed to read dead-letter file {:?}: {}", path, e),
+            }
+        }
+        while entries.len() > config.max_entries {
+            entries.pop_front();
+        }
+        let next_id = entries.iter().map(|entry| entry.id + 1).max().unwrap_or(1);
+
+        let writer = config.path.clone().map(|path| {
+            let (tx, rx) = mpsc::channel(WRITE_QUEUE);
+            tokio::spawn(write_file(path, rx));
+            tx
+        });
+        let store = Self {
+            config,
+            inner: Mutex::new(Inner { entries, next_id, evicted: 0, file_lines }),
+            writer,
+        };
+        store.compact_if_stale(&mut *store.inner.lock().await).await;
+        store
+    }
+
+    // ✅ Records one dead-lettered payload and returns its id
+    pub async fn record(
+        &self,
+        source: impl Into<String>,
+        protocol: Protocol,
+        reason: DeadLetterReason,
+        error: impl Into<String>,
+        raw: &[u8],
+    ) -> u64 {
+        let truncated = raw.len() > self.config.max_raw_bytes;
+        let raw = &raw[..raw.len().min(self.config.max_raw_bytes)];
+
+        let mut inner = self.inner.lock().await;
+        let entry = DeadLetterEntry {
+            id: inner.next_id,
+            received_at: Utc::now().to_rfc3339(),
+            source: source.into(),
+            protocol,
+            reason,
+            error: error.into(),
+            raw: RawBytes(raw.to_vec()),
+            truncated,
+        };
+        inner.next_id += 1;
+        warn!("📥 Dead-lettered #{} from {} ({:?}): {}", entry.id, entry.source, entry.reason, entry.error);
+
+        let id = entry.id;
+        self.append(&mut inner, serde_json::to_vec(&entry).unwrap_or_default()).await;
+        inner.entries.push_back(entry);
+        if inner.entries.len() > self.config.max_entries {
+            inner.entries.pop_front();
+            inner.evicted += 1;
+        }
+        self.compact_if_stale(&mut inner).await;
+        id
+    }
+
+    pub async fn get(&self, id: u64) -> Option<DeadLetterEntry> {
+        self.inner.lock().await.entries.iter().find(|entry| entry.id == id).cloned()
+    }
+
+    // ✅ Entries matching the filter, oldest first
+    pub async fn list(&self, filter: &DeadLetterFilter) -> Vec<DeadLetterEntry> {
+        self.inner.lock().await.entries.iter().filter(|entry| filter.matches(entry)).cloned().collect()
+    }
+
+    // List view of the first `limit` matches, without cloning payloads; also returns the match count
+    async fn summaries(&self, filter: &DeadLetterFilter, limit: usize) -> (usize, Vec<Value>) {
+        let inner = self.inner.lock().await;
+        let matched: Vec<&DeadLetterEntry> = inner.entries.iter().filter(|entry| filter.matches(entry)).collect();
+        (matched.len(), matched.iter().take(limit).map(|entry| entry.summary()).collect())
+    }
+
+    // ✅ Removes entries (after a replay or an explicit delete), returning how many were found.
+    // The file only gets one marker line per call; the entries themselves go at the next compaction.
+    pub async fn remove(&self, ids: &[u64]) -> usize {
+        let ids: HashSet<u64> = ids.iter().copied().collect();
+        let mut inner = self.inner.lock().await;
+        let mut removed = Vec::new();
+        inner.entries.retain(|entry| {
+            let found = ids.contains(&entry.id);
+            if found {
+                removed.push(entry.id);
+            }
+            !found
+        });
+        if !removed.is_empty() {
+            self.append(&mut inner, json!({ "removed": removed }).to_string().into_bytes()).await;
+            self.compact_if_stale(&mut inner).await;
+        }
+        removed.len()
+    }
+
+    /// Waits until everything recorded or removed so far has reached the backing file.
+    pub async fn flush(&self) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let (done, flushed) = oneshot::channel();
+        if writer.send(FileOp::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    async fn stats(&self) -> (usize, u64) {
+        let inner = self.inner.lock().await;
+        (inner.entries.len(), inner.evicted)
+    }
+
+    // Queues one line for the writer; waits only when the writer is WRITE_QUEUE lines behind
+    async fn append(&self, inner: &mut Inner, mut line: Vec<u8>) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        line.push(b'\n');
+        if writer.send(FileOp::Append(line)).await.is_ok() {
+            inner.file_lines += 1;
+        }
+    }
+
+    // Evicted entries and removal markers still occupy the file; rewrite it once they dominate
+    async fn compact_if_stale(&self, inner: &mut Inner) {
+        if inner.file_lines > self.config.max_entries.saturating_mul(2) {
+            self.compact(inner).await;
+        }
+    }
+
+    // ✅ Hands the writer a file with just the live entries (temp file + rename, like checkpoints)
+    async fn compact(&self, inner: &mut Inner) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let mut data = Vec::new();
+        for entry in &inner.entries {
+            data.extend(serde_json::to_vec(entry).unwrap_or_default());
+            data.push(b'\n');
+        }
+        if writer.send(FileOp::Rewrite(data)).await.is_ok() {
+            inner.file_lines = inner.entries.len();
+        }
+    }
+}
+
+// ✅ Owns the backing file: appends whatever is queued in one write, and swaps in compacted copies
+async fn write_file(path: PathBuf, mut ops: mpsc::Receiver<FileOp>) {
+    let mut file: Option<File> = None;
+    let mut buffer = Vec::new();
+    while let Some(op) = ops.recv().await {
+        let mut next = Some(op);
+        while let Some(op) = next.take().or_else(|| ops.try_recv().ok()) {
+            match op {
+                FileOp::Append(line) => buffer.extend(line),
+                FileOp::Rewrite(data) => {
+                    // Lines still buffered are part of the compacted copy
+                    buffer.clear();
+                    file = None;
+                    if let Err(e) = rewrite(&path, &data).await {
+                        error!("❌ Failed to compact dead-letter file {:?}: {}", path, e);
+                    }
+                }
+                FileOp::Flush(done) => {
+                    write_lines(&path, &mut file, &mut buffer).await;
+                    let _ = done.send(());
+                }
+            }
+        }
+        write_lines(&path, &mut file, &mut buffer).await;
+    }
+}
+
+async fn write_lines(path: &FsPath, file: &mut Option<File>, buffer: &mut Vec<u8>) {
+    if buffer.is_empty() {
+        return;
+    }
+    let result = async {
+        if file.is_none() {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            *file = Some(OpenOptions::new().create(true).append(true).open(path).await?);
+        }
+        let handle = file.as_mut().expect("file was just opened");
+        handle.write_all(buffer).await?;
+        handle.flush().await
+    }
+    .await;
+    if let Err(e) = result {
+        error!("❌ Failed to persist {} bytes of dead-letter entries to {:?}: {}", buffer.len(), path, e);
+        // Reopen on the next write
+        *file = None;
+    }
+    buffer.clear();
+}
+
+async fn rewrite(path: &FsPath, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeadLetterFilter {
+    source: Option<String>, // Prefix match, so `tcp` selects every TCP peer
+    reason: Option<DeadLetterReason>,
+    after: Option<u64>, // Only entries with a larger id, for paging
+}
+
+impl DeadLetterFilter {
+    fn matches(&self, entry: &DeadLetterEntry) -> bool {
+        self.source.as_ref().is_none_or(|source| entry.source.starts_with(source.as_str()))
+            && self.reason.is_none_or(|reason| entry.reason == reason)
+            && self.after.is_none_or(|after| entry.id > after)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    #[serde(flatten)]
+    filter: DeadLetterFilter,
+    limit: Option<usize>,
+}
+
+// ✅ Parses a replayed payload with the parser of the protocol it arrived in. Whole `_bulk` requests
+// are the exception: their documents were dead-lettered one by one where they could be told apart,
+// and the request itself is best resent to `/_bulk` once fixed.
+fn parse_replay(protocol: Protocol, source: &str, raw: &[u8], max_bytes: usize) -> Result<Vec<LogEntry>, String> {
+    // Oversized events are recorded without their payload
+    if raw.iter().all(u8::is_ascii_whitespace) {
+        return Err("payload is empty; replay it with a corrected body".to_string());
+    }
+    let corrected = |e: String| format!("{}; replay it with a corrected body", e);
+    match protocol {
+        Protocol::Native => parse_native(raw)
+            .map_err(|e| corrected(format!("payload is not in the native log format ({})", e))),
+        Protocol::Syslog => {
+            // Replayed entries keep the peer they were first received from
+            let peer = source.strip_prefix("syslog:").and_then(|peer| peer.parse().ok());
+            let message = std::str::from_utf8(raw).map_err(|e| corrected(format!("payload is not UTF-8 ({})", e)))?;
+            parse_syslog(message, peer.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0))))
+                .map(|log| vec![log])
+                .ok_or_else(|| corrected("payload is not a syslog message".to_string()))
+        }
+        Protocol::OtlpJson | Protocol::OtlpProtobuf => decode_export(protocol == Protocol::OtlpJson, raw)
+            .map(export_request_to_entries)
+            .map_err(|e| corrected(format!("invalid OTLP export request ({})", e))),
+        Protocol::LokiJson => parse_json_push(raw).map_err(corrected),
+        Protocol::LokiProtobuf => parse_protobuf_push(raw, max_bytes).map_err(corrected),
+        Protocol::SplunkHec => parse_hec_events(raw, &HecParams::default(), None)
+            .map_err(|(_, text, i)| corrected(format!("{} (event #{})", text, i))),
+        Protocol::ElasticsearchDocument => match serde_json::from_slice::<Value>(raw) {
+            Ok(document @ Value::Object(_)) => Ok(vec![document_to_log_entry("", document)]),
+            _ => Err(corrected("payload is not a JSON document".to_string())),
+        },
+        Protocol::ElasticsearchBulk => {
+            Err("Elasticsearch bulk requests can't be replayed from here; resend the corrected request to /_bulk".to_string())
+        }
+    }
+}
+
+// One object, a JSON array or NDJSON, the way `/logs` accepts them
+fn parse_native(raw: &[u8]) -> Result<Vec<LogEntry>, serde_json::Error> {
+    if let Ok(logs) = serde_json::from_slice::<Vec<LogEntry>>(raw) {
+        return Ok(logs);
+    }
+    serde_json::Deserializer::from_slice(raw).into_iter::<LogEntry>().collect()
+}
+
+// ✅ Queues replayed events; all-or-nothing so a partial replay never duplicates events on retry
+async fn enqueue(logs: Vec<LogEntry>, state: &AppState) -> Result<(), String> {
+    if send_all(&state.sender, logs, state.queue_timeout).await {
+        Ok(())
+    } else {
+        Err("log queue is full".to_string())
+    }
+}
+
+// ✅ `GET /dead-letter?source=tcp&reason=parse_error&after=120&limit=50`
+pub async fn list_dead_letters(State(state): State<Arc<AppState>>, Query(params): Query<ListParams>) -> Json<Value> {
+    let (matched, entries) = state.dead_letter.summaries(&params.filter, params.limit.unwrap_or(100)).await;
+    let (total, evicted) = state.dead_letter.stats().await;
+    Json(json!({ "total": total, "matched": matched, "evicted": evicted, "entries": entries }))
+}
+
+// ✅ `GET /dead-letter/{id}` — the full entry including its raw payload
+pub async fn get_dead_letter(State(state): State<Arc<AppState>>, Path(id): Path<u64>) -> Response {
+    match state.dead_letter.get(id).await {
+        Some(entry) => Json(entry).into_response(),
+        None => (StatusCode::NOT_FOUND, Json(json!({ "error": format!("no dead-letter entry {}", id) }))).into_response(),
+    }
+}
+
+// ✅ `DELETE /dead-letter/{id}`
+pub async fn delete_dead_letter(State(state): State<Arc<AppState>>, Path(id): Path<u64>) -> StatusCode {
+    if state.dead_letter.remove(&[id]).await > 0 {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// ✅ `POST /dead-letter/{id}/replay` — re-ingests the stored payload, or the request body when one
+// is given (to fix up an entry that can't be parsed as is). Either is parsed as the entry's
+// protocol, so a corrected body uses the format the sender used. Replayed entries are removed.
+pub async fn replay_dead_letter(State(state): State<Arc<AppState>>, Path(id): Path<u64>, body: Bytes) -> Response {
+    let Some(entry) = state.dead_letter.get(id).await else {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": format!("no dead-letter entry {}", id) }))).into_response();
+    };
+    let payload = if body.iter().all(u8::is_ascii_whitespace) { &entry.raw.0[..] } else { &body[..] };
+
+    let logs = match parse_replay(entry.protocol, &entry.source, payload, state.max_body_bytes) {
+        Ok(logs) => logs,
+        Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "id": id, "error": e }))).into_response(),
+    };
+    let replayed = logs.len();
+    if let Err(e) = enqueue(logs, &state).await {
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "id": id, "error": e }))).into_response();
+    }
+    state.dead_letter.remove(&[id]).await;
+    info!("🔁 Replayed dead-letter entry #{} ({} events)", id, replayed);
+
+    Json(json!({ "id": id, "replayed": replayed })).into_response()
+}
+
+// ✅ `POST /dead-letter/replay?source=...&reason=...` — replays every matching entry; entries
+// that still can't be parsed stay in the store and are reported back
+pub async fn replay_dead_letters(State(state): State<Arc<AppState>>, Query(filter): Query<DeadLetterFilter>) -> Response {
+    let mut replayed = Vec::new();
+    let mut events = 0usize;
+    let mut failed = Vec::new();
+    let mut busy = false;
+    for entry in state.dead_letter.list(&filter).await {
+        let logs = match parse_replay(entry.protocol, &entry.source, &entry.raw.0, state.max_body_bytes) {
+            Ok(logs) => logs,
+            Err(e) => {
+                failed.push(json!({ "id": entry.id, "error": e }));
+                continue;
+            }
+        };
+        let count = logs.len();
+        if let Err(e) = enqueue(logs, &state).await {
+            // The queue won't drain faster by trying the rest now
+            failed.push(json!({ "id": entry.id, "error": e }));
+            busy = true;
+            break;
+        }
+        replayed.push(entry.id);
+        events += count;
+    }
+
+    state.dead_letter.remove(&replayed).await;
+    info!("🔁 Replayed {} dead-letter entries ({} events, {} failed)", replayed.len(), events, failed.len());
+
+    let status = if replayed.is_empty() && busy {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if replayed.is_empty() && !failed.is_empty() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(json!({ "replayed": replayed.len(), "events": events, "failed": failed }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(path: &FsPath, max_entries: usize) -> DeadLetterConfig {
+        DeadLetterConfig { path: Some(path.to_path_buf()), max_entries, max_raw_bytes: 16 }
+    }
+
+    fn lines(path: &FsPath) -> Vec<Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    async fn ids(store: &DeadLetterStore) -> Vec<u64> {
+        store.list(&DeadLetterFilter::default()).await.iter().map(|entry| entry.id).collect()
+    }
+
+    #[tokio::test]
+    async fn entries_and_removals_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/dead_letter.ndjson");
+
+        let store = DeadLetterStore::open(config(&path, 100)).await;
+        for i in 0..4 {
+            store.record(format!("tcp:peer-{}", i), Protocol::Native, DeadLetterReason::ParseError, "bad json", b"{nope").await;
+        }
+        store.record("udp:peer", Protocol::Native, DeadLetterReason::ParseError, "binary", &[0xff, 0xfe]).await;
+        store.record("forwarder", Protocol::Native, DeadLetterReason::DeliveryFailed, "503", &[b'x'; 40]).await;
+        assert_eq!(store.remove(&[2, 3, 42]).await, 2);
+        assert_eq!(store.remove(&[42]).await, 0);
+        store.flush().await;
+
+        // Six entries plus one removal marker; nothing was rewritten
+        let file = lines(&path);
+        assert_eq!(file.len(), 7);
+        assert_eq!(file[6], json!({ "removed": [2, 3] }));
+        assert_eq!(file[4]["raw"], json!({ "base64": "//4=" }));
+
+        let reopened = DeadLetterStore::open(config(&path, 100)).await;
+        assert_eq!(ids(&reopened).await, [1, 4, 5, 6]);
+        let binary = reopened.get(5).await.unwrap();
+        assert_eq!(binary.raw.0, [0xff, 0xfe]);
+        let truncated = reopened.get(6).await.unwrap();
+        assert!(truncated.truncated);
+        assert_eq!(truncated.raw.0.len(), 16);
+        // Ids keep counting from the highest one on disk
+        assert_eq!(reopened.record("tcp:peer", Protocol::Native, DeadLetterReason::ParseError, "bad json", b"{").await, 7);
+    }
+
+    #[tokio::test]
+    async fn file_is_compacted_only_once_stale_lines_dominate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead_letter.ndjson");
+        let store = DeadLetterStore::open(config(&path, 3)).await;
+
+        for _ in 0..6 {
+            store.record("tcp:peer", Protocol::Native, DeadLetterReason::ParseError, "bad json", b"{").await;
+        }
+        store.flush().await;
+        // Three evicted entries are still on disk, within the 2 * max_entries allowance
+        assert_eq!(lines(&path).len(), 6);
+        assert_eq!(ids(&store).await, [4, 5, 6]);
+
+        // The marker is the seventh line, which triggers the rewrite
+        store.remove(&[4]).await;
+        store.flush().await;
+        let file = lines(&path);
+        assert_eq!(file.iter().map(|line| line["id"].as_u64().unwrap()).collect::<Vec<_>>(), [5, 6]);
+
+        // Appends after a rewrite go to the new file
+        store.record("tcp:peer", Protocol::Native, DeadLetterReason::ParseError, "bad json", b"{").await;
+        store.flush().await;
+        assert_eq!(lines(&path).len(), 3);
+        assert_eq!(ids(&DeadLetterStore::open(config(&path, 3)).await).await, [5, 6, 7]);
+    }
+
+    #[tokio::test]
+    async fn memory_only_store_evicts_oldest() {
+        let store = DeadLetterStore::open(DeadLetterConfig { max_entries: 2, ..Default::default() }).await;
+        for _ in 0..3 {
+            store.record("tcp:peer", Protocol::Native, DeadLetterReason::ParseError, "bad json", b"{").await;
+        }
+        store.flush().await;
+        assert_eq!(ids(&store).await, [2, 3]);
+        assert_eq!(store.stats().await, (2, 1));
+    }
+
+    fn replay(protocol: Protocol, raw: &[u8]) -> Result<Vec<LogEntry>, String> {
+        parse_replay(protocol, "test", raw, 1024 * 1024)
+    }
+
+    #[test]
+    fn replay_accepts_objects_arrays_and_ndjson() {
+        let entry = r#"{"timestamp":"2024-05-01T12:00:00Z","level":"INFO","message":"m","source":"api"}"#;
+        assert_eq!(replay(Protocol::Native, entry.as_bytes()).unwrap().len(), 1);
+        assert_eq!(replay(Protocol::Native, format!("[{},{}]", entry, entry).as_bytes()).unwrap().len(), 2);
+        assert_eq!(replay(Protocol::Native, format!("{}\n{}\n", entry, entry).as_bytes()).unwrap().len(), 2);
+        assert!(replay(Protocol::Native, b"  ").unwrap_err().contains("empty"));
+        assert!(replay(Protocol::Native, b"{nope").unwrap_err().contains("native log format"));
+    }
+
+    #[test]
+    fn entries_without_a_protocol_are_native() {
+        let line = r#"{"id":1,"received_at":"2024-05-01T12:00:00Z","source":"tcp:peer","reason":"parse_error","error":"bad json","raw":"{"}"#;
+        let entry: DeadLetterEntry = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.protocol, Protocol::Native);
+        assert_eq!(entry.summary()["protocol"], "native");
+    }
+
+    #[test]
+    fn replay_parses_each_protocol_with_its_own_parser() {
+        let logs = parse_replay(Protocol::Syslog, "syslog:10.0.0.5:514", b"ERROR web-1 disk full", 1024).unwrap();
+        assert_eq!((logs[0].level.as_str(), logs[0].message.as_str()), ("ERROR", "disk full"));
+        assert_eq!(logs[0].source, "syslog:10.0.0.5:514");
+
+        let push = r#"{"streams":[{"stream":{"job":"api"},"values":[["1714564800000000000","a"],["1714564801000000000","b"]]}]}"#;
+        assert_eq!(replay(Protocol::LokiJson, push.as_bytes()).unwrap().len(), 2);
+
+        let hec = r#"{"event":"first","host":"web-1"}{"event":{"message":"second"}}"#;
+        let logs = replay(Protocol::SplunkHec, hec.as_bytes()).unwrap();
+        assert_eq!((logs.len(), logs[0].message.as_str()), (2, "first"));
+        assert!(replay(Protocol::SplunkHec, br#"{"host":"web-1"}"#).unwrap_err().contains("Event field is required"));
+
+        let logs = replay(Protocol::ElasticsearchDocument, br#"{"message":"disk full","log":{"level":"error"}}"#).unwrap();
+        assert_eq!((logs[0].level.as_str(), logs[0].message.as_str()), ("error", "disk full"));
+        // Native JSON isn't accepted for entries that arrived in another protocol
+        assert!(replay(Protocol::ElasticsearchDocument, b"[1]").is_err());
+    }
+
+    #[tokio::test]
+    async fn bulk_requests_are_refused_with_422() {
+        let (state, mut rx) = AppState::for_tests(10).await;
+        let id = state.dead_letter.record("http:/_bulk", Protocol::ElasticsearchBulk, DeadLetterReason::ParseError, "bad action", b"{nope\n").await;
+
+        let response = replay_dead_letter(State(Arc::clone(&state)), Path(id), Bytes::new()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("/_bulk"));
+        assert!(state.dead_letter.get(id).await.is_some());
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use bollard::container::{ListContainersOptions, LogOutput, LogsOptions};
 use bollard::Docker;
-use futures_util::stream::{StreamExt, TryStreamExt};
-use std::{sync::Arc};
+use futures_util::stream::StreamExt;
+use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc;
 use tracing::{error, info};
 use crate::models::LogEntry;
@@ -92,6 +92,7 @@ async fn monitor_container_logs(
                         level: "INFO".to_string(),
                         message: text.trim().to_string(),
                         timestamp: Utc::now().to_rfc3339(),
+                        attributes: HashMap::new(),
                     };
 
                     if sender.send(log_entry).await.is_err() {
@@ -0,0 +1,263 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Map, Value};
+use tracing::error;
+use crate::{body_stream::read_body, dead_letter::{DeadLetterReason, Protocol}, http_handler::{send_all, AppState}, models::LogEntry, utils::flatten_json};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+// Version reported to shippers that probe `GET /` before sending (Filebeat, Logstash)
+const COMPAT_ES_VERSION: &str = "8.11.0";
+
+// ✅ `GET /` — minimal Elasticsearch cluster info so Beats/Logstash accept us as an output
+pub async fn elasticsearch_info() -> Json<Value> {
+    Json(json!({
+        "name": "insightx-collector",
+        "cluster_name": "insightx",
+        "version": {
+            "number": COMPAT_ES_VERSION,
+            "build_flavor": "default",
+            "lucene_version": "9.8.0",
+        },
+        "tagline": "You Know, for Search",
+    }))
+}
+
+// ✅ `POST /_bulk` — NDJSON action/document pairs, gzip'd by Filebeat and Logstash by default
+pub async fn elasticsearch_bulk(state: State<Arc<AppState>>, headers: HeaderMap, body: Body) -> Response {
+    handle_bulk(state, None, headers, body).await
+}
+
+// ✅ `POST /{index}/_bulk` — same as `/_bulk` with a default index for actions that omit one
+pub async fn elasticsearch_index_bulk(
+    state: State<Arc<AppState>>,
+    Path(index): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    handle_bulk(state, Some(index), headers, body).await
+}
+
+async fn handle_bulk(
+    State(state): State<Arc<AppState>>,
+    default_index: Option<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let started = Instant::now();
+    let body = match read_body(body, &headers, state.max_body_bytes).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("❌ Failed to read bulk request: {}", e);
+            let error_type = match e.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => "content_too_long_exception",
+                _ => "illegal_argument_exception",
+            };
+            return bulk_error(e.status(), error_type, e.to_string());
+        }
+    };
+    let mut lines = body
+        .split(|b| *b == b'\n')
+        .map(|line| line.trim_ascii())
+        .filter(|line| !line.is_empty());
+
+    let mut items = Vec::new();
+    let mut entries = Vec::new();
+    let mut has_errors = false;
+
+    while let Some(action_line) = lines.next() {
+        let action: Map<String, Value> = match serde_json::from_slice(action_line) {
+            Ok(action) => action,
+            Err(e) => {
+                error!("❌ Failed to parse bulk action line: {}", e);
+                // The whole request is refused, so keep all of it
+                state.dead_letter.record("http:/_bulk", Protocol::ElasticsearchBulk, DeadLetterReason::ParseError, e.to_string(), &body).await;
+                return bulk_parse_error(format!("Malformed action/metadata line: {}", e));
+            }
+        };
+        let Some((op, meta)) = action.into_iter().next() else {
+            return bulk_parse_error("Malformed action/metadata line: empty action".to_string());
+        };
+
+        let index = meta
+            .get("_index")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .or_else(|| default_index.clone())
+            .unwrap_or_default();
+        let id = meta.get("_id").and_then(Value::as_str).map(str::to_string);
+
+        // `delete` is the only action without a source document line; we never store documents
+        if op == "delete" {
+            items.push(bulk_item(&op, &index, id, 404, "not_found", None));
+            continue;
+        }
+
+        let Some(doc_line) = lines.next() else {
+            return bulk_parse_error("The bulk request must be terminated by a newline".to_string());
+        };
+
+        let document = match serde_json::from_slice::<Value>(doc_line) {
+            Ok(Value::Object(doc)) if op == "update" => doc.get("doc").cloned().unwrap_or(Value::Object(doc)),
+            Ok(doc @ Value::Object(_)) => doc,
+            Ok(_) | Err(_) => {
+                state.dead_letter
+                    .record("http:/_bulk", Protocol::ElasticsearchDocument, DeadLetterReason::ParseError, "failed to parse document", doc_line)
+                    .await;
+                has_errors = true;
+                items.push(bulk_item(&op, &index, id, 400, "mapper_parsing_exception", Some("failed to parse document")));
+                continue;
+            }
+        };
+
+        entries.push(document_to_log_entry(&index, document));
+        items.push(bulk_item(&op, &index, id, 201, "created", None));
+    }
+
+    // ✅ Every parsed document is queued or none is, so the shipper's retry can't duplicate any
+    if !send_all(&state.sender, entries, state.queue_timeout).await {
+        error!("❌ Log queue is full, rejecting Elasticsearch bulk request");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "error": { "type": "es_rejected_execution_exception", "reason": "log queue is full" },
+                "status": 503,
+            })),
+        )
+            .into_response();
+    }
+
+    Json(json!({
+        "took": started.elapsed().as_millis() as u64,
+        "errors": has_errors,
+        "items": items,
+    }))
+    .into_response()
+}
+
+fn bulk_item(op: &str, index: &str, id: Option<String>, status: u16, result: &str, reason: Option<&str>) -> Value {
+    let mut item = json!({
+        "_index": index,
+        "_id": id.unwrap_or_default(),
+        "status": status,
+    });
+    match reason {
+        Some(reason) => item["error"] = json!({ "type": result, "reason": reason }),
+        None => item["result"] = json!(result),
+    }
+    json!({ op: item })
+}
+
+fn bulk_parse_error(reason: String) -> Response {
+    bulk_error(StatusCode::BAD_REQUEST, "illegal_argument_exception", reason)
+}
+
+fn bulk_error(status: StatusCode, error_type: &str, reason: String) -> Response {
+    (
+        status,
+        Json(json!({
+            "error": { "type": error_type, "reason": reason },
+            "status": status.as_u16(),
+        })),
+    )
+        .into_response()
+}
+
+// ✅ Maps ECS-style documents (`message`, `@timestamp`, `log.level`, `host.name`) onto LogEntry
+pub(crate) fn document_to_log_entry(index: &str, document: Value) -> LogEntry {
+    let mut fields = HashMap::new();
+    flatten_json("", &document, &mut fields);
+
+    let mut take_str = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| fields.remove(*key))
+            .map(|v| match v {
+                Value::String(s) => s,
+                other => other.to_string(),
+            })
+    };
+
+    let message = take_str(&["message", "log", "msg"]).unwrap_or_default();
+    let timestamp = take_str(&["@timestamp", "timestamp"]).unwrap_or_else(|| Utc::now().to_rfc3339());
+    let level = take_str(&["log.level", "level", "severity"]).unwrap_or_else(|| "INFO".to_string());
+    let source = take_str(&["service.name", "host.name", "host.hostname"])
+        .unwrap_or_else(|| if index.is_empty() { "elasticsearch".to_string() } else { index.to_string() });
+
+    if !index.is_empty() {
+        fields.insert("_index".to_string(), Value::String(index.to_string()));
+    }
+
+    LogEntry {
+        source,
+        level,
+        message,
+        timestamp,
+        attributes: fields,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body_stream::compress;
+    use axum::http::header;
+
+    const BULK: &str = concat!(
+        r#"{"index":{"_index":"filebeat-8","_id":"1"}}"#, "\n",
+        r#"{"@timestamp":"2024-05-01T12:00:00Z","message":"disk full","log":{"level":"error"},"host":{"name":"web-1"}}"#, "\n",
+        r#"{"create":{}}"#, "\n",
+        r#"{"msg":"second"}"#, "\n",
+    );
+
+    async fn bulk(state: &Arc<AppState>, index: Option<&str>, encoding: Option<&str>, body: Vec<u8>) -> (StatusCode, Value) {
+        let mut headers = HeaderMap::new();
+        if let Some(encoding) = encoding {
+            headers.insert(header::CONTENT_ENCODING, encoding.parse().unwrap());
+        }
+        let response = handle_bulk(State(Arc::clone(state)), index.map(str::to_string), headers, Body::from(body)).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn accepts_gzipped_bulk_requests() {
+        let (state, mut rx) = AppState::for_tests(10).await;
+        let (status, response) = bulk(&state, Some("logs"), Some("gzip"), compress("gzip", BULK.as_bytes()).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response["errors"], false);
+        assert_eq!(response["items"][0]["index"]["_index"], "filebeat-8");
+        assert_eq!(response["items"][1]["create"]["_index"], "logs");
+
+        let log = rx.recv().await.unwrap();
+        assert_eq!((log.source.as_str(), log.level.as_str(), log.message.as_str()), ("web-1", "error", "disk full"));
+        assert_eq!(log.timestamp, "2024-05-01T12:00:00Z");
+        let log = rx.recv().await.unwrap();
+        assert_eq!((log.source.as_str(), log.message.as_str()), ("logs", "second"));
+        assert_eq!(log.attributes["_index"], "logs");
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_it_cannot_read() {
+        let (state, mut rx) = AppState::for_tests(10).await;
+        let (status, response) = bulk(&state, None, Some("br"), BULK.as_bytes().to_vec()).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(response["status"], 415);
+        let (status, _) = bulk(&state, None, Some("gzip"), BULK.as_bytes().to_vec()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // The limit applies to the decoded size, not the compressed one
+        let state = Arc::new(AppState { max_body_bytes: BULK.len() - 1, ..(*state).clone() });
+        let (status, response) = bulk(&state, None, Some("gzip"), compress("gzip", BULK.as_bytes()).await).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response["error"]["type"], "content_too_long_exception");
+        assert!(rx.try_recv().is_err());
+    }
+}
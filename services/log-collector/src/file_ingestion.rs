@@ -4,6 +4,7 @@ use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::sync::mpsc;
 use tracing::{info, error, warn};
 use std::{sync::Arc, path::PathBuf};
+use crate::dead_letter::{DeadLetterReason, DeadLetterStore, Protocol};
 use crate::models::LogEntry;
 
 #[derive(Clone)]
@@ -14,7 +15,8 @@ pub struct FileIngestionConfig {
 // ✅ Watches for new logs in files and processes them efficiently
 pub async fn watch_log_files(
     file_config: Arc<FileIngestionConfig>,
-    sender: mpsc::Sender<LogEntry>
+    sender: mpsc::Sender<LogEntry>,
+    dead_letter: Arc<DeadLetterStore>,
 ) {
     let log_dir = file_config.log_directory.clone();
     info!("📂 Watching log directory: {:?}", log_dir);
@@ -42,29 +44,38 @@ pub async fn watch_log_files(
     watcher.watch(&log_dir, RecursiveMode::Recursive).unwrap();
 
     while let Some(path) = rx.recv().await {
-        if let Err(e) = ingest_log_file(path, sender.clone()).await {
+        if let Err(e) = ingest_log_file(path, sender.clone(), &dead_letter).await {
             error!("❌ Failed to read log file: {}", e);
         }
     }
 }
 
 // ✅ Reads a file line-by-line and ingests logs
-async fn ingest_log_file(file_path: PathBuf, sender: mpsc::Sender<LogEntry>) -> tokio::io::Result<()> {
+async fn ingest_log_file(file_path: PathBuf, sender: mpsc::Sender<LogEntry>, dead_letter: &DeadLetterStore) -> tokio::io::Result<()> {
     info!("📄 Ingesting logs from file: {:?}", file_path);
 
     let file = File::open(&file_path).await?;
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
 
-    while let Some(line) = lines.next_line().await? {
-        match serde_json::from_str::<LogEntry>(&line) {
-            Ok(log) => {
-                if sender.send(log).await.is_err() {
-                    error!("❌ Log queue is full, dropping log from file: {:?}", file_path);
+    // Lines are read as bytes so one line of invalid UTF-8 doesn't abort the rest of the file
+    while reader.read_until(b'\n', &mut line).await? > 0 {
+        let raw = line.trim_ascii();
+        if !raw.is_empty() {
+            match serde_json::from_slice::<LogEntry>(raw) {
+                Ok(log) => {
+                    if sender.send(log).await.is_err() {
+                        error!("❌ Log queue is full, dropping log from file: {:?}", file_path);
+                    }
+                }
+                Err(e) => {
+                    warn!("⚠️ Skipping malformed log entry in file: {:?}", file_path);
+                    let source = format!("file:{}", file_path.display());
+                    dead_letter.record(source, Protocol::Native, DeadLetterReason::ParseError, e.to_string(), raw).await;
                 }
             }
-            Err(_) => warn!("⚠️ Skipping malformed log entry in file: {:?}", file_path),
         }
+        line.clear();
     }
 
     Ok(())
@@ -1,31 +1,67 @@
+use crate::grpc_forwarder::GrpcForwarder;
 use crate::models::LogEntry;
-use reqwest::Client;
-use tracing::{info, error};
+use crate::wire::{encode_batch, Negotiated, WireFormat};
+use reqwest::{Client, StatusCode};
+use tracing::{info, error, warn};
 use zstd::stream::encode_all;
 use std::io::Cursor;
 
+// ✅ Where the batcher sends batches: `POST /logs`, or the processor's gRPC stream
+pub enum Forwarder {
+    Http { client: Client, url: String }, // Shares its connection pool across sender tasks
+    Grpc(GrpcForwarder),
+}
+
 // ✅ Compress Logs Before Sending
-pub fn compress_logs(logs: &Vec<LogEntry>) -> Vec<u8> {
-    let json_logs = serde_json::to_string(logs).unwrap();
-    encode_all(Cursor::new(json_logs.as_bytes()), 0).unwrap()
+pub fn compress_logs(format: WireFormat, logs: &[LogEntry]) -> Vec<u8> {
+    let encoded = encode_batch(format, logs);
+    encode_all(Cursor::new(encoded), 0).unwrap()
 }
 
-// ✅ Sends logs in batches to Log Processor
-pub async fn send_logs(logs: Vec<LogEntry>, processor_url: &str) {
+// ✅ Sends logs in batches to Log Processor, in the negotiated wire format
+pub async fn send_logs(client: &Client, logs: &[LogEntry], processor_url: &str, wire: &Negotiated) -> anyhow::Result<()> {
     if logs.is_empty() {
-        return;
+        return Ok(());
+    }
+
+    let format = wire.current();
+    match send_encoded(client, logs, processor_url, format).await? {
+        // ✅ An older processor can't read protobuf: remember that and resend this batch as JSON
+        StatusCode::UNSUPPORTED_MEDIA_TYPE if format != WireFormat::Json => {
+            warn!("⚠️ Log Processor doesn't accept {:?}, falling back to JSON", format);
+            wire.rejected(format);
+            send_encoded(client, logs, processor_url, WireFormat::Json).await?;
+            Ok(())
+        }
+        _ => Ok(()),
     }
+}
 
-    let compressed_logs = compress_logs(&logs);
-    info!("🚀 Sending {} logs to Log Processor: {} ({} bytes)", logs.len(), processor_url, compressed_logs.len());
+// Sends one encoding of the batch; a 415 is handed back so the caller can renegotiate
+async fn send_encoded(client: &Client, logs: &[LogEntry], processor_url: &str, format: WireFormat) -> anyhow::Result<StatusCode> {
+    let compressed_logs = compress_logs(format, logs);
+    info!("🚀 Sending {} logs to Log Processor: {} ({:?}, {} bytes)", logs.len(), processor_url, format, compressed_logs.len());
 
-    let client = Client::new();
     match client.post(processor_url)
+        .header("Content-Type", format.content_type())
         .header("Content-Encoding", "zstd") // ✅ Indicate Compression
         .body(compressed_logs)
         .send()
         .await {
-            Ok(_) => info!("✅ Successfully sent {} logs to Log Processor", logs.len()),
-            Err(e) => error!("❌ Failed to send logs: {}", e),
+            Ok(resp) if resp.status().is_success() => {
+                info!("✅ Successfully sent {} logs to Log Processor", logs.len());
+                Ok(resp.status())
+            }
+            Ok(resp) if resp.status() == StatusCode::UNSUPPORTED_MEDIA_TYPE && format != WireFormat::Json => Ok(resp.status()),
+            Ok(resp) => {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                error!("❌ Log Processor responded with {}: {}", status, body);
+                Err(anyhow::anyhow!("log processor responded with {}: {}", status, body))
+            }
+            Err(e) => {
+                error!("❌ Failed to send logs: {}", e);
+                Err(anyhow::anyhow!("failed to send logs: {}", e))
+            }
         }
 }
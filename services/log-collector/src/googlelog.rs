@@ -1,53 +1,400 @@
-use google_cloud_logging::{Client, ClientOptions};
+use google_cloud_auth::{project::Config as AuthConfig, token::DefaultTokenSourceProvider};
+use google_cloud_token::{TokenSource, TokenSourceProvider};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tokio::sync::mpsc;
 use tracing::{error, info};
 use crate::models::LogEntry;
+use crate::utils::{flatten_json, load_checkpoint, save_checkpoint};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
-use chrono::Utc;
+use std::time::Duration;
+use chrono::{DateTime, SecondsFormat, Utc};
+
+const DEFAULT_ENDPOINT: &str = "https://logging.googleapis.com";
+const LOGGING_READ_SCOPE: &str = "https://www.googleapis.com/auth/logging.read";
 
 #[derive(Clone)]
 pub struct GCPLoggingConfig {
     pub project_id: String, // GCP Project ID
+    pub filter: Option<String>, // Logging query language, e.g. `resource.type="k8s_container"`
+    pub endpoint_url: Option<String>, // Override for a local emulator / private endpoint
+    pub anonymous: bool, // Skip ADC token lookup (emulators don't require auth)
+    pub cursor_path: Option<PathBuf>, // Where to persist the timestamp cursor across restarts
+    pub page_size: u32,
+    pub poll_interval: Duration,
+}
+
+impl Default for GCPLoggingConfig {
+    fn default() -> Self {
+        Self {
+            project_id: String::new(),
+            filter: None,
+            endpoint_url: None,
+            anonymous: false,
+            cursor_path: None,
+            page_size: 500,
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Position of the last forwarded entry. Entries sharing the cursor timestamp are tracked by
+/// `insertId` (or a fingerprint of their content, see `dedup_key`), because the `timestamp >=`
+/// filter will return them again on the next poll.
+#[derive(Default, Serialize, Deserialize)]
+struct GcpCursor {
+    timestamp: Option<DateTime<Utc>>,
+    insert_ids: HashSet<String>,
+}
+
+impl GcpCursor {
+    fn is_seen(&self, timestamp: &DateTime<Utc>, key: &str) -> bool {
+        match &self.timestamp {
+            Some(cursor) if timestamp < cursor => true,
+            Some(cursor) if timestamp == cursor => self.insert_ids.contains(key),
+            _ => false,
+        }
+    }
+
+    // Called once the entry was queued, so an entry that never made it is fetched again
+    fn advance(&mut self, timestamp: DateTime<Utc>, key: String) {
+        if self.timestamp.as_ref() != Some(&timestamp) {
+            self.timestamp = Some(timestamp);
+            self.insert_ids.clear();
+        }
+        self.insert_ids.insert(key);
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListLogEntriesResponse {
+    #[serde(default)]
+    entries: Vec<GcpLogEntry>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcpLogEntry {
+    log_name: Option<String>,
+    insert_id: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    receive_timestamp: Option<DateTime<Utc>>,
+    severity: Option<String>,
+    text_payload: Option<String>,
+    json_payload: Option<Value>,
+    proto_payload: Option<Value>,
+    resource: Option<MonitoredResource>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct MonitoredResource {
+    #[serde(rename = "type")]
+    resource_type: Option<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
 }
 
 pub async fn start_gcp_log_ingestion(
     config: Arc<GCPLoggingConfig>,
     sender: mpsc::Sender<LogEntry>,
 ) {
-    let client = match Client::new(ClientOptions {
-        project_id: config.project_id.clone(),
-        ..Default::default()
-    })
-    .await
-    {
-        Ok(c) => c,
-        Err(e) => {
-            error!("❌ Failed to connect to Google Cloud Logging: {}", e);
-            return;
+    let token_source = if config.anonymous {
+        None
+    } else {
+        let auth_config = AuthConfig::default().with_scopes(&[LOGGING_READ_SCOPE]);
+        match DefaultTokenSourceProvider::new(auth_config).await {
+            Ok(provider) => Some(provider.token_source()),
+            Err(e) => {
+                error!("❌ Failed to connect to Google Cloud Logging: {}", e);
+                return;
+            }
         }
     };
 
+    let client = Client::new();
+    let endpoint = config.endpoint_url.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+
+    let mut cursor = match &config.cursor_path {
+        Some(path) => load_checkpoint(path).await.unwrap_or_default(),
+        None => GcpCursor::default(),
+    };
+
+    info!("☁️ Polling GCP Cloud Logging for project {}", config.project_id);
+
     loop {
-        match client.list_log_entries(None, None, None).await {
-            Ok(entries) => {
-                for entry in entries {
-                    let log_entry = LogEntry {
-                        source: "GCP".to_string(),
-                        level: entry.severity.unwrap_or_else(|| "INFO".to_string()),
-                        message: entry.text_payload.unwrap_or_else(|| "".to_string()),
-                        timestamp: entry.timestamp.unwrap_or_else(|| Utc::now().to_rfc3339()),
-                    };
-
-                    if sender.send(log_entry).await.is_err() {
-                        error!("❌ Log queue full, dropping GCP log.");
-                    }
+        let filter = build_filter(config.filter.as_deref(), cursor.timestamp.as_ref());
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let response = match list_log_entries(
+                &client,
+                endpoint,
+                token_source.as_deref(),
+                &config,
+                &filter,
+                page_token.as_deref(),
+            )
+            .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("❌ Failed to fetch GCP logs: {}", e);
+                    break;
                 }
+            };
+
+            for entry in response.entries {
+                let timestamp = entry
+                    .timestamp
+                    .or(entry.receive_timestamp)
+                    .unwrap_or_else(Utc::now);
+                let key = dedup_key(&entry);
+                if cursor.is_seen(&timestamp, &key) {
+                    continue;
+                }
+                if sender.send(to_log_entry(entry, timestamp)).await.is_err() {
+                    // Shutting down; the entry wasn't forwarded, so the cursor stays put
+                    break;
+                }
+                cursor.advance(timestamp, key);
             }
-            Err(e) => {
-                error!("❌ Failed to fetch GCP logs: {}", e);
+
+            if let Some(path) = &config.cursor_path {
+                if let Err(e) = save_checkpoint(path, &cursor).await {
+                    error!("❌ Failed to persist GCP cursor to {:?}: {}", path, e);
+                }
+            }
+
+            match response.next_page_token {
+                Some(token) if !token.is_empty() && !sender.is_closed() => page_token = Some(token),
+                _ => break,
             }
         }
 
-        tokio::time::sleep(std::time::Duration::from_secs(10)).await; // Poll every 10s
+        if sender.is_closed() {
+            info!("☁️ Log queue closed, GCP Cloud Logging poller stopped");
+            return;
+        }
+        tokio::time::sleep(config.poll_interval).await;
+    }
+}
+
+// ✅ Identifies an entry within the cursor timestamp: its `insertId`, or for entries written
+// without one, a stable hash of what would be forwarded (so they aren't re-sent on every poll)
+fn dedup_key(entry: &GcpLogEntry) -> String {
+    if let Some(insert_id) = &entry.insert_id {
+        return insert_id.clone();
+    }
+    // FNV-1a: unlike `DefaultHasher`, stable across builds, and keys outlive the process
+    let labels: BTreeMap<_, _> = entry.labels.iter().collect(); // Sorted, unlike the HashMap
+    let content = json!([entry.log_name, entry.severity, entry.text_payload, entry.json_payload, entry.proto_payload, labels]);
+    let hash = content.to_string().bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("fnv:{:016x}", hash)
+}
+
+// ✅ Combines the user's filter with the cursor so each poll only returns new entries
+fn build_filter(user_filter: Option<&str>, since: Option<&DateTime<Utc>>) -> String {
+    let cursor_filter = since
+        .map(|ts| format!("timestamp >= \"{}\"", ts.to_rfc3339_opts(SecondsFormat::Nanos, true)));
+
+    match (user_filter.filter(|f| !f.trim().is_empty()), cursor_filter) {
+        (Some(user), Some(cursor)) => format!("({}) AND {}", user, cursor),
+        (Some(user), None) => user.to_string(),
+        (None, Some(cursor)) => cursor,
+        (None, None) => String::new(),
+    }
+}
+
+async fn list_log_entries(
+    client: &Client,
+    endpoint: &str,
+    token_source: Option<&dyn TokenSource>,
+    config: &GCPLoggingConfig,
+    filter: &str,
+    page_token: Option<&str>,
+) -> Result<ListLogEntriesResponse> {
+    let mut body = json!({
+        "resourceNames": [format!("projects/{}", config.project_id)],
+        "orderBy": "timestamp asc",
+        "pageSize": config.page_size,
+    });
+    if !filter.is_empty() {
+        body["filter"] = json!(filter);
+    }
+    if let Some(token) = page_token {
+        body["pageToken"] = json!(token);
+    }
+
+    let mut request = client
+        .post(format!("{}/v2/entries:list", endpoint.trim_end_matches('/')))
+        .json(&body);
+    if let Some(token_source) = token_source {
+        let token = token_source.token().await.map_err(|e| anyhow!(e))?;
+        request = request.header("Authorization", token);
+    }
+
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(anyhow!("Cloud Logging responded with {}: {}", status, error_text));
+    }
+
+    Ok(response.json().await?)
+}
+
+// ✅ Maps a Cloud Logging entry onto our model, lifting labels and JSON payload into attributes
+fn to_log_entry(entry: GcpLogEntry, timestamp: DateTime<Utc>) -> LogEntry {
+    let mut attributes = HashMap::new();
+
+    if let Some(resource) = entry.resource {
+        if let Some(resource_type) = resource.resource_type {
+            attributes.insert("resource.type".to_string(), Value::String(resource_type));
+        }
+        for (key, value) in resource.labels {
+            attributes.insert(format!("resource.labels.{}", key), Value::String(value));
+        }
+    }
+    for (key, value) in entry.labels {
+        attributes.insert(format!("labels.{}", key), Value::String(value));
+    }
+    if let Some(insert_id) = entry.insert_id {
+        attributes.insert("insert_id".to_string(), Value::String(insert_id));
+    }
+
+    let message = if let Some(text) = entry.text_payload {
+        text
+    } else if let Some(payload) = entry.json_payload {
+        let message = ["message", "msg"]
+            .iter()
+            .find_map(|key| payload.get(*key).and_then(Value::as_str).map(str::to_string));
+        flatten_json("", &payload, &mut attributes);
+        message.unwrap_or_else(|| payload.to_string())
+    } else if let Some(payload) = entry.proto_payload {
+        payload.to_string()
+    } else {
+        String::new()
+    };
+
+    LogEntry {
+        source: entry.log_name.unwrap_or_else(|| "GCP".to_string()),
+        level: entry
+            .severity
+            .filter(|s| s != "DEFAULT")
+            .unwrap_or_else(|| "INFO".to_string()),
+        message,
+        timestamp: timestamp.to_rfc3339(),
+        attributes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: Value) -> GcpLogEntry {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    #[test]
+    fn builds_filters() {
+        let since = at("2024-05-01T12:00:00.5Z");
+        assert_eq!(build_filter(None, None), "");
+        assert_eq!(build_filter(Some("  "), None), "");
+        assert_eq!(build_filter(Some("severity>=ERROR"), None), "severity>=ERROR");
+        assert_eq!(build_filter(None, Some(&since)), "timestamp >= \"2024-05-01T12:00:00.500000000Z\"");
+        assert_eq!(
+            build_filter(Some("severity>=ERROR OR labels.team=\"infra\""), Some(&since)),
+            "(severity>=ERROR OR labels.team=\"infra\") AND timestamp >= \"2024-05-01T12:00:00.500000000Z\""
+        );
+    }
+
+    #[test]
+    fn cursor_tracks_entries_at_its_timestamp() {
+        let mut cursor = GcpCursor::default();
+        let (first, second) = (at("2024-05-01T12:00:00Z"), at("2024-05-01T12:00:01Z"));
+        assert!(!cursor.is_seen(&first, "a"));
+
+        cursor.advance(first, "a".to_string());
+        cursor.advance(first, "b".to_string());
+        assert!(cursor.is_seen(&first, "a") && cursor.is_seen(&first, "b"));
+        assert!(!cursor.is_seen(&first, "c"));
+
+        // Moving on forgets the old timestamp's ids; anything older counts as seen
+        cursor.advance(second, "c".to_string());
+        assert_eq!(cursor.insert_ids, HashSet::from(["c".to_string()]));
+        assert!(cursor.is_seen(&first, "z"));
+        assert!(!cursor.is_seen(&second, "a"));
+    }
+
+    #[test]
+    fn entries_without_an_insert_id_get_a_stable_key() {
+        let payload = |message: &str| {
+            json!({
+                "logName": "projects/demo/logs/app",
+                "timestamp": "2024-05-01T12:00:00Z",
+                "textPayload": message,
+                "labels": { "b": "2", "a": "1", "c": "3" },
+            })
+        };
+        assert_eq!(dedup_key(&entry(json!({ "insertId": "abc" }))), "abc");
+        let key = dedup_key(&entry(payload("hello")));
+        assert!(key.starts_with("fnv:"), "{}", key);
+        assert_eq!(dedup_key(&entry(payload("hello"))), key);
+        assert_ne!(dedup_key(&entry(payload("bye"))), key);
+    }
+
+    #[test]
+    fn converts_json_payloads() {
+        let log = to_log_entry(
+            entry(json!({
+                "logName": "projects/demo/logs/app",
+                "insertId": "abc",
+                "severity": "ERROR",
+                "jsonPayload": { "msg": "quota exceeded", "quota": { "name": "cpus" } },
+                "resource": { "type": "gce_instance", "labels": { "zone": "us-east1-b" } },
+                "labels": { "team": "infra" },
+            })),
+            at("2024-05-01T12:00:00Z"),
+        );
+        assert_eq!(log.source, "projects/demo/logs/app");
+        assert_eq!(log.level, "ERROR");
+        assert_eq!(log.message, "quota exceeded");
+        assert_eq!(log.timestamp, "2024-05-01T12:00:00+00:00");
+        assert_eq!(log.attributes["quota.name"], "cpus");
+        assert_eq!(log.attributes["resource.type"], "gce_instance");
+        assert_eq!(log.attributes["resource.labels.zone"], "us-east1-b");
+        assert_eq!(log.attributes["labels.team"], "infra");
+        assert_eq!(log.attributes["insert_id"], "abc");
+    }
+
+    #[test]
+    fn converts_other_payloads() {
+        let now = at("2024-05-01T12:00:00Z");
+        let log = to_log_entry(entry(json!({ "severity": "DEFAULT", "textPayload": "plain" })), now);
+        assert_eq!((log.source.as_str(), log.level.as_str(), log.message.as_str()), ("GCP", "INFO", "plain"));
+
+        // Without a message field the whole JSON payload is the message
+        let log = to_log_entry(entry(json!({ "jsonPayload": { "count": 3 } })), now);
+        assert_eq!(log.message, r#"{"count":3}"#);
+        assert_eq!(log.attributes["count"], 3);
+
+        let log = to_log_entry(entry(json!({ "protoPayload": { "@type": "audit" } })), now);
+        assert_eq!(log.message, r#"{"@type":"audit"}"#);
+        assert_eq!(to_log_entry(entry(json!({})), now).message, "");
     }
 }
@@ -0,0 +1,578 @@
+use crate::models::LogEntry;
+use crate::utils::{load_checkpoint, save_checkpoint};
+use crate::wire::{encode_batch, WireFormat};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::http;
+use tonic::transport::Endpoint;
+use tonic::Streaming;
+use tonic_prost::ProstCodec;
+use tracing::{info, error, warn};
+
+// ✅ Streams batches to the processor's `LogStream` gRPC service (port 4001) instead of `POST /logs`
+//
+// Mirrors `log_processor::grpc_handler`, which owns the schema (and documents it): keep the
+// message tags in step with it. Every batch gets the next sequence on this process's stream id;
+// unacked batches are resent with the same sequence after a reconnect, and the processor answers
+// DUPLICATE for any it had already applied. Each batch is stamped with the oldest sequence still
+// waiting for an ack, so the processor stops waiting for batches given up on here: those it
+// rejected, asked to retry `MAX_ATTEMPTS` times, or didn't ack within `ACK_TIMEOUT`, which the
+// batcher dead-letters. The stream id and a ceiling on the sequences handed out are saved to
+// `FORWARDER_STREAM_STATE_PATH` before any sequence under it is used, so a restarted collector
+// carries on its stream without reusing a sequence (which the processor would take for a
+// duplicate); the processor saves what it applied, so resends are recognized across restarts of
+// either side.
+
+const STREAM_PATH: &str = "/insightx.logs.v1.LogStream/Stream";
+const OUTGOING_BUFFER: usize = 1024; // Batches written ahead of the connection; the batcher's in-flight limit bounds it in practice
+const RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: u32 = 10; // Sends of one batch; a RETRY answer to the last one dead-letters it
+const ACK_TIMEOUT: Duration = Duration::from_secs(60); // From a batch's first send to its ack, across reconnects
+const SEQUENCE_BLOCK: u64 = 1024; // Sequences reserved per save of the stream state
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamBatch {
+    #[prost(string, tag = "1")]
+    pub stream_id: String,
+    #[prost(uint64, tag = "2")]
+    pub sequence: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub batch: Vec<u8>, // An encoded `LogBatch`, see `wire`
+    #[prost(uint64, tag = "4")]
+    pub oldest_unacked: u64, // Stamped when sent; the processor stops waiting for gaps below it
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BatchAck {
+    #[prost(uint64, tag = "1")]
+    pub sequence: u64,
+    #[prost(enumeration = "AckStatus", tag = "2")]
+    pub status: i32,
+    #[prost(string, tag = "3")]
+    pub error: String,
+    #[prost(uint32, tag = "4")]
+    pub retry_after_ms: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum AckStatus {
+    Accepted = 0,
+    Duplicate = 1,
+    Retry = 2,
+    Rejected = 3,
+}
+
+type Done = oneshot::Sender<anyhow::Result<()>>;
+
+// A batch sent on the stream and not yet acked
+struct Pending {
+    batch: StreamBatch,
+    done: Done,
+    attempts: u32, // Sends so far, counting RETRY resends but not resends after reconnecting
+    deadline: Instant, // Failed (and so dead-lettered) if not acked by then
+    resend_at: Option<Instant>, // Set by a RETRY ack
+}
+
+impl Pending {
+    fn new(batch: StreamBatch, done: Done) -> Self {
+        Self { batch, done, attempts: 1, deadline: Instant::now() + ACK_TIMEOUT, resend_at: None }
+    }
+
+    fn fail(self, error: anyhow::Error) {
+        let _ = self.done.send(Err(error));
+    }
+}
+
+// The saved stream state: sequences from `next_sequence` on were never handed out
+#[derive(Serialize, Deserialize)]
+struct StreamCheckpoint {
+    stream_id: String,
+    next_sequence: u64,
+}
+
+// ✅ Hands out this collector's sequences, saving a new ceiling before going past the last one
+struct Sequences {
+    path: Option<PathBuf>,
+    stream_id: String,
+    next: u64,
+    reserved: u64, // Saved ceiling; `next` stays below it
+}
+
+impl Sequences {
+    // Carries on the saved stream, if any; without a path every run gets a new stream
+    async fn open(path: Option<PathBuf>) -> Self {
+        let saved = match &path {
+            Some(path) => load_checkpoint::<StreamCheckpoint>(path).await,
+            None => None,
+        };
+        let (stream_id, next) = match saved {
+            Some(saved) => {
+                info!("📥 Resuming gRPC stream {} at sequence {}", saved.stream_id, saved.next_sequence);
+                (saved.stream_id, saved.next_sequence)
+            }
+            None => (new_stream_id(), 1),
+        };
+        let reserved = if path.is_some() { next } else { u64::MAX };
+        Self { path, stream_id, next, reserved }
+    }
+
+    async fn next(&mut self) -> u64 {
+        if self.next >= self.reserved {
+            self.reserve().await;
+        }
+        self.next += 1;
+        self.next - 1
+    }
+
+    async fn reserve(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let checkpoint = StreamCheckpoint { stream_id: self.stream_id.clone(), next_sequence: self.next + SEQUENCE_BLOCK };
+        match save_checkpoint(path, &checkpoint).await {
+            Ok(()) => self.reserved = checkpoint.next_sequence,
+            Err(e) => {
+                // A restart could hand out the unsaved sequences again, so the rest of this run
+                // goes on a stream no restart will resume (counting on, so pending batches keep their order)
+                error!("❌ Failed to save gRPC stream state to {:?}, switching to a new stream: {}", path, e);
+                self.path = None;
+                self.stream_id = new_stream_id();
+                self.reserved = u64::MAX;
+            }
+        }
+    }
+}
+
+fn new_stream_id() -> String {
+    format!("log-collector-{}-{}", std::process::id(), Utc::now().timestamp_micros())
+}
+
+// ✅ Handle to the task that owns the stream; cheap to share between sender tasks
+pub struct GrpcForwarder {
+    requests: mpsc::Sender<(Vec<u8>, Done)>,
+}
+
+impl GrpcForwarder {
+    // Spawns the stream task; it connects on first use and reconnects as needed. `state_path`
+    // keeps the stream id and sequences across restarts; without it each run is a new stream.
+    pub fn new(url: String, state_path: Option<PathBuf>) -> Self {
+        let (requests, rx) = mpsc::channel(OUTGOING_BUFFER);
+        tokio::spawn(async move { run_stream(url, Sequences::open(state_path).await, rx).await });
+        Self { requests }
+    }
+
+    // Resolves once the processor accepted the batch (or had already), or gave up on it
+    pub async fn send_logs(&self, logs: &[LogEntry]) -> anyhow::Result<()> {
+        if logs.is_empty() {
+            return Ok(());
+        }
+        let (done, result) = oneshot::channel();
+        let batch = encode_batch(WireFormat::Protobuf, logs);
+        self.requests
+            .send((batch, done))
+            .await
+            .map_err(|_| anyhow::anyhow!("gRPC forwarder stopped"))?;
+        result.await.map_err(|_| anyhow::anyhow!("gRPC forwarder stopped"))?
+    }
+}
+
+async fn connect(url: &str, batches: mpsc::Receiver<StreamBatch>) -> anyhow::Result<Streaming<BatchAck>> {
+    let channel = Endpoint::from_shared(url.to_string())?.connect().await?;
+    let mut grpc = tonic::client::Grpc::new(channel)
+        .send_compressed(CompressionEncoding::Zstd)
+        .accept_compressed(CompressionEncoding::Zstd);
+    grpc.ready().await?;
+    let path = http::uri::PathAndQuery::from_static(STREAM_PATH);
+    let codec = ProstCodec::<StreamBatch, BatchAck>::default();
+    let request = tonic::Request::new(ReceiverStream::new(batches));
+    Ok(grpc.streaming(request, path, codec).await?.into_inner())
+}
+
+// Fails every batch in flight or waiting to be sent, so the batcher dead-letters them
+fn give_up(pending: &mut BTreeMap<u64, Pending>, requests: &mut mpsc::Receiver<(Vec<u8>, Done)>, reason: &str) {
+    for (_, pending) in std::mem::take(pending) {
+        pending.fail(anyhow::anyhow!("{}", reason));
+    }
+    while let Ok((_, done)) = requests.try_recv() {
+        let _ = done.send(Err(anyhow::anyhow!("{}", reason)));
+    }
+}
+
+// Owns the connection: assigns sequences, matches acks to batches and resends after reconnecting
+async fn run_stream(url: String, mut sequences: Sequences, mut requests: mpsc::Receiver<(Vec<u8>, Done)>) {
+    let mut pending: BTreeMap<u64, Pending> = BTreeMap::new();
+    let mut backoff = RECONNECT_BACKOFF;
+    let mut open = true; // Until every `GrpcForwarder` handle is gone
+
+    loop {
+        // Nothing to send: wait for a batch before (re)connecting
+        if pending.is_empty() {
+            match requests.recv().await {
+                Some((batch, done)) => {
+                    let sequence = sequences.next().await;
+                    let batch = StreamBatch { stream_id: sequences.stream_id.clone(), sequence, batch, oldest_unacked: 0 };
+                    pending.insert(sequence, Pending::new(batch, done));
+                }
+                None => return,
+            }
+        }
+
+        let (outgoing, batches) = mpsc::channel(OUTGOING_BUFFER);
+        let mut acks = match connect(&url, batches).await {
+            Ok(acks) => acks,
+            Err(e) => {
+                // A processor that's down fails batches fast, like `POST /logs` would
+                error!("❌ Failed to open gRPC stream to {}: {}", url, e);
+                give_up(&mut pending, &mut requests, &format!("failed to open gRPC stream: {}", e));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                continue;
+            }
+        };
+        info!("📡 gRPC stream {} open to {} ({} batches to resend)", sequences.stream_id, url, pending.len());
+        backoff = RECONNECT_BACKOFF;
+        // Batches waiting out a RETRY delay go out once it's up
+        for (sequence, _) in pending.iter().filter(|(_, pending)| pending.resend_at.is_none()) {
+            let _ = outgoing.send(stamped(&pending, *sequence).expect("sequence is pending")).await;
+        }
+
+        loop {
+            let wakeup = next_wakeup(&pending);
+            tokio::select! {
+                request = requests.recv(), if open => {
+                    let Some((batch, done)) = request else {
+                        open = false;
+                        if pending.is_empty() {
+                            return;
+                        }
+                        continue; // Still waiting on acks
+                    };
+                    let sequence = sequences.next().await;
+                    let batch = StreamBatch { stream_id: sequences.stream_id.clone(), sequence, batch, oldest_unacked: 0 };
+                    pending.insert(sequence, Pending::new(batch, done));
+                    if outgoing.send(stamped(&pending, sequence).expect("sequence is pending")).await.is_err() {
+                        break;
+                    }
+                }
+                ack = acks.message() => match ack {
+                    Ok(Some(ack)) => {
+                        acknowledge(ack, &mut pending);
+                        if !open && pending.is_empty() {
+                            return;
+                        }
+                    }
+                    Ok(None) => {
+                        warn!("⚠️ gRPC stream {} closed by the processor, reconnecting", sequences.stream_id);
+                        break;
+                    }
+                    Err(status) => {
+                        warn!("⚠️ gRPC stream {} failed, reconnecting: {}", sequences.stream_id, status);
+                        break;
+                    }
+                },
+                _ = tokio::time::sleep_until(wakeup.unwrap_or_else(Instant::now)), if wakeup.is_some() => {
+                    for batch in due(&mut pending, Instant::now()) {
+                        let _ = outgoing.send(batch).await;
+                    }
+                    if !open && pending.is_empty() {
+                        return;
+                    }
+                }
+            }
+        }
+        // Pending batches are resent on the next connection, if it can be opened
+        tokio::time::sleep(RECONNECT_BACKOFF).await;
+    }
+}
+
+// A pending batch as sent: stamped with the oldest sequence still waiting for an ack
+fn stamped(pending: &BTreeMap<u64, Pending>, sequence: u64) -> Option<StreamBatch> {
+    let oldest_unacked = *pending.keys().next()?;
+    pending.get(&sequence).map(|pending| StreamBatch { oldest_unacked, ..pending.batch.clone() })
+}
+
+// The next deadline or RETRY resend among the pending batches
+fn next_wakeup(pending: &BTreeMap<u64, Pending>) -> Option<Instant> {
+    pending.values().flat_map(|pending| [Some(pending.deadline), pending.resend_at]).flatten().min()
+}
+
+// Fails the batches past their deadline and returns the RETRYed ones whose delay is up, ready to send
+fn due(pending: &mut BTreeMap<u64, Pending>, now: Instant) -> Vec<StreamBatch> {
+    let expired: Vec<u64> = pending.iter().filter(|(_, p)| p.deadline <= now).map(|(sequence, _)| *sequence).collect();
+    for sequence in expired {
+        error!("❌ No ack for batch {} within {:?}, giving up on it", sequence, ACK_TIMEOUT);
+        let error = anyhow::anyhow!("no ack from the log processor within {:?}", ACK_TIMEOUT);
+        pending.remove(&sequence).expect("sequence is pending").fail(error);
+    }
+
+    let ready: Vec<u64> = pending
+        .iter_mut()
+        .filter(|(_, p)| p.resend_at.is_some_and(|at| at <= now))
+        .map(|(sequence, p)| {
+            p.resend_at = None;
+            *sequence
+        })
+        .collect();
+    ready.into_iter().filter_map(|sequence| stamped(pending, sequence)).collect()
+}
+
+fn acknowledge(ack: BatchAck, pending: &mut BTreeMap<u64, Pending>) {
+    let status = AckStatus::try_from(ack.status).unwrap_or(AckStatus::Rejected);
+    match status {
+        AckStatus::Accepted | AckStatus::Duplicate => {
+            // A resent batch can be acked twice; the second ack finds nothing pending
+            if let Some(pending) = pending.remove(&ack.sequence) {
+                info!("✅ Processor {:?} batch {}", status, ack.sequence);
+                let _ = pending.done.send(Ok(()));
+            }
+        }
+        AckStatus::Retry => {
+            let Some(batch) = pending.get_mut(&ack.sequence) else {
+                return;
+            };
+            if batch.attempts >= MAX_ATTEMPTS {
+                error!("❌ Processor asked to retry batch {} {} times, giving up on it: {}", ack.sequence, batch.attempts, ack.error);
+                let error = anyhow::anyhow!("log processor asked to retry batch {} times: {}", batch.attempts, ack.error);
+                pending.remove(&ack.sequence).expect("sequence is pending").fail(error);
+                return;
+            }
+            warn!("⚠️ Processor asked to retry batch {} in {}ms: {}", ack.sequence, ack.retry_after_ms, ack.error);
+            batch.attempts += 1;
+            // If the connection drops meanwhile, the batch is still pending and goes out on the next one
+            batch.resend_at = Some(Instant::now() + Duration::from_millis(u64::from(ack.retry_after_ms)));
+        }
+        AckStatus::Rejected => {
+            if let Some(pending) = pending.remove(&ack.sequence) {
+                error!("❌ Processor rejected batch {}: {}", ack.sequence, ack.error);
+                pending.fail(anyhow::anyhow!("log processor rejected batch: {}", ack.error));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log_processor::grpc_handler::LogStreamServer;
+    use log_processor::http_handler::AppState;
+    use prost::Message;
+    use std::sync::Arc;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::Server;
+
+    fn pending(sequences: &[u64]) -> (BTreeMap<u64, Pending>, Vec<oneshot::Receiver<anyhow::Result<()>>>) {
+        let mut pending = BTreeMap::new();
+        let mut results = Vec::new();
+        for &sequence in sequences {
+            let (done, result) = oneshot::channel();
+            let batch = StreamBatch { stream_id: "s".to_string(), sequence, batch: vec![sequence as u8], oldest_unacked: 0 };
+            pending.insert(sequence, Pending::new(batch, done));
+            results.push(result);
+        }
+        (pending, results)
+    }
+
+    fn ack(sequence: u64, status: AckStatus, retry_after_ms: u32) -> BatchAck {
+        BatchAck { sequence, status: status as i32, error: "outputs full".to_string(), retry_after_ms }
+    }
+
+    #[test]
+    fn messages_match_the_processor_schema() {
+        let batch = StreamBatch { stream_id: "collector-1".to_string(), sequence: 7, batch: vec![1, 2, 3], oldest_unacked: 5 };
+        let theirs = log_processor::grpc_handler::StreamBatch::decode(&batch.encode_to_vec()[..]).unwrap();
+        assert_eq!(
+            (theirs.stream_id.as_str(), theirs.sequence, theirs.batch, theirs.oldest_unacked),
+            ("collector-1", 7, vec![1, 2, 3], 5)
+        );
+
+        for status in [AckStatus::Accepted, AckStatus::Duplicate, AckStatus::Retry, AckStatus::Rejected] {
+            let theirs = log_processor::grpc_handler::BatchAck {
+                sequence: 9,
+                status: status as i32,
+                error: "e".to_string(),
+                retry_after_ms: 500,
+            };
+            let ours = BatchAck::decode(&theirs.encode_to_vec()[..]).unwrap();
+            assert_eq!(ours, BatchAck { error: "e".to_string(), ..ack(9, status, 500) });
+        }
+    }
+
+    #[tokio::test]
+    async fn accepted_and_duplicate_resolve_rejected_fails() {
+        let (mut pending, mut results) = pending(&[1, 2, 3]);
+        acknowledge(ack(1, AckStatus::Accepted, 0), &mut pending);
+        acknowledge(ack(2, AckStatus::Duplicate, 0), &mut pending);
+        acknowledge(ack(3, AckStatus::Rejected, 0), &mut pending);
+        // A second ack for a resent batch finds nothing left to resolve
+        acknowledge(ack(1, AckStatus::Duplicate, 0), &mut pending);
+
+        assert!(pending.is_empty());
+        assert!(results.remove(0).await.unwrap().is_ok());
+        assert!(results.remove(0).await.unwrap().is_ok());
+        let rejected = results.remove(0).await.unwrap().unwrap_err();
+        assert!(rejected.to_string().contains("rejected"), "{}", rejected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_resends_the_same_sequence_after_the_delay() {
+        let (mut pending, _results) = pending(&[3, 4]);
+        let start = Instant::now();
+        acknowledge(ack(4, AckStatus::Retry, 500), &mut pending);
+
+        // Still pending, so a reconnect before the resend would send it too
+        assert!(pending.contains_key(&4));
+        assert_eq!(next_wakeup(&pending), Some(start + Duration::from_millis(500)));
+        assert!(due(&mut pending, start + Duration::from_millis(499)).is_empty());
+        let resent = due(&mut pending, start + Duration::from_millis(500));
+        assert_eq!(resent.len(), 1);
+        assert_eq!((resent[0].sequence, &resent[0].batch, resent[0].oldest_unacked), (4, &vec![4], 3));
+        assert_eq!(pending[&4].attempts, 2);
+        // Only the deadlines are left to wake up for
+        assert_eq!(next_wakeup(&pending), Some(start + ACK_TIMEOUT));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_stop_after_max_attempts() {
+        let (mut pending, mut results) = pending(&[1]);
+        for _ in 1..MAX_ATTEMPTS {
+            acknowledge(ack(1, AckStatus::Retry, 0), &mut pending);
+            assert_eq!(due(&mut pending, Instant::now()).len(), 1);
+        }
+        assert_eq!(pending[&1].attempts, MAX_ATTEMPTS);
+
+        acknowledge(ack(1, AckStatus::Retry, 0), &mut pending);
+        assert!(pending.is_empty());
+        let error = results.remove(0).await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("asked to retry batch 10 times: outputs full"), "{}", error);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unacked_batches_fail_at_their_deadline() {
+        let start = Instant::now();
+        let (mut first, mut first_results) = pending(&[1]);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let (second, mut second_results) = pending(&[2]);
+        first.extend(second);
+        let mut pending = first;
+
+        // A batch waiting out a RETRY delay times out all the same
+        acknowledge(ack(1, AckStatus::Retry, 120_000), &mut pending);
+        assert!(due(&mut pending, start + ACK_TIMEOUT - Duration::from_millis(1)).is_empty());
+        assert_eq!(pending.len(), 2);
+        assert!(due(&mut pending, start + ACK_TIMEOUT).is_empty());
+        assert_eq!(pending.keys().collect::<Vec<_>>(), [&2]);
+        let error = first_results.remove(0).await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("no ack from the log processor within 60s"), "{}", error);
+
+        assert_eq!(next_wakeup(&pending), Some(start + Duration::from_secs(10) + ACK_TIMEOUT));
+        due(&mut pending, start + Duration::from_secs(10) + ACK_TIMEOUT);
+        assert!(pending.is_empty());
+        assert!(second_results.remove(0).await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn sequences_carry_on_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/grpc_stream.json");
+        let saved = || serde_json::from_slice::<serde_json::Value>(&std::fs::read(&path).unwrap()).unwrap();
+
+        let mut sequences = Sequences::open(Some(path.clone())).await;
+        assert_eq!([sequences.next().await, sequences.next().await, sequences.next().await], [1, 2, 3]);
+        let stream_id = sequences.stream_id.clone();
+        assert_eq!(saved()["next_sequence"], 1 + SEQUENCE_BLOCK);
+
+        // Unused reserved sequences are skipped rather than risk handing one out twice
+        let mut restarted = Sequences::open(Some(path.clone())).await;
+        assert_eq!(restarted.stream_id, stream_id);
+        assert_eq!(restarted.next().await, 1 + SEQUENCE_BLOCK);
+        assert_eq!(saved()["next_sequence"], 1 + 2 * SEQUENCE_BLOCK);
+    }
+
+    #[tokio::test]
+    async fn a_failed_save_moves_to_a_new_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        // The parent is a file, so the state can't be saved
+        std::fs::write(dir.path().join("state"), "").unwrap();
+        let mut sequences = Sequences::open(Some(dir.path().join("state/grpc_stream.json"))).await;
+        let stream_id = sequences.stream_id.clone();
+
+        assert_eq!(sequences.next().await, 1);
+        assert_ne!(sequences.stream_id, stream_id);
+        assert_eq!((sequences.path.as_ref(), sequences.reserved), (None, u64::MAX));
+        assert_eq!(sequences.next().await, 2);
+    }
+
+    #[tokio::test]
+    async fn unreachable_processor_fails_the_batch() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let forwarder = GrpcForwarder::new(url, None);
+        let log = LogEntry {
+            source: "api".to_string(),
+            level: "INFO".to_string(),
+            message: "hello".to_string(),
+            timestamp: "2024-05-01T12:00:00Z".to_string(),
+            attributes: Default::default(),
+        };
+        let error = forwarder.send_logs(&[log]).await.unwrap_err();
+        assert!(error.to_string().contains("failed to open gRPC stream"), "{}", error);
+        assert!(forwarder.send_logs(&[]).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn forwards_to_the_processor_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out.ndjson");
+        let config = serde_json::from_value(serde_json::json!({
+            "outputs": {"storage": {"type": "file", "path": out, "batch": {"linger_ms": 10}}}
+        }))
+        .unwrap();
+        let state = Arc::new(AppState::new("http://localhost:1/logs".to_string(), &config).unwrap());
+        let server = LogStreamServer::open(state, dir.path().join("grpc_streams.ndjson")).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(Server::builder().add_service(server).serve_with_incoming(TcpIncoming::from(listener)));
+
+        let log = |message: &str| LogEntry {
+            source: "api".to_string(),
+            level: "INFO".to_string(),
+            message: message.to_string(),
+            timestamp: "2024-05-01T12:00:00Z".to_string(),
+            attributes: Default::default(),
+        };
+        let state_path = dir.path().join("grpc_stream.json");
+        let forwarder = GrpcForwarder::new(url.clone(), Some(state_path.clone()));
+        forwarder.send_logs(&[log("a"), log("b")]).await.unwrap();
+        forwarder.send_logs(&[log("c")]).await.unwrap();
+
+        // A restarted collector carries on its stream past the sequences already applied
+        drop(forwarder);
+        let restarted = GrpcForwarder::new(url, Some(state_path));
+        restarted.send_logs(&[log("d")]).await.unwrap();
+
+        let mut messages = Vec::new();
+        for _ in 0..100 {
+            messages = std::fs::read_to_string(&out)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["message"].as_str().unwrap().to_string())
+                .collect();
+            if messages.len() == 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(messages, ["a", "b", "c", "d"]);
+    }
+}
@@ -1,80 +1,314 @@
-use axum::{extract::State, http::StatusCode};
-use tokio::sync::mpsc;
-use tracing::{info, error};
-use std::{sync::Arc, str};
-use crate::{forwarder::send_logs, models::LogEntry};
-use bytes::Bytes;
-use serde_json::Value;
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncReadExt, sync::mpsc::{self, error::SendTimeoutError}};
+use tracing::{debug, info, error, warn};
+use std::{sync::Arc, time::Duration};
+use crate::{
+    body_stream::{decode_body, JsonSplitter, SplitError, UnsupportedEncoding},
+    dead_letter::{DeadLetterReason, DeadLetterStore, Protocol},
+    models::LogEntry,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub sender: mpsc::Sender<LogEntry>,
+    pub max_body_bytes: usize, // Limit on the decoded request body
+    pub max_event_bytes: usize, // Limit on a single JSON event within the body
+    pub queue_timeout: Duration, // How long to wait for queue capacity before rejecting an event
+    pub strict_by_default: bool, // Reject whole batches atomically unless `?strict=false`
+    pub dead_letter: Arc<DeadLetterStore>, // Where unparseable payloads and undeliverable batches end up
 }
 
-// ✅ Optimized Log Ingestion Handler
+// A state on a fresh queue of `capacity` events, with an in-memory dead-letter store
+#[cfg(test)]
+impl AppState {
+    pub(crate) async fn for_tests(capacity: usize) -> (Arc<Self>, mpsc::Receiver<LogEntry>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let dead_letter = DeadLetterStore::open(crate::dead_letter::DeadLetterConfig::default()).await;
+        let state = Self {
+            sender,
+            max_body_bytes: 1024 * 1024,
+            max_event_bytes: 64 * 1024,
+            queue_timeout: Duration::from_millis(100),
+            strict_by_default: false,
+            dead_letter: Arc::new(dead_letter),
+        };
+        (Arc::new(state), receiver)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IngestParams {
+    strict: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    ParseError,
+    Oversized,
+    QueueFull,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Rejection {
+    pub index: usize, // Position of the event in the request body (0-based)
+    pub reason: RejectReason,
+    pub detail: String,
+}
+
+// ✅ Partial-success response body for `/logs`
+#[derive(Debug, Default, Serialize)]
+pub struct IngestResponse {
+    pub accepted: usize,
+    pub rejected: Vec<Rejection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>, // Request-level failure (encoding, body limit, ...)
+}
+
+impl IngestResponse {
+    fn failed(status: StatusCode, error: String, accepted: usize, rejected: Vec<Rejection>) -> (StatusCode, Json<Self>) {
+        (status, Json(Self { accepted, rejected, error: Some(error) }))
+    }
+}
+
+// ✅ Streaming Log Ingestion Handler
+// Accepts a single object, a JSON array or NDJSON, optionally gzip/zstd/lz4/deflate encoded.
+// By default events are queued as they are parsed and bad ones are reported per index; in
+// strict mode the batch is validated first and queued all-or-nothing.
 pub async fn ingest_log(
     State(state): State<Arc<AppState>>,
-    body: Bytes // Use Zero-Copy `Bytes` for performance
-) -> StatusCode {
-
-    if let Ok(body_str) = str::from_utf8(&body) {
-        let json: Value = match serde_json::from_str(body_str) {
-            Ok(data) => data,
-            Err(_) => {
-                error!("❌ Failed to parse incoming JSON");
-                return StatusCode::BAD_REQUEST;
+    Query(params): Query<IngestParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> (StatusCode, Json<IngestResponse>) {
+    let strict = params.strict.unwrap_or(state.strict_by_default);
+    let encoding = headers.get(header::CONTENT_ENCODING).and_then(|v| v.to_str().ok());
+    let mut reader = match decode_body(body, encoding) {
+        Ok(reader) => reader,
+        Err(UnsupportedEncoding(encoding)) => {
+            error!("❌ Unsupported Content-Encoding: {}", encoding);
+            let error = format!("unsupported Content-Encoding: {}", encoding);
+            return IngestResponse::failed(StatusCode::UNSUPPORTED_MEDIA_TYPE, error, 0, Vec::new());
+        }
+    };
+
+    let mut splitter = JsonSplitter::new(state.max_event_bytes);
+    let mut chunk = vec![0u8; 64 * 1024];
+    let mut total_bytes = 0usize;
+    let mut next_index = 0usize;
+    let mut accepted = 0usize;
+    let mut rejected = Vec::new();
+    let mut pending = Vec::new(); // Strict mode: validated events awaiting the all-or-nothing enqueue
+    let mut queue_full = false; // Set once an event timed out waiting for room; the rest don't wait again
+
+    loop {
+        let (elements, finished) = match reader.read(&mut chunk).await {
+            Ok(0) => (splitter.finish().into_iter().collect(), true),
+            Ok(n) => {
+                // ✅ Enforced on the decoded size so compression bombs are cut off too
+                total_bytes += n;
+                if total_bytes > state.max_body_bytes {
+                    error!("❌ Request body exceeds {} bytes", state.max_body_bytes);
+                    let error = format!("request body exceeds {} bytes", state.max_body_bytes);
+                    return IngestResponse::failed(StatusCode::PAYLOAD_TOO_LARGE, error, accepted, rejected);
+                }
+                (splitter.push(&chunk[..n]), false)
+            }
+            Err(e) => {
+                error!("❌ Failed to read request body: {}", e);
+                let error = format!("failed to read request body: {}", e);
+                return IngestResponse::failed(StatusCode::BAD_REQUEST, error, accepted, rejected);
             }
         };
 
-        // ✅ Handle Single Log Entry
-        if let Ok(log) = serde_json::from_value::<LogEntry>(json.clone()) {
-            return process_log(log, &state).await;
-        }
+        for element in elements {
+            let index = next_index;
+            next_index += 1;
 
-        // ✅ Handle Batch Log Entries
-        if let Ok(logs) = serde_json::from_value::<Vec<LogEntry>>(json) {
-            for log in logs {
-                let _ = process_log(log, &state).await;
+            let log = match parse_element(element, &state) {
+                Ok(log) => log,
+                Err((reason, detail, raw)) => {
+                    warn!("⚠️ Rejecting log entry #{}: {}", index, detail);
+                    let dead_letter_reason = match reason {
+                        RejectReason::Oversized => DeadLetterReason::Oversized,
+                        _ => DeadLetterReason::ParseError,
+                    };
+                    state.dead_letter.record("http:/logs", Protocol::Native, dead_letter_reason, detail.clone(), &raw).await;
+                    rejected.push(Rejection { index, reason, detail });
+                    continue;
+                }
+            };
+
+            if strict {
+                pending.push(log);
+            } else if queue_full {
+                // Otherwise an N-event body would hold the request for N × `queue_timeout`
+                rejected.push(Rejection { index, reason: RejectReason::QueueFull, detail: "log queue is full".to_string() });
+            } else {
+                match process_log(log, &state).await {
+                    Ok(()) => accepted += 1,
+                    Err(reason) => {
+                        queue_full = true;
+                        rejected.push(Rejection { index, reason, detail: "log queue is full".to_string() });
+                    }
+                }
             }
-            return StatusCode::OK;
+        }
+
+        if finished {
+            break;
+        }
+    }
+
+    if strict {
+        if !rejected.is_empty() {
+            error!("❌ Strict batch rejected: {} invalid entries", rejected.len());
+            let error = "batch rejected: strict mode requires every entry to be valid".to_string();
+            return IngestResponse::failed(StatusCode::BAD_REQUEST, error, 0, rejected);
+        }
+        accepted = pending.len();
+        if let Err(reason) = enqueue_all(pending, &state).await {
+            error!("❌ Strict batch rejected: log queue cannot take {} entries", accepted);
+            let rejected = (0..accepted)
+                .map(|index| Rejection { index, reason, detail: "log queue is full".to_string() })
+                .collect();
+            let error = "batch rejected: log queue is full".to_string();
+            return IngestResponse::failed(StatusCode::SERVICE_UNAVAILABLE, error, 0, rejected);
         }
     }
 
-    
-    info!("✅ Received log: {:?}", payload); // ✅ Ensures log is printed
+    info!("✅ Received {} logs ({} rejected)", accepted, rejected.len());
 
+    // Partial success is still a 200; the body says which entries were dropped and why
+    let status = if next_index == 0 {
+        StatusCode::BAD_REQUEST
+    } else if accepted > 0 || rejected.is_empty() {
+        StatusCode::OK
+    } else if rejected.iter().all(|r| matches!(r.reason, RejectReason::QueueFull)) {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    let error = (next_index == 0).then(|| "empty request body".to_string());
 
-    error!("❌ Invalid JSON format: Expected single LogEntry or array");
-    StatusCode::BAD_REQUEST
+    (status, Json(IngestResponse { accepted, rejected, error }))
 }
 
-// ✅ Function to process logs safely
-async fn process_log(log: LogEntry, state: &Arc<AppState>) -> StatusCode {
-    info!("✅ Received log: {:?}", log);
-    
-    if let Err(_) = state.sender.send(log).await {
-        error!("❌ Log queue is full, dropping log");
-        return StatusCode::SERVICE_UNAVAILABLE;
+// ✅ Turns one split element into a LogEntry, or a rejection reason plus the raw bytes
+// (empty for oversized entries, which are never buffered)
+fn parse_element(element: Result<Vec<u8>, SplitError>, state: &AppState) -> Result<LogEntry, (RejectReason, String, Vec<u8>)> {
+    let bytes = match element {
+        Ok(bytes) => bytes,
+        Err(SplitError::Oversized { size }) => {
+            let detail = format!("entry is {} bytes, limit is {}", size, state.max_event_bytes);
+            return Err((RejectReason::Oversized, detail, Vec::new()));
+        }
+    };
+
+    match serde_json::from_slice::<LogEntry>(&bytes) {
+        Ok(log) => Ok(log),
+        Err(e) => Err((RejectReason::ParseError, e.to_string(), bytes)),
     }
+}
 
-    StatusCode::OK
+// ✅ Queues a validated strict batch atomically: capacity for every entry is reserved first
+async fn enqueue_all(logs: Vec<LogEntry>, state: &AppState) -> Result<(), RejectReason> {
+    if send_all(&state.sender, logs, state.queue_timeout).await {
+        Ok(())
+    } else {
+        Err(RejectReason::QueueFull)
+    }
 }
 
-// ✅ Batch Processing for Log Forwarding
-pub async fn start_log_processor(mut receiver: mpsc::Receiver<LogEntry>, processor_url: String) {
-    let mut buffer = Vec::new();
+// ✅ All-or-nothing enqueue shared by the push endpoints: waits up to `queue_timeout` for room
+// for the whole batch, so a rejected push left nothing behind and its retry can't duplicate
+pub async fn send_all(sender: &mpsc::Sender<LogEntry>, logs: Vec<LogEntry>, queue_timeout: Duration) -> bool {
+    if logs.is_empty() {
+        return true;
+    }
 
-    loop {
-        tokio::select! {
-            Some(log) = receiver.recv() => {
-                buffer.push(log);
-                if buffer.len() >= 100 {
-                    send_logs(std::mem::take(&mut buffer), &processor_url).await;
-                }
-            }
-            _ = tokio::time::sleep(tokio::time::Duration::from_secs(5)), if !buffer.is_empty() => {
-                send_logs(std::mem::take(&mut buffer), &processor_url).await;
-            }
+    let permits = match tokio::time::timeout(queue_timeout, sender.reserve_many(logs.len())).await {
+        Ok(Ok(permits)) => permits,
+        Ok(Err(_)) | Err(_) => return false,
+    };
+    for (permit, log) in permits.zip(logs) {
+        permit.send(log);
+    }
+    true
+}
+
+// ✅ Function to process logs safely
+async fn process_log(log: LogEntry, state: &AppState) -> Result<(), RejectReason> {
+    debug!("📥 Received {} log from {}", log.level, log.source);
+
+    match state.sender.send_timeout(log, state.queue_timeout).await {
+        Ok(()) => Ok(()),
+        Err(SendTimeoutError::Timeout(_)) | Err(SendTimeoutError::Closed(_)) => {
+            error!("❌ Log queue is full, dropping log");
+            Err(RejectReason::QueueFull)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ndjson(count: usize) -> Body {
+        let lines: Vec<String> = (0..count)
+            .map(|i| format!(r#"{{"source":"api","level":"INFO","message":"event {}"}}"#, i))
+            .collect();
+        Body::from(lines.join("\n"))
+    }
+
+    async fn ingest(state: &Arc<AppState>, strict: bool, body: Body) -> (StatusCode, IngestResponse) {
+        let params = Query(IngestParams { strict: Some(strict) });
+        let (status, Json(response)) = ingest_log(State(Arc::clone(state)), params, HeaderMap::new(), body).await;
+        (status, response)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_queue_costs_one_timeout_per_request() {
+        let (state, mut rx) = AppState::for_tests(2).await;
+        let start = tokio::time::Instant::now();
+
+        let (status, response) = ingest(&state, false, ndjson(10)).await;
+        assert_eq!(start.elapsed(), state.queue_timeout);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.accepted, 2);
+        let indexes: Vec<usize> = response.rejected.iter().map(|r| r.index).collect();
+        assert_eq!(indexes, (2..10).collect::<Vec<_>>());
+        assert!(response.rejected.iter().all(|r| matches!(r.reason, RejectReason::QueueFull)));
+
+        // Nothing fits at all: one timeout again, and a retryable status
+        let start = tokio::time::Instant::now();
+        let (status, response) = ingest(&state, false, ndjson(5)).await;
+        assert_eq!(start.elapsed(), state.queue_timeout);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!((response.accepted, response.rejected.len()), (0, 5));
+        assert_eq!(rx.recv().await.unwrap().message, "event 0");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn strict_batches_are_queued_all_or_nothing() {
+        let (state, mut rx) = AppState::for_tests(3).await;
+        let (status, response) = ingest(&state, true, ndjson(4)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!((response.accepted, response.rejected.len()), (0, 4));
+        assert!(rx.try_recv().is_err());
+
+        let (status, response) = ingest(&state, true, ndjson(3)).await;
+        assert_eq!((status, response.accepted), (StatusCode::OK, 3));
+
+        // One bad event rejects the whole strict batch
+        let (status, response) = ingest(&state, true, Body::from("{\"source\":\"api\",\"level\":\"INFO\",\"message\":\"ok\"}\n{\"level\":1}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.rejected[0].index, 1);
+    }
+}
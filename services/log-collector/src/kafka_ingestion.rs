@@ -1,12 +1,14 @@
 use tokio::sync::mpsc;
 use tracing::{info, error};
+use crate::dead_letter::{DeadLetterReason, DeadLetterStore, Protocol};
 use crate::models::LogEntry;
 use rdkafka::consumer::{Consumer, StreamConsumer};
 use rdkafka::config::ClientConfig;
 use rdkafka::message::Message;
-use serde_json;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub async fn start_kafka_listener(brokers: &str, topic: &str, group_id: &str, sender: mpsc::Sender<LogEntry>) {
+pub async fn start_kafka_listener(brokers: &str, topic: &str, group_id: &str, sender: mpsc::Sender<LogEntry>, dead_letter: Arc<DeadLetterStore>) {
     let consumer: StreamConsumer = ClientConfig::new()
         .set("group.id", group_id)
         .set("bootstrap.servers", brokers)
@@ -18,15 +20,27 @@ pub async fn start_kafka_listener(brokers: &str, topic: &str, group_id: &str, se
 
     info!("📡 Kafka Listener subscribed to topic: {}", topic);
 
-    while let Ok(message) = consumer.recv().await {
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(e) => {
+                error!("❌ Kafka receive failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
         if let Some(payload) = message.payload() {
             let log_str = String::from_utf8_lossy(payload);
             match serde_json::from_str::<LogEntry>(&log_str) {
                 Ok(log) => {
-                    let _ = sender.send(log).await;
+                    if sender.send(log).await.is_err() {
+                        return;
+                    }
                 }
                 Err(e) => {
                     error!("❌ Failed to parse Kafka log: {}", e);
+                    let source = format!("kafka:{}/{}@{}", message.topic(), message.partition(), message.offset());
+                    dead_letter.record(source, Protocol::Native, DeadLetterReason::ParseError, e.to_string(), payload).await;
                 }
             }
         }
@@ -0,0 +1,70 @@
+pub mod http_handler;
+pub mod body_stream;
+pub mod file_ingestion;
+pub mod tcp_ingestion;
+pub mod udp_ingestion;
+pub mod forwarder;
+pub mod grpc_forwarder;
+pub mod batcher;
+pub mod dead_letter;
+pub mod models;
+pub mod config;
+pub mod syslog_ingestion;
+pub mod docker_ingestion;
+pub mod awscloudwatch;
+pub mod googlelog;
+pub mod azurelog;
+pub mod otlp_ingestion;
+pub mod loki_ingestion;
+pub mod elasticsearch_ingestion;
+pub mod splunk_hec;
+pub mod utils;
+pub mod wire;
+#[cfg(feature = "kafka")]
+pub mod kafka_ingestion;
+
+use axum::{routing::{get, post}, Router};
+use std::sync::Arc;
+use dead_letter::{delete_dead_letter, get_dead_letter, list_dead_letters, replay_dead_letter, replay_dead_letters};
+use elasticsearch_ingestion::{elasticsearch_bulk, elasticsearch_index_bulk, elasticsearch_info};
+use http_handler::{ingest_log, AppState};
+use loki_ingestion::loki_push;
+use otlp_ingestion::ingest_otlp_logs;
+use splunk_hec::{hec_ack, hec_event, hec_health, hec_raw, HecState};
+
+// ✅ Main HTTP API (port 3000): native `/logs` plus drop-in endpoints for existing shippers
+pub fn app(state: Arc<AppState>, hec_state: Arc<HecState>) -> Router {
+    let app = Router::new()
+        .route("/logs", post(ingest_log))
+        // Drop-in endpoints for existing shippers (Promtail, Fluent Bit, Filebeat, ...)
+        .route("/loki/api/v1/push", post(loki_push))
+        .route("/", get(elasticsearch_info))
+        .route("/_bulk", post(elasticsearch_bulk))
+        .route("/{index}/_bulk", post(elasticsearch_index_bulk))
+        // Dead-letter store: inspect and replay payloads that failed ingestion or delivery
+        .route("/dead-letter", get(list_dead_letters))
+        .route("/dead-letter/replay", post(replay_dead_letters))
+        .route("/dead-letter/{id}", get(get_dead_letter).delete(delete_dead_letter))
+        .route("/dead-letter/{id}/replay", post(replay_dead_letter))
+        .with_state(state);
+
+    // Splunk HTTP Event Collector compatible endpoints
+    let hec_app = Router::new()
+        .route("/services/collector", post(hec_event))
+        .route("/services/collector/event", post(hec_event))
+        .route("/services/collector/event/1.0", post(hec_event))
+        .route("/services/collector/raw", post(hec_raw))
+        .route("/services/collector/raw/1.0", post(hec_raw))
+        .route("/services/collector/ack", post(hec_ack))
+        .route("/services/collector/health", get(hec_health))
+        .with_state(hec_state);
+
+    app.merge(hec_app)
+}
+
+// ✅ OTLP/HTTP receiver (standard port 4318, protobuf + JSON)
+pub fn otlp_http_app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/v1/logs", post(ingest_otlp_logs))
+        .with_state(state)
+}
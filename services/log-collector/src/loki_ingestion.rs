@@ -0,0 +1,331 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prost::Message;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::error;
+use crate::{body_stream::read_body, dead_letter::{DeadLetterReason, Protocol}, http_handler::{send_all, AppState}, models::LogEntry};
+use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// ✅ Loki `logproto.PushRequest` wire types (only the fields push clients send)
+#[derive(Clone, PartialEq, Message)]
+struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    streams: Vec<StreamAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StreamAdapter {
+    #[prost(string, tag = "1")]
+    labels: String,
+    #[prost(message, repeated, tag = "2")]
+    entries: Vec<EntryAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    timestamp: Option<ProtoTimestamp>,
+    #[prost(string, tag = "2")]
+    line: String,
+    #[prost(message, repeated, tag = "3")]
+    structured_metadata: Vec<LabelPairAdapter>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct LabelPairAdapter {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoTimestamp {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+// ✅ Loki JSON push body: `{"streams":[{"stream":{..labels..},"values":[["<ns>","line",{..}]]}]}`
+#[derive(Deserialize)]
+struct JsonPushRequest {
+    streams: Vec<JsonStream>,
+}
+
+#[derive(Deserialize)]
+struct JsonStream {
+    #[serde(default)]
+    stream: HashMap<String, String>,
+    values: Vec<Vec<Value>>,
+}
+
+// ✅ `POST /loki/api/v1/push` — accepts snappy-compressed protobuf (default) or JSON, the latter
+// optionally gzip'd (or any other `Content-Encoding` `/logs` takes)
+pub async fn loki_push(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let body = match read_body(body, &headers, state.max_body_bytes).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("❌ Failed to read Loki push request: {}", e);
+            return (e.status(), e.to_string()).into_response();
+        }
+    };
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+
+    let entries = if is_json {
+        parse_json_push(&body)
+    } else {
+        parse_protobuf_push(&body, state.max_body_bytes)
+    };
+
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("❌ Failed to parse Loki push request: {}", e);
+            let protocol = if is_json { Protocol::LokiJson } else { Protocol::LokiProtobuf };
+            state.dead_letter.record("http:/loki/api/v1/push", protocol, DeadLetterReason::ParseError, e.clone(), &body).await;
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    };
+
+    // All or nothing: Promtail retries the whole push on a 5xx
+    if !send_all(&state.sender, entries, state.queue_timeout).await {
+        error!("❌ Log queue is full, rejecting Loki push");
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    // Loki answers successful pushes with an empty 204
+    StatusCode::NO_CONTENT.into_response()
+}
+
+pub(crate) fn parse_protobuf_push(body: &[u8], max_bytes: usize) -> Result<Vec<LogEntry>, String> {
+    // Snappy frames state their decompressed size up front, so a bomb is refused before decoding
+    let size = snap::raw::decompress_len(body).map_err(|e| format!("invalid snappy payload: {}", e))?;
+    if size > max_bytes {
+        return Err(format!("decompressed payload is {} bytes, limit is {}", size, max_bytes));
+    }
+    let decompressed = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .map_err(|e| format!("invalid snappy payload: {}", e))?;
+    let request = PushRequest::decode(decompressed.as_slice())
+        .map_err(|e| format!("invalid protobuf payload: {}", e))?;
+
+    let mut entries = Vec::new();
+    for stream in request.streams {
+        let labels = parse_label_selector(&stream.labels)?;
+        for entry in stream.entries {
+            let timestamp = entry
+                .timestamp
+                .and_then(|ts| Utc.timestamp_opt(ts.seconds, ts.nanos.max(0) as u32).single());
+            let metadata = entry
+                .structured_metadata
+                .into_iter()
+                .map(|pair| (pair.name, pair.value))
+                .collect();
+            entries.push(to_log_entry(&labels, metadata, entry.line, timestamp));
+        }
+    }
+    Ok(entries)
+}
+
+pub(crate) fn parse_json_push(body: &[u8]) -> Result<Vec<LogEntry>, String> {
+    let request: JsonPushRequest =
+        serde_json::from_slice(body).map_err(|e| format!("invalid JSON payload: {}", e))?;
+
+    let mut entries = Vec::new();
+    for stream in request.streams {
+        for value in stream.values {
+            let (Some(ts), Some(line)) = (value.first(), value.get(1).and_then(Value::as_str)) else {
+                return Err("each value must be [\"<unix epoch ns>\", \"<line>\"]".to_string());
+            };
+            let timestamp = ts
+                .as_str()
+                .and_then(|ts| ts.parse::<i64>().ok())
+                .map(|ns| Utc.timestamp_nanos(ns));
+            let metadata = value
+                .get(2)
+                .and_then(Value::as_object)
+                .map(|meta| {
+                    meta.iter()
+                        .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default();
+            entries.push(to_log_entry(&stream.stream, metadata, line.to_string(), timestamp));
+        }
+    }
+    Ok(entries)
+}
+
+// ✅ Parses a Prometheus-style label set: `{app="api", env="prod"}`
+fn parse_label_selector(selector: &str) -> Result<HashMap<String, String>, String> {
+    let inner = selector
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or_else(|| format!("invalid stream labels: {}", selector))?;
+
+    let mut labels = HashMap::new();
+    let mut chars = inner.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect();
+        if name.trim().is_empty() {
+            break;
+        }
+        if chars.next() != Some('=') || chars.next() != Some('"') {
+            return Err(format!("invalid stream labels: {}", selector));
+        }
+
+        let mut value = String::new();
+        loop {
+            match chars.next() {
+                Some('\\') => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some(c) => value.push(c),
+                    None => return Err(format!("invalid stream labels: {}", selector)),
+                },
+                Some('"') => break,
+                Some(c) => value.push(c),
+                None => return Err(format!("invalid stream labels: {}", selector)),
+            }
+        }
+        labels.insert(name.trim().to_string(), value);
+    }
+    Ok(labels)
+}
+
+fn to_log_entry(
+    labels: &HashMap<String, String>,
+    metadata: HashMap<String, String>,
+    line: String,
+    timestamp: Option<chrono::DateTime<Utc>>,
+) -> LogEntry {
+    let level = ["level", "detected_level", "severity"]
+        .iter()
+        .find_map(|key| metadata.get(*key).or_else(|| labels.get(*key)))
+        .cloned()
+        .unwrap_or_else(|| "INFO".to_string());
+    let source = ["service_name", "job", "app", "container"]
+        .iter()
+        .find_map(|key| labels.get(*key))
+        .cloned()
+        .unwrap_or_else(|| "loki".to_string());
+
+    let attributes = labels
+        .iter()
+        .chain(metadata.iter())
+        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+        .collect();
+
+    LogEntry {
+        source,
+        level,
+        message: line,
+        timestamp: timestamp.unwrap_or_else(Utc::now).to_rfc3339(),
+        attributes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body_stream::compress;
+    use serde_json::json;
+
+    fn labels(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    async fn push(state: &Arc<AppState>, headers: &[(header::HeaderName, &str)], body: Vec<u8>) -> StatusCode {
+        let headers = headers.iter().map(|(name, value)| (name.clone(), value.parse().unwrap())).collect();
+        loki_push(State(Arc::clone(state)), headers, Body::from(body)).await.status()
+    }
+
+    fn snappy_push() -> Vec<u8> {
+        let request = PushRequest {
+            streams: vec![StreamAdapter {
+                labels: r#"{job="api", env="prod"}"#.to_string(),
+                entries: vec![EntryAdapter {
+                    timestamp: Some(ProtoTimestamp { seconds: 1_714_564_800, nanos: 500_000_000 }),
+                    line: "payment failed".to_string(),
+                    structured_metadata: vec![LabelPairAdapter { name: "level".to_string(), value: "error".to_string() }],
+                }],
+            }],
+        };
+        snap::raw::Encoder::new().compress_vec(&request.encode_to_vec()).unwrap()
+    }
+
+    #[test]
+    fn parses_label_selectors() {
+        assert_eq!(parse_label_selector(r#"{app="api", env="prod"}"#).unwrap(), labels(&[("app", "api"), ("env", "prod")]));
+        assert_eq!(parse_label_selector(r#" {app="api",env="prod",} "#).unwrap(), labels(&[("app", "api"), ("env", "prod")]));
+        assert_eq!(parse_label_selector("{}").unwrap(), labels(&[]));
+        // Escaped quotes, backslashes and newlines; commas and braces inside values are just text
+        assert_eq!(
+            parse_label_selector(r#"{msg="say \"hi\"\n", path="C:\\logs", expr="{a,b}"}"#).unwrap(),
+            labels(&[("msg", "say \"hi\"\n"), ("path", "C:\\logs"), ("expr", "{a,b}")])
+        );
+        for invalid in [r#"app="api""#, r#"{app=api}"#, r#"{app="api}"#, r#"{app}"#, r#"{app="a\"#] {
+            assert!(parse_label_selector(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_snappy_protobuf_pushes() {
+        let (state, mut rx) = AppState::for_tests(10).await;
+        let status = push(&state, &[(header::CONTENT_TYPE, "application/x-protobuf")], snappy_push()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let log = rx.recv().await.unwrap();
+        assert_eq!((log.source.as_str(), log.level.as_str(), log.message.as_str()), ("api", "error", "payment failed"));
+        assert_eq!(log.timestamp, "2024-05-01T12:00:00.500+00:00");
+        assert_eq!(log.attributes["env"], "prod");
+    }
+
+    #[tokio::test]
+    async fn accepts_gzipped_json_pushes() {
+        let (state, mut rx) = AppState::for_tests(10).await;
+        let body = json!({
+            "streams": [{ "stream": { "app": "web" }, "values": [["1714564800000000000", "hello", { "trace_id": "abc" }]] }],
+        });
+        let headers = [(header::CONTENT_TYPE, "application/json"), (header::CONTENT_ENCODING, "gzip")];
+        let status = push(&state, &headers, compress("gzip", body.to_string().as_bytes()).await).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let log = rx.recv().await.unwrap();
+        assert_eq!((log.source.as_str(), log.message.as_str()), ("web", "hello"));
+        assert_eq!(log.attributes["trace_id"], "abc");
+    }
+
+    #[tokio::test]
+    async fn enforces_the_body_limit_after_decoding() {
+        let (state, mut rx) = AppState::for_tests(10).await;
+        let state = Arc::new(AppState { max_body_bytes: 1024, ..(*state).clone() });
+
+        // A small snappy payload that claims a large decompressed size is refused before decoding
+        let bomb = snap::raw::Encoder::new().compress_vec(&[b'x'; 16 * 1024]).unwrap();
+        assert!(bomb.len() < 1024);
+        let status = push(&state, &[(header::CONTENT_TYPE, "application/x-protobuf")], bomb).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let body = compress("gzip", &[b' '; 4096]).await;
+        let headers = [(header::CONTENT_TYPE, "application/json"), (header::CONTENT_ENCODING, "gzip")];
+        assert_eq!(push(&state, &headers, body).await, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,32 +1,25 @@
-mod http_handler;
-mod file_ingestion;
-mod tcp_ingestion;
-mod udp_ingestion;
-mod forwarder;
-mod models;
-mod config;
-mod syslog_ingestion;
-mod docker_ingestion;
-mod awscloudwatch;
-
-// mod kafka_ingestion;
-
-use axum::Router;
 use tokio::{net::TcpListener, sync::mpsc, task};
 use std::{sync::Arc, path::PathBuf};
 use tracing::info;
-use config::Config;
-use http_handler::{ingest_log, start_log_processor};
-use http_handler::AppState;
-use file_ingestion::{watch_log_files, FileIngestionConfig};
-use tcp_ingestion::start_tcp_server;
-use udp_ingestion::start_udp_listener;
-use syslog_ingestion::start_syslog_listener;
- use docker_ingestion::{start_docker_log_ingestion, DockerIngestionConfig};
- use awscloudwatch::{start_aws_log_ingestion, AWSCloudWatchConfig};
-
-// use kafka_ingestion::start_kafka_listener;
-
+use log_collector::{app, otlp_http_app, models};
+use log_collector::config::Config;
+use log_collector::dead_letter::{DeadLetterConfig, DeadLetterStore};
+use log_collector::batcher::{start_log_processor, BatchConfig};
+use log_collector::forwarder::Forwarder;
+use log_collector::grpc_forwarder::GrpcForwarder;
+use log_collector::http_handler::AppState;
+use log_collector::file_ingestion::{watch_log_files, FileIngestionConfig};
+use log_collector::tcp_ingestion::start_tcp_server;
+use log_collector::udp_ingestion::start_udp_listener;
+use log_collector::syslog_ingestion::start_syslog_listener;
+use log_collector::docker_ingestion::{start_docker_log_ingestion, DockerIngestionConfig};
+use log_collector::awscloudwatch::{start_aws_log_ingestion, AWSCloudWatchConfig};
+use log_collector::googlelog::{start_gcp_log_ingestion, GCPLoggingConfig};
+use log_collector::splunk_hec::HecState;
+use log_collector::otlp_ingestion::start_otlp_grpc_server;
+use log_collector::azurelog::{start_azure_log_ingestion, AzureColumnMapping, AzureCredentials, AzureLogConfig};
+#[cfg(feature = "kafka")]
+use log_collector::kafka_ingestion::start_kafka_listener;
 
 #[tokio::main]
 async fn main() {
@@ -34,17 +27,98 @@ async fn main() {
 
     let config = Config::new();
     let (tx, rx) = mpsc::channel::<models::LogEntry>(10_000);
-    let state = Arc::new(AppState { sender: tx.clone() });
-
-   
-
-     // 🔹 Start AWS CloudWatch Log Ingestion
-     let aws_config = Arc::new(AWSCloudWatchConfig {
-        log_group_name: "your-log-group-name".to_string(), // Change this
-        log_stream_name: Some("your-log-stream-name".to_string()), // Change this
+    let dead_letter = Arc::new(
+        DeadLetterStore::open(DeadLetterConfig {
+            path: config.dead_letter_path.clone(),
+            max_entries: config.dead_letter_max_entries,
+            ..Default::default()
+        })
+        .await,
+    );
+    let state = Arc::new(AppState {
+        sender: tx.clone(),
+        max_body_bytes: config.max_body_bytes,
+        max_event_bytes: config.max_event_bytes,
+        queue_timeout: std::time::Duration::from_millis(config.queue_timeout_ms),
+        strict_by_default: config.strict_batches,
+        dead_letter: dead_letter.clone(),
     });
 
-    task::spawn(start_aws_log_ingestion(aws_config, tx.clone()));
+    // 🔹 Start AWS CloudWatch Log Ingestion (only when a log group is configured)
+    if let Ok(log_group_name) = std::env::var("AWS_CLOUDWATCH_LOG_GROUP") {
+        let aws_config = Arc::new(AWSCloudWatchConfig {
+            log_group_name,
+            log_stream_name: std::env::var("AWS_CLOUDWATCH_LOG_STREAM").ok(),
+            region: std::env::var("AWS_CLOUDWATCH_REGION").ok(),
+            endpoint_url: std::env::var("AWS_CLOUDWATCH_ENDPOINT_URL").ok(), // e.g. LocalStack
+            profile_name: std::env::var("AWS_CLOUDWATCH_PROFILE").ok(),
+            role_arn: std::env::var("AWS_CLOUDWATCH_ROLE_ARN").ok(),
+            role_session_name: std::env::var("AWS_CLOUDWATCH_ROLE_SESSION_NAME").ok(),
+            external_id: std::env::var("AWS_CLOUDWATCH_EXTERNAL_ID").ok(),
+            cursor_path: Some(PathBuf::from(
+                std::env::var("AWS_CLOUDWATCH_CURSOR_PATH").unwrap_or_else(|_| "./state/cloudwatch_cursor.json".to_string()),
+            )),
+            ..Default::default()
+        });
+        task::spawn(start_aws_log_ingestion(aws_config, tx.clone()));
+    }
+
+    // 🔹 Start GCP Cloud Logging Ingestion (only when a project is configured)
+    if let Ok(project_id) = std::env::var("GCP_LOGGING_PROJECT_ID") {
+        let gcp_config = Arc::new(GCPLoggingConfig {
+            project_id,
+            filter: std::env::var("GCP_LOGGING_FILTER").ok(),
+            endpoint_url: std::env::var("GCP_LOGGING_ENDPOINT_URL").ok(), // e.g. local emulator
+            anonymous: std::env::var("GCP_LOGGING_ANONYMOUS").is_ok_and(|v| v == "true"),
+            cursor_path: Some(PathBuf::from(
+                std::env::var("GCP_LOGGING_CURSOR_PATH").unwrap_or_else(|_| "./state/gcp_cursor.json".to_string()),
+            )),
+            ..Default::default()
+        });
+        task::spawn(start_gcp_log_ingestion(gcp_config, tx.clone()));
+    }
+
+    // 🔹 Start Azure Log Analytics Ingestion (only when a workspace is configured)
+    if let Ok(workspace_id) = std::env::var("AZURE_LOG_WORKSPACE_ID") {
+        let credentials = match (
+            std::env::var("AZURE_TENANT_ID"),
+            std::env::var("AZURE_CLIENT_ID"),
+            std::env::var("AZURE_CLIENT_SECRET"),
+        ) {
+            (Ok(tenant_id), Ok(client_id), Ok(client_secret)) => {
+                AzureCredentials::ClientSecret { tenant_id, client_id, client_secret }
+            }
+            (_, client_id, _) => AzureCredentials::ManagedIdentity { client_id: client_id.ok() },
+        };
+        let defaults = AzureColumnMapping::default();
+        let azure_config = Arc::new(AzureLogConfig {
+            workspace_id,
+            query: std::env::var("AZURE_LOG_QUERY").unwrap_or_else(|_| "AppTraces".to_string()),
+            credentials,
+            columns: AzureColumnMapping {
+                timestamp: std::env::var("AZURE_LOG_TIMESTAMP_COLUMN").unwrap_or(defaults.timestamp),
+                message: std::env::var("AZURE_LOG_MESSAGE_COLUMN").unwrap_or(defaults.message),
+                level: std::env::var("AZURE_LOG_LEVEL_COLUMN").ok().or(defaults.level),
+                source: std::env::var("AZURE_LOG_SOURCE_COLUMN").ok().or(defaults.source),
+                ..defaults
+            },
+            endpoint_url: std::env::var("AZURE_LOG_ENDPOINT_URL").ok(),
+            watermark_path: Some(PathBuf::from(
+                std::env::var("AZURE_LOG_WATERMARK_PATH").unwrap_or_else(|_| "./state/azure_watermark.json".to_string()),
+            )),
+            ..Default::default()
+        });
+        task::spawn(start_azure_log_ingestion(azure_config, tx.clone()));
+    }
+
+    // 🔹 Start Kafka Ingestion (only in `kafka` builds, and when brokers are configured)
+    #[cfg(feature = "kafka")]
+    if let Ok(brokers) = std::env::var("KAFKA_BROKERS") {
+        let topic = std::env::var("KAFKA_TOPIC").unwrap_or_else(|_| "logs".to_string());
+        let group_id = std::env::var("KAFKA_GROUP_ID").unwrap_or_else(|_| "log-collector".to_string());
+        let (sender, dead_letter) = (tx.clone(), dead_letter.clone());
+        task::spawn(async move { start_kafka_listener(&brokers, &topic, &group_id, sender, dead_letter).await });
+    }
 
     let docker_config = Arc::new(DockerIngestionConfig {
         container_name: "test-container".to_string(),
@@ -53,27 +127,70 @@ async fn main() {
     task::spawn(start_docker_log_ingestion(docker_config, tx.clone()));
     // Start UDP log ingestion
     let udp_port = "0.0.0.0:5051";
-    task::spawn(start_udp_listener(udp_port, tx.clone()));
+    task::spawn(start_udp_listener(udp_port, tx.clone(), dead_letter.clone()));
 
     // Start Syslog ingestion (UDP port 514 is default for Syslog)
     let syslog_port = "0.0.0.0:514";
-    task::spawn(start_syslog_listener(syslog_port, tx.clone()));
+    task::spawn(start_syslog_listener(syslog_port, tx.clone(), dead_letter.clone()));
 
 
     // 🔹 Start File Log Ingestion
     let file_config = Arc::new(FileIngestionConfig {
         log_directory: PathBuf::from("./logs"),
     });
-    task::spawn(watch_log_files(file_config, tx.clone()));
+    task::spawn(watch_log_files(file_config, tx.clone(), dead_letter.clone()));
 
     // 🔹 Start TCP Log Server
-    task::spawn(start_tcp_server("0.0.0.0:5050", tx.clone()));
+    task::spawn(start_tcp_server("0.0.0.0:5050", tx.clone(), dead_letter.clone()));
+
+    // 🔹 Start OTLP gRPC Receiver (standard port 4317)
+    task::spawn(start_otlp_grpc_server("0.0.0.0:4317", tx.clone(), state.queue_timeout));
+
+    // 🔹 Start OTLP HTTP Receiver (standard port 4318, protobuf + JSON)
+    let otlp_app = otlp_http_app(state.clone());
+    task::spawn(async move {
+        let listener = TcpListener::bind("0.0.0.0:4318").await.unwrap();
+        info!("📡 OTLP HTTP receiver listening on http://0.0.0.0:4318");
+        axum::serve(listener, otlp_app).await.unwrap();
+    });
 
     // 🔹 Start Log Processor
-    task::spawn(start_log_processor(rx, config.processor_url));
+    let batch_config = BatchConfig {
+        max_events: config.batch_max_events,
+        max_bytes: config.batch_max_bytes,
+        max_linger: std::time::Duration::from_millis(config.batch_max_linger_ms),
+        max_in_flight: config.batch_max_in_flight,
+        format: config.forwarder_wire_format,
+    };
+    let forwarder = match config.processor_grpc_url {
+        Some(url) => {
+            info!("📡 Forwarding batches over gRPC to {}", url);
+            Forwarder::Grpc(GrpcForwarder::new(url, config.forwarder_stream_state_path.clone()))
+        }
+        None => {
+            // One pooled client for the lifetime of the process, so connections and TLS sessions are reused
+            let client = config.forwarder_client.build().expect("⚠️ Invalid forwarder HTTP client settings");
+            Forwarder::Http { client, url: config.processor_url }
+        }
+    };
+    task::spawn(start_log_processor(rx, forwarder, batch_config, dead_letter.clone()));
+
+    // 🔹 Splunk HEC state (tokens from HEC_TOKENS, comma-separated)
+    let hec_tokens = std::env::var("HEC_TOKENS")
+        .map(|tokens| tokens.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+        .unwrap_or_default();
+    let hec_ack_enabled = std::env::var("HEC_ACK_ENABLED").is_ok_and(|v| v == "true");
+    let hec_state = Arc::new(HecState::new(
+        tx.clone(),
+        hec_tokens,
+        hec_ack_enabled,
+        std::time::Duration::from_millis(config.queue_timeout_ms),
+        config.max_body_bytes,
+        dead_letter,
+    ));
 
     // 🔹 Define HTTP API routes
-    let app = Router::new().route("/logs", axum::routing::post(ingest_log)).with_state(state);
+    let app = app(state, hec_state);
 
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
     info!("🚀 Log Collector running on http://0.0.0.0:3000");
@@ -1,5 +1,7 @@
-use chrono::{Utc, DateTime};
+use chrono::Utc;
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
@@ -8,6 +10,8 @@ pub struct LogEntry {
     pub message: String,
     #[serde(default = "default_timestamp")]  // ✅ Auto-fill timestamp if missing
     pub timestamp: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]  // ✅ Structured fields (labels, JSON payload keys, ...)
+    pub attributes: HashMap<String, Value>,
 }
 
 fn default_timestamp() -> String {
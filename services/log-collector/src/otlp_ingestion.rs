@@ -0,0 +1,388 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    logs_service_server::{LogsService, LogsServiceServer},
+    ExportLogsPartialSuccess, ExportLogsServiceRequest, ExportLogsServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+use prost::Message;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use tonic::transport::Server;
+use tracing::{info, error};
+use crate::{dead_letter::{DeadLetterReason, Protocol}, http_handler::{send_all, AppState}, models::LogEntry};
+use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+const JSON_CONTENT_TYPE: &str = "application/json";
+// Attributes the collector fills from the record itself; a record attribute with one of these
+// names is kept as `attributes.<name>` instead
+const RESERVED_ATTRIBUTES: [&str; 3] = ["severity_number", "trace_id", "span_id"];
+
+// ✅ OTLP/gRPC LogsService backed by the collector's log queue
+pub struct OtlpLogsService {
+    sender: mpsc::Sender<LogEntry>,
+    queue_timeout: Duration, // How long an export waits for queue capacity before UNAVAILABLE
+}
+
+#[tonic::async_trait]
+impl LogsService for OtlpLogsService {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportLogsServiceRequest>,
+    ) -> Result<tonic::Response<ExportLogsServiceResponse>, tonic::Status> {
+        let (response, all_rejected) = forward_export(request.into_inner(), &self.sender, self.queue_timeout).await;
+        if all_rejected {
+            return Err(tonic::Status::unavailable("log queue is full"));
+        }
+        Ok(tonic::Response::new(response))
+    }
+}
+
+pub async fn start_otlp_grpc_server(addr: &str, sender: mpsc::Sender<LogEntry>, queue_timeout: Duration) {
+    let addr = addr.parse().expect("⚠️ Invalid OTLP gRPC address");
+    info!("📡 OTLP gRPC receiver listening on {}", addr);
+
+    if let Err(e) = Server::builder()
+        .add_service(LogsServiceServer::new(OtlpLogsService { sender, queue_timeout }))
+        .serve(addr)
+        .await
+    {
+        error!("❌ OTLP gRPC server error: {}", e);
+    }
+}
+
+// ✅ OTLP/HTTP `/v1/logs` handler (binary protobuf or JSON, answered in the same encoding)
+pub async fn ingest_otlp_logs(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with(JSON_CONTENT_TYPE));
+
+    let request = match decode_export(is_json, &body) {
+        Ok(request) => request,
+        Err(e) => {
+            error!("❌ Failed to decode OTLP logs request: {}", e);
+            let protocol = if is_json { Protocol::OtlpJson } else { Protocol::OtlpProtobuf };
+            state.dead_letter.record("http:/v1/logs", protocol, DeadLetterReason::ParseError, e.clone(), &body).await;
+            return (StatusCode::BAD_REQUEST, e).into_response();
+        }
+    };
+
+    let (response, all_rejected) = forward_export(request, &state.sender, state.queue_timeout).await;
+    let status = if all_rejected {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    if is_json {
+        let body = serde_json::to_vec(&response).unwrap_or_default();
+        (status, [(header::CONTENT_TYPE, JSON_CONTENT_TYPE)], body).into_response()
+    } else {
+        (status, [(header::CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)], response.encode_to_vec()).into_response()
+    }
+}
+
+pub(crate) fn decode_export(is_json: bool, body: &[u8]) -> Result<ExportLogsServiceRequest, String> {
+    if is_json {
+        serde_json::from_slice(body).map_err(|e| e.to_string())
+    } else {
+        ExportLogsServiceRequest::decode(body).map_err(|e| e.to_string())
+    }
+}
+
+// ✅ Queues every record of an export request all-or-nothing, waiting up to `queue_timeout` for
+// room; a request that didn't fit is reported as rejected in full (partial success), and the flag
+// tells callers to answer with a retryable error. Nothing was queued then, so the retry can't duplicate.
+async fn forward_export(
+    request: ExportLogsServiceRequest,
+    sender: &mpsc::Sender<LogEntry>,
+    queue_timeout: Duration,
+) -> (ExportLogsServiceResponse, bool) {
+    let entries = export_request_to_entries(request);
+    let total = entries.len();
+    if send_all(sender, entries, queue_timeout).await {
+        return (ExportLogsServiceResponse { partial_success: None }, false);
+    }
+
+    error!("❌ Log queue is full, rejecting {} OTLP logs", total);
+    let partial_success = ExportLogsPartialSuccess {
+        rejected_log_records: total as i64,
+        error_message: "log queue is full".to_string(),
+    };
+    (ExportLogsServiceResponse { partial_success: Some(partial_success) }, true)
+}
+
+// ✅ Maps OTLP resource/scope/record fields onto our LogEntry model
+pub(crate) fn export_request_to_entries(request: ExportLogsServiceRequest) -> Vec<LogEntry> {
+    let mut entries = Vec::new();
+
+    for resource_logs in request.resource_logs {
+        let resource_attributes = resource_logs
+            .resource
+            .map(|r| r.attributes)
+            .unwrap_or_default();
+        let service_name = resource_attributes
+            .iter()
+            .find(|kv| kv.key == "service.name")
+            .and_then(|kv| kv.value.as_ref())
+            .map(any_value_to_string);
+
+        for scope_logs in resource_logs.scope_logs {
+            for record in scope_logs.log_records {
+                let mut attributes = HashMap::new();
+                insert_key_values(&mut attributes, "resource.", &resource_attributes);
+                if let Some(scope) = &scope_logs.scope {
+                    if !scope.name.is_empty() {
+                        attributes.insert("scope.name".to_string(), Value::String(scope.name.clone()));
+                    }
+                    if !scope.version.is_empty() {
+                        attributes.insert("scope.version".to_string(), Value::String(scope.version.clone()));
+                    }
+                    insert_key_values(&mut attributes, "scope.", &scope.attributes);
+                }
+                insert_record_attributes(&mut attributes, &record.attributes);
+
+                if record.severity_number != 0 {
+                    attributes.insert("severity_number".to_string(), Value::from(record.severity_number));
+                }
+                if !record.trace_id.is_empty() {
+                    attributes.insert("trace_id".to_string(), Value::String(to_hex(&record.trace_id)));
+                }
+                if !record.span_id.is_empty() {
+                    attributes.insert("span_id".to_string(), Value::String(to_hex(&record.span_id)));
+                }
+
+                let level = if record.severity_text.is_empty() {
+                    severity_number_to_level(record.severity_number).to_string()
+                } else {
+                    record.severity_text
+                };
+
+                let timestamp = [record.time_unix_nano, record.observed_time_unix_nano]
+                    .into_iter()
+                    .find_map(unix_nanos_to_datetime)
+                    .unwrap_or_else(Utc::now)
+                    .to_rfc3339();
+
+                entries.push(LogEntry {
+                    source: service_name.clone().unwrap_or_else(|| "otlp".to_string()),
+                    level,
+                    message: record.body.as_ref().map(any_value_to_string).unwrap_or_default(),
+                    timestamp,
+                    attributes,
+                });
+            }
+        }
+    }
+
+    entries
+}
+
+// OTLP severity numbers come in blocks of four per level (1-4 TRACE ... 21-24 FATAL)
+fn severity_number_to_level(number: i32) -> &'static str {
+    match number {
+        1..=4 => "TRACE",
+        5..=8 => "DEBUG",
+        9..=12 => "INFO",
+        13..=16 => "WARN",
+        17..=20 => "ERROR",
+        21..=24 => "FATAL",
+        _ => "INFO",
+    }
+}
+
+fn insert_key_values(attributes: &mut HashMap<String, Value>, prefix: &str, key_values: &[KeyValue]) {
+    for kv in key_values {
+        if let Some(value) = &kv.value {
+            attributes.insert(format!("{}{}", prefix, kv.key), any_value_to_json(value));
+        }
+    }
+}
+
+// Record attributes never replace the reserved fields, nor the resource/scope ones already set
+fn insert_record_attributes(attributes: &mut HashMap<String, Value>, key_values: &[KeyValue]) {
+    for kv in key_values {
+        let Some(value) = &kv.value else { continue };
+        let key = if RESERVED_ATTRIBUTES.contains(&kv.key.as_str()) || attributes.contains_key(&kv.key) {
+            format!("attributes.{}", kv.key)
+        } else {
+            kv.key.clone()
+        };
+        attributes.insert(key, any_value_to_json(value));
+    }
+}
+
+// 0 means unset; anything past 2262 doesn't fit chrono's nanosecond range
+fn unix_nanos_to_datetime(nanos: u64) -> Option<chrono::DateTime<Utc>> {
+    match i64::try_from(nanos) {
+        Ok(0) | Err(_) => None,
+        Ok(nanos) => Some(Utc.timestamp_nanos(nanos)),
+    }
+}
+
+fn any_value_to_string(value: &AnyValue) -> String {
+    match any_value_to_json(value) {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+fn any_value_to_json(value: &AnyValue) -> Value {
+    match &value.value {
+        Some(any_value::Value::StringValue(s)) => Value::String(s.clone()),
+        Some(any_value::Value::BoolValue(b)) => Value::Bool(*b),
+        Some(any_value::Value::IntValue(i)) => Value::from(*i),
+        Some(any_value::Value::DoubleValue(d)) => Value::from(*d),
+        Some(any_value::Value::ArrayValue(array)) => {
+            Value::Array(array.values.iter().map(any_value_to_json).collect())
+        }
+        Some(any_value::Value::KvlistValue(kvlist)) => Value::Object(
+            kvlist
+                .values
+                .iter()
+                .filter_map(|kv| kv.value.as_ref().map(|v| (kv.key.clone(), any_value_to_json(v))))
+                .collect::<Map<_, _>>(),
+        ),
+        Some(any_value::Value::BytesValue(bytes)) => Value::String(to_hex(bytes)),
+        None => Value::Null,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+
+    fn string_kv(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue { value: Some(any_value::Value::StringValue(value.to_string())) }),
+        }
+    }
+
+    fn entry(record: LogRecord) -> LogEntry {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                scope_logs: vec![ScopeLogs { log_records: vec![record], ..Default::default() }],
+                ..Default::default()
+            }],
+        };
+        export_request_to_entries(request).remove(0)
+    }
+
+    fn export(records: usize) -> ExportLogsServiceRequest {
+        let record = LogRecord { body: Some(AnyValue { value: Some(any_value::Value::StringValue("hi".to_string())) }), ..Default::default() };
+        ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                scope_logs: vec![ScopeLogs { log_records: vec![record; records], ..Default::default() }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    // A queue with room for `capacity` events, `filled` of them already taken
+    fn queue(capacity: usize, filled: usize) -> (mpsc::Sender<LogEntry>, mpsc::Receiver<LogEntry>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        for _ in 0..filled {
+            tx.try_send(entry(LogRecord::default())).unwrap();
+        }
+        (tx, rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn grpc_export_is_unavailable_once_the_queue_timeout_passes() {
+        let (sender, mut rx) = queue(2, 1);
+        let service = OtlpLogsService { sender, queue_timeout: Duration::from_secs(1) };
+        let start = tokio::time::Instant::now();
+
+        // Two records don't fit: nothing is queued and the export fails after the timeout, not never
+        let status = service.export(tonic::Request::new(export(2))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        rx.recv().await.unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // One does
+        let response = service.export(tonic::Request::new(export(1))).await.unwrap().into_inner();
+        assert_eq!(response.partial_success, None);
+        assert_eq!(rx.recv().await.unwrap().message, "hi");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn http_export_reports_rejected_records_when_the_queue_is_full() {
+        let (state, _rx) = AppState::for_tests(1).await;
+        state.sender.try_send(entry(LogRecord::default())).unwrap();
+        let headers = HeaderMap::from_iter([(header::CONTENT_TYPE, PROTOBUF_CONTENT_TYPE.parse().unwrap())]);
+
+        let response = ingest_otlp_logs(State(state), headers, Bytes::from(export(3).encode_to_vec())).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let partial = ExportLogsServiceResponse::decode(body).unwrap().partial_success.unwrap();
+        assert_eq!(partial.rejected_log_records, 3);
+    }
+
+    #[test]
+    fn out_of_range_time_falls_back_to_observed_time() {
+        let log = entry(LogRecord {
+            time_unix_nano: u64::MAX,
+            observed_time_unix_nano: 1_714_564_800_000_000_000,
+            ..Default::default()
+        });
+        assert_eq!(log.timestamp, "2024-05-01T12:00:00+00:00");
+    }
+
+    #[test]
+    fn out_of_range_times_fall_back_to_now() {
+        let before = Utc::now();
+        let log = entry(LogRecord { time_unix_nano: u64::MAX, observed_time_unix_nano: u64::MAX, ..Default::default() });
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&log.timestamp).unwrap();
+        assert!(timestamp >= before && timestamp <= Utc::now());
+    }
+
+    #[test]
+    fn record_attributes_cannot_replace_reserved_fields() {
+        let log = entry(LogRecord {
+            time_unix_nano: 1_714_564_800_000_000_000,
+            severity_number: 17,
+            trace_id: vec![0xab; 16],
+            span_id: vec![0xcd; 8],
+            attributes: vec![
+                string_kv("severity_number", "1"),
+                string_kv("trace_id", "spoofed"),
+                string_kv("span_id", "spoofed"),
+                string_kv("user", "alice"),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(log.attributes["severity_number"], 17);
+        assert_eq!(log.attributes["trace_id"], "ab".repeat(16));
+        assert_eq!(log.attributes["span_id"], "cd".repeat(8));
+        assert_eq!(log.attributes["attributes.severity_number"], "1");
+        assert_eq!(log.attributes["attributes.trace_id"], "spoofed");
+        assert_eq!(log.attributes["user"], "alice");
+    }
+
+    #[test]
+    fn reserved_names_are_namespaced_even_when_unset() {
+        let log = entry(LogRecord { attributes: vec![string_kv("trace_id", "spoofed")], ..Default::default() });
+        assert!(!log.attributes.contains_key("trace_id"));
+        assert_eq!(log.attributes["attributes.trace_id"], "spoofed");
+    }
+}
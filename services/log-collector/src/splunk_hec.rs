@@ -0,0 +1,454 @@
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, Mutex};
+use tracing::error;
+use crate::{body_stream::read_body, dead_letter::{DeadLetterReason, DeadLetterStore, Protocol}, http_handler::send_all, models::LogEntry, utils::flatten_json};
+use chrono::{TimeZone, Utc};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CHANNEL_HEADER: &str = "x-splunk-request-channel";
+const MAX_ACK_CHANNELS: usize = 10_000; // Channels tracked at once; the least recently used goes first
+const MAX_PENDING_ACKS: usize = 10_000; // Unpolled ack IDs kept per channel; the oldest go first
+const ACK_CHANNEL_TTL: Duration = Duration::from_secs(600); // Channels idle this long are forgotten
+
+pub struct HecState {
+    pub sender: mpsc::Sender<LogEntry>,
+    pub tokens: HashSet<String>, // Accepted HEC tokens; empty disables auth
+    pub ack_enabled: bool,
+    pub acks: Mutex<HashMap<String, AckChannel>>, // Per-channel acknowledgement state
+    pub queue_timeout: Duration, // How long to wait for queue capacity before answering "Server is busy"
+    pub max_body_bytes: usize, // Limit on the decoded request body, as for `/logs`
+    pub dead_letter: Arc<DeadLetterStore>,
+}
+
+impl HecState {
+    pub fn new(
+        sender: mpsc::Sender<LogEntry>,
+        tokens: HashSet<String>,
+        ack_enabled: bool,
+        queue_timeout: Duration,
+        max_body_bytes: usize,
+        dead_letter: Arc<DeadLetterStore>,
+    ) -> Self {
+        Self {
+            sender,
+            tokens,
+            ack_enabled,
+            acks: Mutex::new(HashMap::new()),
+            queue_timeout,
+            max_body_bytes,
+            dead_letter,
+        }
+    }
+}
+
+/// Ack state of one channel.
+///
+/// An ack here is weaker than Splunk's indexer acknowledgement: an ID is marked acked once its
+/// events are in the collector's queue, not once they were stored. Events still in the queue or
+/// in a batch can be lost if the collector crashes, and batches the processor keeps refusing end
+/// up in the dead-letter store; forwarders won't resend either.
+pub struct AckChannel {
+    next_ack_id: u64,
+    acked: BTreeSet<u64>,
+    last_used: Instant,
+}
+
+impl Default for AckChannel {
+    fn default() -> Self {
+        Self { next_ack_id: 0, acked: BTreeSet::new(), last_used: Instant::now() }
+    }
+}
+
+// ✅ Makes room for a new channel: idle channels expire, then the least recently used is dropped
+fn evict_ack_channels(acks: &mut HashMap<String, AckChannel>, now: Instant) {
+    acks.retain(|_, channel| now.duration_since(channel.last_used) < ACK_CHANNEL_TTL);
+    while acks.len() >= MAX_ACK_CHANNELS {
+        let Some(oldest) = acks.iter().min_by_key(|(_, c)| c.last_used).map(|(name, _)| name.clone()) else {
+            break;
+        };
+        acks.remove(&oldest);
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct HecParams {
+    channel: Option<String>,
+    host: Option<String>,
+    source: Option<String>,
+    sourcetype: Option<String>,
+    index: Option<String>,
+}
+
+/// One event in the HEC JSON envelope. Events may be concatenated without separators.
+#[derive(Deserialize)]
+struct HecEvent {
+    time: Option<Value>,
+    host: Option<String>,
+    source: Option<String>,
+    sourcetype: Option<String>,
+    index: Option<String>,
+    event: Option<Value>,
+    #[serde(default)]
+    fields: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+pub struct AckRequest {
+    acks: Vec<u64>,
+}
+
+// ✅ HEC error replies use Splunk's numeric codes so forwarders react correctly
+fn hec_error(status: StatusCode, code: u8, text: &str) -> Response {
+    (status, Json(json!({ "text": text, "code": code }))).into_response()
+}
+
+fn hec_error_at(code: u8, text: &str, invalid_event_number: usize) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "text": text, "code": code, "invalid-event-number": invalid_event_number })),
+    )
+        .into_response()
+}
+
+// Decodes the body (forwarders may gzip it) within the configured limit
+async fn read_hec_body(state: &HecState, headers: &HeaderMap, body: Body) -> Result<Vec<u8>, Response> {
+    read_body(body, headers, state.max_body_bytes).await.map_err(|e| {
+        error!("❌ Failed to read HEC request: {}", e);
+        hec_error(e.status(), 6, &e.to_string())
+    })
+}
+
+// Returns the rejection to send when the request isn't authorized
+fn authorize(state: &HecState, headers: &HeaderMap) -> Option<Response> {
+    if state.tokens.is_empty() {
+        return None;
+    }
+
+    let Some(auth) = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) else {
+        return Some(hec_error(StatusCode::UNAUTHORIZED, 2, "Token is required"));
+    };
+    let Some(token) = auth.strip_prefix("Splunk ").map(str::trim) else {
+        return Some(hec_error(StatusCode::UNAUTHORIZED, 3, "Invalid authorization"));
+    };
+    if !state.tokens.contains(token) {
+        return Some(hec_error(StatusCode::FORBIDDEN, 4, "Invalid token"));
+    }
+    None
+}
+
+// Channels come from the `X-Splunk-Request-Channel` header or the `channel` query parameter
+fn request_channel(headers: &HeaderMap, params: &HecParams) -> Option<String> {
+    headers
+        .get(CHANNEL_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| params.channel.clone())
+        .filter(|c| !c.is_empty())
+}
+
+// ✅ `POST /services/collector/event` — batched JSON envelopes
+pub async fn hec_event(
+    State(state): State<Arc<HecState>>,
+    Query(params): Query<HecParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    if let Some(rejection) = authorize(&state, &headers) {
+        return rejection;
+    }
+    let channel = request_channel(&headers, &params);
+    if state.ack_enabled && channel.is_none() {
+        return hec_error(StatusCode::BAD_REQUEST, 10, "Data channel is missing");
+    }
+    let body = match read_hec_body(&state, &headers, body).await {
+        Ok(body) => body,
+        Err(rejection) => return rejection,
+    };
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return hec_error(StatusCode::BAD_REQUEST, 5, "No data");
+    }
+
+    // The whole request is refused, so the whole body is dead-lettered
+    let entries = match parse_hec_events(&body, &params, channel.as_deref()) {
+        Ok(entries) => entries,
+        Err((code, text, i)) => {
+            let error = format!("{} (event #{})", text, i);
+            state.dead_letter.record("http:/services/collector", Protocol::SplunkHec, DeadLetterReason::ParseError, error, &body).await;
+            return hec_error_at(code, text, i);
+        }
+    };
+
+    forward(&state, entries, channel).await
+}
+
+// Parses concatenated HEC envelopes; errors carry the HEC code, text and invalid event number
+pub(crate) fn parse_hec_events(
+    body: &[u8],
+    params: &HecParams,
+    channel: Option<&str>,
+) -> Result<Vec<LogEntry>, (u8, &'static str, usize)> {
+    let mut entries = Vec::new();
+    for (i, event) in serde_json::Deserializer::from_slice(body).into_iter::<HecEvent>().enumerate() {
+        let event = event.map_err(|_| (6, "Invalid data format", i))?;
+        match &event.event {
+            None => return Err((12, "Event field is required", i)),
+            Some(Value::String(s)) if s.is_empty() => return Err((13, "Event field cannot be blank", i)),
+            Some(_) => {}
+        }
+        entries.push(event_to_log_entry(event, params, channel));
+    }
+    Ok(entries)
+}
+
+// ✅ `POST /services/collector/raw` — one event per line, metadata from query parameters
+pub async fn hec_raw(
+    State(state): State<Arc<HecState>>,
+    Query(params): Query<HecParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    if let Some(rejection) = authorize(&state, &headers) {
+        return rejection;
+    }
+    let Some(channel) = request_channel(&headers, &params) else {
+        return hec_error(StatusCode::BAD_REQUEST, 10, "Data channel is missing");
+    };
+    let body = match read_hec_body(&state, &headers, body).await {
+        Ok(body) => body,
+        Err(rejection) => return rejection,
+    };
+
+    let text = String::from_utf8_lossy(&body);
+    let entries: Vec<LogEntry> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let event = HecEvent {
+                time: None,
+                host: None,
+                source: None,
+                sourcetype: None,
+                index: None,
+                event: Some(Value::String(line.to_string())),
+                fields: HashMap::new(),
+            };
+            event_to_log_entry(event, &params, Some(&channel))
+        })
+        .collect();
+    if entries.is_empty() {
+        return hec_error(StatusCode::BAD_REQUEST, 5, "No data");
+    }
+
+    forward(&state, entries, Some(channel)).await
+}
+
+// ✅ `POST /services/collector/ack` — reports which ack IDs on a channel have been queued
+// (not indexed; see `AckChannel`)
+pub async fn hec_ack(
+    State(state): State<Arc<HecState>>,
+    Query(params): Query<HecParams>,
+    headers: HeaderMap,
+    Json(request): Json<AckRequest>,
+) -> Response {
+    if let Some(rejection) = authorize(&state, &headers) {
+        return rejection;
+    }
+    if !state.ack_enabled {
+        return hec_error(StatusCode::BAD_REQUEST, 14, "ACK is disabled");
+    }
+    let Some(channel) = request_channel(&headers, &params) else {
+        return hec_error(StatusCode::BAD_REQUEST, 10, "Data channel is missing");
+    };
+
+    let mut acks = state.acks.lock().await;
+    let Some(ack_channel) = acks.get_mut(&channel) else {
+        return hec_error(StatusCode::BAD_REQUEST, 11, "Invalid data channel");
+    };
+    ack_channel.last_used = Instant::now();
+
+    // Acknowledged IDs are reported once and then forgotten, like Splunk does
+    let statuses: HashMap<String, bool> = request
+        .acks
+        .iter()
+        .map(|id| (id.to_string(), ack_channel.acked.remove(id)))
+        .collect();
+
+    Json(json!({ "acks": statuses })).into_response()
+}
+
+// ✅ `GET /services/collector/health`
+pub async fn hec_health() -> Response {
+    Json(json!({ "text": "HEC is healthy", "code": 17 })).into_response()
+}
+
+async fn forward(state: &HecState, entries: Vec<LogEntry>, channel: Option<String>) -> Response {
+    // All or nothing: forwarders resend the whole request on "Server is busy"
+    if !send_all(&state.sender, entries, state.queue_timeout).await {
+        error!("❌ Log queue is full, rejecting HEC request");
+        return hec_error(StatusCode::SERVICE_UNAVAILABLE, 9, "Server is busy");
+    }
+
+    match channel.filter(|_| state.ack_enabled) {
+        Some(channel) => {
+            let now = Instant::now();
+            let mut acks = state.acks.lock().await;
+            if !acks.contains_key(&channel) {
+                evict_ack_channels(&mut acks, now);
+            }
+            let ack_channel = acks.entry(channel).or_default();
+            ack_channel.last_used = now;
+            let ack_id = ack_channel.next_ack_id;
+            ack_channel.next_ack_id += 1;
+            ack_channel.acked.insert(ack_id);
+            // A forwarder that never polls would otherwise grow this forever
+            while ack_channel.acked.len() > MAX_PENDING_ACKS {
+                ack_channel.acked.pop_first();
+            }
+            Json(json!({ "text": "Success", "code": 0, "ackId": ack_id })).into_response()
+        }
+        None => Json(json!({ "text": "Success", "code": 0 })).into_response(),
+    }
+}
+
+// ✅ Maps HEC `host`/`source`/`sourcetype`/`index`/`fields` onto LogEntry
+fn event_to_log_entry(event: HecEvent, params: &HecParams, channel: Option<&str>) -> LogEntry {
+    let mut attributes = HashMap::new();
+
+    let host = event.host.or_else(|| params.host.clone());
+    let source = event.source.or_else(|| params.source.clone());
+    let sourcetype = event.sourcetype.or_else(|| params.sourcetype.clone());
+    let index = event.index.or_else(|| params.index.clone());
+
+    for (key, value) in [("host", &host), ("sourcetype", &sourcetype), ("index", &index)] {
+        if let Some(value) = value {
+            attributes.insert(key.to_string(), Value::String(value.clone()));
+        }
+    }
+    if let Some(channel) = channel {
+        attributes.insert("hec.channel".to_string(), Value::String(channel.to_string()));
+    }
+    for (key, value) in event.fields {
+        attributes.insert(key, value);
+    }
+
+    let message = match event.event {
+        Some(Value::String(text)) => text,
+        Some(structured @ Value::Object(_)) => {
+            flatten_json("", &structured, &mut attributes);
+            structured
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(|| structured.to_string())
+        }
+        Some(other) => other.to_string(),
+        None => String::new(),
+    };
+
+    let level = ["level", "severity", "log_level"]
+        .iter()
+        .find_map(|key| attributes.get(*key).and_then(Value::as_str))
+        .map(str::to_string)
+        .unwrap_or_else(|| "INFO".to_string());
+
+    // HEC `time` is epoch seconds, possibly fractional, as a number or a string
+    let timestamp = event
+        .time
+        .and_then(|t| t.as_f64().or_else(|| t.as_str().and_then(|s| s.parse().ok())))
+        .and_then(|secs| Utc.timestamp_micros((secs * 1_000_000.0) as i64).single())
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339();
+
+    LogEntry {
+        source: source.or(host).unwrap_or_else(|| "splunk-hec".to_string()),
+        level,
+        message,
+        timestamp,
+        attributes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body_stream::compress;
+    use crate::dead_letter::DeadLetterConfig;
+
+    fn channel(last_used: Instant) -> AckChannel {
+        AckChannel { last_used, ..Default::default() }
+    }
+
+    async fn hec_state(max_body_bytes: usize) -> (Arc<HecState>, mpsc::Receiver<LogEntry>) {
+        let (tx, rx) = mpsc::channel(10);
+        let dead_letter = Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await);
+        (Arc::new(HecState::new(tx, HashSet::new(), false, Duration::from_millis(100), max_body_bytes, dead_letter)), rx)
+    }
+
+    fn gzip_headers() -> HeaderMap {
+        HeaderMap::from_iter([(header::CONTENT_ENCODING, "gzip".parse().unwrap())])
+    }
+
+    #[tokio::test]
+    async fn accepts_gzipped_events() {
+        let (state, mut rx) = hec_state(1024).await;
+        let body = compress("gzip", br#"{"event":"one","host":"web-1"}{"event":{"message":"two","level":"WARN"}}"#).await;
+        let response = hec_event(State(state), Query(HecParams::default()), gzip_headers(), Body::from(body)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let log = rx.recv().await.unwrap();
+        assert_eq!((log.source.as_str(), log.message.as_str()), ("web-1", "one"));
+        let log = rx.recv().await.unwrap();
+        assert_eq!((log.level.as_str(), log.message.as_str()), ("WARN", "two"));
+    }
+
+    #[tokio::test]
+    async fn raw_bodies_are_decoded_within_the_limit() {
+        let (state, mut rx) = hec_state(32).await;
+        let params = || Query(HecParams { channel: Some("c".to_string()), ..Default::default() });
+
+        let body = compress("gzip", b"first line\nsecond\n").await;
+        let response = hec_raw(State(Arc::clone(&state)), params(), gzip_headers(), Body::from(body)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(rx.recv().await.unwrap().message, "first line");
+        assert_eq!(rx.recv().await.unwrap().message, "second");
+
+        let body = compress("gzip", &[b'x'; 33]).await;
+        let response = hec_raw(State(state), params(), gzip_headers(), Body::from(body)).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn idle_ack_channels_expire() {
+        let now = Instant::now() + ACK_CHANNEL_TTL;
+        let mut acks = HashMap::from([
+            ("idle".to_string(), channel(now - ACK_CHANNEL_TTL)),
+            ("active".to_string(), channel(now - Duration::from_secs(1))),
+        ]);
+        evict_ack_channels(&mut acks, now);
+        assert!(acks.contains_key("active"));
+        assert!(!acks.contains_key("idle"));
+    }
+
+    #[test]
+    fn least_recently_used_ack_channel_is_dropped_at_capacity() {
+        let now = Instant::now();
+        let mut acks: HashMap<String, AckChannel> = (0..MAX_ACK_CHANNELS)
+            .map(|i| (format!("channel-{}", i), channel(now - Duration::from_millis(i as u64))))
+            .collect();
+        evict_ack_channels(&mut acks, now);
+        assert_eq!(acks.len(), MAX_ACK_CHANNELS - 1);
+        assert!(!acks.contains_key(&format!("channel-{}", MAX_ACK_CHANNELS - 1)));
+        assert!(acks.contains_key("channel-0"));
+    }
+}
@@ -1,11 +1,14 @@
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tracing::{info, error};
+use crate::dead_letter::{DeadLetterReason, DeadLetterStore, Protocol};
 use crate::models::LogEntry;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::str;
+use std::sync::Arc;
 
-pub async fn start_syslog_listener(addr: &str, sender: mpsc::Sender<LogEntry>) {
+pub async fn start_syslog_listener(addr: &str, sender: mpsc::Sender<LogEntry>, dead_letter: Arc<DeadLetterStore>) {
     let socket = UdpSocket::bind(addr).await.expect("⚠️ Failed to bind Syslog UDP socket");
     let mut buf = vec![0; 2048];
 
@@ -15,10 +18,14 @@ pub async fn start_syslog_listener(addr: &str, sender: mpsc::Sender<LogEntry>) {
         match socket.recv_from(&mut buf).await {
             Ok((size, src)) => {
                 let raw_msg = str::from_utf8(&buf[..size]).unwrap_or("<invalid UTF-8>");
-                let log = parse_syslog(raw_msg, src);
-
-                if let Some(log) = log {
-                    let _ = sender.send(log).await;
+                match parse_syslog(raw_msg, src) {
+                    Some(log) => {
+                        let _ = sender.send(log).await;
+                    }
+                    None => {
+                        let source = format!("syslog:{}", src);
+                        dead_letter.record(source, Protocol::Syslog, DeadLetterReason::ParseError, "invalid syslog format", &buf[..size]).await;
+                    }
                 }
             }
             Err(e) => {
@@ -29,7 +36,7 @@ pub async fn start_syslog_listener(addr: &str, sender: mpsc::Sender<LogEntry>) {
 }
 
 // ✅ Parses raw Syslog message into LogEntry struct
-fn parse_syslog(msg: &str, src: SocketAddr) -> Option<LogEntry> {
+pub(crate) fn parse_syslog(msg: &str, src: SocketAddr) -> Option<LogEntry> {
     let parts: Vec<&str> = msg.splitn(3, ' ').collect();
     
     if parts.len() < 3 {
@@ -42,5 +49,6 @@ fn parse_syslog(msg: &str, src: SocketAddr) -> Option<LogEntry> {
         level: parts[0].to_string(),
         message: parts[2].to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
+        attributes: HashMap::new(),
     })
 }
@@ -2,10 +2,11 @@ use tokio::net::TcpListener;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc;
 use tracing::{info, error};
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
+use crate::dead_letter::{DeadLetterReason, DeadLetterStore, Protocol};
 use crate::models::LogEntry;
 
-pub async fn start_tcp_server(addr: &str, sender: mpsc::Sender<LogEntry>) {
+pub async fn start_tcp_server(addr: &str, sender: mpsc::Sender<LogEntry>, dead_letter: Arc<DeadLetterStore>) {
     let listener = TcpListener::bind(addr).await.expect("❌ Failed to bind TCP server");
     info!("🟢 TCP Log Server listening on {}", addr);
 
@@ -14,25 +15,41 @@ pub async fn start_tcp_server(addr: &str, sender: mpsc::Sender<LogEntry>) {
             Ok((socket, addr)) => {
                 info!("🔌 New TCP connection from {}", addr);
                 let sender_clone = sender.clone();
-                tokio::spawn(handle_tcp_connection(socket, sender_clone));
+                tokio::spawn(handle_tcp_connection(socket, addr, sender_clone, dead_letter.clone()));
             }
             Err(err) => error!("❌ TCP connection error: {}", err),
         }
     }
 }
 
-async fn handle_tcp_connection(socket: tokio::net::TcpStream, sender: mpsc::Sender<LogEntry>) {
-    let reader = BufReader::new(socket);
-    let mut lines = reader.lines();
+async fn handle_tcp_connection(
+    socket: tokio::net::TcpStream,
+    peer: SocketAddr,
+    sender: mpsc::Sender<LogEntry>,
+    dead_letter: Arc<DeadLetterStore>,
+) {
+    let mut reader = BufReader::new(socket);
+    let mut line = Vec::new();
 
-    while let Ok(Some(line)) = lines.next_line().await {
-        match serde_json::from_str::<LogEntry>(&line) {
-            Ok(log) => {
-                if sender.send(log).await.is_err() {
-                    error!("❌ TCP log queue is full, dropping log");
+    // Lines are read as bytes so invalid UTF-8 is dead-lettered instead of closing the connection
+    while let Ok(n) = reader.read_until(b'\n', &mut line).await {
+        if n == 0 {
+            break;
+        }
+        let raw = line.trim_ascii();
+        if !raw.is_empty() {
+            match serde_json::from_slice::<LogEntry>(raw) {
+                Ok(log) => {
+                    if sender.send(log).await.is_err() {
+                        error!("❌ TCP log queue is full, dropping log");
+                    }
+                }
+                Err(e) => {
+                    error!("❌ Failed to parse TCP log: {}", String::from_utf8_lossy(raw));
+                    dead_letter.record(format!("tcp:{}", peer), Protocol::Native, DeadLetterReason::ParseError, e.to_string(), raw).await;
                 }
             }
-            Err(_) => error!("❌ Failed to parse TCP log: {}", line),
         }
+        line.clear();
     }
 }
@@ -1,11 +1,11 @@
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tracing::{info, error};
+use std::sync::Arc;
+use crate::dead_letter::{DeadLetterReason, DeadLetterStore, Protocol};
 use crate::models::LogEntry;
-use serde_json;
-use std::net::SocketAddr;
 
-pub async fn start_udp_listener(addr: &str, sender: mpsc::Sender<LogEntry>) {
+pub async fn start_udp_listener(addr: &str, sender: mpsc::Sender<LogEntry>, dead_letter: Arc<DeadLetterStore>) {
     let socket = UdpSocket::bind(addr).await.expect("⚠️ Failed to bind UDP socket");
     let mut buf = vec![0; 1024];
 
@@ -13,7 +13,7 @@ pub async fn start_udp_listener(addr: &str, sender: mpsc::Sender<LogEntry>) {
 
     loop {
         match socket.recv_from(&mut buf).await {
-            Ok((size, _src)) => {
+            Ok((size, src)) => {
                 let data = String::from_utf8_lossy(&buf[..size]);
                 match serde_json::from_str::<LogEntry>(&data) {
                     Ok(log) => {
@@ -21,6 +21,7 @@ pub async fn start_udp_listener(addr: &str, sender: mpsc::Sender<LogEntry>) {
                     }
                     Err(e) => {
                         error!("❌ Failed to parse UDP log: {}", e);
+                        dead_letter.record(format!("udp:{}", src), Protocol::Native, DeadLetterReason::ParseError, e.to_string(), &buf[..size]).await;
                     }
                 }
             }
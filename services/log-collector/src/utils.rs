@@ -0,0 +1,55 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+// ✅ Flattens nested JSON objects into dotted keys (`{"a":{"b":1}}` -> `a.b = 1`)
+pub fn flatten_json(prefix: &str, value: &Value, out: &mut HashMap<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, nested) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_json(&key, nested, out);
+            }
+        }
+        other => {
+            out.insert(prefix.to_string(), other.clone());
+        }
+    }
+}
+
+// ✅ Loads a source's persisted cursor/watermark, if one was saved by a previous run
+pub async fn load_checkpoint<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("⚠️ Failed to read checkpoint {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    match serde_json::from_slice(&data) {
+        Ok(checkpoint) => Some(checkpoint),
+        Err(e) => {
+            warn!("⚠️ Ignoring corrupt checkpoint {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+// ✅ Persists a cursor/watermark atomically (write to a temp file, then rename over the old one)
+pub async fn save_checkpoint<T: Serialize>(path: &Path, checkpoint: &T) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec(checkpoint)?).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
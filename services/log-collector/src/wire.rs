@@ -0,0 +1,263 @@
+use crate::models::LogEntry;
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// ✅ Binary batch framing for the collector → processor hop (`insightx.logs.v1.LogBatch`)
+//
+// Mirrors `log_processor::wire`, which owns the schema (and documents it): keep the field tags
+// and `SCHEMA_VERSION` in step with it. A processor that can't read a format or version answers
+// 415 and the forwarder falls back to JSON.
+
+pub const SCHEMA_VERSION: u32 = 1;
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf; proto=insightx.logs.v1.LogBatch; version=1";
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+// Protobuf wire types used by the schema
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LEN: u8 = 2;
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, tag: u32, wire_type: u8) {
+    put_varint(buf, u64::from(tag << 3 | u32::from(wire_type)));
+}
+
+fn put_bytes(buf: &mut Vec<u8>, tag: u32, bytes: &[u8]) {
+    put_key(buf, tag, LEN);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+// Encoded size of a length-delimited field (every tag in the schema fits a one-byte key)
+fn bytes_field_len(len: usize) -> usize {
+    1 + varint_len(len as u64) + len
+}
+
+// Scratch buffers reused across a batch: an entry's length prefix is only known once it's written
+#[derive(Default)]
+struct Scratch {
+    entry: Vec<u8>,
+    value: Vec<u8>,
+    json: Vec<u8>,
+}
+
+fn put_value(buf: &mut Vec<u8>, json: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => {
+            put_key(buf, 4, VARINT);
+            put_varint(buf, u64::from(*b));
+        }
+        Value::String(s) => put_bytes(buf, 1, s.as_bytes()),
+        Value::Number(n) if n.is_i64() || n.is_f64() => match n.as_i64() {
+            Some(i) => {
+                put_key(buf, 2, VARINT);
+                put_varint(buf, i as u64);
+            }
+            None => {
+                put_key(buf, 3, FIXED64);
+                buf.extend_from_slice(&n.as_f64().unwrap_or_default().to_bits().to_le_bytes());
+            }
+        },
+        other => {
+            json.clear();
+            let _ = serde_json::to_writer(&mut *json, other);
+            put_bytes(buf, 5, json);
+        }
+    }
+}
+
+fn put_entry(buf: &mut Vec<u8>, log: &LogEntry, scratch: &mut Scratch) {
+    let entry = &mut scratch.entry;
+    entry.clear();
+    // The collector always has a timestamp, level and source, so they're always present
+    put_bytes(entry, 1, log.timestamp.as_bytes());
+    put_bytes(entry, 2, log.level.as_bytes());
+    put_bytes(entry, 4, log.source.as_bytes());
+    if !log.message.is_empty() {
+        put_bytes(entry, 3, log.message.as_bytes());
+    }
+    for (key, value) in &log.attributes {
+        // A map entry is a message of its own: key = 1, value = 2 (left out for null)
+        scratch.value.clear();
+        put_value(&mut scratch.value, &mut scratch.json, value);
+        let mut len = bytes_field_len(key.len());
+        if !scratch.value.is_empty() {
+            len += bytes_field_len(scratch.value.len());
+        }
+        put_key(entry, 5, LEN);
+        put_varint(entry, len as u64);
+        put_bytes(entry, 1, key.as_bytes());
+        if !scratch.value.is_empty() {
+            put_bytes(entry, 2, &scratch.value);
+        }
+    }
+    put_bytes(buf, 2, entry);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    Json,
+    #[default]
+    Protobuf,
+}
+
+impl WireFormat {
+    // `json` or `protobuf` (FORWARDER_WIRE_FORMAT)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Some(WireFormat::Json),
+            "protobuf" | "proto" => Some(WireFormat::Protobuf),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireFormat::Json => JSON_CONTENT_TYPE,
+            WireFormat::Protobuf => PROTOBUF_CONTENT_TYPE,
+        }
+    }
+}
+
+// How long a processor that refused protobuf is sent JSON before protobuf is offered again
+const FALLBACK_PERIOD: Duration = Duration::from_secs(600);
+
+// ✅ The configured format until the processor answers 415, then JSON for `FALLBACK_PERIOD`,
+// so a processor upgraded mid-rollout is picked up again without restarting the collector
+pub struct Negotiated {
+    preferred: WireFormat,
+    json_until: Mutex<Option<Instant>>,
+}
+
+impl Negotiated {
+    pub fn new(preferred: WireFormat) -> Self {
+        Self { preferred, json_until: Mutex::new(None) }
+    }
+
+    pub fn current(&self) -> WireFormat {
+        let json_until = self.json_until.lock().unwrap_or_else(|e| e.into_inner());
+        match *json_until {
+            Some(until) if Instant::now() < until => WireFormat::Json,
+            _ => self.preferred,
+        }
+    }
+
+    pub fn rejected(&self, format: WireFormat) {
+        if format != WireFormat::Json {
+            *self.json_until.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + FALLBACK_PERIOD);
+        }
+    }
+}
+
+// ✅ Encodes a batch for the processor (before compression)
+pub fn encode_batch(format: WireFormat, logs: &[LogEntry]) -> Vec<u8> {
+    match format {
+        WireFormat::Json => serde_json::to_vec(logs).unwrap_or_default(),
+        WireFormat::Protobuf => {
+            let mut buf = Vec::with_capacity(logs.len() * 256);
+            put_key(&mut buf, 1, VARINT);
+            put_varint(&mut buf, u64::from(SCHEMA_VERSION));
+            let mut scratch = Scratch::default();
+            for log in logs {
+                put_entry(&mut buf, log, &mut scratch);
+            }
+            buf
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log_processor::wire::decode_batch;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    // One attribute of every kind the schema distinguishes, plus the edges of each
+    fn attributes() -> HashMap<String, Value> {
+        serde_json::from_value(json!({
+            "null": null,
+            "string": "caf\u{e9} \u{1f680}",
+            "empty": "",
+            "int": 42,
+            "negative": -9_223_372_036_854_775_808_i64,
+            "beyond_i64": u64::MAX,
+            "float": -0.25,
+            "whole_float": 2.0,
+            "huge_float": 1.5e300,
+            "true": true,
+            "false": false,
+            "array": [1, "two", null, [3.5]],
+            "object": {"nested": {"deeper": [true, {"k": -1}]}, "n": 18_446_744_073_709_551_615_u64},
+        }))
+        .unwrap()
+    }
+
+    fn log(message: &str) -> LogEntry {
+        LogEntry {
+            source: "api".to_string(),
+            level: "WARN".to_string(),
+            message: message.to_string(),
+            timestamp: "2024-05-01T12:00:00.123Z".to_string(),
+            attributes: attributes(),
+        }
+    }
+
+    #[test]
+    fn processor_decodes_every_value_kind() {
+        let logs = vec![log("first"), log(""), LogEntry { attributes: HashMap::new(), ..log("bare") }];
+        for format in [WireFormat::Protobuf, WireFormat::Json] {
+            let decoded = decode_batch(Some(format.content_type()), &encode_batch(format, &logs)).unwrap();
+            assert_eq!(decoded.len(), logs.len(), "{:?}", format);
+            for (sent, received) in logs.iter().zip(&decoded) {
+                assert_eq!(received.timestamp.as_deref(), Some(sent.timestamp.as_str()), "{:?}", format);
+                assert_eq!(received.level.as_deref(), Some(sent.level.as_str()), "{:?}", format);
+                assert_eq!(received.source.as_deref(), Some(sent.source.as_str()), "{:?}", format);
+                assert_eq!(received.message, sent.message, "{:?}", format);
+                // `Value` equality also tells integers and floats apart
+                assert_eq!(received.attributes, sent.attributes, "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn integer_kinds_keep_their_sign_and_width() {
+        let protobuf = encode_batch(WireFormat::Protobuf, &[log("x")]);
+        let decoded = decode_batch(Some(PROTOBUF_CONTENT_TYPE), &protobuf).unwrap();
+        let attributes = &decoded[0].attributes;
+        assert_eq!(attributes["negative"].as_i64(), Some(i64::MIN));
+        assert_eq!(attributes["beyond_i64"].as_u64(), Some(u64::MAX));
+        assert_eq!(attributes["whole_float"].as_f64(), Some(2.0));
+        assert!(attributes["whole_float"].is_f64());
+        assert!(attributes["null"].is_null());
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!(WireFormat::parse("JSON"), Some(WireFormat::Json));
+        assert_eq!(WireFormat::parse("proto"), Some(WireFormat::Protobuf));
+        assert_eq!(WireFormat::parse("avro"), None);
+    }
+
+    #[test]
+    fn falls_back_to_json_after_a_415() {
+        let wire = Negotiated::new(WireFormat::Protobuf);
+        wire.rejected(WireFormat::Json);
+        assert_eq!(wire.current(), WireFormat::Protobuf);
+        wire.rejected(WireFormat::Protobuf);
+        assert_eq!(wire.current(), WireFormat::Json);
+    }
+}
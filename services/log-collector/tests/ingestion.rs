@@ -0,0 +1,939 @@
+// End-to-end ingestion tests: every input runs in-process and forwards through the real
+// log-processor to a mock storage service. Each test asserts that the stored event kept its
+// source, level, message, timestamp and attributes.
+
+use axum::{body::Bytes, extract::State, http::{header, HeaderMap, StatusCode}, routing::post, Json, Router};
+use log_collector::dead_letter::{DeadLetterConfig, DeadLetterFilter, DeadLetterReason, DeadLetterStore};
+use log_collector::batcher::{start_log_processor, BatchConfig};
+use log_collector::forwarder::Forwarder;
+use log_collector::grpc_forwarder::GrpcForwarder;
+use http_client::HttpClientConfig;
+use log_collector::http_handler::AppState;
+use log_collector::models::LogEntry;
+use log_collector::splunk_hec::HecState;
+use log_collector::{app, otlp_http_app};
+use log_collector::file_ingestion::{watch_log_files, FileIngestionConfig};
+use log_collector::awscloudwatch::{start_aws_log_ingestion, AWSCloudWatchConfig};
+use log_collector::googlelog::{start_gcp_log_ingestion, GCPLoggingConfig};
+use log_collector::otlp_ingestion::start_otlp_grpc_server;
+use log_collector::syslog_ingestion::start_syslog_listener;
+use log_collector::tcp_ingestion::start_tcp_server;
+use log_collector::udp_ingestion::start_udp_listener;
+use log_processor::grpc_handler::{start_grpc_server, AckStatus, LogStreamClient, StreamBatch};
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    logs_service_client::LogsServiceClient, ExportLogsServiceRequest,
+};
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prost::Message;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+// The collector holds a batch for at most 1s by default, so allow well beyond one flush
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(20);
+
+type Stored = Arc<Mutex<Vec<Value>>>;
+// Batches a processor received, with their Content-Type
+type Received = Arc<Mutex<Vec<(String, Vec<Value>)>>>;
+
+struct Harness {
+    http_url: String,
+    otlp_http_url: String,
+    tcp_addr: SocketAddr,
+    udp_addr: SocketAddr,
+    syslog_addr: SocketAddr,
+    otlp_grpc_addr: SocketAddr,
+    processor_grpc_url: String,
+    log_dir: tempfile::TempDir,
+    sender: mpsc::Sender<LogEntry>,
+    stored: Stored,
+    client: reqwest::Client,
+}
+
+impl Harness {
+    async fn start() -> Self {
+        // 🔹 Mock storage service capturing everything the processor forwards
+        let stored: Stored = Arc::default();
+        let storage_app = Router::new()
+            .route("/logs", post(store_logs))
+            .with_state(stored.clone());
+        let storage_addr = serve(storage_app).await;
+
+        // 🔹 Log processor, forwarding to the mock storage service
+        let processor_state = Arc::new(
+            log_processor::http_handler::AppState::new(
+                format!("http://{}/logs", storage_addr),
+                &log_processor::processor::PipelineConfig::default(),
+            )
+            .unwrap(),
+        );
+        let processor_addr = serve(log_processor::app(processor_state.clone())).await;
+        let processor_grpc_addr = free_tcp_addr();
+        tokio::spawn(async move { start_grpc_server(&processor_grpc_addr.to_string(), processor_state, None).await });
+
+        // 🔹 Collector queue, in-memory dead-letter store and batch forwarder
+        let (tx, rx) = mpsc::channel::<LogEntry>(10_000);
+        let dead_letter = Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await);
+        let forwarder = Forwarder::Http {
+            client: HttpClientConfig::default().build().unwrap(),
+            url: format!("http://{}/logs", processor_addr),
+        };
+        tokio::spawn(start_log_processor(
+            rx,
+            forwarder,
+            BatchConfig::default(),
+            dead_letter.clone(),
+        ));
+
+        let state = Arc::new(AppState {
+            sender: tx.clone(),
+            max_body_bytes: 64 * 1024 * 1024,
+            max_event_bytes: 1024 * 1024,
+            queue_timeout: Duration::from_secs(1),
+            strict_by_default: false,
+            dead_letter: dead_letter.clone(),
+        });
+        let hec_tokens = HashSet::from(["test-token".to_string()]);
+        let hec_state = Arc::new(HecState::new(tx.clone(), hec_tokens, false, Duration::from_secs(1), 64 * 1024 * 1024, dead_letter.clone()));
+        let http_addr = serve(app(state.clone(), hec_state)).await;
+        let otlp_http_addr = serve(otlp_http_app(state)).await;
+
+        // 🔹 Socket listeners on free ports
+        let tcp_addr = free_tcp_addr();
+        let otlp_grpc_addr = free_tcp_addr();
+        let udp_addr = free_udp_addr();
+        let syslog_addr = free_udp_addr();
+        spawn_listener(tcp_addr, &tx, &dead_letter, |addr, tx, dl| async move { start_tcp_server(&addr, tx, dl).await });
+        spawn_listener(udp_addr, &tx, &dead_letter, |addr, tx, dl| async move { start_udp_listener(&addr, tx, dl).await });
+        spawn_listener(syslog_addr, &tx, &dead_letter, |addr, tx, dl| async move { start_syslog_listener(&addr, tx, dl).await });
+        spawn_listener(otlp_grpc_addr, &tx, &dead_letter, |addr, tx, _| async move { start_otlp_grpc_server(&addr, tx, Duration::from_secs(1)).await });
+
+        // 🔹 File watcher on a scratch directory
+        let log_dir = tempfile::tempdir().expect("temp log dir");
+        let file_config = Arc::new(FileIngestionConfig {
+            log_directory: log_dir.path().to_path_buf(),
+        });
+        tokio::spawn(watch_log_files(file_config, tx.clone(), dead_letter));
+
+        wait_for_tcp(tcp_addr).await;
+        wait_for_tcp(otlp_grpc_addr).await;
+        wait_for_tcp(processor_grpc_addr).await;
+        // UDP binds and the file watcher have no readiness signal
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        Self {
+            http_url: format!("http://{}", http_addr),
+            otlp_http_url: format!("http://{}", otlp_http_addr),
+            tcp_addr,
+            udp_addr,
+            syslog_addr,
+            otlp_grpc_addr,
+            processor_grpc_url: format!("http://{}", processor_grpc_addr),
+            log_dir,
+            sender: tx,
+            stored,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Waits until the storage service received an event whose message contains `marker`
+    async fn delivered(&self, marker: &str) -> Value {
+        let deadline = tokio::time::Instant::now() + DELIVERY_TIMEOUT;
+        loop {
+            let found = self
+                .stored
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|log| log["message"].as_str().is_some_and(|m| m.contains(marker)))
+                .cloned();
+            if let Some(log) = found {
+                return log;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "event {:?} never reached storage; stored: {:?}",
+                marker,
+                self.stored.lock().unwrap()
+            );
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+async fn store_logs(State(stored): State<Stored>, Json(logs): Json<Vec<Value>>) {
+    stored.lock().unwrap().extend(logs);
+}
+
+async fn legacy_ingest(State(received): State<Received>, headers: HeaderMap, body: Bytes) -> StatusCode {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+    if content_type.starts_with("application/x-protobuf") {
+        received.lock().unwrap().push((content_type, Vec::new()));
+        return StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    }
+    let logs = serde_json::from_slice(&zstd::stream::decode_all(body.as_ref()).unwrap()).unwrap();
+    received.lock().unwrap().push((content_type, logs));
+    StatusCode::OK
+}
+
+async fn serve(app: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn spawn_listener<F, Fut>(addr: SocketAddr, sender: &mpsc::Sender<LogEntry>, dead_letter: &Arc<DeadLetterStore>, start: F)
+where
+    F: FnOnce(String, mpsc::Sender<LogEntry>, Arc<DeadLetterStore>) -> Fut,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(start(addr.to_string(), sender.clone(), dead_letter.clone()));
+}
+
+fn free_tcp_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn free_udp_addr() -> SocketAddr {
+    std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+async fn wait_for_tcp(addr: SocketAddr) {
+    for _ in 0..50 {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("listener on {} never came up", addr);
+}
+
+fn sample_entry(marker: &str) -> Value {
+    json!({
+        "source": "checkout-api",
+        "level": "warn",
+        "message": format!("payment retry {}", marker),
+        "timestamp": "2024-05-01T12:00:00Z",
+        "attributes": { "order_id": 42, "region": "eu-west-1" },
+    })
+}
+
+// Asserts the fields written by `sample_entry` survived the pipeline
+fn assert_sample_entry(log: &Value, marker: &str) {
+    assert_eq!(log["source"], "checkout-api");
+    assert_eq!(log["level"], "WARN"); // the processor normalizes levels to upper case
+    assert_eq!(log["message"], format!("payment retry {}", marker));
+    assert_eq!(log["timestamp"], "2024-05-01T12:00:00Z");
+    assert_eq!(log["attributes"]["order_id"], 42);
+    assert_eq!(log["attributes"]["region"], "eu-west-1");
+}
+
+fn string_kv(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+fn otlp_request(marker: &str) -> ExportLogsServiceRequest {
+    ExportLogsServiceRequest {
+        resource_logs: vec![ResourceLogs {
+            resource: Some(Resource {
+                attributes: vec![string_kv("service.name", "cart")],
+                ..Default::default()
+            }),
+            scope_logs: vec![ScopeLogs {
+                scope: Some(InstrumentationScope {
+                    name: "cart.checkout".to_string(),
+                    ..Default::default()
+                }),
+                log_records: vec![LogRecord {
+                    time_unix_nano: 1_700_000_000_000_000_000,
+                    severity_number: 17,
+                    body: Some(AnyValue {
+                        value: Some(any_value::Value::StringValue(format!("cart failed {}", marker))),
+                    }),
+                    attributes: vec![string_kv("user.id", "u-7")],
+                    trace_id: vec![0xab; 16],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    }
+}
+
+fn assert_otlp_entry(log: &Value) {
+    assert_eq!(log["source"], "cart");
+    assert_eq!(log["level"], "ERROR");
+    assert_eq!(log["timestamp"], "2023-11-14T22:13:20+00:00");
+    assert_eq!(log["attributes"]["resource.service.name"], "cart");
+    assert_eq!(log["attributes"]["scope.name"], "cart.checkout");
+    assert_eq!(log["attributes"]["user.id"], "u-7");
+    assert_eq!(log["attributes"]["trace_id"], "ab".repeat(16));
+}
+
+#[tokio::test]
+async fn http_json_single_and_array() {
+    let harness = Harness::start().await;
+
+    let response = harness
+        .client
+        .post(format!("{}/logs", harness.http_url))
+        .json(&sample_entry("http-single"))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let batch = json!([sample_entry("http-array-1"), sample_entry("http-array-2")]);
+    let response = harness
+        .client
+        .post(format!("{}/logs", harness.http_url))
+        .json(&batch)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["accepted"], 2);
+
+    for marker in ["http-single", "http-array-1", "http-array-2"] {
+        assert_sample_entry(&harness.delivered(marker).await, marker);
+    }
+}
+
+#[tokio::test]
+async fn http_ndjson_compressed() {
+    let harness = Harness::start().await;
+
+    let ndjson = format!("{}\n{}\n", sample_entry("ndjson-zstd-1"), sample_entry("ndjson-zstd-2"));
+    let compressed = zstd::stream::encode_all(ndjson.as_bytes(), 0).unwrap();
+    let response = harness
+        .client
+        .post(format!("{}/logs", harness.http_url))
+        .header("Content-Type", "application/x-ndjson")
+        .header("Content-Encoding", "zstd")
+        .body(compressed)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["accepted"], 2);
+
+    for marker in ["ndjson-zstd-1", "ndjson-zstd-2"] {
+        assert_sample_entry(&harness.delivered(marker).await, marker);
+    }
+}
+
+#[tokio::test]
+async fn tcp_lines() {
+    let harness = Harness::start().await;
+
+    let mut stream = TcpStream::connect(harness.tcp_addr).await.unwrap();
+    let line = format!("{}\n", sample_entry("tcp"));
+    stream.write_all(line.as_bytes()).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    assert_sample_entry(&harness.delivered("tcp").await, "tcp");
+}
+
+#[tokio::test]
+async fn dead_letter_inspect_and_replay() {
+    let harness = Harness::start().await;
+
+    let mut stream = TcpStream::connect(harness.tcp_addr).await.unwrap();
+    stream.write_all(b"{\"source\": \"checkout-api\", \"message\": \"truncated\n").await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    // The malformed line is kept with its raw bytes and where it came from
+    let deadline = tokio::time::Instant::now() + DELIVERY_TIMEOUT;
+    let listing = loop {
+        let listing: Value = harness
+            .client
+            .get(format!("{}/dead-letter?source=tcp", harness.http_url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if listing["matched"] == 1 {
+            break listing;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "malformed line never dead-lettered");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    let id = listing["entries"][0]["id"].as_u64().unwrap();
+    assert_eq!(listing["entries"][0]["reason"], "parse_error");
+
+    let entry: Value = harness
+        .client
+        .get(format!("{}/dead-letter/{}", harness.http_url, id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(entry["raw"], "{\"source\": \"checkout-api\", \"message\": \"truncated");
+    assert!(entry["source"].as_str().unwrap().starts_with("tcp:127.0.0.1:"));
+
+    // As stored it still doesn't parse, so replay needs a corrected body
+    let response = harness
+        .client
+        .post(format!("{}/dead-letter/{}/replay", harness.http_url, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 422);
+
+    let response = harness
+        .client
+        .post(format!("{}/dead-letter/{}/replay", harness.http_url, id))
+        .json(&sample_entry("dead-letter"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_sample_entry(&harness.delivered("dead-letter").await, "dead-letter");
+
+    let response = harness
+        .client
+        .get(format!("{}/dead-letter/{}", harness.http_url, id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn forwarder_falls_back_to_json() {
+    // 🔹 A processor that predates the binary format: 415 for protobuf, JSON accepted
+    let received: Received = Arc::default();
+    let legacy_processor = Router::new()
+        .route("/logs", post(legacy_ingest))
+        .with_state(received.clone());
+    let processor_addr = serve(legacy_processor).await;
+
+    let (tx, rx) = mpsc::channel::<LogEntry>(16);
+    let forwarder = Forwarder::Http {
+        client: HttpClientConfig::default().build().unwrap(),
+        url: format!("http://{}/logs", processor_addr),
+    };
+    tokio::spawn(start_log_processor(
+        rx,
+        forwarder,
+        BatchConfig { max_events: 1, max_in_flight: 1, ..BatchConfig::default() },
+        Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await),
+    ));
+
+    for marker in ["first", "second"] {
+        let entry: LogEntry = serde_json::from_value(sample_entry(marker)).unwrap();
+        tx.send(entry).await.unwrap();
+    }
+
+    let deadline = tokio::time::Instant::now() + DELIVERY_TIMEOUT;
+    while received.lock().unwrap().len() < 3 {
+        assert!(tokio::time::Instant::now() < deadline, "batches never delivered: {:?}", received.lock().unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // Protobuf is offered once, the same batch is resent as JSON, and later batches go straight to JSON
+    let received = received.lock().unwrap();
+    let content_types: Vec<&str> = received.iter().map(|(ct, _)| ct.as_str()).collect();
+    assert!(content_types[0].starts_with("application/x-protobuf"), "{:?}", content_types);
+    assert_eq!(content_types[1..], ["application/json", "application/json"]);
+    assert_eq!(received[1].1[0]["message"], "payment retry first");
+    assert_eq!(received[2].1[0]["message"], "payment retry second");
+    assert_eq!(received[2].1[0]["attributes"]["order_id"], 42);
+}
+
+#[tokio::test]
+async fn processor_grpc_stream() {
+    let harness = Harness::start().await;
+    let batch = |sequence: u64, marker: &str| {
+        let entry: log_processor::models::LogEntry = serde_json::from_value(sample_entry(marker)).unwrap();
+        StreamBatch::encode("collector-1", sequence, &[entry]).unwrap()
+    };
+
+    let mut client = LogStreamClient::connect(harness.processor_grpc_url.clone()).await.unwrap();
+    let batches = vec![
+        batch(1, "grpc-one"),
+        batch(2, "grpc-two"),
+        batch(1, "grpc-one"), // resent: already applied
+        batch(0, "grpc-zero"),
+        StreamBatch { stream_id: "collector-1".to_string(), sequence: 3, batch: b"\x12\x09".to_vec(), oldest_unacked: 0 },
+    ];
+    let mut acks = client.stream(futures_util::stream::iter(batches)).await.unwrap();
+    let mut statuses = Vec::new();
+    while let Some(ack) = acks.message().await.unwrap() {
+        statuses.push((ack.sequence, ack.status()));
+    }
+    assert_eq!(
+        statuses,
+        [
+            (1, AckStatus::Accepted),
+            (2, AckStatus::Accepted),
+            (1, AckStatus::Duplicate),
+            (0, AckStatus::Rejected),
+            (3, AckStatus::Rejected),
+        ]
+    );
+
+    // Sequences are remembered per stream id across connections, so a resend after reconnecting is a no-op
+    let mut acks = client.stream(futures_util::stream::iter(vec![batch(2, "grpc-two")])).await.unwrap();
+    assert_eq!(acks.message().await.unwrap().unwrap().status(), AckStatus::Duplicate);
+
+    assert_sample_entry(&harness.delivered("grpc-one").await, "grpc-one");
+    assert_sample_entry(&harness.delivered("grpc-two").await, "grpc-two");
+    let stored = harness.stored.lock().unwrap();
+    let copies = stored.iter().filter(|log| log["message"] == "payment retry grpc-one").count();
+    assert_eq!(copies, 1);
+}
+
+#[tokio::test]
+async fn collector_forwards_over_grpc() {
+    let harness = Harness::start().await;
+
+    // A second batcher on the same processor, streaming instead of POSTing
+    let (tx, rx) = mpsc::channel::<LogEntry>(16);
+    let dead_letter = Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await);
+    let forwarder = Forwarder::Grpc(GrpcForwarder::new(harness.processor_grpc_url.clone(), None));
+    let batcher = tokio::spawn(start_log_processor(
+        rx,
+        forwarder,
+        BatchConfig { max_events: 1, ..BatchConfig::default() },
+        dead_letter.clone(),
+    ));
+    for marker in ["grpc-forward-one", "grpc-forward-two", "grpc-forward-three"] {
+        tx.send(serde_json::from_value(sample_entry(marker)).unwrap()).await.unwrap();
+    }
+    drop(tx);
+    batcher.await.unwrap();
+
+    for marker in ["grpc-forward-one", "grpc-forward-two", "grpc-forward-three"] {
+        assert_sample_entry(&harness.delivered(marker).await, marker);
+    }
+    assert!(dead_letter.list(&DeadLetterFilter::default()).await.is_empty());
+}
+
+#[tokio::test]
+async fn grpc_forwarder_dead_letters_when_processor_is_down() {
+    let (tx, rx) = mpsc::channel::<LogEntry>(16);
+    let dead_letter = Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await);
+    let forwarder = Forwarder::Grpc(GrpcForwarder::new(format!("http://{}", free_tcp_addr()), None));
+    let batcher = tokio::spawn(start_log_processor(rx, forwarder, BatchConfig::default(), dead_letter.clone()));
+    tx.send(serde_json::from_value(sample_entry("grpc-down")).unwrap()).await.unwrap();
+    drop(tx);
+    batcher.await.unwrap();
+
+    let entries = dead_letter.list(&DeadLetterFilter::default()).await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].reason, DeadLetterReason::DeliveryFailed);
+    let logs: Vec<LogEntry> = serde_json::from_slice(&entries[0].raw.0).unwrap();
+    assert_eq!(logs[0].message, "payment retry grpc-down");
+}
+
+#[tokio::test]
+async fn udp_datagrams() {
+    let harness = Harness::start().await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let datagram = sample_entry("udp").to_string();
+    socket.send_to(datagram.as_bytes(), harness.udp_addr).await.unwrap();
+
+    assert_sample_entry(&harness.delivered("udp").await, "udp");
+}
+
+#[tokio::test]
+async fn syslog_messages() {
+    let harness = Harness::start().await;
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket
+        .send_to(b"error web-01 disk almost full syslog-marker", harness.syslog_addr)
+        .await
+        .unwrap();
+
+    let log = harness.delivered("syslog-marker").await;
+    assert_eq!(log["level"], "ERROR");
+    assert_eq!(log["message"], "disk almost full syslog-marker");
+    assert_eq!(log["source"], format!("syslog:{}", socket.local_addr().unwrap()));
+    assert!(log["timestamp"].as_str().is_some_and(|ts| !ts.is_empty()));
+}
+
+#[tokio::test]
+async fn file_tailing() {
+    let harness = Harness::start().await;
+
+    let path = harness.log_dir.path().join("app.log");
+    let mut file = tokio::fs::File::create(&path).await.unwrap();
+    file.write_all(format!("{}\n", sample_entry("file")).as_bytes()).await.unwrap();
+    file.flush().await.unwrap();
+
+    assert_sample_entry(&harness.delivered("file").await, "file");
+}
+
+#[tokio::test]
+async fn otlp_http_protobuf() {
+    let harness = Harness::start().await;
+
+    let response = harness
+        .client
+        .post(format!("{}/v1/logs", harness.otlp_http_url))
+        .header("Content-Type", "application/x-protobuf")
+        .body(otlp_request("otlp-http").encode_to_vec())
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    assert_otlp_entry(&harness.delivered("otlp-http").await);
+}
+
+#[tokio::test]
+async fn otlp_grpc_export() {
+    let harness = Harness::start().await;
+
+    let mut client = LogsServiceClient::connect(format!("http://{}", harness.otlp_grpc_addr))
+        .await
+        .unwrap();
+    let response = client.export(otlp_request("otlp-grpc")).await.unwrap().into_inner();
+    assert!(response.partial_success.is_none());
+
+    assert_otlp_entry(&harness.delivered("otlp-grpc").await);
+}
+
+#[tokio::test]
+async fn loki_json_push() {
+    let harness = Harness::start().await;
+
+    let push = json!({
+        "streams": [{
+            "stream": { "job": "billing", "level": "error", "env": "prod" },
+            "values": [["1714564800000000000", "invoice failed loki-marker", { "tenant": "acme" }]],
+        }],
+    });
+    let response = harness
+        .client
+        .post(format!("{}/loki/api/v1/push", harness.http_url))
+        .json(&push)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+
+    let log = harness.delivered("loki-marker").await;
+    assert_eq!(log["source"], "billing");
+    assert_eq!(log["level"], "ERROR");
+    assert_eq!(log["timestamp"], "2024-05-01T12:00:00+00:00");
+    assert_eq!(log["attributes"]["env"], "prod");
+    assert_eq!(log["attributes"]["tenant"], "acme");
+}
+
+#[tokio::test]
+async fn elasticsearch_bulk() {
+    let harness = Harness::start().await;
+
+    let bulk = format!(
+        "{}\n{}\n",
+        json!({ "index": { "_index": "app-logs" } }),
+        json!({
+            "@timestamp": "2024-05-01T12:00:00Z",
+            "message": "slow query es-marker",
+            "log": { "level": "warn" },
+            "service": { "name": "orders" },
+            "duration_ms": 1200,
+        }),
+    );
+    let response = harness
+        .client
+        .post(format!("{}/_bulk", harness.http_url))
+        .header("Content-Type", "application/x-ndjson")
+        .body(bulk)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["errors"], false);
+
+    let log = harness.delivered("es-marker").await;
+    assert_eq!(log["source"], "orders");
+    assert_eq!(log["level"], "WARN");
+    assert_eq!(log["timestamp"], "2024-05-01T12:00:00Z");
+    assert_eq!(log["attributes"]["_index"], "app-logs");
+    assert_eq!(log["attributes"]["duration_ms"], 1200);
+}
+
+// A collector whose queue holds two events and is never drained
+async fn full_queue_app() -> (String, mpsc::Receiver<LogEntry>) {
+    let (tx, rx) = mpsc::channel::<LogEntry>(2);
+    let dead_letter = Arc::new(DeadLetterStore::open(DeadLetterConfig::default()).await);
+    let state = Arc::new(AppState {
+        sender: tx.clone(),
+        max_body_bytes: 1024 * 1024,
+        max_event_bytes: 64 * 1024,
+        queue_timeout: Duration::from_millis(100),
+        strict_by_default: false,
+        dead_letter: dead_letter.clone(),
+    });
+    let hec_state = Arc::new(HecState::new(tx, HashSet::new(), false, Duration::from_millis(100), 64 * 1024 * 1024, dead_letter));
+    (format!("http://{}", serve(app(state, hec_state)).await), rx)
+}
+
+#[tokio::test]
+async fn push_endpoints_are_all_or_nothing() {
+    let (url, mut rx) = full_queue_app().await;
+    let client = reqwest::Client::new();
+    let loki_push = |lines: &[&str]| {
+        let values: Vec<Value> = lines.iter().map(|line| json!(["1714564800000000000", line])).collect();
+        json!({ "streams": [{ "stream": { "job": "billing" }, "values": values }] })
+    };
+
+    // Three events don't fit a queue of two: nothing is queued and the push can be retried as is
+    let response = client
+        .post(format!("{}/loki/api/v1/push", url))
+        .json(&loki_push(&["a", "b", "c"]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert!(rx.try_recv().is_err());
+
+    let bulk: String = ["a", "b", "c"]
+        .iter()
+        .map(|message| format!("{}\n{}\n", json!({ "index": { "_index": "app-logs" } }), json!({ "message": message })))
+        .collect();
+    let response = client
+        .post(format!("{}/_bulk", url))
+        .header("Content-Type", "application/x-ndjson")
+        .body(bulk)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert!(rx.try_recv().is_err());
+
+    let events: String = ["a", "b", "c"].iter().map(|message| json!({ "event": message }).to_string()).collect();
+    let response = client.post(format!("{}/services/collector/event", url)).body(events).send().await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.json::<Value>().await.unwrap()["code"], 9);
+    assert!(rx.try_recv().is_err());
+
+    // Two do fit
+    let response = client
+        .post(format!("{}/loki/api/v1/push", url))
+        .json(&loki_push(&["a", "b"]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    assert_eq!(rx.try_recv().unwrap().message, "a");
+    assert_eq!(rx.try_recv().unwrap().message, "b");
+}
+
+#[tokio::test]
+async fn splunk_hec_event() {
+    let harness = Harness::start().await;
+
+    let event = json!({
+        "time": 1714564800,
+        "host": "web-02",
+        "source": "nginx",
+        "sourcetype": "access_combined",
+        "event": "GET /health 200 hec-marker",
+        "fields": { "datacenter": "fra1" },
+    });
+    let response = harness
+        .client
+        .post(format!("{}/services/collector/event", harness.http_url))
+        .header("Authorization", "Splunk test-token")
+        .json(&event)
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["code"], 0);
+
+    let log = harness.delivered("hec-marker").await;
+    assert_eq!(log["source"], "nginx");
+    assert_eq!(log["level"], "INFO");
+    assert_eq!(log["timestamp"], "2024-05-01T12:00:00+00:00");
+    assert_eq!(log["attributes"]["host"], "web-02");
+    assert_eq!(log["attributes"]["sourcetype"], "access_combined");
+    assert_eq!(log["attributes"]["datacenter"], "fra1");
+}
+
+#[tokio::test]
+async fn gcp_logging_poll() {
+    let harness = Harness::start().await;
+
+    // Mock Cloud Logging API: always returns the same entries, which the cursor must dedupe,
+    // including the one written without an insertId
+    let gcp_app = Router::new().route(
+        "/v2/entries:list",
+        post(|| async {
+            Json(json!({
+                "entries": [{
+                    "logName": "projects/demo/logs/app",
+                    "insertId": "abc123",
+                    "timestamp": "2024-05-01T12:00:00Z",
+                    "severity": "ERROR",
+                    "jsonPayload": { "message": "quota exceeded gcp-marker", "quota": "cpus" },
+                    "resource": { "type": "gce_instance", "labels": { "zone": "us-east1-b" } },
+                    "labels": { "team": "infra" },
+                }, {
+                    "logName": "projects/demo/logs/app",
+                    "timestamp": "2024-05-01T12:00:00Z",
+                    "textPayload": "no insert id gcp-anonymous",
+                }],
+            }))
+        }),
+    );
+    let gcp_addr = serve(gcp_app).await;
+
+    let config = Arc::new(GCPLoggingConfig {
+        project_id: "demo".to_string(),
+        endpoint_url: Some(format!("http://{}", gcp_addr)),
+        anonymous: true,
+        poll_interval: Duration::from_millis(200),
+        ..Default::default()
+    });
+    tokio::spawn(start_gcp_log_ingestion(config, harness.sender.clone()));
+
+    let log = harness.delivered("gcp-marker").await;
+    assert_eq!(log["source"], "projects/demo/logs/app");
+    assert_eq!(log["level"], "ERROR");
+    assert_eq!(log["timestamp"], "2024-05-01T12:00:00+00:00");
+    assert_eq!(log["attributes"]["quota"], "cpus");
+    assert_eq!(log["attributes"]["resource.type"], "gce_instance");
+    assert_eq!(log["attributes"]["resource.labels.zone"], "us-east1-b");
+    assert_eq!(log["attributes"]["labels.team"], "infra");
+
+    harness.delivered("gcp-anonymous").await;
+
+    // Repeated polls (every 200ms) must not re-deliver either entry
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let stored = harness.stored.lock().unwrap();
+    for message in ["quota exceeded gcp-marker", "no insert id gcp-anonymous"] {
+        assert_eq!(stored.iter().filter(|log| log["message"] == message).count(), 1, "{}", message);
+    }
+}
+
+#[tokio::test]
+async fn aws_cloudwatch_poll() {
+    let harness = Harness::start().await;
+    let state_dir = tempfile::tempdir().unwrap();
+    let cursor_path = state_dir.path().join("cloudwatch_cursor.json");
+
+    // Static credentials for the SDK's provider chain; the mock doesn't check signatures
+    std::env::set_var("AWS_ACCESS_KEY_ID", "test");
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", "test");
+
+    // Mock CloudWatch Logs endpoint (LocalStack-style): two pages, the second of which fails the
+    // first time it's asked for. Records the `startTime` of every FilterLogEvents call.
+    let requests: Arc<Mutex<Vec<Value>>> = Arc::default();
+    let cloudwatch_app = Router::new()
+        .route(
+            "/",
+            post(|State(requests): State<Arc<Mutex<Vec<Value>>>>, headers: HeaderMap, body: Bytes| async move {
+                // awsJson1.1 bodies are `application/x-amz-json-1.1`, which `Json` won't accept
+                assert_eq!(headers["x-amz-target"], "Logs_20140328.FilterLogEvents");
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(body["logGroupName"], "app-logs");
+                let second_page = body["nextToken"] == "page-2";
+                let start_time = body["startTime"].as_i64();
+                let failures = {
+                    let mut requests = requests.lock().unwrap();
+                    let failures = requests.iter().filter(|r| r["nextToken"] == "page-2").count();
+                    requests.push(body);
+                    failures
+                };
+
+                let event = |id: &str, timestamp: i64, message: &str| {
+                    json!({ "eventId": id, "logStreamName": "web-1", "timestamp": timestamp, "message": message })
+                };
+                let (status, response) = match (second_page, failures) {
+                    (true, 0) => (
+                        StatusCode::BAD_REQUEST,
+                        json!({ "__type": "InvalidParameterException", "message": "simulated page failure" }),
+                    ),
+                    (true, _) => (
+                        StatusCode::OK,
+                        json!({ "events": [event("ev-2", 1_714_564_801_000, "second page cloudwatch-marker-2")] }),
+                    ),
+                    // Like the real API, nothing older than `startTime` comes back
+                    (false, _) if start_time.is_some_and(|start| start > 1_714_564_800_000) => (
+                        StatusCode::OK,
+                        json!({ "events": [event("ev-2", 1_714_564_801_000, "second page cloudwatch-marker-2")] }),
+                    ),
+                    (false, _) => (
+                        StatusCode::OK,
+                        json!({
+                            "events": [event("ev-1", 1_714_564_800_000, "first page cloudwatch-marker-1")],
+                            "nextToken": "page-2",
+                        }),
+                    ),
+                };
+                (status, [(header::CONTENT_TYPE, "application/x-amz-json-1.1")], response.to_string())
+            }),
+        )
+        .with_state(requests.clone());
+    let cloudwatch_addr = serve(cloudwatch_app).await;
+
+    let config = Arc::new(AWSCloudWatchConfig {
+        log_group_name: "app-logs".to_string(),
+        region: Some("us-east-1".to_string()),
+        endpoint_url: Some(format!("http://{}", cloudwatch_addr)),
+        cursor_path: Some(cursor_path.clone()),
+        poll_interval: Duration::from_millis(200),
+        ..Default::default()
+    });
+    tokio::spawn(start_aws_log_ingestion(config, harness.sender.clone()));
+
+    let log = harness.delivered("cloudwatch-marker-2").await;
+    assert_eq!(log["source"], "app-logs");
+    assert_eq!(log["timestamp"], "2024-05-01T12:00:01+00:00");
+    assert_eq!(log["attributes"]["log_stream"], "web-1");
+    harness.delivered("cloudwatch-marker-1").await;
+
+    // The pass that failed on page 2 must not have moved the start time: the retry starts over
+    // from the beginning, and only a complete pass advances to the newest event
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let start_times: Vec<Value> = requests.lock().unwrap().iter().map(|r| r["startTime"].clone()).collect();
+    let first_page_retry = requests
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| r["nextToken"].is_null())
+        .nth(1)
+        .cloned()
+        .unwrap();
+    assert!(first_page_retry["startTime"].is_null(), "start times: {:?}", start_times);
+    assert!(start_times.contains(&json!(1_714_564_801_000_i64)), "start times: {:?}", start_times);
+
+    // Re-reading the first page after the failure must not deliver its event again
+    let copies = harness
+        .stored
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|log| log["message"].as_str().is_some_and(|m| m.contains("cloudwatch-marker")))
+        .count();
+    assert_eq!(copies, 2);
+
+    // The cursor survives a restart
+    let cursor: Value = serde_json::from_slice(&std::fs::read(&cursor_path).unwrap()).unwrap();
+    assert_eq!(cursor["start_time"], 1_714_564_801_000_i64);
+}
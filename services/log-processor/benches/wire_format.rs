@@ -0,0 +1,78 @@
+// JSON vs protobuf for a forwarded batch: encode + zstd on the sending side, zstd + decode on the
+// receiving side, plus the `Value`-first JSON parse `/logs` used before `wire::decode_batch`.
+//
+//     cargo bench -p log-processor --bench wire_format
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use log_processor::models::LogEntry;
+use log_processor::wire::{decode_batch, encode_batch, WireFormat, JSON_CONTENT_TYPE, PROTOBUF_CONTENT_TYPE};
+use serde_json::{json, Value};
+
+const BATCH_SIZE: usize = 1000; // The collector's default `BATCH_MAX_EVENTS`
+
+// A batch shaped like typical application logs: a few labels plus some numeric and nested fields
+fn sample_batch() -> Vec<LogEntry> {
+    (0..BATCH_SIZE)
+        .map(|i| {
+            let level = ["INFO", "WARN", "ERROR", "DEBUG"][i % 4];
+            serde_json::from_value(json!({
+                "timestamp": format!("2024-05-01T12:{:02}:{:02}.{:03}Z", i / 60 % 60, i % 60, i % 1000),
+                "level": level,
+                "message": format!("GET /api/v1/orders/{} completed with status 200 in {}ms", 10_000 + i, i % 250),
+                "source": "checkout-api",
+                "attributes": {
+                    "host": format!("checkout-{}.eu-west-1.internal", i % 8),
+                    "trace_id": format!("{:032x}", i * 7919),
+                    "status": 200,
+                    "duration_ms": (i % 250) as f64 + 0.25,
+                    "cached": i % 3 == 0,
+                    "user": { "id": i, "plan": "enterprise" },
+                },
+            }))
+            .unwrap()
+        })
+        .collect()
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    zstd::stream::encode_all(data, 0).unwrap()
+}
+
+fn wire_format(c: &mut Criterion) {
+    let logs = sample_batch();
+    let formats = [(WireFormat::Json, JSON_CONTENT_TYPE), (WireFormat::Protobuf, PROTOBUF_CONTENT_TYPE)];
+
+    let mut encode = c.benchmark_group("encode_compress");
+    encode.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    for (format, _) in formats {
+        encode.bench_function(BenchmarkId::from_parameter(format!("{:?}", format)), |b| {
+            b.iter(|| compress(&encode_batch(format, black_box(&logs)).unwrap()))
+        });
+    }
+    encode.finish();
+
+    let mut decode = c.benchmark_group("decompress_decode");
+    decode.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    for (format, content_type) in formats {
+        let body = compress(&encode_batch(format, &logs).unwrap());
+        decode.bench_function(BenchmarkId::from_parameter(format!("{:?}", format)), |b| {
+            b.iter(|| {
+                let data = zstd::stream::decode_all(black_box(body.as_slice())).unwrap();
+                decode_batch(Some(content_type), &data).unwrap()
+            })
+        });
+    }
+    // The previous `/logs` path: parse into a `Value`, then convert it into entries
+    let json_body = compress(&encode_batch(WireFormat::Json, &logs).unwrap());
+    decode.bench_function(BenchmarkId::from_parameter("JsonViaValue"), |b| {
+        b.iter(|| {
+            let data = zstd::stream::decode_all(black_box(json_body.as_slice())).unwrap();
+            let value: Value = serde_json::from_slice(&data).unwrap();
+            serde_json::from_value::<Vec<LogEntry>>(value).unwrap()
+        })
+    });
+    decode.finish();
+}
+
+criterion_group!(benches, wire_format);
+criterion_main!(benches);
@@ -1,8 +1,11 @@
 use dotenv::dotenv;
 use std::env;
+use std::path::PathBuf;
 
 pub struct Config {
     pub storage_service_url: String,
+    pub pipeline_config: Option<PathBuf>, // JSON pipeline definition; defaults to `normalize` only
+    pub grpc_state_path: Option<PathBuf>, // GRPC_STATE_PATH: applied gRPC stream sequences; empty keeps them in memory only
 }
 
 impl Config {
@@ -11,6 +14,18 @@ impl Config {
 
         let storage_service_url = env::var("STORAGE_SERVICE_URL")
             .unwrap_or_else(|_| "http://localhost:5000/logs".to_string());
-        Self { storage_service_url }
+        let pipeline_config = env::var("PIPELINE_CONFIG").ok().map(PathBuf::from);
+        let grpc_state_path = match env::var("GRPC_STATE_PATH") {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from("./state/grpc_streams.ndjson")),
+        };
+        Self { storage_service_url, pipeline_config, grpc_state_path }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,236 @@
+use crate::models::LogEntry;
+use crate::processor::{Action, BatchOutcome, Event, Stage, apply_actions};
+use crate::sampling::stable_hash;
+use anyhow::{bail, Result};
+use lru::LruCache;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupMode {
+    #[default]
+    Drop, // Only the first copy is forwarded
+    Count, // Every copy is forwarded, annotated with how often it has been seen
+}
+
+// ✅ `{"type": "dedup", "fields": ["source", "timestamp", "message"], "window_secs": 300, "max_entries": 100000}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct DedupConfig {
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>, // Fingerprinted together; place the stage before `timestamp` rewrites stamps
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64, // A fingerprint older than this counts as new again
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize, // Least recently seen fingerprints are evicted beyond this
+    #[serde(default)]
+    pub mode: DedupMode,
+}
+
+fn default_fields() -> Vec<String> {
+    ["source", "timestamp", "message"].iter().map(|f| f.to_string()).collect()
+}
+
+fn default_window_secs() -> u64 {
+    300
+}
+
+fn default_max_entries() -> usize {
+    100_000
+}
+
+struct Seen {
+    first: Instant,
+    count: u64,
+}
+
+pub struct DedupStage {
+    fields: Vec<String>,
+    window: Duration,
+    mode: DedupMode,
+    cache: Mutex<LruCache<u64, Seen>>,
+    duplicates: AtomicU64,
+}
+
+impl DedupStage {
+    pub fn new(config: &DedupConfig) -> Result<Self> {
+        let Some(capacity) = NonZeroUsize::new(config.max_entries) else {
+            bail!("dedup max_entries must be positive");
+        };
+        if config.fields.is_empty() {
+            bail!("dedup needs at least one field to fingerprint");
+        }
+        Ok(Self {
+            fields: config.fields.clone(),
+            window: Duration::from_secs(config.window_secs),
+            mode: config.mode,
+            cache: Mutex::new(LruCache::new(capacity)),
+            duplicates: AtomicU64::new(0),
+        })
+    }
+
+    // Missing fields hash differently from empty ones
+    fn fingerprint(&self, log: &LogEntry) -> u64 {
+        let values: Vec<_> = self.fields.iter().map(|field| log.field(field)).collect();
+        let parts: Vec<&str> = values.iter().map(|value| value.as_deref().unwrap_or("\u{0}")).collect();
+        stable_hash(&parts)
+    }
+}
+
+impl Stage for DedupStage {
+    fn name(&self) -> &str {
+        "dedup"
+    }
+
+    // The cache is only touched from `process_batch`
+    fn process(&self, _log: &mut LogEntry) -> Action {
+        Action::Keep
+    }
+
+    // Sequential, so the first copy in arrival order survives
+    fn process_batch(&self, events: &mut Vec<Event>) -> BatchOutcome {
+        let now = Instant::now();
+        let fingerprints: Vec<u64> = events.iter().map(|event| self.fingerprint(&event.log)).collect();
+        let mut actions = Vec::with_capacity(events.len());
+        let mut counts = Vec::with_capacity(events.len());
+        // Survivor index per fingerprint, so later copies in the same batch fold into it
+        let mut survivors = HashMap::new();
+        let mut duplicates = 0u64;
+        {
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            for (index, fingerprint) in fingerprints.iter().enumerate() {
+                let seen = match cache.get_mut(fingerprint) {
+                    Some(seen) if now.duration_since(seen.first) < self.window => {
+                        seen.count += 1;
+                        seen.count
+                    }
+                    _ => {
+                        cache.put(*fingerprint, Seen { first: now, count: 1 });
+                        1
+                    }
+                };
+                if seen > 1 {
+                    duplicates += 1;
+                }
+                counts.push(seen);
+
+                match self.mode {
+                    DedupMode::Count => actions.push(Action::Keep),
+                    DedupMode::Drop => match survivors.get(fingerprint) {
+                        Some(&survivor) => {
+                            counts[survivor] += 1;
+                            actions.push(Action::Drop);
+                        }
+                        // Copies of an event forwarded in an earlier batch have nothing left to fold into
+                        None if seen > 1 => actions.push(Action::Drop),
+                        None => {
+                            survivors.insert(*fingerprint, index);
+                            actions.push(Action::Keep);
+                        }
+                    },
+                }
+            }
+        }
+        self.duplicates.fetch_add(duplicates, Ordering::Relaxed);
+
+        for ((event, action), count) in events.iter_mut().zip(&actions).zip(&counts) {
+            if matches!(action, Action::Keep) && *count > 1 {
+                event.log.attributes.insert("dup_count".to_string(), json!(count));
+            }
+        }
+        apply_actions(events, actions)
+    }
+
+    fn details(&self) -> Option<Value> {
+        let cached = self.cache.lock().unwrap_or_else(|e| e.into_inner()).len();
+        Some(json!({ "duplicates": self.duplicates.load(Ordering::Relaxed), "cached_fingerprints": cached }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, events, log};
+
+    fn messages(messages: &[&str]) -> Vec<Event> {
+        events(messages.iter().map(|message| log(message, None, json!({}))))
+    }
+
+    fn summary(events: &[Event]) -> Vec<(&str, Option<u64>)> {
+        events
+            .iter()
+            .map(|e| (e.log.message.as_str(), e.log.attributes.get("dup_count").and_then(Value::as_u64)))
+            .collect()
+    }
+
+    #[test]
+    fn drop_mode_folds_copies_into_the_first() {
+        let stage = configured(DedupStage::new, json!({})).unwrap();
+        let mut batch = messages(&["a", "a", "b", "a"]);
+        let outcome = stage.process_batch(&mut batch);
+        assert_eq!(outcome.dropped, 2);
+        assert_eq!(summary(&batch), vec![("a", Some(3)), ("b", None)]);
+
+        // Within the window a later copy is dropped outright
+        let mut batch = messages(&["a", "c"]);
+        stage.process_batch(&mut batch);
+        assert_eq!(summary(&batch), vec![("c", None)]);
+        assert_eq!(stage.details().unwrap()["duplicates"], 3);
+    }
+
+    #[test]
+    fn count_mode_forwards_every_copy() {
+        let stage = configured(DedupStage::new, json!({ "mode": "count" })).unwrap();
+        let mut batch = messages(&["a", "a"]);
+        assert_eq!(stage.process_batch(&mut batch).dropped, 0);
+        assert_eq!(summary(&batch), vec![("a", None), ("a", Some(2))]);
+
+        let mut batch = messages(&["a"]);
+        stage.process_batch(&mut batch);
+        assert_eq!(summary(&batch), vec![("a", Some(3))]);
+    }
+
+    #[test]
+    fn expired_fingerprints_count_as_new() {
+        let stage = configured(DedupStage::new, json!({ "window_secs": 0 })).unwrap();
+        let mut batch = messages(&["a"]);
+        stage.process_batch(&mut batch);
+        let mut batch = messages(&["a"]);
+        stage.process_batch(&mut batch);
+        assert_eq!(summary(&batch), vec![("a", None)]);
+    }
+
+    #[test]
+    fn least_recently_seen_fingerprints_are_evicted() {
+        let stage = configured(DedupStage::new, json!({ "max_entries": 2 })).unwrap();
+        stage.process_batch(&mut messages(&["a", "b", "c"]));
+        assert_eq!(stage.details().unwrap()["cached_fingerprints"], 2);
+
+        // `a` was evicted, so it's forwarded again; `c` is still remembered
+        let mut batch = messages(&["a", "c"]);
+        stage.process_batch(&mut batch);
+        assert_eq!(summary(&batch), vec![("a", None)]);
+    }
+
+    #[test]
+    fn fingerprint_covers_only_configured_fields_and_missing_differs_from_empty() {
+        let stage = configured(DedupStage::new, json!({ "fields": ["message", "attributes.user"] })).unwrap();
+        let mut batch = messages(&["a", "a", "a"]);
+        batch[0].log.timestamp = None; // Not fingerprinted
+        batch[1].log.attributes.insert("user".to_string(), json!(""));
+        batch[2].log.attributes.insert("user".to_string(), json!(""));
+        stage.process_batch(&mut batch);
+        assert_eq!(summary(&batch), vec![("a", None), ("a", Some(2))]);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        assert!(configured(DedupStage::new, json!({ "max_entries": 0 })).is_err());
+        assert!(configured(DedupStage::new, json!({ "fields": [] })).is_err());
+    }
+}
@@ -1,35 +1,91 @@
-use reqwest::Client;
-use tracing::{info, error};
+use reqwest::{Client, StatusCode};
+use tracing::{info, error, warn};
 use crate::models::LogEntry;
+use crate::wire::{encode_batch, Negotiated, WireFormat};
 use anyhow::{Result, anyhow};
+use std::fmt;
 
-pub async fn send_logs(logs: Vec<LogEntry>, storage_url: &str) -> Result<()> {
+/// A non-success response from the downstream, kept typed so callers can tell
+/// permanent rejections from transient failures.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl StatusError {
+    // 4xx means the batch itself was refused; retrying it won't help (except timeouts and throttling)
+    pub fn is_permanent(&self) -> bool {
+        self.status.is_client_error()
+            && self.status != StatusCode::REQUEST_TIMEOUT
+            && self.status != StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "responded with {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+// ✅ Sends a batch in the negotiated format, retrying once as JSON if the downstream refuses protobuf
+pub async fn send_logs(
+    client: &Client,
+    logs: &[LogEntry],
+    url: &str,
+    headers: &[(String, String)],
+    wire: &Negotiated,
+) -> Result<()> {
+    let format = wire.current();
+    match send_encoded(client, logs, url, headers, format).await {
+        Err(e) if format != WireFormat::Json && is_unsupported_media_type(&e) => {
+            warn!("⚠️ {} doesn't accept {:?}, falling back to JSON", url, format);
+            wire.rejected(format);
+            send_encoded(client, logs, url, headers, WireFormat::Json).await
+        }
+        result => result,
+    }
+}
+
+fn is_unsupported_media_type(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<StatusError>().is_some_and(|e| e.status == StatusCode::UNSUPPORTED_MEDIA_TYPE)
+}
+
+async fn send_encoded(client: &Client, logs: &[LogEntry], url: &str, headers: &[(String, String)], format: WireFormat) -> Result<()> {
     if logs.is_empty() {
         return Ok(());
     }
 
-    let client = Client::new();
-    info!("🚀 Sending {} logs to Storage Service: {}", logs.len(), storage_url);
+    info!("🚀 Sending {} logs to {} as {:?}", logs.len(), url, format);
 
-    let response = client.post(storage_url)
-        .header("Content-Type", "application/json")
-        .json(&logs)
-        .send()
-        .await;
+    let mut request = client.post(url).header("Content-Type", format.content_type());
+    // JSON stays uncompressed for downstreams that predate the binary format
+    request = match format {
+        WireFormat::Json => request.body(encode_batch(format, logs)?),
+        WireFormat::Protobuf => request
+            .header("Content-Encoding", "zstd")
+            .body(zstd::stream::encode_all(encode_batch(format, logs)?.as_slice(), 0)?),
+    };
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request.send().await;
 
     match response {
         Ok(resp) if resp.status().is_success() => {
-            info!("✅ Successfully sent logs to Storage Service");
+            info!("✅ Successfully sent logs to {}", url);
             Ok(())
         },
         Ok(resp) => {
             let status = resp.status();
-            let error_text = resp.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            error!("❌ Storage Service responded with {}: {}", status, error_text);
-            Err(anyhow!("Storage Service responded with {}: {}", status, error_text))
+            let body = resp.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            error!("❌ {} responded with {}: {}", url, status, body);
+            Err(anyhow!(StatusError { status, body }))
         },
         Err(e) => {
-            error!("❌ Request to Storage Service failed: {}", e);
+            error!("❌ Request to {} failed: {}", url, e);
             Err(anyhow!(e))
         }
     }
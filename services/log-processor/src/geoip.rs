@@ -0,0 +1,569 @@
+use crate::models::LogEntry;
+use crate::processor::{Action, BatchOutcome, Event, FileReloader, Stage, apply_actions};
+use anyhow::{anyhow, bail, Context, Result};
+use lru::LruCache;
+use maxminddb::{geoip2, Reader};
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeoIpField {
+    pub field: String, // Attribute holding the address, e.g. `client.ip`
+    pub target: Option<String>, // Prefix for added fields; defaults to `field` without a trailing `.ip`
+    #[serde(default = "default_true")]
+    pub geo: bool, // Country/city/location from City or Country databases
+    #[serde(default = "default_true")]
+    pub asn: bool, // Autonomous system from ASN databases
+    #[serde(default)]
+    pub reverse_dns: bool, // Adds `<target>.domain`
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// ✅ Reverse lookups never block the pipeline: a miss queues a lookup and later events get the answer
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReverseDnsConfig {
+    #[serde(default = "default_dns_cache_size")]
+    pub cache_size: usize,
+    #[serde(default = "default_dns_ttl_secs")]
+    pub ttl_secs: u64, // Failed lookups are cached for as long as answers
+    #[serde(default = "default_dns_threads")]
+    pub threads: usize,
+    #[serde(default = "default_dns_max_pending")]
+    pub max_pending: usize, // Misses beyond this many queued lookups are not resolved
+}
+
+impl Default for ReverseDnsConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: default_dns_cache_size(),
+            ttl_secs: default_dns_ttl_secs(),
+            threads: default_dns_threads(),
+            max_pending: default_dns_max_pending(),
+        }
+    }
+}
+
+fn default_dns_cache_size() -> usize {
+    10_000
+}
+
+fn default_dns_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_dns_threads() -> usize {
+    2
+}
+
+fn default_dns_max_pending() -> usize {
+    1_000
+}
+
+// ✅ `{"type": "geoip", "databases": ["GeoLite2-City.mmdb", "GeoLite2-ASN.mmdb"], "fields": [{"field": "client.ip", "reverse_dns": true}]}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeoIpConfig {
+    pub fields: Vec<GeoIpField>,
+    #[serde(default)]
+    pub databases: Vec<PathBuf>, // MaxMind-format files; the kind is read from each file's metadata
+    #[serde(default = "default_reload_secs")]
+    pub reload_secs: u64, // How often database files are checked for changes; 0 disables reloading
+    #[serde(default = "default_language")]
+    pub language: String, // Locale used for place names
+    #[serde(default)]
+    pub reverse_dns: ReverseDnsConfig,
+}
+
+fn default_reload_secs() -> u64 {
+    60
+}
+
+fn default_language() -> String {
+    "en".to_string()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum DatabaseKind {
+    City, // City and Country databases share a layout
+    Asn,
+}
+
+struct Database {
+    kind: DatabaseKind,
+    reader: Reader<Vec<u8>>,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self> {
+        let reader = Reader::open_readfile(path).map_err(|e| anyhow!("failed to open GeoIP database {:?}: {}", path, e))?;
+        let database_type = reader.metadata.database_type.to_ascii_lowercase();
+        let kind = if database_type.contains("asn") || database_type.contains("isp") {
+            DatabaseKind::Asn
+        } else {
+            DatabaseKind::City
+        };
+        info!("🌍 Loaded GeoIP database {:?} ({})", path, reader.metadata.database_type);
+        Ok(Self { kind, reader })
+    }
+}
+
+// Accepts `1.2.3.4`, `1.2.3.4:443`, `[::1]:443` and forwarded-for lists (first hop wins)
+fn parse_ip(raw: &str) -> Option<IpAddr> {
+    let first = raw.split(',').next()?.trim();
+    first
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| first.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+// Hostname (or `None` for failed lookups) and when it was resolved
+type DnsCache = LruCache<IpAddr, (Option<String>, Instant)>;
+
+struct ReverseDns {
+    ttl: Duration,
+    max_pending: usize,
+    cache: Arc<Mutex<DnsCache>>,
+    pending: Arc<Mutex<HashSet<IpAddr>>>,
+    pool: rayon::ThreadPool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ReverseDns {
+    fn new(config: &ReverseDnsConfig) -> Result<Self> {
+        let capacity = NonZeroUsize::new(config.cache_size).ok_or_else(|| anyhow!("reverse_dns cache_size must be positive"))?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(config.threads.max(1))
+            .thread_name(|i| format!("reverse-dns-{}", i))
+            .build()
+            .context("failed to build reverse DNS thread pool")?;
+        Ok(Self {
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_pending: config.max_pending,
+            cache: Arc::new(Mutex::new(LruCache::new(capacity))),
+            pending: Arc::new(Mutex::new(HashSet::new())),
+            pool,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Cached hostname for `ip`; `None` on a miss, which queues a background lookup.
+    fn hostname(&self, ip: IpAddr) -> Option<String> {
+        if let Some((name, resolved)) = self.cache.lock().unwrap_or_else(|e| e.into_inner()).get(&ip) {
+            if resolved.elapsed() < self.ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return name.clone();
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            if pending.len() >= self.max_pending || !pending.insert(ip) {
+                return None;
+            }
+        }
+        let cache = Arc::clone(&self.cache);
+        let pending = Arc::clone(&self.pending);
+        self.pool.spawn(move || {
+            let name = dns_lookup::lookup_addr(&ip).ok().filter(|name| name.parse::<IpAddr>().is_err());
+            cache.lock().unwrap_or_else(|e| e.into_inner()).put(ip, (name, Instant::now()));
+            pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&ip);
+        });
+        None
+    }
+}
+
+pub struct GeoIpStage {
+    fields: Vec<(GeoIpField, String)>,
+    language: String,
+    databases: Vec<FileReloader<Database>>,
+    reverse_dns: Option<ReverseDns>,
+    enriched: AtomicU64,
+    not_found: AtomicU64,
+}
+
+impl GeoIpStage {
+    pub fn new(config: &GeoIpConfig) -> Result<Self> {
+        if config.fields.is_empty() {
+            bail!("geoip needs at least one field");
+        }
+        let databases = config
+            .databases
+            .iter()
+            .map(|path| FileReloader::new(path, "GeoIP database", config.reload_secs, Database::open))
+            .collect::<Result<Vec<_>>>()?;
+        let reverse_dns = if config.fields.iter().any(|f| f.reverse_dns) {
+            Some(ReverseDns::new(&config.reverse_dns)?)
+        } else {
+            None
+        };
+        let fields = config
+            .fields
+            .iter()
+            .map(|f| {
+                let target = f.target.clone().unwrap_or_else(|| f.field.strip_suffix(".ip").unwrap_or(&f.field).to_string());
+                (f.clone(), target)
+            })
+            .collect();
+
+        Ok(Self {
+            fields,
+            language: config.language.clone(),
+            databases,
+            reverse_dns,
+            enriched: AtomicU64::new(0),
+            not_found: AtomicU64::new(0),
+        })
+    }
+
+    fn snapshot(&self) -> Vec<Arc<Database>> {
+        self.databases.iter().map(FileReloader::get).collect()
+    }
+
+    fn name_of(&self, names: &Option<BTreeMap<&str, &str>>) -> Option<String> {
+        names.as_ref().and_then(|names| names.get(self.language.as_str())).map(|name| name.to_string())
+    }
+
+    fn enrich(&self, databases: &[Arc<Database>], log: &mut LogEntry) {
+        for (field, target) in &self.fields {
+            let Some(ip) = log.field(&field.field).and_then(|raw| parse_ip(&raw)) else {
+                continue;
+            };
+            let mut added: HashMap<String, Value> = HashMap::new();
+            let mut put = |name: &str, value: Value| {
+                added.insert(format!("{}.{}", target, name), value);
+            };
+
+            for Database { kind, reader } in databases.iter().map(Arc::as_ref) {
+                match kind {
+                    DatabaseKind::City if field.geo => {
+                        let Ok(city) = reader.lookup::<geoip2::City>(ip) else {
+                            continue;
+                        };
+                        if let Some(country) = &city.country {
+                            if let Some(code) = country.iso_code {
+                                put("geo.country_iso_code", json!(code));
+                            }
+                            if let Some(name) = self.name_of(&country.names) {
+                                put("geo.country_name", json!(name));
+                            }
+                        }
+                        if let Some(continent) = city.continent.as_ref().and_then(|c| c.code) {
+                            put("geo.continent_code", json!(continent));
+                        }
+                        if let Some(region) = city.subdivisions.as_ref().and_then(|s| s.first()) {
+                            if let Some(code) = region.iso_code {
+                                put("geo.region_iso_code", json!(code));
+                            }
+                            if let Some(name) = self.name_of(&region.names) {
+                                put("geo.region_name", json!(name));
+                            }
+                        }
+                        if let Some(name) = city.city.as_ref().and_then(|c| self.name_of(&c.names)) {
+                            put("geo.city_name", json!(name));
+                        }
+                        if let Some(location) = &city.location {
+                            if let (Some(lat), Some(lon)) = (location.latitude, location.longitude) {
+                                put("geo.location", json!({ "lat": lat, "lon": lon }));
+                            }
+                            if let Some(zone) = location.time_zone {
+                                put("geo.timezone", json!(zone));
+                            }
+                        }
+                    }
+                    DatabaseKind::Asn if field.asn => {
+                        let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) else {
+                            continue;
+                        };
+                        if let Some(number) = asn.autonomous_system_number {
+                            put("as.number", json!(number));
+                        }
+                        if let Some(organization) = asn.autonomous_system_organization {
+                            put("as.organization.name", json!(organization));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if field.reverse_dns {
+                if let Some(name) = self.reverse_dns.as_ref().and_then(|dns| dns.hostname(ip)) {
+                    put("domain", json!(name));
+                }
+            }
+
+            if added.is_empty() {
+                self.not_found.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.enriched.fetch_add(1, Ordering::Relaxed);
+                log.attributes.extend(added);
+            }
+        }
+    }
+}
+
+impl Stage for GeoIpStage {
+    fn name(&self) -> &str {
+        "geoip"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        let databases = self.snapshot();
+        self.enrich(&databases, log);
+        Action::Keep
+    }
+
+    fn process_batch(&self, events: &mut Vec<Event>) -> BatchOutcome {
+        for database in &self.databases {
+            database.reload_if_changed(Database::open);
+        }
+        let databases = self.snapshot();
+        let actions: Vec<Action> = events
+            .par_iter_mut()
+            .map(|event| {
+                self.enrich(&databases, &mut event.log);
+                Action::Keep
+            })
+            .collect();
+        apply_actions(events, actions)
+    }
+
+    fn details(&self) -> Option<Value> {
+        let databases: Vec<Value> = self
+            .databases
+            .iter()
+            .map(|db| {
+                let metadata = &db.get().reader.metadata;
+                json!({ "path": db.path(), "type": metadata.database_type, "build_epoch": metadata.build_epoch })
+            })
+            .collect();
+        let mut details = json!({
+            "enriched": self.enriched.load(Ordering::Relaxed),
+            "not_found": self.not_found.load(Ordering::Relaxed),
+            "reloads": self.databases.iter().map(FileReloader::reloads).sum::<u64>(),
+            "databases": databases,
+        });
+        if let Some(dns) = &self.reverse_dns {
+            details["reverse_dns"] = json!({
+                "hits": dns.hits.load(Ordering::Relaxed),
+                "misses": dns.misses.load(Ordering::Relaxed),
+                "cached": dns.cache.lock().unwrap_or_else(|e| e.into_inner()).len(),
+            });
+        }
+        Some(details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, events, log};
+
+    fn dns(ttl_secs: u64, max_pending: usize) -> ReverseDns {
+        ReverseDns::new(&ReverseDnsConfig { cache_size: 4, ttl_secs, threads: 1, max_pending }).unwrap()
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    // Generated by `tests/data/make_mmdb.py`
+    fn test_database(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(name)
+    }
+
+    #[test]
+    fn parses_addresses_ports_and_forwarded_lists() {
+        assert_eq!(parse_ip("1.2.3.4"), Some(ip("1.2.3.4")));
+        assert_eq!(parse_ip("1.2.3.4:443"), Some(ip("1.2.3.4")));
+        assert_eq!(parse_ip("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_ip("[2001:db8::1]:8443"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_ip(" 203.0.113.7 , 10.0.0.1, 10.0.0.2"), Some(ip("203.0.113.7")));
+        assert_eq!(parse_ip("unknown, 203.0.113.7"), None);
+        assert_eq!(parse_ip(""), None);
+    }
+
+    #[test]
+    fn target_defaults_to_field_without_ip_suffix() {
+        let stage = configured(GeoIpStage::new, json!({
+            "fields": [
+                {"field": "client.ip"},
+                {"field": "peer_addr"},
+                {"field": "server.ip", "target": "upstream"}
+            ]
+        }))
+        .unwrap();
+        let targets: Vec<&str> = stage.fields.iter().map(|(_, target)| target.as_str()).collect();
+        assert_eq!(targets, ["client", "peer_addr", "upstream"]);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(configured(GeoIpStage::new, json!({"fields": []})).is_err());
+
+        let missing = configured(GeoIpStage::new, json!({"fields": [{"field": "client.ip"}], "databases": ["/nonexistent/GeoLite2-City.mmdb"]}));
+        assert!(format!("{:#}", missing.err().unwrap()).contains("failed to open GeoIP database"));
+
+        let no_cache = configured(GeoIpStage::new, json!({"fields": [{"field": "client.ip", "reverse_dns": true}], "reverse_dns": {"cache_size": 0}}));
+        assert!(no_cache.err().unwrap().to_string().contains("cache_size"));
+
+        // A zero cache only matters when some field asks for reverse lookups
+        assert!(configured(GeoIpStage::new, json!({"fields": [{"field": "client.ip"}], "reverse_dns": {"cache_size": 0}})).is_ok());
+    }
+
+    #[test]
+    fn enriches_from_city_and_asn_databases() {
+        let stage = configured(GeoIpStage::new, json!({
+            "databases": [test_database("GeoLite2-City-Test.mmdb"), test_database("GeoLite2-ASN-Test.mmdb")],
+            "fields": [
+                {"field": "client.ip"},
+                {"field": "peer", "target": "upstream", "asn": false}
+            ]
+        }))
+        .unwrap();
+
+        let mut entry = log("request", None, json!({"client.ip": "81.2.69.142:443", "peer": "81.2.69.160, 10.0.0.1"}));
+        stage.process(&mut entry);
+        let added: BTreeMap<&str, &Value> = entry
+            .attributes
+            .iter()
+            .filter(|(name, _)| name.starts_with("client.") && *name != "client.ip")
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        assert_eq!(
+            json!(added),
+            json!({
+                "client.geo.country_iso_code": "GB",
+                "client.geo.country_name": "United Kingdom",
+                "client.geo.continent_code": "EU",
+                "client.geo.region_iso_code": "ENG",
+                "client.geo.region_name": "England",
+                "client.geo.city_name": "London",
+                "client.geo.location": {"lat": 51.5142, "lon": -0.0931},
+                "client.geo.timezone": "Europe/London",
+                "client.as.number": 20712,
+                "client.as.organization.name": "Andrews & Arnold Ltd",
+            })
+        );
+        // `target` prefixes the second field, which skips the ASN database
+        assert_eq!(entry.attributes["upstream.geo.city_name"], "London");
+        assert!(!entry.attributes.contains_key("upstream.as.number"));
+
+        // Outside the databases' networks nothing is added
+        let mut outside = log("request", None, json!({"client.ip": "203.0.113.7"}));
+        stage.process(&mut outside);
+        assert_eq!(outside.attributes.len(), 1);
+
+        let details = stage.details().unwrap();
+        assert_eq!((details["enriched"].as_u64(), details["not_found"].as_u64()), (Some(2), Some(1)));
+        let types: Vec<&Value> = details["databases"].as_array().unwrap().iter().map(|db| &db["type"]).collect();
+        assert_eq!(types, [&json!("GeoLite2-City"), &json!("GeoLite2-ASN")]);
+    }
+
+    #[test]
+    fn replaced_databases_are_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-City.mmdb");
+        std::fs::copy(test_database("GeoLite2-City-Test.mmdb"), &path).unwrap();
+        let stage = configured(GeoIpStage::new, json!({"databases": [path], "fields": [{"field": "client.ip"}], "reload_secs": 1})).unwrap();
+        let city = || {
+            let mut events = events([log("request", None, json!({"client.ip": "81.2.69.142"}))]);
+            stage.process_batch(&mut events);
+            events[0].log.attributes["client.geo.city_name"].clone()
+        };
+        assert_eq!(city(), "London");
+
+        // A truncated download keeps the previous database
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(&path, b"not a database").unwrap();
+        assert_eq!(city(), "London");
+
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::copy(test_database("GeoLite2-City-Test-Updated.mmdb"), &path).unwrap();
+        assert_eq!(city(), "Boxford");
+        assert_eq!(stage.details().unwrap()["reloads"], 1);
+    }
+
+    #[test]
+    fn counts_unresolved_addresses_as_not_found() {
+        let stage = configured(GeoIpStage::new, json!({"fields": [{"field": "client.ip"}]})).unwrap();
+        assert!(stage.reverse_dns.is_none());
+
+        let attributes = [json!({"client.ip": "203.0.113.7"}), json!({"client.ip": "not an ip"}), json!({})];
+        let mut events = events(attributes.map(|attributes| log("request", None, attributes)));
+        stage.process_batch(&mut events);
+
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|e| e.log.attributes.len() <= 1));
+        let details = stage.details().unwrap();
+        // Only the parseable address was looked up; there is nothing to find it in
+        assert_eq!(details["not_found"], 1);
+        assert_eq!(details["enriched"], 0);
+        assert_eq!(details["databases"], json!([]));
+        assert!(details.get("reverse_dns").is_none());
+    }
+
+    #[test]
+    fn adds_cached_reverse_dns_names() {
+        let stage = configured(GeoIpStage::new, json!({
+            "fields": [{"field": "client.ip", "reverse_dns": true}],
+            "reverse_dns": {"max_pending": 0}
+        }))
+        .unwrap();
+        let dns = stage.reverse_dns.as_ref().unwrap();
+        dns.cache.lock().unwrap().put(ip("10.0.0.1"), (Some("web-1.internal".to_string()), Instant::now()));
+        dns.cache.lock().unwrap().put(ip("10.0.0.2"), (None, Instant::now()));
+
+        let mut named = log("request", None, json!({"client.ip": "10.0.0.1:51234"}));
+        let mut unnamed = log("request", None, json!({"client.ip": "10.0.0.2"}));
+        stage.process(&mut named);
+        stage.process(&mut unnamed);
+
+        assert_eq!(named.attributes["client.domain"], "web-1.internal");
+        assert!(!unnamed.attributes.contains_key("client.domain"));
+        let details = stage.details().unwrap();
+        assert_eq!(details["enriched"], 1);
+        assert_eq!(details["not_found"], 1);
+        assert_eq!(details["reverse_dns"], json!({"hits": 2, "misses": 0, "cached": 2}));
+    }
+
+    #[test]
+    fn reverse_dns_expires_entries_after_ttl() {
+        let fresh = dns(3600, 0);
+        fresh.cache.lock().unwrap().put(ip("10.0.0.1"), (Some("web-1".to_string()), Instant::now()));
+        assert_eq!(fresh.hostname(ip("10.0.0.1")).as_deref(), Some("web-1"));
+        assert_eq!(fresh.hits.load(Ordering::Relaxed), 1);
+
+        let stale = dns(0, 0);
+        stale.cache.lock().unwrap().put(ip("10.0.0.1"), (Some("web-1".to_string()), Instant::now()));
+        assert_eq!(stale.hostname(ip("10.0.0.1")), None);
+        assert_eq!(stale.misses.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn reverse_dns_bounds_and_dedupes_pending_lookups() {
+        let dns = dns(3600, 1);
+        // Stand in for a lookup that is still in flight
+        dns.pending.lock().unwrap().insert(ip("192.0.2.1"));
+
+        assert_eq!(dns.hostname(ip("192.0.2.1")), None);
+        assert_eq!(dns.hostname(ip("192.0.2.2")), None);
+        assert_eq!(dns.misses.load(Ordering::Relaxed), 2);
+        // The duplicate wasn't queued again and the queue was full for the new address
+        assert_eq!(*dns.pending.lock().unwrap(), HashSet::from([ip("192.0.2.1")]));
+        assert_eq!(dns.cache.lock().unwrap().len(), 0);
+    }
+}
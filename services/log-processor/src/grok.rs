@@ -0,0 +1,434 @@
+use crate::models::LogEntry;
+use crate::processor::{Action, Stage};
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// ✅ Bundled grok library: Logstash's core patterns (rewritten without look-around, which the
+// `regex` crate doesn't support) plus nginx, Apache, Postgres and Java log formats.
+const BUNDLED_PATTERNS: &str = r#"
+USERNAME [a-zA-Z0-9._-]+
+USER %{USERNAME}
+INT [+-]?[0-9]+
+BASE10NUM [+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+)
+NUMBER %{BASE10NUM}
+BASE16NUM [+-]?(?:0x)?[0-9A-Fa-f]+
+POSINT \b[1-9][0-9]*\b
+NONNEGINT \b[0-9]+\b
+WORD \b\w+\b
+NOTSPACE \S+
+SPACE \s*
+DATA .*?
+GREEDYDATA .*
+QUOTEDSTRING "(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'
+UUID [A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}
+IPV4 (?:(?:25[0-5]|2[0-4][0-9]|1?[0-9]?[0-9])\.){3}(?:25[0-5]|2[0-4][0-9]|1?[0-9]?[0-9])
+IPV6 (?:[0-9A-Fa-f]{0,4}:){2,7}(?:%{IPV4}|[0-9A-Fa-f]{0,4})
+IP (?:%{IPV6}|%{IPV4})
+HOSTNAME \b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?\b
+IPORHOST (?:%{IP}|%{HOSTNAME})
+HOSTPORT %{IPORHOST}:%{POSINT}
+UNIXPATH (?:/[^\s]*)+
+URIPATH (?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+
+URIPARAM \?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*
+URIPATHPARAM %{URIPATH}(?:%{URIPARAM})?
+MONTH \b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b
+MONTHNUM (?:0?[1-9]|1[0-2])
+MONTHDAY (?:0[1-9]|[12][0-9]|3[01]|[1-9])
+DAY (?:Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?)
+YEAR (?:\d\d){1,2}
+HOUR (?:2[0123]|[01]?[0-9])
+MINUTE (?:[0-5][0-9])
+SECOND (?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)
+TIME %{HOUR}:%{MINUTE}(?::%{SECOND})?
+ISO8601_TIMEZONE (?:Z|[+-]%{HOUR}(?::?%{MINUTE}))
+TIMESTAMP_ISO8601 %{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?
+HTTPDATE %{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}
+SYSLOGTIMESTAMP %{MONTH} +%{MONTHDAY} %{TIME}
+TZ (?:[A-Z]{3,5}|[+-]\d{2}:?\d{2})
+LOGLEVEL (?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo?(?:rmation)?|INFO?(?:RMATION)?|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?)
+COMMONAPACHELOG ^%{IPORHOST:client.ip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:http.method} %{NOTSPACE:url.original}(?: HTTP/%{NUMBER:http.version})?|%{DATA:http.request.raw})" %{INT:http.status_code:int} (?:%{INT:http.response.bytes:int}|-)
+COMBINEDAPACHELOG %{COMMONAPACHELOG} "%{DATA:http.referrer}" "%{DATA:user_agent}"
+APACHE_ACCESS %{COMBINEDAPACHELOG}
+HTTPDERROR_DATE %{DAY} %{MONTH} %{MONTHDAY} %{TIME} %{YEAR}
+APACHE_ERROR ^\[%{HTTPDERROR_DATE:timestamp}\] \[(?:%{WORD:apache.module}:)?%{LOGLEVEL:level}\] (?:\[pid %{INT:pid:int}(?::tid %{INT:tid:int})?\] )?(?:\[client %{IPORHOST:client.ip}(?::%{INT:client.port:int})?\] )?%{GREEDYDATA:msg}
+NGINX_ACCESS %{COMBINEDAPACHELOG}(?: "%{DATA:http.x_forwarded_for}")?
+NGINX_ERROR_DATE %{YEAR}/%{MONTHNUM}/%{MONTHDAY} %{TIME}
+NGINX_ERROR ^%{NGINX_ERROR_DATE:timestamp} \[%{LOGLEVEL:level}\] %{INT:pid:int}#%{INT:tid:int}: (?:\*%{INT:connection_id:int} )?%{GREEDYDATA:msg}
+POSTGRES_LEVEL (?:DEBUG[1-5]?|INFO|NOTICE|WARNING|ERROR|LOG|FATAL|PANIC|STATEMENT|DETAIL|HINT|CONTEXT)
+POSTGRES ^%{TIMESTAMP_ISO8601:timestamp}(?: %{TZ:timezone})? \[%{INT:pid:int}\](?: %{USERNAME:postgres.user}@%{USERNAME:postgres.database})? %{POSTGRES_LEVEL:level}:\s+%{GREEDYDATA:msg}
+JAVACLASS (?:[a-zA-Z$_][a-zA-Z$_0-9]*\.)*[a-zA-Z$_][a-zA-Z$_0-9]*
+JAVAFILE (?:[A-Za-z0-9_. -]+)
+JAVAMETHOD (?:<init>|<clinit>|[a-zA-Z$_][a-zA-Z$_0-9]*)
+JAVASTACKTRACEPART \s*at %{JAVACLASS:java.class}\.%{JAVAMETHOD:java.method}\(%{JAVAFILE:java.file}(?::%{INT:java.line:int})?\)
+JAVA_LOGBACK ^%{TIMESTAMP_ISO8601:timestamp} \[%{DATA:thread}\] %{LOGLEVEL:level}\s+%{JAVACLASS:logger} - %{GREEDYDATA:msg}
+JAVA_LOG4J ^%{TIMESTAMP_ISO8601:timestamp}\s+%{LOGLEVEL:level}\s+\[%{DATA:thread}\]\s+%{JAVACLASS:logger}(?::%{INT:line:int})?\s+-\s+%{GREEDYDATA:msg}
+SPRING_BOOT ^%{TIMESTAMP_ISO8601:timestamp}\s+%{LOGLEVEL:level}\s+%{INT:pid:int} --- \[\s*%{DATA:thread}\]\s+%{JAVACLASS:logger}\s+:\s+%{GREEDYDATA:msg}
+JAVA (?:%{JAVA_LOGBACK}|%{JAVA_LOG4J}|%{SPRING_BOOT})
+"#;
+
+// Nesting deeper than this means a pattern references itself
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+impl FieldType {
+    fn parse(name: &str) -> Result<Self> {
+        match name {
+            "string" => Ok(Self::String),
+            "int" | "integer" | "long" => Ok(Self::Int),
+            "float" | "double" => Ok(Self::Float),
+            "bool" | "boolean" => Ok(Self::Bool),
+            other => bail!("unknown grok type '{}'", other),
+        }
+    }
+
+    // Falls back to the raw string when the capture doesn't convert
+    fn coerce(self, raw: &str) -> Value {
+        let coerced = match self {
+            Self::String => None,
+            Self::Int => raw.parse::<i64>().ok().map(Value::from),
+            Self::Float => raw.parse::<f64>().ok().map(Value::from),
+            Self::Bool => match raw.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "1" => Some(Value::Bool(true)),
+                "false" | "no" | "0" => Some(Value::Bool(false)),
+                _ => None,
+            },
+        };
+        coerced.unwrap_or_else(|| Value::String(raw.to_string()))
+    }
+}
+
+// ✅ `{"type": "grok", "patterns": ["%{NGINX_ACCESS}"]}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct GrokConfig {
+    pub patterns: Vec<String>, // Tried in order; the first match wins
+    #[serde(default = "default_field")]
+    pub field: String, // Field to parse
+    #[serde(default)]
+    pub pattern_definitions: HashMap<String, String>, // Extra or overriding named patterns
+    #[serde(default)]
+    pub target: Option<String>, // Prefix for captured attribute names
+    #[serde(default = "default_grok_failure_tag")]
+    pub tag_on_failure: String,
+}
+
+// ✅ `{"type": "regex", "patterns": ["(?P<user>\\w+) logged in"], "types": {"user": "string"}}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegexConfig {
+    pub patterns: Vec<String>, // Named groups become attributes
+    #[serde(default = "default_field")]
+    pub field: String,
+    #[serde(default)]
+    pub types: HashMap<String, FieldType>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default = "default_regex_failure_tag")]
+    pub tag_on_failure: String,
+}
+
+fn default_field() -> String {
+    "message".to_string()
+}
+
+fn default_grok_failure_tag() -> String {
+    "_grokparsefailure".to_string()
+}
+
+fn default_regex_failure_tag() -> String {
+    "_regexparsefailure".to_string()
+}
+
+struct Capture {
+    group: String, // Regex group name
+    field: String, // Attribute name
+    field_type: FieldType,
+}
+
+struct Matcher {
+    regex: Regex,
+    captures: Vec<Capture>,
+}
+
+pub struct ParseStage {
+    name: &'static str,
+    field: String,
+    target: Option<String>,
+    tag_on_failure: String,
+    matchers: Vec<Matcher>,
+}
+
+impl ParseStage {
+    pub fn grok(config: &GrokConfig) -> Result<Self> {
+        let mut library = bundled_library();
+        library.extend(config.pattern_definitions.clone());
+
+        let matchers = config
+            .patterns
+            .iter()
+            .map(|pattern| compile_grok(pattern, &library).with_context(|| format!("invalid grok pattern {:?}", pattern)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            name: "grok",
+            field: config.field.clone(),
+            target: config.target.clone(),
+            tag_on_failure: config.tag_on_failure.clone(),
+            matchers,
+        })
+    }
+
+    pub fn regex(config: &RegexConfig) -> Result<Self> {
+        let matchers = config
+            .patterns
+            .iter()
+            .map(|pattern| {
+                let regex = Regex::new(pattern).with_context(|| format!("invalid regex {:?}", pattern))?;
+                let captures = regex
+                    .capture_names()
+                    .flatten()
+                    .map(|name| Capture {
+                        group: name.to_string(),
+                        field: name.to_string(),
+                        field_type: config.types.get(name).copied().unwrap_or_default(),
+                    })
+                    .collect();
+                Ok(Matcher { regex, captures })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            name: "regex",
+            field: config.field.clone(),
+            target: config.target.clone(),
+            tag_on_failure: config.tag_on_failure.clone(),
+            matchers,
+        })
+    }
+}
+
+impl Stage for ParseStage {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        let Some(input) = log.field(&self.field).map(|v| v.into_owned()) else {
+            add_tag(log, &self.tag_on_failure);
+            return Action::Keep;
+        };
+
+        let matched = self.matchers.iter().find_map(|matcher| {
+            matcher.regex.captures(&input).map(|caps| (matcher, caps))
+        });
+        let Some((matcher, caps)) = matched else {
+            add_tag(log, &self.tag_on_failure);
+            return Action::Keep;
+        };
+
+        for capture in &matcher.captures {
+            // Alternations can bind the same field twice; the branch that participated wins
+            let Some(value) = caps.name(&capture.group).map(|m| m.as_str()).filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            let key = match &self.target {
+                Some(prefix) => format!("{}.{}", prefix, capture.field),
+                None => capture.field.clone(),
+            };
+            log.attributes.insert(key, capture.field_type.coerce(value));
+        }
+        Action::Keep
+    }
+}
+
+// Appends to the Logstash-style `tags` array attribute
+pub fn add_tag(log: &mut LogEntry, tag: &str) {
+    if tag.is_empty() {
+        return;
+    }
+    let tags = log.attributes.entry("tags".to_string()).or_insert_with(|| Value::Array(Vec::new()));
+    match tags {
+        Value::Array(tags) => {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(Value::String(tag.to_string()));
+            }
+        }
+        other => *other = Value::Array(vec![other.take(), Value::String(tag.to_string())]),
+    }
+}
+
+fn bundled_library() -> HashMap<String, String> {
+    BUNDLED_PATTERNS
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, definition)| (name.to_string(), definition.to_string()))
+        .collect()
+}
+
+fn compile_grok(pattern: &str, library: &HashMap<String, String>) -> Result<Matcher> {
+    let mut captures = Vec::new();
+    let expanded = expand(pattern, library, &mut captures, 0)?;
+    let regex = Regex::new(&expanded)?;
+    Ok(Matcher { regex, captures })
+}
+
+// ✅ Rewrites `%{NAME}`, `%{NAME:field}` and `%{NAME:field:type}` into plain regex. Fields get
+// generated group names, since the same field may appear in several alternation branches.
+fn expand(pattern: &str, library: &HashMap<String, String>, captures: &mut Vec<Capture>, depth: usize) -> Result<String> {
+    if depth > MAX_EXPANSION_DEPTH {
+        bail!("grok patterns nest too deeply (recursive definition?)");
+    }
+
+    let mut out = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find("%{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| anyhow!("unterminated %{{ in {:?}", pattern))?;
+        let mut parts = after[..end].splitn(3, ':');
+        let name = parts.next().unwrap_or_default();
+        let field = parts.next().filter(|f| !f.is_empty());
+        let field_type = parts.next().map(FieldType::parse).transpose()?.unwrap_or_default();
+
+        let definition = library.get(name).ok_or_else(|| anyhow!("unknown grok pattern '{}'", name))?;
+        let inner = expand(definition, library, captures, depth + 1)?;
+        match field {
+            Some(field) => {
+                let group = format!("g{}", captures.len());
+                out.push_str(&format!("(?P<{}>{})", group, inner));
+                captures.push(Capture { group, field: field.to_string(), field_type });
+            }
+            None => out.push_str(&format!("(?:{})", inner)),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, log};
+    use serde_json::json;
+
+    #[test]
+    fn nginx_access_with_typed_fields() {
+        let stage = configured(ParseStage::grok, json!({ "patterns": ["%{NGINX_ACCESS}"] })).unwrap();
+        let mut entry = log(r#"203.0.113.9 - alice [01/May/2024:12:00:00 +0000] "GET /api/orders?id=7 HTTP/1.1" 404 153 "-" "curl/8.5.0""#, None, json!({}));
+        stage.process(&mut entry);
+
+        assert_eq!(entry.attributes["client.ip"], "203.0.113.9");
+        assert_eq!(entry.attributes["auth"], "alice");
+        assert_eq!(entry.attributes["timestamp"], "01/May/2024:12:00:00 +0000");
+        assert_eq!(entry.attributes["http.method"], "GET");
+        assert_eq!(entry.attributes["url.original"], "/api/orders?id=7");
+        assert_eq!(entry.attributes["http.status_code"], 404);
+        assert_eq!(entry.attributes["http.response.bytes"], 153);
+        assert_eq!(entry.attributes["user_agent"], "curl/8.5.0");
+        assert!(!entry.attributes.contains_key("tags"));
+    }
+
+    #[test]
+    fn alternation_binds_fields_from_the_matching_branch() {
+        let stage = configured(ParseStage::grok, json!({ "patterns": ["%{JAVA}"] })).unwrap();
+        let mut entry = log("2024-05-01 12:00:00.123  WARN 4242 --- [  main] com.acme.OrderService  : Retrying payment", None, json!({}));
+        stage.process(&mut entry);
+
+        assert_eq!(entry.attributes["level"], "WARN");
+        assert_eq!(entry.attributes["pid"], 4242);
+        assert_eq!(entry.attributes["thread"], "main");
+        assert_eq!(entry.attributes["logger"], "com.acme.OrderService");
+        assert_eq!(entry.attributes["msg"], "Retrying payment");
+    }
+
+    #[test]
+    fn first_matching_pattern_wins_with_custom_definitions_and_target() {
+        let stage = configured(ParseStage::grok, json!({
+            "patterns": ["^order %{ORDER_ID:id} paid", "^order %{ORDER_ID:id} %{WORD:state}"],
+            "pattern_definitions": { "ORDER_ID": "[A-Z]{2}-%{INT}" },
+            "target": "order",
+        }))
+        .unwrap();
+
+        let mut entry = log("order AB-17 paid", None, json!({}));
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["order.id"], "AB-17");
+        assert!(!entry.attributes.contains_key("order.state"));
+
+        let mut entry = log("order AB-18 refunded", None, json!({}));
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["order.state"], "refunded");
+    }
+
+    #[test]
+    fn parse_failure_is_tagged_once() {
+        let stage = configured(ParseStage::grok, json!({ "patterns": ["%{IPV4:ip}"] })).unwrap();
+        let mut entry = log("no address here", None, json!({}));
+        stage.process(&mut entry);
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["tags"], json!(["_grokparsefailure"]));
+
+        // A missing field is a failure too
+        let stage = configured(ParseStage::grok, json!({ "patterns": ["%{IPV4:ip}"], "field": "peer", "tag_on_failure": "no_peer" })).unwrap();
+        let mut entry = log("10.0.0.1", None, json!({}));
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["tags"], json!(["no_peer"]));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        let unknown = configured(ParseStage::grok, json!({ "patterns": ["%{NOPE:x}"] })).err().unwrap();
+        assert!(format!("{:#}", unknown).contains("unknown grok pattern 'NOPE'"));
+
+        let recursive = configured(ParseStage::grok, json!({ "patterns": ["%{LOOP}"], "pattern_definitions": { "LOOP": "a%{LOOP}" } }));
+        assert!(format!("{:#}", recursive.err().unwrap()).contains("nest too deeply"));
+
+        assert!(configured(ParseStage::grok, json!({ "patterns": ["%{INT:n:decimal}"] })).is_err());
+        assert!(configured(ParseStage::grok, json!({ "patterns": ["%{INT"] })).is_err());
+    }
+
+    #[test]
+    fn regex_stage_coerces_types() {
+        let stage = configured(ParseStage::regex, json!({
+            "patterns": [r"took (?P<ms>\S+)ms cached=(?P<cached>\w+) ratio=(?P<ratio>\S+)"],
+            "types": { "ms": "int", "cached": "bool", "ratio": "float" },
+        }))
+        .unwrap();
+
+        let mut entry = log("took 42ms cached=yes ratio=0.25", None, json!({}));
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["ms"], 42);
+        assert_eq!(entry.attributes["cached"], true);
+        assert_eq!(entry.attributes["ratio"], 0.25);
+
+        // Values that don't convert stay strings
+        let mut entry = log("took ?ms cached=maybe ratio=n/a", None, json!({}));
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["ms"], "?");
+        assert_eq!(entry.attributes["cached"], "maybe");
+        assert_eq!(entry.attributes["ratio"], "n/a");
+
+        let mut entry = log("unrelated", None, json!({}));
+        stage.process(&mut entry);
+        assert_eq!(entry.attributes["tags"], json!(["_regexparsefailure"]));
+    }
+
+    #[test]
+    fn add_tag_keeps_an_existing_scalar_tag() {
+        let mut entry = log("x", None, json!({}));
+        entry.attributes.insert("tags".to_string(), json!("legacy"));
+        add_tag(&mut entry, "_grokparsefailure");
+        assert_eq!(entry.attributes["tags"], json!(["legacy", "_grokparsefailure"]));
+    }
+}
@@ -0,0 +1,657 @@
+use crate::http_handler::AppState;
+use crate::models::LogEntry;
+use crate::outputs::QueueFull;
+use crate::wire::{decode_batch, encode_batch, WireFormat, PROTOBUF_CONTENT_TYPE};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::CompressionEncoding;
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::server::{Grpc, NamedService, StreamingService};
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{IntoStreamingRequest, Request, Response, Status, Streaming};
+use tonic_prost::ProstCodec;
+use tracing::{info, error, warn};
+
+// ✅ gRPC streaming ingestion (port 4001), an alternative to `POST /logs` for long-lived senders
+//
+//     service LogStream {
+//       rpc Stream(stream StreamBatch) returns (stream BatchAck);
+//     }
+//     message StreamBatch {
+//       string stream_id = 1;   // sender session; sequences are deduplicated per stream_id
+//       uint64 sequence = 2;    // 1, 2, 3, ... within a stream_id
+//       LogBatch batch = 3;     // see `wire`
+//       uint64 oldest_unacked = 4;  // the sender's oldest batch still waiting for an ack; 0 if unknown
+//     }
+//     message BatchAck {
+//       uint64 sequence = 1;
+//       AckStatus status = 2;
+//       string error = 3;
+//       uint32 retry_after_ms = 4;
+//     }
+//     enum AckStatus { ACCEPTED = 0; DUPLICATE = 1; RETRY = 2; REJECTED = 3; }
+//
+// Batches are processed in the order they arrive and each gets exactly one ack. A sender keeps
+// its unacked batches, resends them (same stream_id and sequence) after reconnecting, and is
+// told DUPLICATE for any the processor had already applied. Applied sequences are saved to
+// `GRPC_STATE_PATH` before the ACCEPTED ack goes out, and the collector keeps its stream id and
+// sequences across its own restarts, so resends are recognized across restarts of either side.
+// Two gaps remain: a crash between queueing a batch on the outputs and saving its sequence
+// applies the batch again when it's resent, and the output queues themselves are in memory, so a
+// crash loses what they hold. Acks queue up to `ACK_BUFFER` deep; a sender that stops reading
+// them stops being read from, and HTTP/2 flow control pushes back on it. RETRY means the outputs were full, or that the batch
+// is too far past a gap.
+//
+// Each batch also carries the sender's oldest unacked sequence. Gaps below it are batches the
+// sender gave up on, and are closed; a gap above it is a batch still coming, so at most
+// `MAX_OUT_OF_ORDER` later batches are applied ahead of it and the rest are asked to retry.
+
+const SERVICE_NAME: &str = "insightx.logs.v1.LogStream";
+const STREAM_PATH: &str = "/insightx.logs.v1.LogStream/Stream";
+const ACK_BUFFER: usize = 64;
+const RETRY_AFTER_MS: u32 = 500;
+const MAX_STREAMS: usize = 10_000; // Stream ids whose applied sequences are remembered (LRU)
+const MAX_OUT_OF_ORDER: usize = 1024; // Applied sequences kept above a gap; later ones are asked to retry until it's filled
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamBatch {
+    #[prost(string, tag = "1")]
+    pub stream_id: String,
+    #[prost(uint64, tag = "2")]
+    pub sequence: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub batch: Vec<u8>, // An encoded `LogBatch`; on the wire this is the same as the embedded message
+    #[prost(uint64, tag = "4")]
+    pub oldest_unacked: u64, // The sender never resends anything older, so gaps below it are closed
+}
+
+impl StreamBatch {
+    pub fn encode(stream_id: &str, sequence: u64, logs: &[LogEntry]) -> anyhow::Result<Self> {
+        Ok(Self {
+            stream_id: stream_id.to_string(),
+            sequence,
+            batch: encode_batch(WireFormat::Protobuf, logs)?,
+            oldest_unacked: 0,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BatchAck {
+    #[prost(uint64, tag = "1")]
+    pub sequence: u64,
+    #[prost(enumeration = "AckStatus", tag = "2")]
+    pub status: i32,
+    #[prost(string, tag = "3")]
+    pub error: String,
+    #[prost(uint32, tag = "4")]
+    pub retry_after_ms: u32, // Set with RETRY
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum AckStatus {
+    Accepted = 0,
+    Duplicate = 1, // Already applied; nothing was done this time
+    Retry = 2, // Not applied; resend the same sequence after `retry_after_ms`
+    Rejected = 3, // Not applied and never will be (malformed batch, pipeline failure); don't resend
+}
+
+// ✅ The settled sequences of one stream: everything up to `through` (applied, or given up on by
+// the sender), plus the applied ones in `above`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Applied {
+    through: u64,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    above: BTreeSet<u64>,
+}
+
+impl Applied {
+    fn contains(&self, sequence: u64) -> bool {
+        sequence <= self.through || self.above.contains(&sequence)
+    }
+
+    // Only the batch that fills the first gap is taken once `above` is full
+    fn has_room_for(&self, sequence: u64) -> bool {
+        sequence == self.through + 1 || self.above.len() < MAX_OUT_OF_ORDER
+    }
+
+    fn insert(&mut self, sequence: u64) {
+        self.above.insert(sequence);
+        self.advance();
+    }
+
+    // The sender has nothing older than `oldest_unacked` left to resend, so gaps below it are closed
+    fn settle_below(&mut self, oldest_unacked: u64) {
+        if oldest_unacked > self.through + 1 {
+            self.through = oldest_unacked - 1;
+            self.above = self.above.split_off(&oldest_unacked);
+            self.advance();
+        }
+    }
+
+    fn advance(&mut self) {
+        while self.above.remove(&(self.through + 1)) {
+            self.through += 1;
+        }
+    }
+}
+
+// A line of the watermark file
+#[derive(Serialize, Deserialize)]
+struct Watermark {
+    stream_id: String,
+    #[serde(flatten)]
+    applied: Applied,
+}
+
+// ✅ Keeps applied sequences across restarts: a line with a stream's `Applied` for every batch it
+// applies (the last line per stream wins), rewritten with one line per stream once stale lines dominate
+struct Watermarks {
+    path: PathBuf,
+    file: AsyncMutex<WatermarkFile>,
+}
+
+struct WatermarkFile {
+    file: Option<File>, // Opened on the first append
+    latest: LruCache<String, Vec<u8>>, // Each stream's last line, for rewriting
+    lines: usize, // Lines in the file, stale ones included
+}
+
+impl Watermarks {
+    // Loads the streams saved by a previous run, least recently applied first
+    async fn open(path: PathBuf) -> (Self, Vec<Watermark>) {
+        let mut latest = LruCache::new(NonZeroUsize::new(MAX_STREAMS).expect("MAX_STREAMS is non-zero"));
+        let mut lines = 0;
+        match tokio::fs::read(&path).await {
+            Ok(data) => {
+                for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                    lines += 1;
+                    match serde_json::from_slice::<Watermark>(line) {
+                        Ok(watermark) => {
+                            latest.put(watermark.stream_id, [line, b"\n"].concat());
+                        }
+                        Err(e) => warn!("⚠️ Skipping corrupt stream watermark in {:?}: {}", path, e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("⚠️ Failed to read stream watermarks {:?}: {}", path, e),
+        }
+        let loaded: Vec<Watermark> = latest
+            .iter()
+            .rev()
+            .filter_map(|(_, line)| serde_json::from_slice(line).ok())
+            .collect();
+        if !loaded.is_empty() {
+            info!("📥 Loaded watermarks of {} gRPC streams from {:?}", loaded.len(), path);
+        }
+        let file = WatermarkFile { file: None, latest, lines };
+        (Self { path, file: AsyncMutex::new(file) }, loaded)
+    }
+
+    // ✅ Saves a stream's applied sequences; returns once the line is written
+    async fn record(&self, stream_id: &str, applied: &Applied) {
+        let watermark = Watermark { stream_id: stream_id.to_string(), applied: applied.clone() };
+        let mut line = serde_json::to_vec(&watermark).unwrap_or_default();
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        file.latest.put(watermark.stream_id, line.clone());
+        file.lines += 1;
+        let result = if file.lines > MAX_STREAMS.saturating_mul(2) {
+            self.rewrite(&mut file).await
+        } else {
+            append(&self.path, &mut file.file, &line).await
+        };
+        if let Err(e) = result {
+            error!("❌ Failed to save the watermark of stream {:?} to {:?}: {}", stream_id, self.path, e);
+            // Reopen on the next write
+            file.file = None;
+        }
+    }
+
+    // Temp file + rename, so a crash leaves the old file or the new one
+    async fn rewrite(&self, file: &mut WatermarkFile) -> std::io::Result<()> {
+        let data: Vec<u8> = file.latest.iter().rev().flat_map(|(_, line)| line.iter().copied()).collect();
+        file.file = None;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        file.lines = file.latest.len();
+        Ok(())
+    }
+}
+
+async fn append(path: &Path, file: &mut Option<File>, line: &[u8]) -> std::io::Result<()> {
+    if file.is_none() {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        *file = Some(OpenOptions::new().create(true).append(true).open(path).await?);
+    }
+    let handle = file.as_mut().expect("file was just opened");
+    handle.write_all(line).await?;
+    handle.flush().await
+}
+
+type Streams = Mutex<LruCache<String, Arc<AsyncMutex<Applied>>>>;
+
+// ✅ `LogStream` service backed by the same pipeline and outputs as `/logs`
+#[derive(Clone)]
+pub struct LogStreamServer {
+    state: Arc<AppState>,
+    streams: Arc<Streams>, // Outlives connections, so a resend after reconnecting is recognized
+    watermarks: Option<Arc<Watermarks>>, // Set when applied sequences are saved across restarts
+}
+
+impl LogStreamServer {
+    // Applied sequences are kept in memory only
+    pub fn new(state: Arc<AppState>) -> Self {
+        let capacity = NonZeroUsize::new(MAX_STREAMS).expect("MAX_STREAMS is non-zero");
+        Self { state, streams: Arc::new(Mutex::new(LruCache::new(capacity))), watermarks: None }
+    }
+
+    // ✅ Applied sequences are saved to `path`, and those saved by a previous run are reloaded
+    pub async fn open(state: Arc<AppState>, path: PathBuf) -> Self {
+        let (watermarks, loaded) = Watermarks::open(path).await;
+        let server = Self { watermarks: Some(Arc::new(watermarks)), ..Self::new(state) };
+        {
+            let mut streams = server.streams.lock().unwrap_or_else(|e| e.into_inner());
+            for Watermark { stream_id, applied } in loaded {
+                streams.put(stream_id, Arc::new(AsyncMutex::new(applied)));
+            }
+        }
+        server
+    }
+
+    fn applied(&self, stream_id: &str) -> Arc<AsyncMutex<Applied>> {
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(streams.get_or_insert(stream_id.to_string(), Default::default))
+    }
+
+    // Reads batches off one connection and answers each with an ack, in order
+    async fn acknowledge(self, mut batches: Streaming<StreamBatch>, acks: mpsc::Sender<Result<BatchAck, Status>>) {
+        // Batches without a stream id are only deduplicated within this connection
+        let unnamed = Arc::new(AsyncMutex::new(Applied::default()));
+        let mut current: Option<(String, Arc<AsyncMutex<Applied>>)> = None;
+
+        loop {
+            let batch = match batches.message().await {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                Err(status) => {
+                    warn!("⚠️ gRPC stream closed with an error: {}", status);
+                    break;
+                }
+            };
+
+            let applied = match &current {
+                _ if batch.stream_id.is_empty() => Arc::clone(&unnamed),
+                Some((stream_id, applied)) if *stream_id == batch.stream_id => Arc::clone(applied),
+                _ => {
+                    let applied = self.applied(&batch.stream_id);
+                    current = Some((batch.stream_id.clone(), Arc::clone(&applied)));
+                    applied
+                }
+            };
+
+            let ack = self.apply(batch, &applied).await;
+            if acks.send(Ok(ack)).await.is_err() {
+                break; // The sender hung up
+            }
+        }
+        info!("📡 gRPC stream finished");
+    }
+
+    async fn apply(&self, batch: StreamBatch, applied: &AsyncMutex<Applied>) -> BatchAck {
+        let sequence = batch.sequence;
+        let ack = |status: AckStatus, error: String, retry_after_ms: u32| BatchAck {
+            sequence,
+            status: status as i32,
+            error,
+            retry_after_ms,
+        };
+        if sequence == 0 {
+            return ack(AckStatus::Rejected, "sequence numbers start at 1".to_string(), 0);
+        }
+        if batch.oldest_unacked > sequence {
+            return ack(AckStatus::Rejected, "oldest_unacked is past the batch's own sequence".to_string(), 0);
+        }
+
+        // Held until the batch is applied, so the same sequence arriving over a second
+        // connection waits for the first and is then answered DUPLICATE
+        let mut applied = applied.lock().await;
+        applied.settle_below(batch.oldest_unacked);
+        if applied.contains(sequence) {
+            return ack(AckStatus::Duplicate, String::new(), 0);
+        }
+        if !applied.has_room_for(sequence) {
+            warn!("⚠️ Stream {:?} is {} batches past gap {}, asking it to retry batch {}",
+                batch.stream_id, MAX_OUT_OF_ORDER, applied.through + 1, sequence);
+            let error = format!("waiting for batch {} before taking more out of order", applied.through + 1);
+            return ack(AckStatus::Retry, error, RETRY_AFTER_MS);
+        }
+
+        let logs = match decode_batch(Some(PROTOBUF_CONTENT_TYPE), &batch.batch) {
+            Ok(logs) => logs,
+            Err(e) => {
+                error!("❌ Invalid batch {} on stream {:?}: {}", sequence, batch.stream_id, e);
+                return ack(AckStatus::Rejected, e.to_string(), 0);
+            }
+        };
+        info!("✅ Received {} logs for processing (stream {:?}, sequence {})", logs.len(), batch.stream_id, sequence);
+
+        match self.state.ingest(logs).await {
+            Ok(()) => {
+                applied.insert(sequence);
+                if let Some(watermarks) = self.watermarks.as_ref().filter(|_| !batch.stream_id.is_empty()) {
+                    watermarks.record(&batch.stream_id, &applied).await;
+                }
+                ack(AckStatus::Accepted, String::new(), 0)
+            }
+            Err(e) if e.is::<QueueFull>() => {
+                warn!("⚠️ Outputs full, asking stream {:?} to retry batch {}", batch.stream_id, sequence);
+                ack(AckStatus::Retry, e.to_string(), RETRY_AFTER_MS)
+            }
+            Err(e) => {
+                error!("❌ Failed to ingest batch {} on stream {:?}: {}", sequence, batch.stream_id, e);
+                ack(AckStatus::Rejected, e.to_string(), 0)
+            }
+        }
+    }
+}
+
+impl StreamingService<StreamBatch> for LogStreamServer {
+    type Response = BatchAck;
+    type ResponseStream = ReceiverStream<Result<BatchAck, Status>>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<Streaming<StreamBatch>>) -> Self::Future {
+        let server = self.clone();
+        Box::pin(async move {
+            info!("📡 gRPC stream opened from {:?}", request.remote_addr());
+            let (acks, rx) = mpsc::channel(ACK_BUFFER);
+            tokio::spawn(server.acknowledge(request.into_inner(), acks));
+            Ok(Response::new(ReceiverStream::new(rx)))
+        })
+    }
+}
+
+// Routing glue that `tonic-build` would otherwise generate
+impl<B> Service<http::Request<B>> for LogStreamServer
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if request.uri().path() != STREAM_PATH {
+            return Box::pin(async { Ok(Status::unimplemented("unknown method").into_http()) });
+        }
+        let server = self.clone();
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::<BatchAck, StreamBatch>::default())
+                .accept_compressed(CompressionEncoding::Zstd)
+                .send_compressed(CompressionEncoding::Zstd);
+            Ok(grpc.streaming(server, request).await)
+        })
+    }
+}
+
+impl NamedService for LogStreamServer {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+// `state_path` keeps applied sequences across restarts; without it they're kept in memory only
+pub async fn start_grpc_server(addr: &str, state: Arc<AppState>, state_path: Option<PathBuf>) {
+    let addr = addr.parse().expect("⚠️ Invalid gRPC address");
+    let server = match state_path {
+        Some(path) => LogStreamServer::open(state, path).await,
+        None => LogStreamServer::new(state),
+    };
+    info!("📡 gRPC stream ingestion listening on {}", addr);
+
+    if let Err(e) = Server::builder()
+        .add_service(server)
+        .serve(addr)
+        .await
+    {
+        error!("❌ gRPC server error: {}", e);
+    }
+}
+
+// ✅ Sender side of `LogStream`: `stream` takes the outgoing batches and yields their acks
+#[derive(Clone)]
+pub struct LogStreamClient {
+    inner: tonic::client::Grpc<Channel>,
+}
+
+impl LogStreamClient {
+    pub async fn connect(url: String) -> Result<Self, tonic::transport::Error> {
+        let channel = Endpoint::from_shared(url)?.connect().await?;
+        let inner = tonic::client::Grpc::new(channel)
+            .send_compressed(CompressionEncoding::Zstd)
+            .accept_compressed(CompressionEncoding::Zstd);
+        Ok(Self { inner })
+    }
+
+    pub async fn stream(
+        &mut self,
+        batches: impl IntoStreamingRequest<Message = StreamBatch>,
+    ) -> Result<Streaming<BatchAck>, Status> {
+        self.inner
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(format!("gRPC channel not ready: {}", e)))?;
+        let path = http::uri::PathAndQuery::from_static(STREAM_PATH);
+        let codec = ProstCodec::<StreamBatch, BatchAck>::default();
+        Ok(self.inner.streaming(batches.into_streaming_request(), path, codec).await?.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::log;
+    use serde_json::json;
+    use tonic::transport::server::TcpIncoming;
+
+    // A pipeline whose only output appends to `path`, answering QueueFull past `queue_capacity`
+    fn state(path: &Path, queue_capacity: usize) -> Arc<AppState> {
+        let config = serde_json::from_value(json!({
+            "outputs": {"storage": {"type": "file", "path": path, "queue_capacity": queue_capacity, "when_full": "reject"}}
+        }))
+        .unwrap();
+        Arc::new(AppState::new("http://localhost:1/logs".to_string(), &config).unwrap())
+    }
+
+    fn batch(stream_id: &str, sequence: u64, messages: &[&str]) -> StreamBatch {
+        let logs: Vec<LogEntry> = messages.iter().map(|message| log(message, Some("INFO"), json!({}))).collect();
+        StreamBatch::encode(stream_id, sequence, &logs).unwrap()
+    }
+
+    fn status(ack: &BatchAck) -> AckStatus {
+        AckStatus::try_from(ack.status).unwrap()
+    }
+
+    async fn serve(server: LogStreamServer) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(Server::builder().add_service(server).serve_with_incoming(TcpIncoming::from(listener)));
+        url
+    }
+
+    // Sends `batches` over a new connection and returns the acks
+    async fn send(url: &str, batches: Vec<StreamBatch>) -> Vec<(u64, AckStatus)> {
+        let mut client = LogStreamClient::connect(url.to_string()).await.unwrap();
+        let mut acks = client.stream(tokio_stream::iter(batches)).await.unwrap();
+        let mut received = Vec::new();
+        while let Some(ack) = acks.message().await.unwrap() {
+            received.push((ack.sequence, status(&ack)));
+        }
+        received
+    }
+
+    fn applied(sequences: &[u64]) -> Applied {
+        let mut applied = Applied::default();
+        for &sequence in sequences {
+            applied.insert(sequence);
+        }
+        applied
+    }
+
+    #[test]
+    fn applied_tracks_sequences_above_a_gap() {
+        let mut applied = applied(&[1, 2, 4, 5]);
+        assert_eq!((applied.through, applied.above.len()), (2, 2));
+        assert!(applied.contains(4) && !applied.contains(3) && !applied.contains(6));
+
+        applied.insert(3);
+        assert_eq!((applied.through, applied.above.len()), (5, 0));
+    }
+
+    #[test]
+    fn a_full_gap_is_waited_for_not_skipped() {
+        let mut applied = applied(&[1]);
+        for sequence in 3..3 + MAX_OUT_OF_ORDER as u64 {
+            assert!(applied.has_room_for(sequence));
+            applied.insert(sequence);
+        }
+        // Batch 2 may still be resent, so later batches wait for it instead of jumping past it
+        let next = 3 + MAX_OUT_OF_ORDER as u64;
+        assert!(!applied.has_room_for(next));
+        assert!(applied.has_room_for(2));
+        assert!(!applied.contains(2));
+        assert_eq!(applied.through, 1);
+
+        applied.insert(2);
+        assert_eq!(applied.through, next - 1);
+        assert!(applied.has_room_for(next));
+    }
+
+    #[tokio::test]
+    async fn watermarks_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/grpc_streams.ndjson");
+        let (watermarks, loaded) = Watermarks::open(path.clone()).await;
+        assert!(loaded.is_empty());
+        watermarks.record("a", &applied(&[1])).await;
+        watermarks.record("b", &applied(&[1, 3])).await;
+        watermarks.record("a", &applied(&[1, 2])).await;
+
+        // The last line per stream wins, least recently applied first
+        let (watermarks, loaded) = Watermarks::open(path.clone()).await;
+        let loaded: Vec<(&str, &Applied)> = loaded.iter().map(|w| (w.stream_id.as_str(), &w.applied)).collect();
+        assert_eq!(loaded, [("b", &applied(&[1, 3])), ("a", &applied(&[1, 2]))]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        // A rewrite keeps one line per stream, and appends carry on after it
+        watermarks.rewrite(&mut *watermarks.file.lock().await).await.unwrap();
+        watermarks.record("c", &applied(&[1])).await;
+        let lines: Vec<String> = std::fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        assert_eq!(lines, [r#"{"stream_id":"b","through":1,"above":[3]}"#, r#"{"stream_id":"a","through":2}"#, r#"{"stream_id":"c","through":1}"#]);
+        assert_eq!(watermarks.file.lock().await.lines, 3);
+    }
+
+    #[test]
+    fn gaps_below_the_oldest_unacked_batch_are_closed() {
+        let mut applied = applied(&[1, 3, 6]);
+        // The sender gave up on 2; 4 and 5 are still on their way
+        applied.settle_below(4);
+        assert_eq!(applied.through, 3);
+        assert_eq!(applied.above, BTreeSet::from([6]));
+        // An older stamp (say, from a delayed resend) changes nothing
+        applied.settle_below(2);
+        assert_eq!(applied.through, 3);
+
+        applied.settle_below(7);
+        assert_eq!((applied.through, applied.above.len()), (6, 0));
+    }
+
+    #[tokio::test]
+    async fn malformed_batches_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = LogStreamServer::new(state(&dir.path().join("out.ndjson"), 100));
+        let applied = AsyncMutex::new(Applied::default());
+
+        let ack = server.apply(batch("s", 0, &["a"]), &applied).await;
+        assert_eq!((status(&ack), ack.error.as_str()), (AckStatus::Rejected, "sequence numbers start at 1"));
+        let ack = server.apply(StreamBatch { oldest_unacked: 3, ..batch("s", 2, &["a"]) }, &applied).await;
+        assert_eq!(status(&ack), AckStatus::Rejected);
+        // A length-delimited field that runs past the end of the batch
+        let ack = server.apply(StreamBatch { batch: b"\x12\x09".to_vec(), ..batch("s", 1, &[]) }, &applied).await;
+        assert_eq!((ack.sequence, status(&ack)), (1, AckStatus::Rejected));
+
+        // None of them counts as applied
+        assert_eq!(*applied.lock().await, Applied::default());
+    }
+
+    #[tokio::test]
+    async fn full_outputs_are_answered_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = LogStreamServer::new(state(&dir.path().join("out.ndjson"), 1));
+        let applied = AsyncMutex::new(Applied::default());
+
+        // Two events never fit a queue of one
+        let ack = server.apply(batch("s", 1, &["a", "b"]), &applied).await;
+        assert_eq!((status(&ack), ack.retry_after_ms), (AckStatus::Retry, RETRY_AFTER_MS));
+        assert!(ack.error.contains("storage"), "{}", ack.error);
+        // Not applied, so the resend is tried again rather than answered DUPLICATE
+        assert!(!applied.lock().await.contains(1));
+        let ack = server.apply(batch("s", 1, &["a", "b"]), &applied).await;
+        assert_eq!(status(&ack), AckStatus::Retry);
+    }
+
+    #[tokio::test]
+    async fn resends_over_a_new_connection_are_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = serve(LogStreamServer::new(state(&dir.path().join("out.ndjson"), 100))).await;
+
+        let first = send(&url, vec![batch("s", 1, &["a"]), batch("s", 2, &["b"])]).await;
+        assert_eq!(first, [(1, AckStatus::Accepted), (2, AckStatus::Accepted)]);
+        let second = send(&url, vec![batch("s", 2, &["b"]), batch("s", 3, &["c"]), batch("t", 2, &["b"])]).await;
+        assert_eq!(second, [(2, AckStatus::Duplicate), (3, AckStatus::Accepted), (2, AckStatus::Accepted)]);
+
+        // Unnamed batches are only deduplicated within their connection
+        let unnamed = send(&url, vec![batch("", 1, &["a"]), batch("", 1, &["a"])]).await;
+        assert_eq!(unnamed, [(1, AckStatus::Accepted), (1, AckStatus::Duplicate)]);
+        assert_eq!(send(&url, vec![batch("", 1, &["a"])]).await, [(1, AckStatus::Accepted)]);
+    }
+
+    #[tokio::test]
+    async fn resends_after_a_restart_are_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(&dir.path().join("out.ndjson"), 100);
+        let path = dir.path().join("grpc_streams.ndjson");
+
+        let server = LogStreamServer::open(Arc::clone(&state), path.clone()).await;
+        let ack = server.apply(batch("s", 1, &["a"]), &server.applied("s")).await;
+        assert_eq!(status(&ack), AckStatus::Accepted);
+
+        let restarted = LogStreamServer::open(state, path).await;
+        let ack = restarted.apply(batch("s", 1, &["a"]), &restarted.applied("s")).await;
+        assert_eq!(status(&ack), AckStatus::Duplicate);
+        let ack = restarted.apply(batch("s", 2, &["b"]), &restarted.applied("s")).await;
+        assert_eq!(status(&ack), AckStatus::Accepted);
+    }
+}
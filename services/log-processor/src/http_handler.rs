@@ -1,70 +1,175 @@
 use axum::{
     extract::State,
     body::Bytes,
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
-use serde_json::Value;
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::io::Read;
 use std::sync::Arc;
 use tracing::{info, error};
-use crate::{models::LogEntry, forwarder::send_logs};
-use lz4_flex::decompress_size_prepended;
+use crate::{models::LogEntry, outputs::{Outputs, QueueFull}, processor::{Pipeline, PipelineConfig}, wire::{decode_batch, UnsupportedFormat}};
+use lz4_flex::block::{decompress_into, uncompressed_size};
+
+const MAX_DECODED_BYTES: usize = 64 * 1024 * 1024; // Largest batch accepted once decompressed (the collector's default body limit)
 
-#[derive(Clone)]
 pub struct AppState {
-    pub storage_service_url: String,
+    pub pipeline: Arc<Pipeline>,
+    pub outputs: Outputs, // Routed, batched delivery to storage and other destinations
+}
+
+impl AppState {
+    pub fn new(storage_service_url: String, pipeline_config: &PipelineConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            pipeline: Arc::new(Pipeline::new(pipeline_config)?),
+            outputs: Outputs::new(&storage_service_url, pipeline_config)?,
+        })
+    }
+
+    // ✅ Runs a decoded batch through the pipeline and queues the surviving events for delivery
+    // (shared by `/logs` and the gRPC stream); a `QueueFull` error means the batch can be retried
+    pub async fn ingest(&self, logs: Vec<LogEntry>) -> anyhow::Result<()> {
+        let events = self.pipeline.run(logs).await.context("processing pipeline failed")?;
+        // Delivery, retries and dead-lettering happen in the background.
+        self.outputs.dispatch(events).await
+    }
 }
 
 pub async fn ingest_logs(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     body: Bytes,
-) -> impl IntoResponse {
-    // If a "Content-Encoding" header is provided and equals "lz4", decompress the payload.
-    let data = if let Some(encoding) = headers.get("content-encoding") {
-        if encoding.to_str().unwrap_or("").eq_ignore_ascii_case("lz4") {
-            match decompress_size_prepended(&body) {
-                Ok(decompressed) => decompressed,
+) -> Response {
+    // Decompress the payload according to "Content-Encoding" (the collector sends zstd).
+    let encoding = headers
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let data = match decompress(&encoding, &body, MAX_DECODED_BYTES) {
+        Ok(data) => data,
+        Err(status) => return status.into_response(),
+    };
+
+    // Decode protobuf or JSON (a single entry or an array) according to "Content-Type".
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let logs = match decode_batch(content_type, &data) {
+        Ok(logs) => logs,
+        Err(e) if e.is::<UnsupportedFormat>() => {
+            // 415 tells the sender to fall back to JSON
+            error!("❌ {}", e);
+            return (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string()).into_response();
+        }
+        Err(e) => {
+            error!("❌ Invalid log batch: {}", e);
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+
+    info!("✅ Received {} logs for processing", logs.len());
+
+    if let Err(e) = state.ingest(logs).await {
+        error!("❌ Failed to ingest logs: {}", e);
+        return if e.is::<QueueFull>() {
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        };
+    }
+
+    StatusCode::OK.into_response()
+}
+
+// ✅ Decompresses without ever holding more than `limit` bytes, so a small compressed body can't expand
+// into an arbitrarily large allocation; past the limit the request is refused with 413
+fn decompress(encoding: &str, body: &[u8], limit: usize) -> Result<Vec<u8>, StatusCode> {
+    match encoding {
+        "lz4" => {
+            // The size prefix is checked before anything is allocated
+            let (size, compressed) = uncompressed_size(body).map_err(|e| {
+                error!("❌ Failed to decompress LZ4 payload: {}", e);
+                StatusCode::BAD_REQUEST
+            })?;
+            if size > limit {
+                error!("❌ LZ4 payload expands to {} bytes, more than {}", size, limit);
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            let mut decompressed = vec![0u8; size];
+            match decompress_into(compressed, &mut decompressed) {
+                Ok(n) if n == size => Ok(decompressed),
+                Ok(n) => {
+                    error!("❌ Failed to decompress LZ4 payload: expected {} bytes, got {}", size, n);
+                    Err(StatusCode::BAD_REQUEST)
+                }
                 Err(e) => {
                     error!("❌ Failed to decompress LZ4 payload: {}", e);
-                    return StatusCode::BAD_REQUEST;
+                    Err(StatusCode::BAD_REQUEST)
                 }
             }
-        } else {
-            body.to_vec()
         }
-    } else {
-        body.to_vec()
-    };
-
-    // Parse the (possibly decompressed) data as JSON.
-    let json_payload: Value = match serde_json::from_slice(&data) {
-        Ok(val) => val,
-        Err(e) => {
-            error!("❌ Failed to parse JSON: {}", e);
-            return StatusCode::BAD_REQUEST;
+        "zstd" => {
+            let decoder = zstd::stream::read::Decoder::new(body).map_err(|e| {
+                error!("❌ Failed to decompress zstd payload: {}", e);
+                StatusCode::BAD_REQUEST
+            })?;
+            let mut decompressed = Vec::new();
+            if let Err(e) = decoder.take(limit as u64 + 1).read_to_end(&mut decompressed) {
+                error!("❌ Failed to decompress zstd payload: {}", e);
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            if decompressed.len() > limit {
+                error!("❌ zstd payload expands to more than {} bytes", limit);
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Ok(decompressed)
         }
-    };
+        _ => Ok(body.to_vec()),
+    }
+}
 
-    info!("📥 Incoming request: {:?}", json_payload);
+// ✅ Per-stage pipeline counters and timings, plus per-output delivery counters
+pub async fn pipeline_metrics(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let mut metrics = state.outputs.metrics();
+    metrics["stages"] = json!(state.pipeline.metrics());
+    Json(metrics)
+}
 
-    // Attempt to parse the JSON payload as an array of logs; if that fails, try parsing a single log entry.
-    let logs: Vec<LogEntry> = if let Ok(logs) = serde_json::from_value(json_payload.clone()) {
-        logs
-    } else if let Ok(single_log) = serde_json::from_value::<LogEntry>(json_payload) {
-        vec![single_log]
-    } else {
-        error!("❌ Invalid log format");
-        return StatusCode::BAD_REQUEST;
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lz4_flex::compress_prepend_size;
 
-    info!("✅ Received {} logs for processing", logs.len());
+    #[test]
+    fn decompresses_within_the_limit() {
+        let data = br#"[{"source":"api","level":"INFO","message":"hello"}]"#;
+        let zstd = zstd::stream::encode_all(&data[..], 3).unwrap();
+        assert_eq!(decompress("zstd", &zstd, data.len()).unwrap(), data);
+        assert_eq!(decompress("lz4", &compress_prepend_size(data), data.len()).unwrap(), data);
+        assert_eq!(decompress("", data, 0).unwrap(), data);
+    }
 
-    // Forward the logs to the storage service.
-    if let Err(e) = send_logs(logs, &state.storage_service_url).await {
-        error!("❌ Failed to forward logs: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR;
+    #[test]
+    fn refuses_bodies_that_expand_past_the_limit() {
+        // A zstd bomb: a few hundred bytes that decode to 16MiB
+        let bomb = zstd::stream::encode_all(&vec![0u8; 16 * 1024 * 1024][..], 19).unwrap();
+        assert!(bomb.len() < 4096);
+        assert_eq!(decompress("zstd", &bomb, 1024 * 1024), Err(StatusCode::PAYLOAD_TOO_LARGE));
+
+        // LZ4 is refused from its size prefix alone
+        let mut lying = compress_prepend_size(b"tiny");
+        lying[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decompress("lz4", &lying, 1024 * 1024), Err(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(decompress("lz4", &compress_prepend_size(&[7u8; 2048]), 1024), Err(StatusCode::PAYLOAD_TOO_LARGE));
     }
 
-    StatusCode::OK
+    #[test]
+    fn rejects_corrupt_payloads() {
+        assert_eq!(decompress("zstd", b"not zstd", 1024), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(decompress("lz4", &[1, 2], 1024), Err(StatusCode::BAD_REQUEST));
+        let mut truncated = compress_prepend_size(&[7u8; 512]);
+        truncated.truncate(truncated.len() - 2);
+        assert_eq!(decompress("lz4", &truncated, 1024), Err(StatusCode::BAD_REQUEST));
+    }
 }
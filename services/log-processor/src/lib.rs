@@ -0,0 +1,29 @@
+pub mod http_handler;
+pub mod models;
+pub mod forwarder;
+pub mod config;
+pub mod processor;
+pub mod grok;
+pub mod timestamp;
+pub mod severity;
+pub mod redaction;
+pub mod sampling;
+pub mod dedup;
+pub mod script;
+pub mod geoip;
+pub mod lookup;
+pub mod outputs;
+pub mod wire;
+pub mod grpc_handler;
+
+use axum::{Router, routing::{get, post}};
+use std::sync::Arc;
+use http_handler::{ingest_logs, pipeline_metrics, AppState};
+
+// Build the Axum application with state.
+pub fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/logs", post(ingest_logs))
+        .route("/metrics", get(pipeline_metrics))
+        .with_state(state)
+}
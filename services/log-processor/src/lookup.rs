@@ -0,0 +1,366 @@
+use crate::models::LogEntry;
+use crate::processor::{Action, BatchOutcome, Event, FileReloader, Stage, apply_actions};
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::info;
+
+// ✅ `{"type": "lookup", "path": "lookups/services.csv", "key_field": "source", "key_column": "service", "default": {"team": "unknown"}}`
+//
+// CSV files need a header row. JSON files are either an object keyed by lookup key, or an array
+// of objects carrying `key_column`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LookupConfig {
+    pub path: PathBuf, // `.csv` or `.json`
+    #[serde(default = "default_key_field")]
+    pub key_field: String, // Event field whose value is looked up
+    pub key_column: Option<String>, // Column holding the key; the first CSV column by default
+    #[serde(default)]
+    pub columns: Vec<String>, // Columns to merge; all but the key by default
+    #[serde(default)]
+    pub target: Option<String>, // Prefix for merged attributes, e.g. `service` -> `service.owner`
+    #[serde(default)]
+    pub default: HashMap<String, Value>, // Merged when the key is missing or not in the table
+    #[serde(default)]
+    pub case_insensitive: bool,
+    #[serde(default)]
+    pub overwrite: bool, // Replace attributes the event already has
+    #[serde(default = "default_reload_secs")]
+    pub reload_secs: u64, // How often the file is checked for changes; 0 disables reloading
+}
+
+fn default_key_field() -> String {
+    "source".to_string()
+}
+
+fn default_reload_secs() -> u64 {
+    30
+}
+
+type Table = HashMap<String, Map<String, Value>>;
+
+// How rows are read from the file, shared by the first load and every reload
+struct TableFormat {
+    key_column: Option<String>,
+    columns: Vec<String>,
+    case_insensitive: bool,
+}
+
+pub struct LookupStage {
+    key_field: String,
+    target: Option<String>,
+    default: Map<String, Value>,
+    overwrite: bool,
+    format: TableFormat,
+    table: FileReloader<Table>,
+    matched: AtomicU64,
+    missed: AtomicU64,
+}
+
+impl LookupStage {
+    pub fn new(config: &LookupConfig) -> Result<Self> {
+        let format = TableFormat {
+            key_column: config.key_column.clone(),
+            columns: config.columns.clone(),
+            case_insensitive: config.case_insensitive,
+        };
+        let table = FileReloader::new(&config.path, "lookup table", config.reload_secs, |path| format.load(path))?;
+        info!("📇 Loaded {} lookup rows from {:?}", table.get().len(), config.path);
+        Ok(Self {
+            key_field: config.key_field.clone(),
+            target: config.target.clone(),
+            default: config.default.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            overwrite: config.overwrite,
+            format,
+            table,
+            matched: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+        })
+    }
+
+    fn enrich(&self, table: &Table, log: &mut LogEntry) {
+        let row = log.field(&self.key_field).and_then(|key| table.get(&self.format.normalize_key(&key)));
+        let values = match row {
+            Some(row) => {
+                self.matched.fetch_add(1, Ordering::Relaxed);
+                row
+            }
+            None => {
+                self.missed.fetch_add(1, Ordering::Relaxed);
+                &self.default
+            }
+        };
+        for (column, value) in values {
+            let name = match &self.target {
+                Some(prefix) => format!("{}.{}", prefix, column),
+                None => column.clone(),
+            };
+            if self.overwrite || !log.attributes.contains_key(&name) {
+                log.attributes.insert(name, value.clone());
+            }
+        }
+    }
+}
+
+impl TableFormat {
+    fn load(&self, path: &Path) -> Result<Table> {
+        let raw = std::fs::read_to_string(path).with_context(|| format!("failed to read lookup table {:?}", path))?;
+        let rows = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => self.parse_csv(&raw),
+            Some("json") => self.parse_json(&raw),
+            _ => bail!("lookup tables must be .csv or .json, got {:?}", path),
+        }
+        .with_context(|| format!("invalid lookup table {:?}", path))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(key, mut row)| {
+                if !self.columns.is_empty() {
+                    row.retain(|column, _| self.columns.contains(column));
+                }
+                (self.normalize_key(&key), row)
+            })
+            .collect())
+    }
+
+    fn parse_csv(&self, raw: &str) -> Result<Vec<(String, Map<String, Value>)>> {
+        let mut reader = csv::Reader::from_reader(raw.as_bytes());
+        let headers = reader.headers()?.clone();
+        let key_index = match &self.key_column {
+            Some(column) => headers
+                .iter()
+                .position(|h| h == column)
+                .ok_or_else(|| anyhow!("key column '{}' not in header", column))?,
+            None => 0,
+        };
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let Some(key) = record.get(key_index) else {
+                continue;
+            };
+            // Empty cells are treated as absent, not as empty strings
+            let row = headers
+                .iter()
+                .zip(record.iter())
+                .enumerate()
+                .filter(|(i, (_, cell))| *i != key_index && !cell.is_empty())
+                .map(|(_, (header, cell))| (header.to_string(), Value::String(cell.to_string())))
+                .collect();
+            rows.push((key.to_string(), row));
+        }
+        Ok(rows)
+    }
+
+    fn parse_json(&self, raw: &str) -> Result<Vec<(String, Map<String, Value>)>> {
+        let as_key = |value: &Value| match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        };
+        match serde_json::from_str::<Value>(raw)? {
+            Value::Object(table) => table
+                .into_iter()
+                .map(|(key, row)| match row {
+                    Value::Object(row) => Ok((key, row)),
+                    _ => bail!("row '{}' is not an object", key),
+                })
+                .collect(),
+            Value::Array(rows) => {
+                let column = self.key_column.as_deref().ok_or_else(|| anyhow!("array lookup tables need `key_column`"))?;
+                rows.into_iter()
+                    .filter_map(|row| match row {
+                        Value::Object(mut row) => row.remove(column).and_then(|key| as_key(&key)).map(|key| Ok((key, row))),
+                        _ => Some(Err(anyhow!("lookup rows must be objects"))),
+                    })
+                    .collect()
+            }
+            _ => bail!("lookup tables must be a JSON object or array"),
+        }
+    }
+
+    fn normalize_key(&self, key: &str) -> String {
+        if self.case_insensitive {
+            key.trim().to_lowercase()
+        } else {
+            key.trim().to_string()
+        }
+    }
+}
+
+impl Stage for LookupStage {
+    fn name(&self) -> &str {
+        "lookup"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        self.enrich(&self.table.get(), log);
+        Action::Keep
+    }
+
+    fn process_batch(&self, events: &mut Vec<Event>) -> BatchOutcome {
+        self.table.reload_if_changed(|path| self.format.load(path));
+        let table = self.table.get();
+        let actions: Vec<Action> = events
+            .par_iter_mut()
+            .map(|event| {
+                self.enrich(&table, &mut event.log);
+                Action::Keep
+            })
+            .collect();
+        apply_actions(events, actions)
+    }
+
+    fn details(&self) -> Option<Value> {
+        Some(json!({
+            "rows": self.table.get().len(),
+            "matched": self.matched.load(Ordering::Relaxed),
+            "missed": self.missed.load(Ordering::Relaxed),
+            "reloads": self.table.reloads(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, events, log};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    // Writes `contents` to `name` in a fresh directory, which must outlive the stage
+    fn table(name: &str, contents: &str) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        (dir, path)
+    }
+
+    fn stage(path: &Path, mut config: Value) -> Result<LookupStage> {
+        config["path"] = json!(path);
+        configured(LookupStage::new, config)
+    }
+
+    fn from(source: &str, attributes: Value) -> LogEntry {
+        LogEntry { source: Some(source.to_string()), ..log("request", None, attributes) }
+    }
+
+    fn run(stage: &LookupStage, sources: &[&str]) -> Vec<Event> {
+        let mut events = events(sources.iter().map(|source| from(source, json!({}))));
+        stage.process_batch(&mut events);
+        events
+    }
+
+    const SERVICES: &str = "service,team,tier\napi,payments,1\nWorker,,2\n";
+
+    #[test]
+    fn csv_rows_are_keyed_by_first_column() {
+        let (_dir, path) = table("services.csv", SERVICES);
+        let stage = stage(&path, json!({})).unwrap();
+        let events = run(&stage, &["api", "Worker", "worker"]);
+
+        assert_eq!(events[0].log.attributes, serde_json::from_value(json!({"team": "payments", "tier": "1"})).unwrap());
+        // Empty cells are absent rather than empty strings
+        assert_eq!(events[1].log.attributes, serde_json::from_value(json!({"tier": "2"})).unwrap());
+        assert!(events[2].log.attributes.is_empty());
+        assert_eq!(stage.details().unwrap(), json!({"rows": 2, "matched": 2, "missed": 1, "reloads": 0}));
+    }
+
+    #[test]
+    fn csv_key_column_columns_target_and_case() {
+        let (_dir, path) = table("services.csv", SERVICES);
+        let by_team = stage(
+            &path,
+            json!({"key_column": "team", "key_field": "team", "columns": ["service"], "target": "svc", "case_insensitive": true}),
+        )
+        .unwrap();
+
+        let mut entry = from("edge", json!({"team": " PAYMENTS "}));
+        by_team.process(&mut entry);
+        assert_eq!(entry.attributes["svc.service"], "api");
+        assert!(!entry.attributes.contains_key("svc.tier"));
+
+        let missing = stage(&path, json!({"key_column": "owner"}));
+        assert!(format!("{:#}", missing.err().unwrap()).contains("key column 'owner' not in header"));
+    }
+
+    #[test]
+    fn defaults_fill_misses_and_existing_attributes_are_kept() {
+        let (_dir, path) = table("services.csv", SERVICES);
+        let kept = stage(&path, json!({"default": {"team": "unknown"}})).unwrap();
+        let mut unknown = from("billing", json!({}));
+        let mut tagged = from("api", json!({"team": "platform"}));
+        kept.process(&mut unknown);
+        kept.process(&mut tagged);
+        assert_eq!(unknown.attributes["team"], "unknown");
+        assert_eq!(tagged.attributes["team"], "platform");
+        assert_eq!(tagged.attributes["tier"], "1");
+
+        let overwriting = stage(&path, json!({"overwrite": true})).unwrap();
+        let mut tagged = from("api", json!({"team": "platform"}));
+        overwriting.process(&mut tagged);
+        assert_eq!(tagged.attributes["team"], "payments");
+    }
+
+    #[test]
+    fn json_objects_and_arrays() {
+        let (_dir, path) = table("hosts.json", r#"{"web-1": {"rack": "a1", "cores": 16}}"#);
+        let keyed = stage(&path, json!({"key_field": "host"})).unwrap();
+        let mut entry = from("edge", json!({"host": "web-1"}));
+        keyed.process(&mut entry);
+        assert_eq!(entry.attributes["rack"], "a1");
+        assert_eq!(entry.attributes["cores"], 16);
+
+        // Non-string keys are matched by their JSON text; rows without a key are skipped
+        let (_dir, path) = table("ports.json", r#"[{"port": 443, "name": "https"}, {"port": null, "name": "none"}, {"name": "keyless"}]"#);
+        let rows = stage(&path, json!({"key_field": "port", "key_column": "port"})).unwrap();
+        assert_eq!(rows.details().unwrap()["rows"], 1);
+        let mut entry = from("edge", json!({"port": 443}));
+        rows.process(&mut entry);
+        assert_eq!(entry.attributes["name"], "https");
+        // The key column itself isn't merged back
+        assert_eq!(entry.attributes.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        let cases = [
+            ("rows.json", r#"[{"port": 443}]"#, json!({}), "need `key_column`"),
+            ("rows.json", r#"{"web-1": "a1"}"#, json!({}), "row 'web-1' is not an object"),
+            ("rows.json", r#"[{"port": 443}, 7]"#, json!({"key_column": "port"}), "lookup rows must be objects"),
+            ("rows.json", r#""web-1""#, json!({}), "JSON object or array"),
+            ("rows.yaml", "web-1: a1", json!({}), "must be .csv or .json"),
+        ];
+        for (name, contents, config, expected) in cases {
+            let (_dir, path) = table(name, contents);
+            let error = format!("{:#}", stage(&path, config).err().unwrap());
+            assert!(error.contains(expected), "{}: {}", name, error);
+        }
+
+        let missing = stage(Path::new("/nonexistent/services.csv"), json!({}));
+        assert!(format!("{:#}", missing.err().unwrap()).contains("failed to read lookup table"));
+    }
+
+    #[test]
+    fn changed_tables_are_reloaded() {
+        let (_dir, path) = table("services.csv", SERVICES);
+        let stage = stage(&path, json!({"reload_secs": 1})).unwrap();
+        assert_eq!(run(&stage, &["api"])[0].log.attributes["team"], "payments");
+
+        // A broken edit keeps the previous table
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(&path, "service,team\n\"api,payments\n").unwrap();
+        assert_eq!(run(&stage, &["api"])[0].log.attributes["team"], "payments");
+
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(&path, "service,team\napi,checkout\nbilling,finance\n").unwrap();
+        assert_eq!(run(&stage, &["api"])[0].log.attributes["team"], "checkout");
+        assert_eq!(stage.details().unwrap()["rows"], 2);
+        assert_eq!(stage.details().unwrap()["reloads"], 1);
+    }
+}
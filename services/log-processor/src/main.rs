@@ -1,14 +1,11 @@
-mod http_handler;
-mod models;
-mod forwarder;
-mod config;
-
-use axum::{Router, routing::post};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tracing::info;
-use http_handler::{ingest_logs, AppState};
-use config::Config;
+use log_processor::app;
+use log_processor::grpc_handler::start_grpc_server;
+use log_processor::http_handler::AppState;
+use log_processor::config::Config;
+use log_processor::processor::PipelineConfig;
 
 #[tokio::main]
 async fn main() {
@@ -19,14 +16,23 @@ async fn main() {
 
     // Load configuration from the environment.
     let config = Config::new();
-    let state = Arc::new(AppState { 
-        storage_service_url: config.storage_service_url 
+    let pipeline_config = match &config.pipeline_config {
+        Some(path) => PipelineConfig::from_file(path).expect("⚠️ Failed to load pipeline config"),
+        None => PipelineConfig::default(),
+    };
+    let state = Arc::new(
+        AppState::new(config.storage_service_url, &pipeline_config).expect("⚠️ Failed to build processing pipeline"),
+    );
+
+    // gRPC streaming ingestion alongside the HTTP API, sharing the pipeline and outputs.
+    tokio::spawn({
+        let state = state.clone();
+        let state_path = config.grpc_state_path.clone();
+        async move { start_grpc_server("0.0.0.0:4001", state, state_path).await }
     });
 
     // Build the Axum application with state.
-    let app = Router::new()
-        .route("/logs", post(ingest_logs))
-        .with_state(state);
+    let app = app(state);
 
     // Define the socket address for binding.
     let addr: SocketAddr = "0.0.0.0:4000".parse().expect("Invalid address");
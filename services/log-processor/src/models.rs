@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LogEntry {
     // Optional timestamp (modify the type/format as needed)
     pub timestamp: Option<String>,
@@ -8,4 +11,29 @@ pub struct LogEntry {
     pub level: Option<String>,
     // Required log message
     pub message: String,
+    // Origin reported by the collector (file, container, service name, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    // Structured fields carried over from the collector
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub attributes: HashMap<String, Value>,
+}
+
+impl LogEntry {
+    // ✅ Looks up a top-level field or, failing that, an attribute (non-strings are stringified)
+    pub fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "message" => Some(Cow::Borrowed(self.message.as_str())),
+            "level" => self.level.as_deref().map(Cow::Borrowed),
+            "source" => self.source.as_deref().map(Cow::Borrowed),
+            "timestamp" => self.timestamp.as_deref().map(Cow::Borrowed),
+            _ => {
+                let key = name.strip_prefix("attributes.").unwrap_or(name);
+                self.attributes.get(key).map(|value| match value {
+                    Value::String(s) => Cow::Borrowed(s.as_str()),
+                    other => Cow::Owned(other.to_string()),
+                })
+            }
+        }
+    }
 }
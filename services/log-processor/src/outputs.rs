@@ -0,0 +1,862 @@
+use crate::forwarder::{send_logs, StatusError};
+use http_client::HttpClientConfig;
+use crate::wire::{Negotiated, WireFormat};
+use crate::models::LogEntry;
+use crate::processor::{Condition, Event, PipelineConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout_at, Instant};
+use tracing::{error, info, warn};
+
+// Name of the implicit output pointing at `STORAGE_SERVICE_URL`
+pub const STORAGE_OUTPUT: &str = "storage";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        client: Option<HttpClientConfig>, // Own client instead of the shared `http_client`
+        #[serde(default)]
+        format: WireFormat, // `protobuf` falls back to JSON while the downstream answers 415
+    },
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        client: Option<HttpClientConfig>, // Own client instead of the shared `http_client`
+    },
+    Kafka {
+        brokers: String,
+        topic: String,
+        #[serde(default)]
+        key_field: Option<String>, // Message key, e.g. `source` to keep a source on one partition
+        #[serde(default)]
+        properties: HashMap<String, String>, // Extra librdkafka producer settings
+    },
+    File {
+        path: PathBuf, // NDJSON, appended
+        #[serde(default)]
+        max_bytes: Option<u64>, // Rotates to `<path>.<unix seconds>` beyond this size
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchConfig {
+    #[serde(default = "default_max_events")]
+    pub max_events: usize,
+    #[serde(default = "default_linger_ms")]
+    pub linger_ms: u64, // Longest an event waits for its batch to fill
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self { max_events: default_max_events(), linger_ms: default_linger_ms() }
+    }
+}
+
+fn default_max_events() -> usize {
+    500
+}
+
+fn default_linger_ms() -> u64 {
+    1_000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64, // Doubled after every failed attempt
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    200
+}
+
+fn default_max_backoff_ms() -> u64 {
+    10_000
+}
+
+// ✅ What happens when an output's queue is full
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenFull {
+    #[default]
+    Block, // Wait for room, slowing down ingestion
+    Reject, // Fail the request with 503 so the sender retries
+    Drop, // Discard the event
+    DeadLetter, // Hand the event to the dead-letter output
+}
+
+// ✅ `{"type": "http", "url": "...", "batch": {"max_events": 500}, "retry": {"max_attempts": 5}, "when_full": "block"}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutputConfig {
+    #[serde(flatten)]
+    pub sink: SinkConfig,
+    #[serde(default)]
+    pub batch: BatchConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize, // Events buffered ahead of the sink
+    #[serde(default)]
+    pub when_full: WhenFull,
+}
+
+fn default_queue_capacity() -> usize {
+    10_000
+}
+
+// ✅ First matching rule wins unless it sets `continue`; events routed by a pipeline `route` stage skip the rules
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoutingRule {
+    #[serde(default)]
+    pub when: Vec<Condition>, // All must match; empty matches everything
+    pub outputs: Vec<String>,
+    #[serde(default, rename = "continue")]
+    pub continue_matching: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoutingConfig {
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+    #[serde(default)]
+    pub default: Option<Vec<String>>, // For events no rule matched; `["storage"]` if unset, `[]` sends them to the dead letter
+    #[serde(default)]
+    pub dead_letter: Option<String>, // Output for unroutable and undeliverable events
+}
+
+#[derive(Default)]
+struct OutputMetrics {
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    batches: AtomicU64,
+    retries: AtomicU64,
+    failed_batches: AtomicU64,
+    dead_lettered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+enum Sink {
+    Http { client: Client, url: String, headers: Vec<(String, String)>, wire: Negotiated },
+    File { path: PathBuf, max_bytes: Option<u64> },
+    #[cfg(feature = "kafka")]
+    Kafka(kafka::KafkaSink),
+}
+
+impl Sink {
+    fn new(config: &SinkConfig, shared_client: &Client) -> Result<Self> {
+        Ok(match config {
+            SinkConfig::Http { url, headers, client, format } => Sink::http(url, headers, client, *format, shared_client)?,
+            // Webhook receivers are third-party endpoints, so they only ever get JSON
+            SinkConfig::Webhook { url, headers, client } => Sink::http(url, headers, client, WireFormat::Json, shared_client)?,
+            SinkConfig::File { path, max_bytes } => Sink::File { path: path.clone(), max_bytes: *max_bytes },
+            #[cfg(feature = "kafka")]
+            SinkConfig::Kafka { brokers, topic, key_field, properties } => {
+                Sink::Kafka(kafka::KafkaSink::new(brokers, topic, key_field.clone(), properties)?)
+            }
+            #[cfg(not(feature = "kafka"))]
+            SinkConfig::Kafka { .. } => bail!("Kafka outputs need log-processor built with the `kafka` feature"),
+        })
+    }
+
+    fn http(
+        url: &str,
+        headers: &HashMap<String, String>,
+        client: &Option<HttpClientConfig>,
+        format: WireFormat,
+        shared_client: &Client,
+    ) -> Result<Self> {
+        Ok(Sink::Http {
+            // Cloning shares the pool; only an explicit `client` block gets its own
+            client: match client {
+                Some(config) => config.build()?,
+                None => shared_client.clone(),
+            },
+            url: url.to_string(),
+            headers: headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            wire: Negotiated::new(format),
+        })
+    }
+
+    async fn send(&self, batch: &[LogEntry]) -> Result<()> {
+        match self {
+            Sink::Http { client, url, headers, wire } => send_logs(client, batch, url, headers, wire).await,
+            Sink::File { path, max_bytes } => append_ndjson(path, *max_bytes, batch).await,
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(sink) => sink.send(batch).await,
+        }
+    }
+}
+
+async fn append_ndjson(path: &Path, max_bytes: Option<u64>, batch: &[LogEntry]) -> Result<()> {
+    if let Some(max_bytes) = max_bytes {
+        let size = tokio::fs::metadata(path).await.map(|meta| meta.len()).unwrap_or(0);
+        if size >= max_bytes {
+            let rotated = format!("{}.{}", path.display(), chrono::Utc::now().timestamp());
+            tokio::fs::rename(path, &rotated).await.with_context(|| format!("failed to rotate {:?}", path))?;
+        }
+    }
+    let mut buffer = Vec::new();
+    for log in batch {
+        serde_json::to_writer(&mut buffer, log)?;
+        buffer.push(b'\n');
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("failed to open {:?}", path))?;
+    file.write_all(&buffer).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+fn is_permanent(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<StatusError>().is_some_and(StatusError::is_permanent)
+}
+
+// Marks why an event ended up in the dead letter; unroutable events have no output
+fn dead_letter_annotate(log: &mut LogEntry, output: Option<&str>, reason: &str) {
+    if let Some(output) = output {
+        log.attributes.insert("dead_letter.output".to_string(), json!(output));
+    }
+    log.attributes.insert("dead_letter.reason".to_string(), json!(reason));
+}
+
+#[derive(Clone)]
+struct DeadLetter {
+    name: String,
+    tx: mpsc::Sender<LogEntry>,
+    metrics: Arc<OutputMetrics>,
+}
+
+impl DeadLetter {
+    async fn send(&self, mut logs: Vec<LogEntry>, output: Option<&str>, reason: &str) {
+        for mut log in logs.drain(..) {
+            dead_letter_annotate(&mut log, output, reason);
+            if self.tx.send(log).await.is_err() {
+                error!("❌ Dead-letter output '{}' is gone, losing events", self.name);
+                return;
+            }
+            self.metrics.enqueued.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Output {
+    tx: mpsc::Sender<LogEntry>,
+    capacity: usize,
+    when_full: WhenFull,
+    metrics: Arc<OutputMetrics>,
+}
+
+struct Worker {
+    name: String,
+    sink: Sink,
+    batch: BatchConfig,
+    retry: RetryConfig,
+    metrics: Arc<OutputMetrics>,
+    dead_letter: Option<DeadLetter>,
+}
+
+impl Worker {
+    async fn run(self, mut rx: mpsc::Receiver<LogEntry>) {
+        let linger = Duration::from_millis(self.batch.linger_ms);
+        let max_events = self.batch.max_events.max(1);
+        loop {
+            let Some(first) = rx.recv().await else {
+                return;
+            };
+            let mut batch = vec![first];
+            let deadline = Instant::now() + linger;
+            while batch.len() < max_events {
+                match timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(log)) => batch.push(log),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            self.deliver(batch).await;
+        }
+    }
+
+    async fn deliver(&self, batch: Vec<LogEntry>) {
+        let mut backoff = Duration::from_millis(self.retry.initial_backoff_ms);
+        let max_backoff = Duration::from_millis(self.retry.max_backoff_ms);
+        let mut attempt = 1;
+        loop {
+            match self.sink.send(&batch).await {
+                Ok(()) => {
+                    self.metrics.batches.fetch_add(1, Ordering::Relaxed);
+                    self.metrics.sent.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) if attempt < self.retry.max_attempts && !is_permanent(&e) => {
+                    self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+                    warn!("⚠️ Output '{}' attempt {} failed, retrying in {:?}: {:#}", self.name, attempt, backoff, e);
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(max_backoff);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    self.metrics.failed_batches.fetch_add(1, Ordering::Relaxed);
+                    error!("❌ Output '{}' gave up on {} events after {} attempts: {:#}", self.name, batch.len(), attempt, e);
+                    match &self.dead_letter {
+                        Some(dead_letter) => {
+                            self.metrics.dead_lettered.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                            dead_letter.send(batch, Some(&self.name), &format!("{:#}", e)).await;
+                        }
+                        None => {
+                            self.metrics.dropped.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QueueFull(pub String);
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "output '{}' is full", self.0)
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+// ✅ Named outputs, each drained by its own batching worker, and the rules that pick them
+pub struct Outputs {
+    outputs: HashMap<String, Output>,
+    rules: Vec<RoutingRule>,
+    default: Vec<String>,
+    dead_letter: Option<DeadLetter>,
+    unroutable: AtomicU64,
+}
+
+impl Outputs {
+    /// Spawns one worker per output; must be called inside a tokio runtime.
+    pub fn new(storage_service_url: &str, config: &PipelineConfig) -> Result<Self> {
+        let mut configs = config.outputs.clone();
+        configs.entry(STORAGE_OUTPUT.to_string()).or_insert_with(|| http_output(storage_service_url));
+        // Legacy `routes` (route name -> storage URL) become plain HTTP outputs
+        for (route, url) in &config.routes {
+            configs.entry(route.clone()).or_insert_with(|| http_output(url));
+        }
+
+        let routing = &config.routing;
+        let default = routing.default.clone().unwrap_or_else(|| vec![STORAGE_OUTPUT.to_string()]);
+        for name in routing.rules.iter().flat_map(|rule| &rule.outputs).chain(&default).chain(&routing.dead_letter) {
+            if !configs.contains_key(name) {
+                bail!("routing refers to unknown output '{}'", name);
+            }
+        }
+
+        // The dead-letter output starts first so the others can hand failures to it
+        let mut names: Vec<&String> = configs.keys().collect();
+        names.sort_by_key(|name| Some(*name) != routing.dead_letter.as_ref());
+
+        let client = config.http_client.build().context("invalid `http_client`")?;
+        let mut outputs = HashMap::new();
+        let mut dead_letter = None;
+        for name in names {
+            let config = &configs[name];
+            let sink = Sink::new(&config.sink, &client).with_context(|| format!("invalid output '{}'", name))?;
+            let capacity = config.queue_capacity.max(1);
+            let (tx, rx) = mpsc::channel(capacity);
+            let metrics = Arc::new(OutputMetrics::default());
+            let is_dead_letter = Some(name) == routing.dead_letter.as_ref();
+            let worker = Worker {
+                name: name.clone(),
+                sink,
+                batch: config.batch.clone(),
+                retry: config.retry.clone(),
+                metrics: Arc::clone(&metrics),
+                // The dead letter itself has nowhere further to go
+                dead_letter: if is_dead_letter { None } else { dead_letter.clone() },
+            };
+            tokio::spawn(worker.run(rx));
+            if is_dead_letter {
+                dead_letter = Some(DeadLetter { name: name.clone(), tx: tx.clone(), metrics: Arc::clone(&metrics) });
+            }
+            outputs.insert(name.clone(), Output { tx, capacity, when_full: config.when_full, metrics });
+        }
+
+        info!("📤 Outputs ready: {}", {
+            let mut names: Vec<_> = outputs.keys().map(String::as_str).collect();
+            names.sort();
+            names.join(", ")
+        });
+        Ok(Self {
+            outputs,
+            rules: routing.rules.clone(),
+            default,
+            dead_letter,
+            unroutable: AtomicU64::new(0),
+        })
+    }
+
+    fn targets(&self, event: &Event) -> Vec<&str> {
+        if let Some(route) = &event.route {
+            if let Some((name, _)) = self.outputs.get_key_value(route) {
+                return vec![name.as_str()];
+            }
+            warn!("⚠️ No output configured for route '{}', using routing rules", route);
+        }
+        let mut targets: Vec<&str> = Vec::new();
+        for rule in &self.rules {
+            if rule.when.iter().all(|condition| condition.matches(&event.log)) {
+                for name in &rule.outputs {
+                    if !targets.contains(&name.as_str()) {
+                        targets.push(name);
+                    }
+                }
+                if !rule.continue_matching {
+                    return targets;
+                }
+            }
+        }
+        if targets.is_empty() {
+            targets.extend(self.default.iter().map(String::as_str));
+        }
+        targets
+    }
+
+    /// Queues every event on its outputs. Fails only when an output set to `reject` is full;
+    /// room on those is reserved for the whole batch first, so a rejected batch was queued
+    /// nowhere and the sender's retry can't duplicate it.
+    pub async fn dispatch(&self, events: Vec<Event>) -> Result<()> {
+        let routed: Vec<(LogEntry, Vec<&str>)> = events
+            .into_iter()
+            .map(|event| {
+                let targets = self.targets(&event);
+                (event.log, targets)
+            })
+            .collect();
+
+        let mut needed: HashMap<&str, usize> = HashMap::new();
+        for name in routed.iter().flat_map(|(_, targets)| targets) {
+            if self.outputs[*name].when_full == WhenFull::Reject {
+                *needed.entry(name).or_default() += 1;
+            }
+        }
+        let mut reserved = HashMap::new();
+        for (name, count) in needed {
+            match self.outputs[name].tx.try_reserve_many(count) {
+                Ok(permits) => reserved.insert(name, permits),
+                Err(mpsc::error::TrySendError::Full(())) => return Err(anyhow!(QueueFull(name.to_string()))),
+                Err(mpsc::error::TrySendError::Closed(())) => bail!("output '{}' has stopped", name),
+            };
+        }
+
+        for (log, targets) in routed {
+            if targets.is_empty() {
+                self.unroutable.fetch_add(1, Ordering::Relaxed);
+                match &self.dead_letter {
+                    Some(dead_letter) => dead_letter.send(vec![log], None, "no matching route").await,
+                    None => warn!("⚠️ Dropping event that matched no route"),
+                }
+                continue;
+            }
+            // Fan-out copies the event; the last output takes the original
+            let (last, rest) = targets.split_last().expect("targets is not empty");
+            for name in rest {
+                self.enqueue(name, log.clone(), &mut reserved).await?;
+            }
+            self.enqueue(last, log, &mut reserved).await?;
+        }
+        Ok(())
+    }
+
+    async fn enqueue(&self, name: &str, log: LogEntry, reserved: &mut HashMap<&str, mpsc::PermitIterator<'_, LogEntry>>) -> Result<()> {
+        let output = &self.outputs[name];
+        if let Some(permit) = reserved.get_mut(name).and_then(Iterator::next) {
+            permit.send(log);
+            output.metrics.enqueued.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        let log = match output.when_full {
+            WhenFull::Block => {
+                output.tx.send(log).await.map_err(|_| anyhow!("output '{}' has stopped", name))?;
+                output.metrics.enqueued.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            _ => match output.tx.try_send(log) {
+                Ok(()) => {
+                    output.metrics.enqueued.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => bail!("output '{}' has stopped", name),
+                Err(mpsc::error::TrySendError::Full(log)) => log,
+            },
+        };
+
+        match (output.when_full, &self.dead_letter) {
+            (WhenFull::Reject, _) => Err(anyhow!(QueueFull(name.to_string()))),
+            (WhenFull::DeadLetter, Some(dead_letter)) if dead_letter.name != name => {
+                output.metrics.dead_lettered.fetch_add(1, Ordering::Relaxed);
+                dead_letter.send(vec![log], Some(name), "output queue full").await;
+                Ok(())
+            }
+            _ => {
+                output.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> Value {
+        let outputs: serde_json::Map<String, Value> = self
+            .outputs
+            .iter()
+            .map(|(name, output)| {
+                let m = &output.metrics;
+                (
+                    name.clone(),
+                    json!({
+                        "queued": output.capacity - output.tx.capacity(),
+                        "enqueued": m.enqueued.load(Ordering::Relaxed),
+                        "sent": m.sent.load(Ordering::Relaxed),
+                        "batches": m.batches.load(Ordering::Relaxed),
+                        "retries": m.retries.load(Ordering::Relaxed),
+                        "failed_batches": m.failed_batches.load(Ordering::Relaxed),
+                        "dead_lettered": m.dead_lettered.load(Ordering::Relaxed),
+                        "dropped": m.dropped.load(Ordering::Relaxed),
+                    }),
+                )
+            })
+            .collect();
+        json!({ "outputs": outputs, "unroutable": self.unroutable.load(Ordering::Relaxed) })
+    }
+}
+
+fn http_output(url: &str) -> OutputConfig {
+    OutputConfig {
+        sink: SinkConfig::Http { url: url.to_string(), headers: HashMap::new(), client: None, format: WireFormat::default() },
+        batch: BatchConfig::default(),
+        retry: RetryConfig::default(),
+        queue_capacity: default_queue_capacity(),
+        when_full: WhenFull::default(),
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use crate::models::LogEntry;
+    use anyhow::{anyhow, Context, Result};
+    use rdkafka::config::ClientConfig;
+    use rdkafka::producer::{FutureProducer, FutureRecord};
+    use std::collections::HashMap;
+
+    pub struct KafkaSink {
+        producer: FutureProducer,
+        topic: String,
+        key_field: Option<String>,
+    }
+
+    impl KafkaSink {
+        pub fn new(brokers: &str, topic: &str, key_field: Option<String>, properties: &HashMap<String, String>) -> Result<Self> {
+            let mut config = ClientConfig::new();
+            config.set("bootstrap.servers", brokers).set("message.timeout.ms", "30000");
+            for (key, value) in properties {
+                config.set(key, value);
+            }
+            let producer = config.create().context("failed to create Kafka producer")?;
+            Ok(Self { producer, topic: topic.to_string(), key_field })
+        }
+
+        // Queues the whole batch before awaiting any delivery, so librdkafka can batch it
+        pub async fn send(&self, batch: &[LogEntry]) -> Result<()> {
+            let mut deliveries = Vec::with_capacity(batch.len());
+            for log in batch {
+                let payload = serde_json::to_vec(log)?;
+                let key = self.key_field.as_deref().and_then(|field| log.field(field)).map(|key| key.into_owned());
+                let mut record = FutureRecord::<String, Vec<u8>>::to(&self.topic).payload(&payload);
+                if let Some(key) = &key {
+                    record = record.key(key);
+                }
+                let delivery = self.producer.send_result(record).map_err(|(e, _)| anyhow!("Kafka enqueue failed: {}", e))?;
+                deliveries.push(delivery);
+            }
+            for delivery in deliveries {
+                match delivery.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err((e, _))) => return Err(anyhow!("Kafka delivery failed: {}", e)),
+                    Err(_) => return Err(anyhow!("Kafka producer dropped the message")),
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{events, log};
+    use axum::{http::StatusCode, routing::post, Router};
+
+    fn batch(messages: &[&str]) -> Vec<Event> {
+        events(messages.iter().map(|message| log(message, Some("INFO"), json!({}))))
+    }
+
+    // Outputs without workers: the test holds each queue's receiving end
+    fn outputs(
+        specs: &[(&str, usize, WhenFull)],
+        routing: Value,
+    ) -> (Outputs, HashMap<String, mpsc::Receiver<LogEntry>>) {
+        let routing: RoutingConfig = serde_json::from_value(routing).unwrap();
+        let mut outputs = HashMap::new();
+        let mut receivers = HashMap::new();
+        for (name, capacity, when_full) in specs {
+            let (tx, rx) = mpsc::channel(*capacity);
+            let metrics = Arc::new(OutputMetrics::default());
+            outputs.insert(name.to_string(), Output { tx, capacity: *capacity, when_full: *when_full, metrics });
+            receivers.insert(name.to_string(), rx);
+        }
+        let dead_letter = routing.dead_letter.as_ref().map(|name| {
+            let output: &Output = &outputs[name];
+            DeadLetter { name: name.clone(), tx: output.tx.clone(), metrics: Arc::clone(&output.metrics) }
+        });
+        let outputs = Outputs {
+            outputs,
+            rules: routing.rules,
+            default: routing.default.unwrap_or_else(|| vec![STORAGE_OUTPUT.to_string()]),
+            dead_letter,
+            unroutable: AtomicU64::new(0),
+        };
+        (outputs, receivers)
+    }
+
+    fn drain(rx: &mut mpsc::Receiver<LogEntry>) -> Vec<LogEntry> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    fn messages(rx: &mut mpsc::Receiver<LogEntry>) -> Vec<String> {
+        drain(rx).into_iter().map(|log| log.message).collect()
+    }
+
+    fn worker(sink: Sink, retry: Value, dead_letter: Option<DeadLetter>) -> Worker {
+        Worker {
+            name: "primary".to_string(),
+            sink,
+            batch: BatchConfig::default(),
+            retry: serde_json::from_value(retry).unwrap(),
+            metrics: Arc::new(OutputMetrics::default()),
+            dead_letter,
+        }
+    }
+
+    #[test]
+    fn routing_rules_pick_targets() {
+        let (outputs, _rx) = outputs(
+            &[("storage", 1, WhenFull::Block), ("errors", 1, WhenFull::Block), ("audit", 1, WhenFull::Block), ("pager", 1, WhenFull::Block)],
+            json!({"rules": [
+                {"when": [{"field": "level", "equals": "ERROR"}], "outputs": ["errors", "audit"], "continue": true},
+                {"when": [{"field": "team", "equals": "payments"}], "outputs": ["audit", "pager"]},
+                {"when": [{"field": "level", "equals": "ERROR"}], "outputs": ["storage"]}
+            ]}),
+        );
+        let targets = |level: &str, attributes: Value, route: Option<&str>| {
+            let mut entry = log("x", Some("INFO"), attributes);
+            entry.level = Some(level.to_string());
+            let event = Event { log: entry, route: route.map(str::to_string) };
+            outputs.targets(&event).into_iter().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        // `continue` keeps matching and repeated outputs are listed once; the first stopping rule ends it
+        assert_eq!(targets("ERROR", json!({"team": "payments"}), None), ["errors", "audit", "pager"]);
+        assert_eq!(targets("ERROR", json!({}), None), ["errors", "audit", "storage"]);
+        assert_eq!(targets("INFO", json!({"team": "payments"}), None), ["audit", "pager"]);
+        assert_eq!(targets("INFO", json!({}), None), ["storage"]);
+        // A pipeline route skips the rules, unless it names no output
+        assert_eq!(targets("ERROR", json!({}), Some("pager")), ["pager"]);
+        assert_eq!(targets("INFO", json!({}), Some("nowhere")), ["storage"]);
+    }
+
+    #[tokio::test]
+    async fn unroutable_events_go_to_the_dead_letter() {
+        let (outputs, mut rx) = outputs(
+            &[("storage", 4, WhenFull::Block), ("dlq", 4, WhenFull::Block)],
+            json!({"rules": [{"when": [{"field": "level", "equals": "ERROR"}], "outputs": ["storage"]}], "default": [], "dead_letter": "dlq"}),
+        );
+        outputs.dispatch(batch(&["lost"])).await.unwrap();
+
+        let dead = drain(rx.get_mut("dlq").unwrap());
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attributes["dead_letter.reason"], "no matching route");
+        assert!(!dead[0].attributes.contains_key("dead_letter.output"));
+        assert_eq!(outputs.metrics()["unroutable"], 1);
+        assert!(drain(rx.get_mut("storage").unwrap()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn reject_fails_the_whole_batch_before_queueing_anything() {
+        let (outputs, mut rx) = outputs(
+            &[("archive", 10, WhenFull::Block), ("primary", 2, WhenFull::Reject)],
+            json!({"default": ["archive", "primary"]}),
+        );
+
+        let error = outputs.dispatch(batch(&["a", "b", "c"])).await.unwrap_err();
+        assert!(error.downcast_ref::<QueueFull>().is_some());
+        // Nothing reached the earlier `block` target, so a retry of the batch can't duplicate
+        assert!(drain(rx.get_mut("archive").unwrap()).is_empty());
+        assert!(drain(rx.get_mut("primary").unwrap()).is_empty());
+
+        outputs.dispatch(batch(&["a", "b"])).await.unwrap();
+        assert_eq!(messages(rx.get_mut("archive").unwrap()), ["a", "b"]);
+        assert_eq!(messages(rx.get_mut("primary").unwrap()), ["a", "b"]);
+        assert_eq!(outputs.metrics()["outputs"]["primary"]["enqueued"], 2);
+    }
+
+    #[tokio::test]
+    async fn drop_and_dead_letter_when_full() {
+        let (outputs, mut rx) = outputs(
+            &[("lossy", 1, WhenFull::Drop), ("spill", 1, WhenFull::DeadLetter), ("dlq", 4, WhenFull::Block)],
+            json!({"default": ["lossy", "spill"], "dead_letter": "dlq"}),
+        );
+        outputs.dispatch(batch(&["a", "b"])).await.unwrap();
+
+        assert_eq!(messages(rx.get_mut("lossy").unwrap()), ["a"]);
+        assert_eq!(messages(rx.get_mut("spill").unwrap()), ["a"]);
+        let dead = drain(rx.get_mut("dlq").unwrap());
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].message, "b");
+        assert_eq!(dead[0].attributes["dead_letter.output"], "spill");
+        assert_eq!(dead[0].attributes["dead_letter.reason"], "output queue full");
+
+        let metrics = outputs.metrics();
+        assert_eq!(metrics["outputs"]["lossy"]["dropped"], 1);
+        assert_eq!(metrics["outputs"]["spill"]["dead_lettered"], 1);
+        assert_eq!(metrics["outputs"]["dlq"]["enqueued"], 1);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let (outputs, mut rx) = outputs(&[("storage", 1, WhenFull::Block)], json!({}));
+        let outputs = Arc::new(outputs);
+        let dispatch = tokio::spawn({
+            let outputs = Arc::clone(&outputs);
+            async move { outputs.dispatch(batch(&["a", "b"])).await }
+        });
+
+        let storage = rx.get_mut("storage").unwrap();
+        assert_eq!(storage.recv().await.unwrap().message, "a");
+        assert_eq!(storage.recv().await.unwrap().message, "b");
+        dispatch.await.unwrap().unwrap();
+        assert_eq!(outputs.metrics()["outputs"]["storage"]["dropped"], 0);
+    }
+
+    #[tokio::test]
+    async fn routing_must_name_configured_outputs() {
+        let config: PipelineConfig = serde_json::from_value(json!({
+            "outputs": {"archive": {"type": "file", "path": "/tmp/archive.ndjson"}},
+            "routing": {"rules": [{"outputs": ["archive", "missing"]}]}
+        }))
+        .unwrap();
+        let error = Outputs::new("http://localhost:1/logs", &config).err().unwrap();
+        assert!(error.to_string().contains("unknown output 'missing'"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deliver_backs_off_then_dead_letters() {
+        let (tx, mut dead) = mpsc::channel(4);
+        let dead_letter = DeadLetter { name: "dlq".to_string(), tx, metrics: Arc::default() };
+        let sink = Sink::File { path: PathBuf::from("/nonexistent/out.ndjson"), max_bytes: None };
+        let worker = worker(sink, json!({"max_attempts": 4, "initial_backoff_ms": 100, "max_backoff_ms": 250}), Some(dead_letter));
+
+        let started = Instant::now();
+        worker.deliver(vec![log("a", Some("INFO"), json!({})), log("b", Some("INFO"), json!({}))]).await;
+        // 100ms, 200ms, then capped at 250ms between the four attempts
+        assert_eq!(started.elapsed(), Duration::from_millis(550));
+
+        let m = &worker.metrics;
+        assert_eq!(m.retries.load(Ordering::Relaxed), 3);
+        assert_eq!(m.failed_batches.load(Ordering::Relaxed), 1);
+        assert_eq!(m.dead_lettered.load(Ordering::Relaxed), 2);
+        assert_eq!(m.sent.load(Ordering::Relaxed), 0);
+        let dead = drain(&mut dead);
+        assert_eq!(dead.len(), 2);
+        assert_eq!(dead[0].attributes["dead_letter.output"], "primary");
+        assert!(dead[0].attributes["dead_letter.reason"].as_str().unwrap().contains("failed to open"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deliver_drops_without_a_dead_letter() {
+        let sink = Sink::File { path: PathBuf::from("/nonexistent/out.ndjson"), max_bytes: None };
+        let worker = worker(sink, json!({"max_attempts": 2}), None);
+        worker.deliver(vec![log("a", Some("INFO"), json!({}))]).await;
+        assert_eq!(worker.metrics.retries.load(Ordering::Relaxed), 1);
+        assert_eq!(worker.metrics.dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn deliver_writes_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.ndjson");
+        let worker = worker(Sink::File { path: path.clone(), max_bytes: None }, json!({}), None);
+        worker.deliver(vec![log("a", Some("INFO"), json!({})), log("b", Some("INFO"), json!({}))]).await;
+
+        let lines: Vec<LogEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.iter().map(|log| log.message.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(worker.metrics.sent.load(Ordering::Relaxed), 2);
+        assert_eq!(worker.metrics.batches.load(Ordering::Relaxed), 1);
+        assert_eq!(worker.metrics.retries.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn deliver_gives_up_at_once_on_client_errors() {
+        let app = Router::new().route("/logs", post(|| async { (StatusCode::BAD_REQUEST, "bad batch") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/logs", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let sink = Sink::Http { client: Client::new(), url, headers: Vec::new(), wire: Negotiated::new(WireFormat::Json) };
+        let worker = worker(sink, json!({"max_attempts": 5, "initial_backoff_ms": 1}), None);
+        worker.deliver(vec![log("a", Some("INFO"), json!({}))]).await;
+
+        assert_eq!(worker.metrics.retries.load(Ordering::Relaxed), 0);
+        assert_eq!(worker.metrics.failed_batches.load(Ordering::Relaxed), 1);
+        assert_eq!(worker.metrics.dropped.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,13 +1,645 @@
+use crate::dedup::{DedupConfig, DedupStage};
+use crate::geoip::{GeoIpConfig, GeoIpStage};
+use crate::grok::{GrokConfig, ParseStage, RegexConfig};
+use crate::lookup::{LookupConfig, LookupStage};
+use http_client::HttpClientConfig;
 use crate::models::LogEntry;
-use tracing::info;
+use crate::outputs::{OutputConfig, RoutingConfig};
+use crate::redaction::{RedactionConfig, RedactionStage};
+use crate::sampling::{RateLimitConfig, RateLimitStage, SamplingConfig, SamplingStage};
+use crate::script::{ScriptConfig, ScriptStage};
+use crate::severity::{SeverityConfig, SeverityStage};
+use crate::timestamp::{TimestampConfig, TimestampStage};
+use anyhow::{anyhow, Context, Result};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::oneshot;
+use tracing::{error, info};
 
-pub fn process_logs(mut logs: Vec<LogEntry>) -> Vec<LogEntry> {
-    logs.par_iter_mut().for_each(|log| {
-        log.message = log.message.trim().to_string();
-        log.level = log.level.to_uppercase();
+// ✅ Pipeline definition, loaded from the JSON file named by `PIPELINE_CONFIG`
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineConfig {
+    #[serde(default = "default_stages")]
+    pub stages: Vec<StageConfig>, // Applied in order to every batch
+    #[serde(default)]
+    pub routes: HashMap<String, String>, // Route name -> storage URL; shorthand for an `http` output
+    #[serde(default)]
+    pub outputs: HashMap<String, OutputConfig>, // Named destinations; `storage` is STORAGE_SERVICE_URL unless overridden
+    #[serde(default)]
+    pub routing: RoutingConfig, // Which outputs receive which events
+    #[serde(default)]
+    pub threads: Option<usize>, // Rayon pool size; defaults to the number of CPUs
+    #[serde(default)]
+    pub http_client: HttpClientConfig, // Pooled client shared by http/webhook outputs
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            stages: default_stages(),
+            routes: HashMap::new(),
+            outputs: HashMap::new(),
+            routing: RoutingConfig::default(),
+            threads: None,
+            http_client: HttpClientConfig::default(),
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read pipeline config {:?}", path))?;
+        serde_json::from_str(&raw).with_context(|| format!("invalid pipeline config {:?}", path))
+    }
+}
+
+fn default_stages() -> Vec<StageConfig> {
+    vec![StageConfig::Normalize]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StageConfig {
+    Normalize, // Trim messages and upper-case levels
+    Drop { when: Condition },
+    Route { when: Condition, to: String },
+    Grok(GrokConfig), // Named grok patterns lifted into attributes
+    Regex(RegexConfig), // Raw regexes with named groups
+    Timestamp(TimestampConfig), // Event time normalized to UTC, plus ingest time
+    Severity(SeverityConfig), // Canonical TRACE..FATAL levels, inferred when missing
+    Redact(RedactionConfig), // PII masking, hashing or dropping
+    Sample(SamplingConfig), // Deterministic, level-aware probabilistic sampling
+    RateLimit(RateLimitConfig), // Per-key token buckets
+    Dedup(DedupConfig), // Drops or counts repeats within a time window
+    Script(ScriptConfig), // Sandboxed Rhai transforms
+    Geoip(GeoIpConfig), // Location, ASN and reverse DNS for IP attributes
+    Lookup(LookupConfig), // Joins events to CSV/JSON reference tables
+}
+
+// ✅ Matches a single field (`message`, `level`, `source`, `timestamp` or an attribute name)
+#[derive(Debug, Clone, Deserialize)]
+pub struct Condition {
+    pub field: String,
+    pub equals: Option<String>,
+    #[serde(default, rename = "in")]
+    pub one_of: Vec<String>,
+    pub contains: Option<String>,
+    pub exists: Option<bool>,
+}
+
+impl Condition {
+    pub fn matches(&self, log: &LogEntry) -> bool {
+        let value = log.field(&self.field);
+        if let Some(exists) = self.exists {
+            if value.is_some() != exists {
+                return false;
+            }
+        }
+        let Some(value) = value else {
+            return self.equals.is_none() && self.one_of.is_empty() && self.contains.is_none();
+        };
+        self.equals.as_deref().is_none_or(|expected| value == expected)
+            && (self.one_of.is_empty() || self.one_of.iter().any(|v| *v == value))
+            && self.contains.as_deref().is_none_or(|needle| value.contains(needle))
+    }
+}
+
+/// What a stage decided for one event.
+pub enum Action {
+    Keep,
+    Drop,
+    Route(String),
+}
+
+/// An event moving through the pipeline, with the output it has been routed to.
+pub struct Event {
+    pub log: LogEntry,
+    pub route: Option<String>,
+}
+
+#[derive(Default)]
+pub struct BatchOutcome {
+    pub dropped: usize,
+    pub routed: usize,
+}
+
+pub trait Stage: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn process(&self, log: &mut LogEntry) -> Action;
+
+    /// Runs the stage over a whole batch. The default processes events in parallel on the
+    /// current rayon pool; stages that need ordering or shared state override this.
+    fn process_batch(&self, events: &mut Vec<Event>) -> BatchOutcome {
+        let actions: Vec<Action> = events.par_iter_mut().map(|event| self.process(&mut event.log)).collect();
+        apply_actions(events, actions)
+    }
+
+    /// Stage-specific counters reported next to the timing metrics.
+    fn details(&self) -> Option<Value> {
+        None
+    }
+}
+
+// Applies per-event decisions in order, removing dropped events
+pub fn apply_actions(events: &mut Vec<Event>, actions: Vec<Action>) -> BatchOutcome {
+    let mut outcome = BatchOutcome::default();
+    let mut actions = actions.into_iter();
+    events.retain_mut(|event| match actions.next() {
+        Some(Action::Drop) => {
+            outcome.dropped += 1;
+            false
+        }
+        Some(Action::Route(route)) => {
+            outcome.routed += 1;
+            event.route = Some(route);
+            true
+        }
+        Some(Action::Keep) | None => true,
     });
+    outcome
+}
+
+// ✅ Built-in stages
+struct NormalizeStage;
+
+impl Stage for NormalizeStage {
+    fn name(&self) -> &str {
+        "normalize"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        let trimmed = log.message.trim();
+        if trimmed.len() != log.message.len() {
+            log.message = trimmed.to_string();
+        }
+        log.level = log.level.as_ref().map(|level| level.to_uppercase());
+        Action::Keep
+    }
+}
+
+struct DropStage {
+    when: Condition,
+}
+
+impl Stage for DropStage {
+    fn name(&self) -> &str {
+        "drop"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        if self.when.matches(log) {
+            Action::Drop
+        } else {
+            Action::Keep
+        }
+    }
+}
+
+struct RouteStage {
+    when: Condition,
+    to: String,
+}
+
+impl Stage for RouteStage {
+    fn name(&self) -> &str {
+        "route"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        if self.when.matches(log) {
+            Action::Route(self.to.clone())
+        } else {
+            Action::Keep
+        }
+    }
+}
+
+fn build_stage(config: &StageConfig) -> Result<Box<dyn Stage>> {
+    Ok(match config {
+        StageConfig::Normalize => Box::new(NormalizeStage),
+        StageConfig::Drop { when } => Box::new(DropStage { when: when.clone() }),
+        StageConfig::Route { when, to } => Box::new(RouteStage { when: when.clone(), to: to.clone() }),
+        StageConfig::Grok(config) => Box::new(ParseStage::grok(config)?),
+        StageConfig::Regex(config) => Box::new(ParseStage::regex(config)?),
+        StageConfig::Timestamp(config) => Box::new(TimestampStage::new(config)?),
+        StageConfig::Severity(config) => Box::new(SeverityStage::new(config)?),
+        StageConfig::Redact(config) => Box::new(RedactionStage::new(config)?),
+        StageConfig::Sample(config) => Box::new(SamplingStage::new(config)?),
+        StageConfig::RateLimit(config) => Box::new(RateLimitStage::new(config)?),
+        StageConfig::Dedup(config) => Box::new(DedupStage::new(config)?),
+        StageConfig::Script(config) => Box::new(ScriptStage::new(config)?),
+        StageConfig::Geoip(config) => Box::new(GeoIpStage::new(config)?),
+        StageConfig::Lookup(config) => Box::new(LookupStage::new(config)?),
+    })
+}
+
+#[derive(Default)]
+struct StageMetrics {
+    batches: AtomicU64,
+    events_in: AtomicU64,
+    dropped: AtomicU64,
+    routed: AtomicU64,
+    total_nanos: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct StageSnapshot {
+    pub name: String,
+    pub batches: u64,
+    pub events_in: u64,
+    pub dropped: u64,
+    pub routed: u64,
+    pub total_time_us: u64,
+    pub avg_ns_per_event: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+// ✅ Ordered stages executed on a dedicated rayon pool, off the tokio runtime
+pub struct Pipeline {
+    stages: Vec<(Box<dyn Stage>, StageMetrics)>,
+    pool: rayon::ThreadPool,
+}
+
+impl Pipeline {
+    pub fn new(config: &PipelineConfig) -> Result<Self> {
+        let stages = config
+            .stages
+            .iter()
+            .map(|stage| Ok((build_stage(stage)?, StageMetrics::default())))
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::with_stages(stages, config.threads.unwrap_or(0))
+    }
+
+    fn with_stages(stages: Vec<(Box<dyn Stage>, StageMetrics)>, threads: usize) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("pipeline-{}", i))
+            // Backstop only: `run` catches stage panics itself, but without a handler rayon
+            // aborts the process on any panic that escapes a spawned job
+            .panic_handler(|payload| error!("❌ Pipeline thread panicked: {}", panic_message(payload.as_ref())))
+            .build()
+            .context("failed to build pipeline thread pool")?;
+
+        info!(
+            "🧩 Pipeline ready: [{}] on {} threads",
+            stages.iter().map(|(stage, _)| stage.name()).collect::<Vec<_>>().join(" → "),
+            pool.current_num_threads()
+        );
+        Ok(Self { stages, pool })
+    }
+
+    /// Runs a batch through every stage without blocking the calling task.
+    pub async fn run(self: &Arc<Self>, logs: Vec<LogEntry>) -> Result<Vec<Event>> {
+        let (tx, rx) = oneshot::channel();
+        let pipeline = Arc::clone(self);
+        self.pool.spawn(move || {
+            // A panicking stage fails this batch only; the pool and the process keep running
+            let result = panic::catch_unwind(AssertUnwindSafe(|| pipeline.run_blocking(logs)));
+            let _ = tx.send(result.map_err(|payload| panic_message(payload.as_ref())));
+        });
+        match rx.await {
+            Ok(Ok(events)) => Ok(events),
+            Ok(Err(message)) => {
+                error!("❌ Pipeline stage panicked: {}", message);
+                Err(anyhow!("pipeline stage panicked: {}", message))
+            }
+            Err(_) => Err(anyhow!("pipeline stage panicked")),
+        }
+    }
+
+    fn run_blocking(&self, logs: Vec<LogEntry>) -> Vec<Event> {
+        let mut events: Vec<Event> = logs.into_iter().map(|log| Event { log, route: None }).collect();
+
+        for (stage, metrics) in &self.stages {
+            if events.is_empty() {
+                break;
+            }
+            let events_in = events.len() as u64;
+            let started = Instant::now();
+            let outcome = stage.process_batch(&mut events);
+            let elapsed = started.elapsed().as_nanos() as u64;
+
+            metrics.batches.fetch_add(1, Ordering::Relaxed);
+            metrics.events_in.fetch_add(events_in, Ordering::Relaxed);
+            metrics.dropped.fetch_add(outcome.dropped as u64, Ordering::Relaxed);
+            metrics.routed.fetch_add(outcome.routed as u64, Ordering::Relaxed);
+            metrics.total_nanos.fetch_add(elapsed, Ordering::Relaxed);
+        }
+
+        events
+    }
+
+    pub fn metrics(&self) -> Vec<StageSnapshot> {
+        self.stages
+            .iter()
+            .map(|(stage, metrics)| {
+                let events_in = metrics.events_in.load(Ordering::Relaxed);
+                let total_nanos = metrics.total_nanos.load(Ordering::Relaxed);
+                StageSnapshot {
+                    name: stage.name().to_string(),
+                    batches: metrics.batches.load(Ordering::Relaxed),
+                    events_in,
+                    dropped: metrics.dropped.load(Ordering::Relaxed),
+                    routed: metrics.routed.load(Ordering::Relaxed),
+                    total_time_us: total_nanos / 1_000,
+                    avg_ns_per_event: total_nanos.checked_div(events_in).unwrap_or(0),
+                    details: stage.details(),
+                }
+            })
+            .collect()
+    }
+}
+
+// ✅ A value loaded from a file (lookup table, script, GeoIP database) and swapped out when the file
+// changes. The mtime is checked at most once per `reload_secs`; a file that fails to load keeps the
+// previous version, and is reported once rather than on every check.
+pub(crate) struct FileReloader<T> {
+    path: PathBuf,
+    what: &'static str, // For log messages, e.g. "lookup table"
+    current: RwLock<(Arc<T>, Option<SystemTime>)>,
+    reload_every: Option<Duration>, // `None` when reloading is disabled
+    last_check: Mutex<Instant>,
+    reloads: AtomicU64,
+}
+
+impl<T> FileReloader<T> {
+    pub(crate) fn new(path: &Path, what: &'static str, reload_secs: u64, load: impl FnOnce(&Path) -> Result<T>) -> Result<Self> {
+        // Read before loading, so an edit made during the load is picked up by the next check
+        let modified = modified_at(path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            what,
+            current: RwLock::new((Arc::new(load(path)?), modified)),
+            reload_every: (reload_secs > 0).then(|| Duration::from_secs(reload_secs)),
+            last_check: Mutex::new(Instant::now()),
+            reloads: AtomicU64::new(0),
+        })
+    }
+
+    /// The current version. Stages take one per batch, so a reload never blocks lookups.
+    pub(crate) fn get(&self) -> Arc<T> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()).0)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn reloads(&self) -> u64 {
+        self.reloads.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reload_if_changed(&self, load: impl FnOnce(&Path) -> Result<T>) {
+        let Some(every) = self.reload_every else {
+            return;
+        };
+        {
+            let mut last_check = self.last_check.lock().unwrap_or_else(|e| e.into_inner());
+            if last_check.elapsed() < every {
+                return;
+            }
+            *last_check = Instant::now();
+        }
+
+        let modified = modified_at(&self.path);
+        if modified == self.current.read().unwrap_or_else(|e| e.into_inner()).1 {
+            return;
+        }
+        let loaded = load(&self.path);
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        current.1 = modified;
+        match loaded {
+            Ok(value) => {
+                current.0 = Arc::new(value);
+                self.reloads.fetch_add(1, Ordering::Relaxed);
+                info!("🔄 Reloaded {} {:?}", self.what, self.path);
+            }
+            Err(e) => error!("❌ Keeping previous {}: {:#}", self.what, e),
+        }
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+// ✅ Fixtures shared by the stage tests
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+    use serde::de::DeserializeOwned;
+
+    /// Builds a stage from its JSON config, e.g. `configured(DedupStage::new, json!({ ... }))`.
+    pub(crate) fn configured<C: DeserializeOwned, S>(new: impl FnOnce(&C) -> Result<S>, config: Value) -> Result<S> {
+        new(&serde_json::from_value(config).unwrap())
+    }
+
+    /// A log entry with no timestamp or source; tests set those with struct update syntax.
+    pub(crate) fn log(message: &str, level: Option<&str>, attributes: Value) -> LogEntry {
+        LogEntry {
+            timestamp: None,
+            level: level.map(str::to_string),
+            message: message.to_string(),
+            source: None,
+            attributes: serde_json::from_value(attributes).unwrap(),
+        }
+    }
+
+    pub(crate) fn events(logs: impl IntoIterator<Item = LogEntry>) -> Vec<Event> {
+        logs.into_iter().map(|log| Event { log, route: None }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::log;
+    use super::*;
+    use serde_json::json;
+
+    fn condition(value: Value) -> Condition {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn pipeline(stages: Value) -> Arc<Pipeline> {
+        let config: PipelineConfig = serde_json::from_value(json!({ "stages": stages, "threads": 1 })).unwrap();
+        Arc::new(Pipeline::new(&config).unwrap())
+    }
+
+    #[test]
+    fn condition_exists() {
+        let entry = log("hello", None, json!({ "user": "alice" }));
+        assert!(condition(json!({ "field": "user", "exists": true })).matches(&entry));
+        assert!(!condition(json!({ "field": "user", "exists": false })).matches(&entry));
+        assert!(condition(json!({ "field": "level", "exists": false })).matches(&entry));
+        assert!(!condition(json!({ "field": "attributes.session", "exists": true })).matches(&entry));
+    }
+
+    #[test]
+    fn condition_in_and_equals() {
+        let entry = log("hello", Some("WARN"), json!({ "status": 503 }));
+        assert!(condition(json!({ "field": "level", "in": ["WARN", "ERROR"] })).matches(&entry));
+        assert!(!condition(json!({ "field": "level", "in": ["DEBUG"] })).matches(&entry));
+        // Non-string attributes compare by their JSON text
+        assert!(condition(json!({ "field": "status", "equals": "503" })).matches(&entry));
+        assert!(condition(json!({ "field": "attributes.status", "in": ["502", "503"] })).matches(&entry));
+    }
+
+    #[test]
+    fn condition_contains() {
+        let entry = log("GET /healthz 200", Some("INFO"), json!({}));
+        assert!(condition(json!({ "field": "message", "contains": "/healthz" })).matches(&entry));
+        assert!(!condition(json!({ "field": "message", "contains": "/login" })).matches(&entry));
+        // Every given test has to pass
+        assert!(!condition(json!({ "field": "message", "contains": "/healthz", "equals": "GET" })).matches(&entry));
+    }
+
+    #[test]
+    fn condition_on_missing_field() {
+        let entry = log("hello", None, json!({}));
+        // A value test can't match a field that isn't there
+        assert!(!condition(json!({ "field": "level", "equals": "INFO" })).matches(&entry));
+        assert!(!condition(json!({ "field": "level", "in": ["INFO"] })).matches(&entry));
+        assert!(!condition(json!({ "field": "level", "contains": "I" })).matches(&entry));
+        // With no test at all the condition holds
+        assert!(condition(json!({ "field": "level" })).matches(&entry));
+    }
+
+    #[test]
+    fn apply_actions_counts_drops_and_routes() {
+        let mut events = test_support::events(["a", "b", "c", "d"].map(|message| log(message, None, json!({}))));
+        let actions = vec![Action::Keep, Action::Drop, Action::Route("alerts".to_string()), Action::Drop];
+
+        let outcome = apply_actions(&mut events, actions);
+        assert_eq!(outcome.dropped, 2);
+        assert_eq!(outcome.routed, 1);
+        let kept: Vec<_> = events.iter().map(|e| (e.log.message.as_str(), e.route.as_deref())).collect();
+        assert_eq!(kept, vec![("a", None), ("c", Some("alerts"))]);
+    }
+
+    #[tokio::test]
+    async fn pipeline_counts_drops_and_routes_per_stage() {
+        let pipeline = pipeline(json!([
+            { "type": "normalize" },
+            { "type": "drop", "when": { "field": "message", "contains": "healthz" } },
+            { "type": "route", "when": { "field": "level", "equals": "ERROR" }, "to": "alerts" },
+        ]));
+        let events = pipeline
+            .run(vec![
+                log("  GET /healthz  ", Some("info"), json!({})),
+                log("disk full", Some("error"), json!({})),
+                log("user signed in", Some("info"), json!({})),
+            ])
+            .await
+            .unwrap();
+
+        let kept: Vec<_> = events.iter().map(|e| (e.log.message.as_str(), e.route.as_deref())).collect();
+        assert_eq!(kept, vec![("disk full", Some("alerts")), ("user signed in", None)]);
+
+        let metrics = pipeline.metrics();
+        let counts: Vec<_> = metrics.iter().map(|m| (m.name.as_str(), m.events_in, m.dropped, m.routed)).collect();
+        assert_eq!(counts, vec![("normalize", 3, 0, 0), ("drop", 3, 1, 0), ("route", 2, 0, 1)]);
+    }
+
+    #[tokio::test]
+    async fn stages_run_in_configured_order() {
+        let event = || vec![log("boom", Some("error"), json!({}))];
+        let drop_errors = json!({ "type": "drop", "when": { "field": "level", "equals": "ERROR" } });
+
+        // Normalized first, the level is `ERROR` by the time the drop stage sees it
+        let normalized_first = pipeline(json!([{ "type": "normalize" }, drop_errors]));
+        assert!(normalized_first.run(event()).await.unwrap().is_empty());
+
+        // The other way round it's still `error` and survives
+        let dropped_first = pipeline(json!([drop_errors, { "type": "normalize" }]));
+        let events = dropped_first.run(event()).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].log.level.as_deref(), Some("ERROR"));
+    }
+
+    #[tokio::test]
+    async fn stages_after_an_empty_batch_are_skipped() {
+        let pipeline = pipeline(json!([
+            { "type": "drop", "when": { "field": "message", "exists": true } },
+            { "type": "normalize" },
+        ]));
+        assert!(pipeline.run(vec![log("a", None, json!({}))]).await.unwrap().is_empty());
+        assert_eq!(pipeline.metrics()[1].batches, 0);
+    }
+
+    struct Panicking;
+
+    impl Stage for Panicking {
+        fn name(&self) -> &str {
+            "panicking"
+        }
+
+        fn process(&self, log: &mut LogEntry) -> Action {
+            if log.message == "boom" {
+                panic!("stage exploded on {}", log.message);
+            }
+            Action::Keep
+        }
+    }
+
+    #[tokio::test]
+    async fn a_panicking_stage_fails_only_its_batch() {
+        let pipeline = Arc::new(Pipeline::with_stages(vec![(Box::new(Panicking), StageMetrics::default())], 2).unwrap());
+
+        // The panic happens inside the stage's parallel iterator, on a pool thread
+        let batch = (0..64).map(|i| log(if i == 40 { "boom" } else { "ok" }, None, json!({}))).collect();
+        let Err(error) = pipeline.run(batch).await else { panic!("the batch should have failed") };
+        assert!(error.to_string().contains("stage exploded on boom"), "{}", error);
+
+        // The pool is still there for the next batch
+        let events = pipeline.run(vec![log("ok", None, json!({}))]).await.unwrap();
+        assert_eq!(events.len(), 1);
+    }
+
+    #[test]
+    fn file_reloader_swaps_versions_only_when_the_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("value");
+        std::fs::write(&path, "1").unwrap();
+        let parse = |path: &Path| -> Result<u32> { Ok(std::fs::read_to_string(path)?.trim().parse()?) };
+
+        let fixed = FileReloader::new(&path, "value", 0, parse).unwrap();
+        let reloaded = FileReloader::new(&path, "value", 1, parse).unwrap();
+        assert_eq!((*fixed.get(), *reloaded.get()), (1, 1));
+
+        // Checks are throttled to one per `reload_secs`
+        std::fs::write(&path, "2").unwrap();
+        reloaded.reload_if_changed(|_| panic!("checked too early"));
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        reloaded.reload_if_changed(parse);
+        fixed.reload_if_changed(|_| panic!("reloading is disabled"));
+        assert_eq!((*fixed.get(), *reloaded.get(), reloaded.reloads()), (1, 2, 1));
 
-    info!("🔄 Processed {} logs in parallel", logs.len());
-    logs
+        // A failed load keeps the previous version and isn't retried until the file changes again
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        std::fs::write(&path, "two").unwrap();
+        reloaded.reload_if_changed(parse);
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        reloaded.reload_if_changed(|_| panic!("retried an unchanged file"));
+        assert_eq!((*reloaded.get(), reloaded.reloads()), (2, 1));
+    }
 }
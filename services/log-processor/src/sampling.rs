@@ -0,0 +1,345 @@
+use crate::models::LogEntry;
+use crate::processor::{Action, BatchOutcome, Event, Stage, apply_actions};
+use crate::severity::Severity;
+use anyhow::{bail, Result};
+use lru::LruCache;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Retained events carry the probability they were kept with, so counts can be extrapolated
+const SAMPLE_RATE_ATTRIBUTE: &str = "sample.rate";
+
+// ✅ `{"type": "sample", "rate": 0.1, "level_rates": {"ERROR": 1.0, "DEBUG": 0.01}, "key_fields": ["attributes.trace_id"]}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SamplingConfig {
+    #[serde(default = "default_rate")]
+    pub rate: f64, // Keep probability for levels without their own rate
+    #[serde(default)]
+    pub level_rates: HashMap<Severity, f64>,
+    #[serde(default)]
+    pub key_fields: Vec<String>, // Events sharing a key are kept or dropped together
+}
+
+fn default_rate() -> f64 {
+    1.0
+}
+
+// ✅ `{"type": "rate_limit", "key_field": "source", "events_per_second": 100, "burst": 500}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_key_field")]
+    pub key_field: String, // One bucket per distinct value
+    pub events_per_second: f64,
+    pub burst: Option<f64>, // Bucket size; defaults to one second of traffic
+    #[serde(default)]
+    pub exempt_levels: Vec<Severity>, // Never rate limited
+    #[serde(default = "default_max_keys")]
+    pub max_keys: usize, // Least recently used buckets are evicted beyond this many keys
+}
+
+fn default_key_field() -> String {
+    "source".to_string()
+}
+
+fn default_max_keys() -> usize {
+    10_000
+}
+
+fn level_of(log: &LogEntry) -> Option<Severity> {
+    log.level.as_deref().and_then(Severity::from_name)
+}
+
+// Multiplies into any rate applied by an earlier stage
+fn annotate_rate(log: &mut LogEntry, rate: f64) {
+    let previous = log.attributes.get(SAMPLE_RATE_ATTRIBUTE).and_then(Value::as_f64).unwrap_or(1.0);
+    log.attributes.insert(SAMPLE_RATE_ATTRIBUTE.to_string(), json!(previous * rate));
+}
+
+// FNV-1a with a splitmix64 finalizer: stable across processes and releases, unlike std's hasher,
+// so every processor instance makes the same decision for a key
+pub(crate) fn stable_hash(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0xff)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+pub struct SamplingStage {
+    rate: f64,
+    level_rates: HashMap<Severity, f64>,
+    key_fields: Vec<String>,
+}
+
+impl SamplingStage {
+    pub fn new(config: &SamplingConfig) -> Result<Self> {
+        for rate in std::iter::once(&config.rate).chain(config.level_rates.values()) {
+            if !(0.0..=1.0).contains(rate) {
+                bail!("sampling rates must be between 0 and 1, got {}", rate);
+            }
+        }
+        Ok(Self {
+            rate: config.rate,
+            level_rates: config.level_rates.clone(),
+            key_fields: config.key_fields.clone(),
+        })
+    }
+
+    // Uniform in [0, 1), derived from the sampling key (or the event itself when it has none)
+    fn position(&self, log: &LogEntry) -> f64 {
+        let hash = match self.key_fields.iter().find_map(|field| log.field(field)) {
+            Some(key) => stable_hash(&[&key]),
+            None => stable_hash(&[
+                log.source.as_deref().unwrap_or_default(),
+                log.timestamp.as_deref().unwrap_or_default(),
+                &log.message,
+            ]),
+        };
+        (hash >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Stage for SamplingStage {
+    fn name(&self) -> &str {
+        "sample"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        let rate = level_of(log)
+            .and_then(|level| self.level_rates.get(&level).copied())
+            .unwrap_or(self.rate);
+        if rate >= 1.0 {
+            return Action::Keep;
+        }
+        if self.position(log) >= rate {
+            return Action::Drop;
+        }
+        annotate_rate(log, rate);
+        Action::Keep
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+pub struct RateLimitStage {
+    key_field: String,
+    events_per_second: f64,
+    burst: f64,
+    exempt_levels: Vec<Severity>,
+    buckets: Mutex<LruCache<String, Bucket>>,
+}
+
+impl RateLimitStage {
+    pub fn new(config: &RateLimitConfig) -> Result<Self> {
+        if config.events_per_second <= 0.0 {
+            bail!("rate_limit events_per_second must be positive");
+        }
+        let Some(max_keys) = NonZeroUsize::new(config.max_keys) else {
+            bail!("rate_limit max_keys must be positive");
+        };
+        Ok(Self {
+            key_field: config.key_field.clone(),
+            events_per_second: config.events_per_second,
+            burst: config.burst.unwrap_or(config.events_per_second).max(1.0),
+            exempt_levels: config.exempt_levels.clone(),
+            buckets: Mutex::new(LruCache::new(max_keys)),
+        })
+    }
+
+    // A key evicted while throttled starts over with a full burst; with `max_keys` well above the
+    // number of active keys only idle ones fall off the end
+    fn take(&self, buckets: &mut LruCache<String, Bucket>, key: &str, now: Instant) -> bool {
+        let bucket = buckets.get_or_insert_mut_ref(key, || Bucket { tokens: self.burst, refilled: now });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.events_per_second).min(self.burst);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Stage for RateLimitStage {
+    fn name(&self) -> &str {
+        "rate_limit"
+    }
+
+    // Buckets are only touched from `process_batch`
+    fn process(&self, _log: &mut LogEntry) -> Action {
+        Action::Keep
+    }
+
+    // Sequential, so buckets drain in arrival order
+    fn process_batch(&self, events: &mut Vec<Event>) -> BatchOutcome {
+        let now = Instant::now();
+        let mut seen: HashMap<String, (u64, u64)> = HashMap::new(); // key -> (seen, kept)
+        let mut keys = Vec::with_capacity(events.len());
+        let mut actions = Vec::with_capacity(events.len());
+        {
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            for event in events.iter() {
+                if level_of(&event.log).is_some_and(|level| self.exempt_levels.contains(&level)) {
+                    keys.push(None);
+                    actions.push(Action::Keep);
+                    continue;
+                }
+                let key = event.log.field(&self.key_field).map(|k| k.into_owned()).unwrap_or_default();
+                let kept = self.take(&mut buckets, &key, now);
+                let counts = seen.entry(key.clone()).or_default();
+                counts.0 += 1;
+                counts.1 += kept as u64;
+                keys.push(Some(key));
+                actions.push(if kept { Action::Keep } else { Action::Drop });
+            }
+        }
+
+        // Survivors of a throttled key stand for everything that key sent in this batch
+        for (event, key) in events.iter_mut().zip(&keys) {
+            if let Some((total, kept)) = key.as_ref().and_then(|key| seen.get(key)) {
+                if kept < total && *kept > 0 {
+                    annotate_rate(&mut event.log, *kept as f64 / *total as f64);
+                }
+            }
+        }
+        apply_actions(events, actions)
+    }
+
+    fn details(&self) -> Option<Value> {
+        let tracked = self.buckets.lock().unwrap_or_else(|e| e.into_inner()).len();
+        Some(json!({ "tracked_keys": tracked }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, events, log};
+    use std::time::Duration;
+
+    #[test]
+    fn stable_hash_is_pinned() {
+        // Changing these values changes which events every deployed processor keeps
+        assert_eq!(stable_hash(&[]), 17_665_956_581_633_026_203);
+        assert_eq!(stable_hash(&["trace-1"]), 15_866_647_858_912_015_065);
+        // Parts are delimited, so moving a boundary changes the hash
+        assert_ne!(stable_hash(&["ab", "c"]), stable_hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn events_sharing_a_key_are_sampled_together() {
+        let stage = configured(SamplingStage::new, json!({ "rate": 0.5, "key_fields": ["attributes.trace_id"] })).unwrap();
+        let kept = |trace: &str| {
+            let decisions: Vec<bool> = (0..20)
+                .map(|i| {
+                    let mut entry = log(&format!("step {}", i), None, json!({ "trace_id": trace }));
+                    matches!(stage.process(&mut entry), Action::Keep)
+                })
+                .collect();
+            assert!(decisions.iter().all(|d| *d == decisions[0]), "trace {} was split", trace);
+            decisions[0]
+        };
+        // About half of the traces survive, each one whole
+        let survivors = (0..200).filter(|i| kept(&format!("trace-{}", i))).count();
+        assert!((70..=130).contains(&survivors), "{} of 200 traces kept", survivors);
+    }
+
+    #[test]
+    fn level_rates_and_rate_annotation() {
+        let stage = configured(SamplingStage::new, json!({ "rate": 0.0, "level_rates": { "ERROR": 1.0, "WARN": 0.999999 } })).unwrap();
+        assert!(matches!(stage.process(&mut log("a", Some("INFO"), json!({}))), Action::Drop));
+
+        // Rate 1 keeps without annotating
+        let mut error = log("b", Some("error"), json!({}));
+        assert!(matches!(stage.process(&mut error), Action::Keep));
+        assert!(!error.attributes.contains_key(SAMPLE_RATE_ATTRIBUTE));
+
+        // Rates multiply with one set by an earlier stage
+        let mut warn = log("c", Some("WARN"), json!({ "sample.rate": 0.5 }));
+        assert!(matches!(stage.process(&mut warn), Action::Keep));
+        assert_eq!(warn.attributes[SAMPLE_RATE_ATTRIBUTE], json!(0.5 * 0.999999));
+    }
+
+    #[test]
+    fn sampling_rates_are_validated() {
+        assert!(configured(SamplingStage::new, json!({ "rate": 1.5 })).is_err());
+        assert!(configured(SamplingStage::new, json!({ "level_rates": { "DEBUG": -0.1 } })).is_err());
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let stage = configured(RateLimitStage::new, json!({ "events_per_second": 2.0, "burst": 3.0 })).unwrap();
+        let mut buckets = LruCache::unbounded();
+        let start = Instant::now();
+
+        // A fresh bucket holds the whole burst
+        assert!((0..3).all(|_| stage.take(&mut buckets, "api", start)));
+        assert!(!stage.take(&mut buckets, "api", start));
+
+        // Two tokens a second: after 500ms there's exactly one
+        let later = start + Duration::from_millis(500);
+        assert!(stage.take(&mut buckets, "api", later));
+        assert!(!stage.take(&mut buckets, "api", later));
+
+        // Refill never exceeds the burst
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| stage.take(&mut buckets, "api", much_later)).count(), 3);
+
+        // Keys have their own buckets
+        assert!(stage.take(&mut buckets, "worker", much_later));
+    }
+
+    #[test]
+    fn max_keys_bounds_the_tracked_buckets() {
+        let stage = configured(RateLimitStage::new, json!({ "events_per_second": 1.0, "burst": 1.0, "max_keys": 2 })).unwrap();
+        let batch = |keys: &[&str]| {
+            let mut batch = events(keys.iter().map(|key| LogEntry { source: Some(key.to_string()), ..log("x", None, json!({})) }));
+            stage.process_batch(&mut batch);
+            batch.iter().map(|e| e.log.source.clone().unwrap()).collect::<Vec<_>>()
+        };
+
+        // Each key's single token is spent
+        assert_eq!(batch(&["a", "b"]), ["a", "b"]);
+        assert_eq!(batch(&["a"]), Vec::<String>::new());
+
+        // A flood of new keys never grows the map past `max_keys`
+        let flood: Vec<String> = (0..100).map(|i| format!("k{}", i)).collect();
+        assert_eq!(batch(&flood.iter().map(String::as_str).collect::<Vec<_>>()).len(), 100);
+        assert_eq!(stage.details().unwrap()["tracked_keys"], 2);
+
+        // Keys in use stay tracked; the least recently used one is evicted and starts over
+        assert_eq!(batch(&["k99", "b"]), ["b"]);
+        assert_eq!(batch(&["k99", "b"]), Vec::<String>::new());
+        assert!(configured(RateLimitStage::new, json!({ "events_per_second": 1.0, "max_keys": 0 })).is_err());
+    }
+
+    #[test]
+    fn rate_limited_batches_exempt_levels_and_annotate_survivors() {
+        let stage = configured(RateLimitStage::new, json!({ "events_per_second": 2.0, "exempt_levels": ["ERROR"] })).unwrap();
+        let mut events = events((0..4).map(|i| log(&format!("{}", i), Some("INFO"), json!({}))).chain([log("boom", Some("ERROR"), json!({}))]));
+
+        let outcome = stage.process_batch(&mut events);
+        assert_eq!(outcome.dropped, 2);
+        let kept: Vec<_> = events.iter().map(|e| e.log.message.as_str()).collect();
+        assert_eq!(kept, vec!["0", "1", "boom"]);
+        assert_eq!(events[0].log.attributes[SAMPLE_RATE_ATTRIBUTE], json!(0.5));
+        assert!(!events[2].log.attributes.contains_key(SAMPLE_RATE_ATTRIBUTE));
+        assert_eq!(stage.details().unwrap()["tracked_keys"], 1);
+    }
+}
@@ -0,0 +1,338 @@
+use crate::grok::add_tag;
+use crate::models::LogEntry;
+use crate::processor::{Action, BatchOutcome, Event, FileReloader, Stage};
+use anyhow::{anyhow, bail, Context, Result};
+use rayon::prelude::*;
+use rhai::serde::{from_dynamic, to_dynamic};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+// ✅ `{"type": "script", "path": "scripts/enrich.rhai", "timeout_ms": 20}`
+//
+// The script sees the event as the map `event` (`timestamp`, `level`, `message`, `source`,
+// `attributes`) and may change it in place. Setting `event = ()` drops it; maps pushed onto
+// `emit` are forwarded as extra events.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptConfig {
+    pub path: Option<PathBuf>, // Script file, reloaded when it changes
+    pub source: Option<String>, // Inline script, for one-liners
+    #[serde(default = "default_max_operations")]
+    pub max_operations: u64, // Per event
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64, // Per event wall-clock limit
+    #[serde(default = "default_max_emitted")]
+    pub max_emitted: usize, // Extra events one event may produce
+    #[serde(default = "default_reload_secs")]
+    pub reload_secs: u64, // How often `path` is checked for changes; 0 disables reloading
+    #[serde(default = "default_failure_tag")]
+    pub tag_on_failure: String, // Failed events pass through unchanged with this tag
+}
+
+fn default_max_operations() -> u64 {
+    100_000
+}
+
+fn default_timeout_ms() -> u64 {
+    50
+}
+
+fn default_max_emitted() -> usize {
+    100
+}
+
+fn default_reload_secs() -> u64 {
+    5
+}
+
+fn default_failure_tag() -> String {
+    "_scriptfailure".to_string()
+}
+
+// Operations between deadline checks; reading the clock on every operation is too costly
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+thread_local! {
+    // Deadline of the script currently running on this thread
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// Inline scripts never change; script files are reloaded
+enum Script {
+    Inline(Arc<AST>),
+    File(FileReloader<AST>),
+}
+
+pub struct ScriptStage {
+    engine: Engine,
+    script: Script,
+    timeout: Duration,
+    max_emitted: usize,
+    tag_on_failure: String,
+    failures: AtomicU64,
+    emitted: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl ScriptStage {
+    pub fn new(config: &ScriptConfig) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(config.max_operations)
+            .set_max_call_levels(32)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(1 << 20)
+            .set_max_array_size(10_000)
+            .set_max_map_size(10_000)
+            .disable_symbol("eval")
+            .on_print(|text| debug!("📜 script: {}", text))
+            .on_debug(|text, _, pos| debug!("📜 script {:?}: {}", pos, text))
+            .on_progress(|operations| {
+                if operations % DEADLINE_CHECK_INTERVAL != 0 {
+                    return None;
+                }
+                let expired = DEADLINE.with(|deadline| deadline.get().is_some_and(|d| Instant::now() >= d));
+                expired.then(|| Dynamic::from("script timed out"))
+            });
+
+        let script = match (&config.path, &config.source) {
+            (Some(path), None) => Script::File(FileReloader::new(path, "script", config.reload_secs, |path| compile(&engine, path))?),
+            (None, Some(source)) => {
+                Script::Inline(Arc::new(engine.compile(source).map_err(|e| anyhow!("failed to compile script: {}", e))?))
+            }
+            _ => bail!("a script stage needs exactly one of `path` or `source`"),
+        };
+
+        Ok(Self {
+            engine,
+            script,
+            timeout: Duration::from_millis(config.timeout_ms),
+            max_emitted: config.max_emitted,
+            tag_on_failure: config.tag_on_failure.clone(),
+            failures: AtomicU64::new(0),
+            emitted: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+        })
+    }
+
+    /// Runs the script for one event: the (possibly changed) event, unless dropped, and any emitted events.
+    fn eval(&self, ast: &AST, log: &LogEntry) -> Result<(Option<LogEntry>, Vec<LogEntry>)> {
+        let mut event: Map = to_dynamic(log).map_err(|e| anyhow!("{}", e))?.cast();
+        event.entry("attributes".into()).or_insert_with(|| Map::new().into());
+
+        let mut scope = Scope::new();
+        scope.push("event", event);
+        scope.push("emit", Array::new());
+
+        DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.timeout)));
+        let result = self.engine.run_ast_with_scope(&mut scope, ast);
+        DEADLINE.with(|deadline| deadline.set(None));
+        // Only the deadline terminates scripts, and Rhai's message doesn't say why
+        result.map_err(|e| match *e {
+            EvalAltResult::ErrorTerminated(..) => anyhow!("script timed out after {:?}", self.timeout),
+            e => anyhow!("{}", e),
+        })?;
+
+        let event = scope.get_value::<Dynamic>("event").unwrap_or(Dynamic::UNIT);
+        let kept = if event.is_unit() {
+            None
+        } else {
+            Some(from_dynamic::<LogEntry>(&event).map_err(|e| anyhow!("invalid event: {}", e))?)
+        };
+
+        let emitted = scope.get_value::<Array>("emit").unwrap_or_default();
+        if emitted.len() > self.max_emitted {
+            bail!("script emitted {} events, limit is {}", emitted.len(), self.max_emitted);
+        }
+        let emitted = emitted
+            .iter()
+            .map(|event| from_dynamic::<LogEntry>(event).map_err(|e| anyhow!("invalid emitted event: {}", e)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((kept, emitted))
+    }
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read script {:?}", path))
+}
+
+fn compile(engine: &Engine, path: &Path) -> Result<AST> {
+    let source = read_script(path)?;
+    engine.compile(&source).map_err(|e| anyhow!("failed to compile script {:?}: {}", path, e))
+}
+
+impl Stage for ScriptStage {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    // Scripts can emit events, which per-event actions can't express; see `process_batch`
+    fn process(&self, _log: &mut LogEntry) -> Action {
+        Action::Keep
+    }
+
+    fn process_batch(&self, events: &mut Vec<Event>) -> BatchOutcome {
+        let ast = match &self.script {
+            Script::Inline(ast) => Arc::clone(ast),
+            Script::File(file) => {
+                file.reload_if_changed(|path| compile(&self.engine, path));
+                file.get()
+            }
+        };
+        let results: Vec<_> = events.par_iter().map(|event| self.eval(&ast, &event.log)).collect();
+
+        let mut outcome = BatchOutcome::default();
+        let mut output = Vec::with_capacity(events.len());
+        for (mut event, result) in events.drain(..).zip(results) {
+            match result {
+                Ok((kept, emitted)) => {
+                    match kept {
+                        Some(log) => {
+                            event.log = log;
+                            output.push(event);
+                        }
+                        None => outcome.dropped += 1,
+                    }
+                    self.emitted.fetch_add(emitted.len() as u64, Ordering::Relaxed);
+                    output.extend(emitted.into_iter().map(|log| Event { log, route: None }));
+                }
+                Err(e) => {
+                    self.failures.fetch_add(1, Ordering::Relaxed);
+                    debug!("📜 script failed: {:#}", e);
+                    *self.last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(format!("{:#}", e));
+                    add_tag(&mut event.log, &self.tag_on_failure);
+                    output.push(event);
+                }
+            }
+        }
+        *events = output;
+        outcome
+    }
+
+    fn details(&self) -> Option<Value> {
+        Some(json!({
+            "failures": self.failures.load(Ordering::Relaxed),
+            "emitted": self.emitted.load(Ordering::Relaxed),
+            "reloads": match &self.script {
+                Script::Inline(_) => 0,
+                Script::File(file) => file.reloads(),
+            },
+            "last_error": *self.last_error.lock().unwrap_or_else(|e| e.into_inner()),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, events, log};
+
+    fn run(stage: &ScriptStage, messages: &[&str]) -> (Vec<Event>, BatchOutcome) {
+        let mut events = events(messages.iter().map(|message| log(message, Some("INFO"), json!({}))));
+        let outcome = stage.process_batch(&mut events);
+        (events, outcome)
+    }
+
+    #[test]
+    fn scripts_change_events_in_place() {
+        let stage = configured(ScriptStage::new, json!({
+            "source": r#"event.level = "WARN"; event.attributes.len = event.message.len(); event.message += "!";"#,
+        }))
+        .unwrap();
+        let (events, _) = run(&stage, &["disk"]);
+        assert_eq!(events[0].log.level.as_deref(), Some("WARN"));
+        assert_eq!(events[0].log.message, "disk!");
+        assert_eq!(events[0].log.attributes["len"], 4);
+    }
+
+    #[test]
+    fn unit_event_drops_and_emit_adds_events() {
+        let stage = configured(ScriptStage::new, json!({
+            "source": r#"
+                if event.message == "noise" { event = (); }
+                else if event.message == "split" {
+                    for part in ["a", "b"] { emit.push(#{ message: part, level: "DEBUG" }); }
+                }
+            "#,
+        }))
+        .unwrap();
+        let (events, outcome) = run(&stage, &["noise", "split", "keep"]);
+        assert_eq!(outcome.dropped, 1);
+        let messages: Vec<_> = events.iter().map(|e| e.log.message.as_str()).collect();
+        // Emitted events follow the event that produced them
+        assert_eq!(messages, vec!["split", "a", "b", "keep"]);
+        assert_eq!(events[1].log.level.as_deref(), Some("DEBUG"));
+        assert_eq!(stage.details().unwrap()["emitted"], 2);
+    }
+
+    #[test]
+    fn too_many_emitted_events_fail_the_event() {
+        let stage = configured(ScriptStage::new, json!({
+            "source": r#"for i in 0..3 { emit.push(#{ message: `copy ${i}` }); }"#,
+            "max_emitted": 2,
+        }))
+        .unwrap();
+        let (events, _) = run(&stage, &["x"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].log.attributes["tags"], json!(["_scriptfailure"]));
+        assert!(stage.details().unwrap()["last_error"].as_str().unwrap().contains("limit is 2"));
+    }
+
+    #[test]
+    fn runaway_scripts_time_out() {
+        let stage = configured(ScriptStage::new, json!({
+            "source": "event.message = \"changed\"; loop { }",
+            "max_operations": 0, // Unlimited, so only the deadline stops it
+            "timeout_ms": 20,
+        }))
+        .unwrap();
+        let started = Instant::now();
+        let (events, _) = run(&stage, &["x"]);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // A failed event passes through unchanged
+        assert_eq!(events[0].log.message, "x");
+        assert_eq!(events[0].log.attributes["tags"], json!(["_scriptfailure"]));
+        let last_error = stage.details().unwrap()["last_error"].clone();
+        assert!(last_error.as_str().unwrap().contains("script timed out"), "{}", last_error);
+    }
+
+    #[test]
+    fn operation_limit_applies_per_event() {
+        let stage = configured(ScriptStage::new, json!({ "source": "let n = 0; while n < 1000 { n += 1; }", "max_operations": 100 })).unwrap();
+        let (events, _) = run(&stage, &["x", "y"]);
+        assert!(events.iter().all(|e| e.log.attributes.contains_key("tags")));
+        assert_eq!(stage.details().unwrap()["failures"], 2);
+    }
+
+    #[test]
+    fn changed_script_files_are_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("enrich.rhai");
+        std::fs::write(&path, r#"event.attributes.version = 1;"#).unwrap();
+        let stage = configured(ScriptStage::new, json!({ "path": path, "reload_secs": 1 })).unwrap();
+        assert_eq!(run(&stage, &["x"]).0[0].log.attributes["version"], 1);
+
+        // A broken edit keeps the old script running
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(&path, "event.attributes.version = ").unwrap();
+        assert_eq!(run(&stage, &["x"]).0[0].log.attributes["version"], 1);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        std::fs::write(&path, r#"event.attributes.version = 2;"#).unwrap();
+        assert_eq!(run(&stage, &["x"]).0[0].log.attributes["version"], 2);
+        assert_eq!(stage.details().unwrap()["reloads"], 1);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        assert!(configured(ScriptStage::new, json!({})).is_err());
+        assert!(configured(ScriptStage::new, json!({ "source": "let x = ;" })).is_err());
+        assert!(configured(ScriptStage::new, json!({ "source": "eval(\"1\")" })).is_err());
+    }
+}
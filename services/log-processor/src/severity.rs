@@ -0,0 +1,374 @@
+use crate::models::LogEntry;
+use crate::processor::{Action, Stage};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Canonical severity. Discriminants match the first OTLP severity number of each level, so
+/// ordering and numeric comparisons agree with OpenTelemetry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Severity {
+    Trace = 1,
+    Debug = 5,
+    Info = 9,
+    Warn = 13,
+    Error = 17,
+    Fatal = 21,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Trace => "TRACE",
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+            Self::Fatal => "FATAL",
+        }
+    }
+
+    pub fn number(self) -> i64 {
+        self as i64
+    }
+
+    // ✅ Built-in names and abbreviations used by common loggers (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        Some(match name.as_str() {
+            "trace" | "trc" | "t" | "finest" | "finer" | "verbose" | "v" => Self::Trace,
+            "debug" | "dbg" | "d" | "fine" | "config" | "debug1" | "debug2" | "debug3" | "debug4" | "debug5" => Self::Debug,
+            "info" | "inf" | "i" | "information" | "informational" | "notice" | "log" | "statement" | "detail"
+            | "hint" | "context" | "default" => Self::Info,
+            "warn" | "warning" | "wrn" | "w" => Self::Warn,
+            "error" | "err" | "e" | "severe" | "eror" => Self::Error,
+            "fatal" | "ftl" | "f" | "critical" | "crit" | "c" | "alert" | "emerg" | "emergency" | "panic" => Self::Fatal,
+            _ => return None,
+        })
+    }
+
+    /// RFC 5424 syslog severities (0 = emergency ... 7 = debug).
+    pub fn from_syslog(number: i64) -> Option<Self> {
+        Some(match number {
+            0..=2 => Self::Fatal,
+            3 => Self::Error,
+            4 => Self::Warn,
+            5 | 6 => Self::Info,
+            7 => Self::Debug,
+            _ => return None,
+        })
+    }
+
+    /// OTLP severity numbers come in blocks of four per level (1-4 TRACE ... 21-24 FATAL).
+    pub fn from_otlp(number: i64) -> Option<Self> {
+        Some(match number {
+            1..=4 => Self::Trace,
+            5..=8 => Self::Debug,
+            9..=12 => Self::Info,
+            13..=16 => Self::Warn,
+            17..=20 => Self::Error,
+            21..=24 => Self::Fatal,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumericLevels {
+    #[default]
+    Syslog,
+    Otlp,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Heuristic {
+    pub pattern: String,
+    pub level: Severity,
+}
+
+// ✅ `{"type": "severity", "mapping": {"notice": "WARN"}, "placeholder_levels": ["INFO"]}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeverityConfig {
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>, // Candidate level fields, first mappable one wins
+    #[serde(default)]
+    pub mapping: HashMap<String, Severity>, // Extra or overriding names (case-insensitive)
+    #[serde(default)]
+    pub numeric_levels: NumericLevels, // How bare numbers are read; `severity_number` is always OTLP
+    #[serde(default)]
+    pub placeholder_levels: Vec<String>, // Levels set by shippers that don't know better; re-inferred
+    #[serde(default)]
+    pub heuristics: Vec<Heuristic>, // Tried before the built-in message heuristics
+    #[serde(default = "default_level")]
+    pub default_level: Severity, // When nothing maps and nothing can be inferred
+}
+
+fn default_fields() -> Vec<String> {
+    ["level", "attributes.severity_number", "attributes.level", "attributes.severity", "attributes.log.level"]
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+}
+
+fn default_level() -> Severity {
+    Severity::Info
+}
+
+// Explicit level tokens only count near the start of a line, where log layouts put them
+const LEVEL_TOKEN_WINDOW: usize = 64;
+// Heuristics never scan past this many bytes of a message
+const HEURISTIC_WINDOW: usize = 4096;
+
+pub struct SeverityStage {
+    fields: Vec<String>,
+    mapping: HashMap<String, Severity>,
+    numeric_levels: NumericLevels,
+    placeholder_levels: Vec<String>,
+    level_token: Regex,
+    heuristics: Vec<(Regex, Severity)>,
+    default_level: Severity,
+}
+
+impl SeverityStage {
+    pub fn new(config: &SeverityConfig) -> Result<Self> {
+        let mut heuristics = config
+            .heuristics
+            .iter()
+            .map(|h| Ok((Regex::new(&h.pattern).with_context(|| format!("invalid severity heuristic {:?}", h.pattern))?, h.level)))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Built-in content heuristics, most severe first
+        for (pattern, level) in [
+            (r"\bpanicked at\b|\bFATAL\b|\bPANIC\b|\bCRITICAL\b|\bEMERG(?:ENCY)?\b", Severity::Fatal),
+            (
+                r"\bERROR\b|\bSEVERE\b|\b[A-Z]\w*(?:Exception|Error)\b|Traceback \(most recent call last\)|(?m)^\s+at [\w$.<>]+\(",
+                Severity::Error,
+            ),
+            (r"\bWARN(?:ING)?\b|\b[A-Z]\w*Warning\b", Severity::Warn),
+        ] {
+            heuristics.push((Regex::new(pattern).expect("built-in severity heuristic"), level));
+        }
+
+        Ok(Self {
+            fields: config.fields.clone(),
+            mapping: config.mapping.iter().map(|(name, level)| (name.to_ascii_lowercase(), *level)).collect(),
+            numeric_levels: config.numeric_levels,
+            placeholder_levels: config.placeholder_levels.iter().map(|l| l.to_ascii_lowercase()).collect(),
+            level_token: Regex::new(
+                r"(?i)(?:^|[\s\[(|<])(TRACE|DEBUG|INFO|NOTICE|WARN|WARNING|ERROR|ERR|SEVERE|FATAL|CRITICAL|CRIT|PANIC)(?:$|[\s\])|>:])",
+            )
+            .expect("built-in level token pattern"),
+            heuristics,
+            default_level: config.default_level,
+        })
+    }
+
+    fn map_value(&self, field: &str, raw: &str) -> Option<Severity> {
+        let raw = raw.trim();
+        if let Some(level) = self.mapping.get(&raw.to_ascii_lowercase()) {
+            return Some(*level);
+        }
+        if let Ok(number) = raw.parse::<i64>() {
+            return if field.ends_with("severity_number") {
+                Severity::from_otlp(number)
+            } else {
+                match self.numeric_levels {
+                    NumericLevels::Syslog => Severity::from_syslog(number),
+                    NumericLevels::Otlp => Severity::from_otlp(number),
+                }
+            };
+        }
+        Severity::from_name(raw)
+    }
+
+    fn infer(&self, message: &str) -> Option<Severity> {
+        let head = truncate(message, LEVEL_TOKEN_WINDOW);
+        if let Some(token) = self.level_token.captures(head).and_then(|caps| caps.get(1)) {
+            if let Some(level) = self.mapping.get(&token.as_str().to_ascii_lowercase()).copied().or_else(|| Severity::from_name(token.as_str())) {
+                return Some(level);
+            }
+        }
+        let window = truncate(message, HEURISTIC_WINDOW);
+        self.heuristics.iter().find(|(regex, _)| regex.is_match(window)).map(|(_, level)| *level)
+    }
+}
+
+// Cuts at a char boundary at or below `max` bytes
+fn truncate(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+impl Stage for SeverityStage {
+    fn name(&self) -> &str {
+        "severity"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        let original = log.level.clone();
+        let is_placeholder = original
+            .as_deref()
+            .is_some_and(|level| self.placeholder_levels.contains(&level.to_ascii_lowercase()));
+
+        let supplied = if is_placeholder {
+            None
+        } else {
+            self.fields
+                .iter()
+                .find_map(|field| log.field(field).and_then(|raw| self.map_value(field, &raw)))
+        };
+
+        let (severity, inferred) = match supplied {
+            Some(severity) => (severity, false),
+            None => match self.infer(&log.message) {
+                Some(severity) => (severity, true),
+                // A placeholder is still better than the default when the message gives no hint
+                None => match original.as_deref().filter(|_| is_placeholder).and_then(Severity::from_name) {
+                    Some(severity) => (severity, false),
+                    None => (self.default_level, true),
+                },
+            },
+        };
+
+        if let Some(original) = original.filter(|o| !o.eq_ignore_ascii_case(severity.as_str())) {
+            log.attributes.insert("level.original".to_string(), Value::String(original));
+        }
+        if inferred {
+            log.attributes.insert("level.inferred".to_string(), Value::Bool(true));
+        }
+        // Keep finer-grained OTLP numbers when they agree with the canonical level
+        let keep_number = log
+            .attributes
+            .get("severity_number")
+            .and_then(Value::as_i64)
+            .and_then(Severity::from_otlp)
+            == Some(severity);
+        if !keep_number {
+            log.attributes.insert("severity_number".to_string(), Value::from(severity.number()));
+        }
+        log.level = Some(severity.as_str().to_string());
+        Action::Keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, log};
+    use serde_json::json;
+
+    fn process(stage: &SeverityStage, mut log: LogEntry) -> LogEntry {
+        stage.process(&mut log);
+        log
+    }
+
+    #[test]
+    fn syslog_and_otlp_numbers_map_differently() {
+        assert_eq!(Severity::from_syslog(0), Some(Severity::Fatal));
+        assert_eq!(Severity::from_syslog(3), Some(Severity::Error));
+        assert_eq!(Severity::from_syslog(4), Some(Severity::Warn));
+        assert_eq!(Severity::from_syslog(5), Some(Severity::Info));
+        assert_eq!(Severity::from_syslog(7), Some(Severity::Debug));
+        assert_eq!(Severity::from_syslog(8), None);
+
+        assert_eq!(Severity::from_otlp(1), Some(Severity::Trace));
+        assert_eq!(Severity::from_otlp(12), Some(Severity::Info));
+        assert_eq!(Severity::from_otlp(13), Some(Severity::Warn));
+        assert_eq!(Severity::from_otlp(24), Some(Severity::Fatal));
+        assert_eq!(Severity::from_otlp(0), None);
+        assert_eq!(Severity::from_otlp(25), None);
+    }
+
+    #[test]
+    fn bare_numbers_follow_numeric_levels() {
+        let syslog = configured(SeverityStage::new, json!({})).unwrap();
+        assert_eq!(process(&syslog, log("", Some("3"), json!({}))).level.as_deref(), Some("ERROR"));
+
+        let otlp = configured(SeverityStage::new, json!({ "numeric_levels": "otlp" })).unwrap();
+        assert_eq!(process(&otlp, log("", Some("3"), json!({}))).level.as_deref(), Some("TRACE"));
+        assert_eq!(process(&otlp, log("", Some("17"), json!({}))).level.as_deref(), Some("ERROR"));
+    }
+
+    #[test]
+    fn severity_number_is_always_otlp() {
+        let syslog = configured(SeverityStage::new, json!({})).unwrap();
+        let entry = process(&syslog, log("", None, json!({ "severity_number": 14 })));
+        assert_eq!(entry.level.as_deref(), Some("WARN"));
+        // The finer-grained number agrees with WARN, so it's kept
+        assert_eq!(entry.attributes["severity_number"], 14);
+
+        let entry = process(&syslog, log("", Some("ERROR"), json!({ "severity_number": 14 })));
+        assert_eq!(entry.level.as_deref(), Some("ERROR"));
+        assert_eq!(entry.attributes["severity_number"], 17);
+    }
+
+    #[test]
+    fn names_and_custom_mapping() {
+        let stage = configured(SeverityStage::new, json!({ "mapping": { "Notice": "WARN" } })).unwrap();
+        let entry = process(&stage, log("", Some("notice"), json!({})));
+        assert_eq!(entry.level.as_deref(), Some("WARN"));
+        assert_eq!(entry.attributes["level.original"], "notice");
+
+        let entry = process(&stage, log("", Some("crit"), json!({})));
+        assert_eq!(entry.level.as_deref(), Some("FATAL"));
+
+        // A level that's already canonical keeps no `level.original`
+        let entry = process(&stage, log("", Some("error"), json!({})));
+        assert_eq!(entry.level.as_deref(), Some("ERROR"));
+        assert!(!entry.attributes.contains_key("level.original"));
+    }
+
+    #[test]
+    fn missing_levels_are_inferred_from_the_message() {
+        let stage = configured(SeverityStage::new, json!({})).unwrap();
+        let entry = process(&stage, log("2024-05-01 12:00:00 [WARN] disk 91% full", None, json!({})));
+        assert_eq!(entry.level.as_deref(), Some("WARN"));
+        assert_eq!(entry.attributes["level.inferred"], true);
+
+        let trace = "Traceback (most recent call last):\n  File \"app.py\", line 3";
+        assert_eq!(process(&stage, log(trace, None, json!({}))).level.as_deref(), Some("ERROR"));
+        let rust = "thread 'main' panicked at src/main.rs:2:5";
+        assert_eq!(process(&stage, log(rust, None, json!({}))).level.as_deref(), Some("FATAL"));
+
+        let entry = process(&stage, log("user signed in", None, json!({})));
+        assert_eq!(entry.level.as_deref(), Some("INFO"));
+        assert_eq!(entry.attributes["level.inferred"], true);
+    }
+
+    #[test]
+    fn placeholder_levels_are_reinferred() {
+        let stage = configured(SeverityStage::new, json!({ "placeholder_levels": ["info"], "default_level": "DEBUG" })).unwrap();
+        let entry = process(&stage, log("java.lang.NullPointerException: boom", Some("INFO"), json!({})));
+        assert_eq!(entry.level.as_deref(), Some("ERROR"));
+        assert_eq!(entry.attributes["level.original"], "INFO");
+
+        // Nothing to infer from: the placeholder beats the default
+        let entry = process(&stage, log("user signed in", Some("INFO"), json!({})));
+        assert_eq!(entry.level.as_deref(), Some("INFO"));
+        assert!(!entry.attributes.contains_key("level.inferred"));
+    }
+
+    #[test]
+    fn truncate_respects_char_boundaries() {
+        assert_eq!(truncate("héllo", 2), "h");
+        assert_eq!(truncate("héllo", 3), "hé");
+        assert_eq!(truncate("hi", 64), "hi");
+    }
+}
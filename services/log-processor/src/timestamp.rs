@@ -0,0 +1,351 @@
+use crate::grok::add_tag;
+use crate::models::LogEntry;
+use crate::processor::{Action, Stage};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDateTime, Offset, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::Value;
+
+// Naive layouts tried after RFC 3339 / RFC 2822; the zone comes from the stage config
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%d/%b/%Y:%H:%M:%S%.f",
+    "%a %b %d %H:%M:%S%.f %Y", // Apache error log, ctime
+    "%d-%b-%Y %H:%M:%S%.f",
+];
+
+// Layouts carrying their own offset
+const OFFSET_FORMATS: &[&str] = &[
+    "%d/%b/%Y:%H:%M:%S %z", // Common/combined access log
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+];
+
+// RFC 3164 syslog stamps omit the year
+const SYSLOG_FORMATS: &[&str] = &["%Y %b %d %H:%M:%S%.f", "%Y %b %d %H:%M:%S"];
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EpochUnit {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+// ✅ `{"type": "timestamp", "fields": ["attributes.timestamp", "timestamp"], "timezone": "Europe/Berlin"}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimestampConfig {
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>, // Candidate fields, first parseable one wins
+    #[serde(default)]
+    pub formats: Vec<String>, // strftime layouts tried before the built-in ones
+    #[serde(default)]
+    pub timezone: Option<String>, // IANA name or fixed offset for stamps without one (default UTC)
+    #[serde(default)]
+    pub epoch_unit: Option<EpochUnit>, // Unit of numeric stamps; guessed from magnitude if unset
+    #[serde(default = "default_failure_tag")]
+    pub tag_on_failure: String,
+}
+
+fn default_fields() -> Vec<String> {
+    vec!["timestamp".to_string()]
+}
+
+fn default_failure_tag() -> String {
+    "_timestampparsefailure".to_string()
+}
+
+#[derive(Clone, Copy)]
+enum Zone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    fn parse(name: &str) -> Result<Self> {
+        if let Ok(tz) = name.parse::<Tz>() {
+            return Ok(Self::Named(tz));
+        }
+        DateTime::parse_from_str(&format!("2000-01-01 00:00:00 {}", name), "%Y-%m-%d %H:%M:%S %z")
+            .map(|dt| Self::Fixed(*dt.offset()))
+            .map_err(|_| anyhow!("unknown timezone '{}'", name))
+    }
+
+    // Ambiguous local times take the earlier instant; times skipped by DST use the current offset
+    fn localize(self, naive: &NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            Self::Named(tz) => tz
+                .from_local_datetime(naive)
+                .earliest()
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|| {
+                    let offset = tz.offset_from_utc_datetime(naive).fix();
+                    Utc.from_utc_datetime(&(*naive - offset))
+                }),
+            Self::Fixed(offset) => Utc.from_utc_datetime(&(*naive - offset)),
+        }
+    }
+}
+
+pub struct TimestampStage {
+    fields: Vec<String>,
+    formats: Vec<String>,
+    zone: Zone,
+    epoch_unit: Option<EpochUnit>,
+    tag_on_failure: String,
+}
+
+impl TimestampStage {
+    pub fn new(config: &TimestampConfig) -> Result<Self> {
+        let zone = match &config.timezone {
+            Some(name) => Zone::parse(name).context("invalid timestamp stage timezone")?,
+            None => Zone::Fixed(FixedOffset::east_opt(0).expect("zero offset")),
+        };
+        Ok(Self {
+            fields: config.fields.clone(),
+            formats: config.formats.clone(),
+            zone,
+            epoch_unit: config.epoch_unit,
+            tag_on_failure: config.tag_on_failure.clone(),
+        })
+    }
+
+    fn parse(&self, raw: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let raw = raw.trim();
+        if raw.is_empty() {
+            return None;
+        }
+
+        for format in &self.formats {
+            if let Some(parsed) = self.parse_with_format(raw, format) {
+                return Some(parsed);
+            }
+        }
+
+        if let Some(parsed) = self.parse_epoch(raw) {
+            return Some(parsed);
+        }
+        if let Ok(parsed) = DateTime::parse_from_rfc3339(raw) {
+            return Some(parsed.with_timezone(&Utc));
+        }
+        if let Ok(parsed) = DateTime::parse_from_rfc2822(raw) {
+            return Some(parsed.with_timezone(&Utc));
+        }
+
+        // Java and Postgres use a comma or a trailing zone name; normalize both
+        let cleaned = raw.replacen(',', ".", 1);
+        let (cleaned, zone) = match cleaned.rsplit_once(' ') {
+            Some((rest, "UTC" | "GMT" | "Z")) => (rest.to_string(), Zone::Fixed(FixedOffset::east_opt(0)?)),
+            Some((rest, name)) if name.contains('/') => match name.parse::<Tz>() {
+                Ok(tz) => (rest.to_string(), Zone::Named(tz)),
+                Err(_) => (cleaned.clone(), self.zone),
+            },
+            _ => (cleaned.clone(), self.zone),
+        };
+
+        if let Ok(parsed) = DateTime::parse_from_rfc3339(&cleaned) {
+            return Some(parsed.with_timezone(&Utc));
+        }
+        for format in OFFSET_FORMATS {
+            if let Ok(parsed) = DateTime::parse_from_str(&cleaned, format) {
+                return Some(parsed.with_timezone(&Utc));
+            }
+        }
+        for format in NAIVE_FORMATS {
+            if let Ok(naive) = NaiveDateTime::parse_from_str(&cleaned, format) {
+                return Some(zone.localize(&naive));
+            }
+        }
+        self.parse_syslog(&cleaned, zone, now)
+    }
+
+    fn parse_with_format(&self, raw: &str, format: &str) -> Option<DateTime<Utc>> {
+        if let Ok(parsed) = DateTime::parse_from_str(raw, format) {
+            return Some(parsed.with_timezone(&Utc));
+        }
+        NaiveDateTime::parse_from_str(raw, format)
+            .ok()
+            .map(|naive| self.zone.localize(&naive))
+    }
+
+    fn parse_epoch(&self, raw: &str) -> Option<DateTime<Utc>> {
+        let (whole, fraction) = raw.split_once('.').unwrap_or((raw, ""));
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let whole: i64 = whole.parse().ok()?;
+        let unit = self.epoch_unit.unwrap_or(match whole {
+            v if v < 100_000_000_000 => EpochUnit::Seconds,
+            v if v < 100_000_000_000_000 => EpochUnit::Millis,
+            v if v < 100_000_000_000_000_000 => EpochUnit::Micros,
+            _ => EpochUnit::Nanos,
+        });
+        let nanos_per_unit: i64 = match unit {
+            EpochUnit::Seconds => 1_000_000_000,
+            EpochUnit::Millis => 1_000_000,
+            EpochUnit::Micros => 1_000,
+            EpochUnit::Nanos => 1,
+        };
+
+        // Decimal arithmetic keeps fractional stamps exact down to the nanosecond
+        let digits = nanos_per_unit.ilog10() as usize;
+        let fraction_nanos: i64 = format!("{:0<width$}", &fraction[..fraction.len().min(digits)], width = digits)
+            .parse()
+            .unwrap_or(0);
+        let nanos = whole.checked_mul(nanos_per_unit)?.checked_add(fraction_nanos)?;
+        Some(Utc.timestamp_nanos(nanos))
+    }
+
+    // `May  1 12:00:00` — assume the current year, or last year if that lands in the future
+    fn parse_syslog(&self, raw: &str, zone: Zone, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let collapsed = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+        let year = now.year();
+        for format in SYSLOG_FORMATS {
+            if let Ok(naive) = NaiveDateTime::parse_from_str(&format!("{} {}", year, collapsed), format) {
+                let parsed = zone.localize(&naive);
+                if parsed > now + Duration::days(1) {
+                    let naive = NaiveDateTime::parse_from_str(&format!("{} {}", year - 1, collapsed), format).ok()?;
+                    return Some(zone.localize(&naive));
+                }
+                return Some(parsed);
+            }
+        }
+        None
+    }
+}
+
+impl Stage for TimestampStage {
+    fn name(&self) -> &str {
+        "timestamp"
+    }
+
+    fn process(&self, log: &mut LogEntry) -> Action {
+        let now = Utc::now();
+        let raw = self.fields.iter().find_map(|field| log.field(field).map(|v| v.into_owned()));
+        let event_time = raw.as_deref().and_then(|raw| self.parse(raw, now));
+
+        let ingested_nanos = now.timestamp_nanos_opt().unwrap_or_default();
+        log.attributes.insert("event.ingested".to_string(), Value::String(now.to_rfc3339_opts(SecondsFormat::Nanos, true)));
+        log.attributes.insert("event.ingested_unix_nano".to_string(), Value::from(ingested_nanos));
+
+        // Unparseable or missing stamps fall back to ingest time, keeping the original for inspection
+        let event_time = match event_time {
+            Some(event_time) => event_time,
+            None => {
+                if let Some(raw) = raw {
+                    log.attributes.insert("timestamp.original".to_string(), Value::String(raw));
+                }
+                add_tag(log, &self.tag_on_failure);
+                now
+            }
+        };
+
+        log.timestamp = Some(event_time.to_rfc3339_opts(SecondsFormat::Nanos, true));
+        log.attributes.insert(
+            "event.time_unix_nano".to_string(),
+            Value::from(event_time.timestamp_nanos_opt().unwrap_or_default()),
+        );
+        Action::Keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::{configured, log};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn utc(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap()
+    }
+
+    #[test]
+    fn epoch_unit_is_guessed_from_magnitude() {
+        let stage = configured(TimestampStage::new, json!({})).unwrap();
+        let now = Utc::now();
+        let expected = utc(2024, 5, 1, 12, 0, 0);
+        assert_eq!(stage.parse("1714564800", now), Some(expected));
+        assert_eq!(stage.parse("1714564800123", now), Some(expected + Duration::milliseconds(123)));
+        assert_eq!(stage.parse("1714564800123456", now), Some(expected + Duration::microseconds(123_456)));
+        assert_eq!(stage.parse("1714564800123456789", now), Some(expected + Duration::nanoseconds(123_456_789)));
+        // Fractions are exact, and digits finer than a nanosecond are ignored
+        assert_eq!(stage.parse("1714564800.5", now), Some(expected + Duration::milliseconds(500)));
+        assert_eq!(stage.parse("1714564800123.4567", now), Some(expected + Duration::nanoseconds(123_456_700)));
+        assert_eq!(stage.parse("1714564800.1234567891", now), Some(expected + Duration::nanoseconds(123_456_789)));
+    }
+
+    #[test]
+    fn configured_epoch_unit_wins_over_the_guess() {
+        let millis = configured(TimestampStage::new, json!({ "epoch_unit": "millis" })).unwrap();
+        assert_eq!(millis.parse("1714564800", Utc::now()), Some(utc(1970, 1, 20, 20, 16, 4) + Duration::milliseconds(800)));
+        // Too large to represent in nanoseconds
+        let seconds = configured(TimestampStage::new, json!({ "epoch_unit": "seconds" })).unwrap();
+        assert_eq!(seconds.parse("1714564800123456789", Utc::now()), None);
+    }
+
+    #[test]
+    fn syslog_year_rolls_back_over_new_year() {
+        let stage = configured(TimestampStage::new, json!({})).unwrap();
+        let now = utc(2024, 1, 2, 8, 0, 0);
+        assert_eq!(stage.parse("Dec 31 23:59:59", now), Some(utc(2023, 12, 31, 23, 59, 59)));
+        assert_eq!(stage.parse("Jan  2 07:59:00", now), Some(utc(2024, 1, 2, 7, 59, 0)));
+        // Up to a day ahead is clock skew, not last year
+        assert_eq!(stage.parse("Jan  2 20:00:00", now), Some(utc(2024, 1, 2, 20, 0, 0)));
+        assert_eq!(stage.parse("Jan  4 00:00:00", now), Some(utc(2023, 1, 4, 0, 0, 0)));
+    }
+
+    #[test]
+    fn dst_gaps_and_overlaps_in_a_named_zone() {
+        let stage = configured(TimestampStage::new, json!({ "timezone": "Europe/Berlin" })).unwrap();
+        let now = Utc::now();
+        assert_eq!(stage.parse("2024-01-15 12:00:00", now), Some(utc(2024, 1, 15, 11, 0, 0)));
+        assert_eq!(stage.parse("2024-07-15 12:00:00", now), Some(utc(2024, 7, 15, 10, 0, 0)));
+        // 02:30 on 31 March doesn't exist in Berlin; it's read with the summer offset
+        assert_eq!(stage.parse("2024-03-31 02:30:00", now), Some(utc(2024, 3, 31, 0, 30, 0)));
+        // 02:30 on 27 October happens twice; the earlier (summer time) one is taken
+        assert_eq!(stage.parse("2024-10-27 02:30:00", now), Some(utc(2024, 10, 27, 0, 30, 0)));
+    }
+
+    #[test]
+    fn explicit_offsets_and_zone_names_beat_the_configured_zone() {
+        let stage = configured(TimestampStage::new, json!({ "timezone": "+05:30" })).unwrap();
+        let now = Utc::now();
+        assert_eq!(stage.parse("2024-05-01 17:30:00", now), Some(utc(2024, 5, 1, 12, 0, 0)));
+        assert_eq!(stage.parse("2024-05-01T12:00:00Z", now), Some(utc(2024, 5, 1, 12, 0, 0)));
+        assert_eq!(stage.parse("01/May/2024:14:00:00 +0200", now), Some(utc(2024, 5, 1, 12, 0, 0)));
+        assert_eq!(stage.parse("2024-05-01 12:00:00,250 UTC", now), Some(utc(2024, 5, 1, 12, 0, 0) + Duration::milliseconds(250)));
+        assert_eq!(stage.parse("2024-05-01 14:00:00 Europe/Berlin", now), Some(utc(2024, 5, 1, 12, 0, 0)));
+    }
+
+    #[test]
+    fn custom_formats_are_tried_first() {
+        let stage = configured(TimestampStage::new, json!({ "formats": ["%d.%m.%Y %H:%M"], "timezone": "UTC" })).unwrap();
+        assert_eq!(stage.parse("01.05.2024 12:00", Utc::now()), Some(utc(2024, 5, 1, 12, 0, 0)));
+    }
+
+    #[test]
+    fn invalid_timezone_is_rejected() {
+        assert!(configured(TimestampStage::new, json!({ "timezone": "Mars/Olympus" })).is_err());
+    }
+
+    #[test]
+    fn process_normalizes_or_falls_back_to_ingest_time() {
+        let stage = configured(TimestampStage::new, json!({ "fields": ["attributes.time", "timestamp"] })).unwrap();
+        let mut log = LogEntry { timestamp: Some("ignored".to_string()), ..log("", None, json!({ "time": 1714564800 })) };
+        stage.process(&mut log);
+        assert_eq!(log.timestamp.as_deref(), Some("2024-05-01T12:00:00.000000000Z"));
+        assert_eq!(log.attributes["event.time_unix_nano"], 1_714_564_800_000_000_000_i64);
+        assert!(log.attributes.contains_key("event.ingested"));
+
+        let mut log = LogEntry { timestamp: Some("sometime".to_string()), attributes: HashMap::new(), ..log };
+        stage.process(&mut log);
+        assert_eq!(log.attributes["timestamp.original"], "sometime");
+        assert_eq!(log.attributes["tags"], json!(["_timestampparsefailure"]));
+        assert_eq!(log.attributes["event.time_unix_nano"], log.attributes["event.ingested_unix_nano"]);
+    }
+}
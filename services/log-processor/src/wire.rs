@@ -0,0 +1,465 @@
+use crate::models::LogEntry;
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// ✅ Binary batch framing shared with the collector (`insightx.logs.v1.LogBatch`)
+//
+// Content-Type is `application/x-protobuf; proto=insightx.logs.v1.LogBatch; version=1`, normally
+// zstd-compressed. Receivers answer 415 to a format or version they can't read, and senders fall
+// back to JSON. Field tags are the schema: never reuse or renumber them; add fields with new tags
+// and bump `SCHEMA_VERSION` only for changes old readers would misinterpret.
+//
+//     message LogBatch {
+//       uint32 schema_version = 1;
+//       repeated LogEntry entries = 2;
+//     }
+//     message LogEntry {
+//       optional string timestamp = 1;
+//       optional string level = 2;
+//       string message = 3;
+//       optional string source = 4;
+//       map<string, AttributeValue> attributes = 5;
+//     }
+//     message AttributeValue {        // no kind set means JSON `null`
+//       oneof kind {
+//         string string = 1;
+//         int64 int = 2;
+//         double double = 3;
+//         bool bool = 4;
+//         string json = 5;            // arrays, objects and integers beyond i64, JSON-encoded
+//       }
+//     }
+//
+// The codec is written out by hand rather than generated so entries go straight between
+// `LogEntry` and the wire, without an intermediate copy of every string and attribute map.
+
+pub const SCHEMA_VERSION: u32 = 1;
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf; proto=insightx.logs.v1.LogBatch; version=1";
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+// Protobuf wire types used by the schema
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LEN: u8 = 2;
+const FIXED32: u8 = 5;
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_key(buf: &mut Vec<u8>, tag: u32, wire_type: u8) {
+    put_varint(buf, u64::from(tag << 3 | u32::from(wire_type)));
+}
+
+fn put_bytes(buf: &mut Vec<u8>, tag: u32, bytes: &[u8]) {
+    put_key(buf, tag, LEN);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+// Encoded size of a length-delimited field (every tag in the schema fits a one-byte key)
+fn bytes_field_len(len: usize) -> usize {
+    1 + varint_len(len as u64) + len
+}
+
+// Scratch buffers reused across a batch: an entry's length prefix is only known once it's written
+#[derive(Default)]
+struct Scratch {
+    entry: Vec<u8>,
+    value: Vec<u8>,
+    json: Vec<u8>,
+}
+
+fn put_value(buf: &mut Vec<u8>, json: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => {
+            put_key(buf, 4, VARINT);
+            put_varint(buf, u64::from(*b));
+        }
+        Value::String(s) => put_bytes(buf, 1, s.as_bytes()),
+        Value::Number(n) if n.is_i64() || n.is_f64() => match n.as_i64() {
+            Some(i) => {
+                put_key(buf, 2, VARINT);
+                put_varint(buf, i as u64);
+            }
+            None => {
+                put_key(buf, 3, FIXED64);
+                buf.extend_from_slice(&n.as_f64().unwrap_or_default().to_bits().to_le_bytes());
+            }
+        },
+        other => {
+            json.clear();
+            let _ = serde_json::to_writer(&mut *json, other);
+            put_bytes(buf, 5, json);
+        }
+    }
+}
+
+fn put_entry(buf: &mut Vec<u8>, log: &LogEntry, scratch: &mut Scratch) {
+    let entry = &mut scratch.entry;
+    entry.clear();
+    for (tag, field) in [(1, &log.timestamp), (2, &log.level), (4, &log.source)] {
+        if let Some(field) = field {
+            put_bytes(entry, tag, field.as_bytes());
+        }
+    }
+    if !log.message.is_empty() {
+        put_bytes(entry, 3, log.message.as_bytes());
+    }
+    for (key, value) in &log.attributes {
+        // A map entry is a message of its own: key = 1, value = 2 (left out for null)
+        scratch.value.clear();
+        put_value(&mut scratch.value, &mut scratch.json, value);
+        let mut len = bytes_field_len(key.len());
+        if !scratch.value.is_empty() {
+            len += bytes_field_len(scratch.value.len());
+        }
+        put_key(entry, 5, LEN);
+        put_varint(entry, len as u64);
+        put_bytes(entry, 1, key.as_bytes());
+        if !scratch.value.is_empty() {
+            put_bytes(entry, 2, &scratch.value);
+        }
+    }
+    put_bytes(buf, 2, entry);
+}
+
+// Reads fields off a protobuf message; anything malformed is a plain error (400)
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for (i, byte) in self.0.iter().take(10).enumerate() {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[i + 1..];
+                return Ok(value);
+            }
+        }
+        bail!("malformed protobuf varint")
+    }
+
+    fn key(&mut self) -> Result<(u32, u8)> {
+        let key = self.varint()?;
+        Ok(((key >> 3) as u32, (key & 0x7) as u8))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.0.len() {
+            bail!("truncated protobuf message");
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(std::str::from_utf8(self.bytes()?)?.to_owned())
+    }
+
+    fn fixed64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    // Fields from a newer schema are skipped, which is what keeps additions backward compatible
+    fn skip(&mut self, wire_type: u8) -> Result<()> {
+        match wire_type {
+            VARINT => self.varint().map(drop),
+            FIXED64 => self.take(8).map(drop),
+            LEN => self.bytes().map(drop),
+            FIXED32 => self.take(4).map(drop),
+            other => bail!("unsupported protobuf wire type {}", other),
+        }
+    }
+}
+
+fn read_value(mut reader: Reader) -> Result<Value> {
+    let mut value = Value::Null;
+    while !reader.is_empty() {
+        value = match reader.key()? {
+            (1, LEN) => Value::String(reader.string()?),
+            (2, VARINT) => Value::from(reader.varint()? as i64),
+            (3, FIXED64) => Value::from(f64::from_bits(reader.fixed64()?)),
+            (4, VARINT) => Value::Bool(reader.varint()? != 0),
+            (5, LEN) => {
+                let json = reader.bytes()?;
+                serde_json::from_slice(json).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(json).into_owned()))
+            }
+            (_, wire_type) => {
+                reader.skip(wire_type)?;
+                continue;
+            }
+        };
+    }
+    Ok(value)
+}
+
+// Counts the occurrences of a repeated field, so its collection is allocated once at its final size
+fn count_fields(data: &[u8], field: u32) -> Result<usize> {
+    let mut reader = Reader(data);
+    let mut count = 0;
+    while !reader.is_empty() {
+        let (tag, wire_type) = reader.key()?;
+        count += usize::from(tag == field);
+        reader.skip(wire_type)?;
+    }
+    Ok(count)
+}
+
+fn read_entry(data: &[u8]) -> Result<LogEntry> {
+    let mut reader = Reader(data);
+    let mut log = LogEntry {
+        timestamp: None,
+        level: None,
+        message: String::new(),
+        source: None,
+        attributes: HashMap::with_capacity(count_fields(data, 5)?),
+    };
+    while !reader.is_empty() {
+        match reader.key()? {
+            (1, LEN) => log.timestamp = Some(reader.string()?),
+            (2, LEN) => log.level = Some(reader.string()?),
+            (3, LEN) => log.message = reader.string()?,
+            (4, LEN) => log.source = Some(reader.string()?),
+            (5, LEN) => {
+                let mut attribute = Reader(reader.bytes()?);
+                let (mut key, mut value) = (String::new(), Value::Null);
+                while !attribute.is_empty() {
+                    match attribute.key()? {
+                        (1, LEN) => key = attribute.string()?,
+                        (2, LEN) => value = read_value(Reader(attribute.bytes()?))?,
+                        (_, wire_type) => attribute.skip(wire_type)?,
+                    }
+                }
+                log.attributes.insert(key, value);
+            }
+            (_, wire_type) => reader.skip(wire_type)?,
+        }
+    }
+    Ok(log)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Protobuf,
+}
+
+impl WireFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireFormat::Json => JSON_CONTENT_TYPE,
+            WireFormat::Protobuf => PROTOBUF_CONTENT_TYPE,
+        }
+    }
+}
+
+// How long a downstream that refused protobuf is sent JSON before protobuf is offered again
+const FALLBACK_PERIOD: Duration = Duration::from_secs(600);
+
+// ✅ Per-downstream format negotiation: the configured format until the downstream answers 415,
+// then JSON for `FALLBACK_PERIOD`, so an upgraded downstream is picked up again without a restart
+pub struct Negotiated {
+    preferred: WireFormat,
+    json_until: Mutex<Option<Instant>>,
+}
+
+impl Negotiated {
+    pub fn new(preferred: WireFormat) -> Self {
+        Self { preferred, json_until: Mutex::new(None) }
+    }
+
+    pub fn current(&self) -> WireFormat {
+        let json_until = self.json_until.lock().unwrap_or_else(|e| e.into_inner());
+        match *json_until {
+            Some(until) if Instant::now() < until => WireFormat::Json,
+            _ => self.preferred,
+        }
+    }
+
+    pub fn rejected(&self, format: WireFormat) {
+        if format != WireFormat::Json {
+            *self.json_until.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + FALLBACK_PERIOD);
+        }
+    }
+}
+
+/// The request used a format or schema version this build can't read (answered with 415).
+#[derive(Debug)]
+pub struct UnsupportedFormat(pub String);
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported wire format: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+// ✅ Decodes a (decompressed) request body according to its Content-Type; anything that isn't
+// protobuf is read as JSON, which is what older collectors send without a Content-Type
+pub fn decode_batch(content_type: Option<&str>, data: &[u8]) -> Result<Vec<LogEntry>> {
+    let Some(content_type) = content_type.filter(|ct| ct.starts_with("application/x-protobuf")) else {
+        // An array or a single object, told apart up front so the body is parsed only once
+        return match data.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'[') => Ok(serde_json::from_slice(data)?),
+            _ => Ok(vec![serde_json::from_slice(data)?]),
+        };
+    };
+
+    let params: HashMap<&str, &str> = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .collect();
+    if let Some(proto) = params.get("proto").filter(|proto| **proto != "insightx.logs.v1.LogBatch") {
+        return Err(anyhow!(UnsupportedFormat(format!("message type {}", proto))));
+    }
+    let version = match params.get("version") {
+        Some(version) => version.parse::<u32>().map_err(|_| anyhow!(UnsupportedFormat(format!("version {}", version))))?,
+        None => SCHEMA_VERSION,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(UnsupportedFormat(format!("schema version {} (newest known is {})", version, SCHEMA_VERSION))));
+    }
+
+    let mut reader = Reader(data);
+    let mut logs = Vec::with_capacity(count_fields(data, 2)?);
+    while !reader.is_empty() {
+        match reader.key()? {
+            // Written first, so a newer batch is refused before any entry is read
+            (1, VARINT) => match reader.varint()? {
+                version if version > u64::from(SCHEMA_VERSION) => {
+                    return Err(anyhow!(UnsupportedFormat(format!("schema version {}", version))));
+                }
+                _ => {}
+            },
+            (2, LEN) => logs.push(read_entry(reader.bytes()?)?),
+            (_, wire_type) => reader.skip(wire_type)?,
+        }
+    }
+    Ok(logs)
+}
+
+// ✅ Encodes a batch for a downstream (before compression)
+pub fn encode_batch(format: WireFormat, logs: &[LogEntry]) -> Result<Vec<u8>> {
+    match format {
+        WireFormat::Json => Ok(serde_json::to_vec(logs)?),
+        WireFormat::Protobuf => {
+            let mut buf = Vec::with_capacity(logs.len() * 256);
+            put_key(&mut buf, 1, VARINT);
+            put_varint(&mut buf, u64::from(SCHEMA_VERSION));
+            let mut scratch = Scratch::default();
+            for log in logs {
+                put_entry(&mut buf, log, &mut scratch);
+            }
+            Ok(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::test_support::log;
+    use serde_json::json;
+
+    fn decode(data: &[u8]) -> Result<Vec<LogEntry>> {
+        decode_batch(Some(PROTOBUF_CONTENT_TYPE), data)
+    }
+
+    #[test]
+    fn round_trips_entries_and_absent_fields() {
+        let logs = vec![
+            LogEntry {
+                timestamp: Some("2024-05-01T12:00:00Z".to_string()),
+                source: Some("api".to_string()),
+                ..log("hello", Some("INFO"), json!({"null": null, "i": -7, "u": u64::MAX, "f": 0.5, "b": true, "s": "x", "nested": {"a": [1, null]}}))
+            },
+            log("", None, json!({})),
+        ];
+        for format in [WireFormat::Protobuf, WireFormat::Json] {
+            let decoded = decode_batch(Some(format.content_type()), &encode_batch(format, &logs).unwrap()).unwrap();
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&logs).unwrap(), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn skips_fields_from_newer_schemas() {
+        let mut entry = Vec::new();
+        put_bytes(&mut entry, 3, b"hello");
+        put_key(&mut entry, 9, VARINT);
+        put_varint(&mut entry, 300);
+        put_key(&mut entry, 10, FIXED32);
+        entry.extend_from_slice(&[0; 4]);
+        put_bytes(&mut entry, 11, b"future");
+        let mut batch = Vec::new();
+        put_key(&mut batch, 1, VARINT);
+        put_varint(&mut batch, u64::from(SCHEMA_VERSION));
+        put_bytes(&mut batch, 2, &entry);
+        put_bytes(&mut batch, 7, b"trailer");
+
+        let logs = decode(&batch).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].message, "hello");
+        assert!(logs[0].attributes.is_empty());
+    }
+
+    #[test]
+    fn refuses_unknown_versions_and_message_types() {
+        let unsupported = |result: Result<Vec<LogEntry>>| result.unwrap_err().downcast_ref::<UnsupportedFormat>().is_some();
+
+        let mut newer = Vec::new();
+        put_key(&mut newer, 1, VARINT);
+        put_varint(&mut newer, u64::from(SCHEMA_VERSION) + 1);
+        assert!(unsupported(decode(&newer)));
+        assert!(unsupported(decode_batch(Some("application/x-protobuf; version=2"), &[])));
+        assert!(unsupported(decode_batch(Some("application/x-protobuf; version=v1"), &[])));
+        assert!(unsupported(decode_batch(Some("application/x-protobuf; proto=other.Batch"), &[])));
+        assert_eq!(decode_batch(Some("application/x-protobuf"), &[]).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        let batch = encode_batch(WireFormat::Protobuf, &[log("hello", None, json!({"k": "v"}))]).unwrap();
+        let error = decode(&batch[..batch.len() - 1]).unwrap_err();
+        assert!(error.downcast_ref::<UnsupportedFormat>().is_none());
+        assert!(error.to_string().contains("truncated"));
+        assert!(decode(&[0xff; 11]).unwrap_err().to_string().contains("varint"));
+        assert!(decode(&[(2 << 3) | 6]).unwrap_err().to_string().contains("wire type 6"));
+    }
+
+    #[test]
+    fn json_bodies_are_objects_or_arrays() {
+        let entry = json!({"message": "one"}).to_string();
+        assert_eq!(decode_batch(None, entry.as_bytes()).unwrap().len(), 1);
+        assert_eq!(decode_batch(Some(JSON_CONTENT_TYPE), format!(" [{},{}]", entry, entry).as_bytes()).unwrap().len(), 2);
+        assert!(decode_batch(None, b"not json").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+"""Writes the tiny MaxMind-format databases the geoip tests open.
+
+    python3 make_mmdb.py [output-dir]
+
+Each database maps one IPv4 network to one record, using the addresses and
+values of MaxMind's published test data (81.2.69.0/24 is London, AS20712).
+"""
+import ipaddress
+import os
+import struct
+import sys
+
+UINT16, UINT32, UINT64 = 5, 6, 9
+
+
+def encode(value):
+    def control(kind, size, payload):
+        extra = b""
+        if size >= 29:
+            extra, size = bytes([size - 29]), 29
+        if kind <= 7:
+            return bytes([(kind << 5) | size]) + extra + payload
+        return bytes([size, kind - 7]) + extra + payload
+
+    if isinstance(value, bool):
+        return control(14, int(value), b"")
+    if isinstance(value, str):
+        data = value.encode()
+        return control(2, len(data), data)
+    if isinstance(value, float):
+        return control(3, 8, struct.pack(">d", value))
+    if isinstance(value, tuple):  # (unsigned type, number)
+        kind, number = value
+        data = number.to_bytes(8, "big").lstrip(b"\0")
+        return control(kind, len(data), data)
+    if isinstance(value, int):
+        return encode((UINT32, value))
+    if isinstance(value, list):
+        return control(11, len(value), b"".join(encode(item) for item in value))
+    if isinstance(value, dict):
+        return control(7, len(value), b"".join(encode(k) + encode(v) for k, v in value.items()))
+    raise TypeError(value)
+
+
+def build(path, database_type, network, record):
+    network = ipaddress.ip_network(network)
+    bits = format(int(network.network_address), "032b")[: network.prefixlen]
+    node_count = len(bits)
+    data_pointer = node_count + 16  # First record in the data section
+    tree = b""
+    for i, bit in enumerate(bits):
+        records = [node_count, node_count]  # node_count means "not found"
+        records[int(bit)] = i + 1 if i < node_count - 1 else data_pointer
+        tree += b"".join(r.to_bytes(3, "big") for r in records)
+    metadata = {
+        "node_count": (UINT32, node_count),
+        "record_size": (UINT16, 24),
+        "ip_version": (UINT16, 4),
+        "database_type": database_type,
+        "languages": ["en"],
+        "binary_format_major_version": (UINT16, 2),
+        "binary_format_minor_version": (UINT16, 0),
+        "build_epoch": (UINT64, 1700000000),
+        "description": {"en": "log-processor test data"},
+    }
+    with open(path, "wb") as f:
+        f.write(tree + b"\0" * 16 + encode(record) + b"\xab\xcd\xefMaxMind.com" + encode(metadata))
+
+
+def city(name):
+    return {
+        "city": {"names": {"en": name}},
+        "continent": {"code": "EU"},
+        "country": {"iso_code": "GB", "names": {"en": "United Kingdom"}},
+        "location": {"latitude": 51.5142, "longitude": -0.0931, "time_zone": "Europe/London"},
+        "subdivisions": [{"iso_code": "ENG", "names": {"en": "England"}}],
+    }
+
+
+if __name__ == "__main__":
+    out = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__))
+    build(os.path.join(out, "GeoLite2-City-Test.mmdb"), "GeoLite2-City", "81.2.69.0/24", city("London"))
+    # Same network, another city: stands in for a database update in the reload test
+    build(os.path.join(out, "GeoLite2-City-Test-Updated.mmdb"), "GeoLite2-City", "81.2.69.0/24", city("Boxford"))
+    build(
+        os.path.join(out, "GeoLite2-ASN-Test.mmdb"),
+        "GeoLite2-ASN",
+        "81.2.69.0/24",
+        {"autonomous_system_number": (UINT32, 20712), "autonomous_system_organization": "Andrews & Arnold Ltd"},
+    )